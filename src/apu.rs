@@ -0,0 +1,1167 @@
+// Audio processing unit: the four DMG sound channels, the frame sequencer
+// and the NR50/NR51/NR52 mixer.
+
+use super::savestate::{StateReader, StateWriter};
+
+pub const CPU_CLOCK_HZ: u32 = 4_194_304;
+pub const APU_SAMPLE_RATE: u32 = CPU_CLOCK_HZ / 4;
+
+// The frame sequencer is clocked by the falling edge of DIV bit 4, which is
+// bit 12 of the internal 16 bit divider counter.
+const DIV_APU_BIT: u16 = 1 << 12;
+
+const DUTY_TABLE: [[u8; 8]; 4] = [
+    [0, 0, 0, 0, 0, 0, 0, 1], // 12.5%
+    [1, 0, 0, 0, 0, 0, 0, 1], // 25%
+    [1, 0, 0, 0, 0, 1, 1, 1], // 50%
+    [0, 1, 1, 1, 1, 1, 1, 0], // 75%
+];
+
+const STATE_VERSION: u8 = 1;
+
+const NOISE_DIVISORS: [u32; 8] = [8, 16, 32, 48, 64, 80, 96, 112];
+
+// Bits that always read back as 1, indexed from NR10 (0xFF10) to NR52 (0xFF26)
+const READ_MASK: [u8; 0x17] = [
+    0x80, 0x3F, 0x00, 0xFF, 0xBF, // NR10-NR14
+    0xFF, 0x3F, 0x00, 0xFF, 0xBF, // NR20-NR24
+    0x7F, 0xFF, 0x9F, 0xFF, 0xBF, // NR30-NR34
+    0xFF, 0xFF, 0x00, 0x00, 0xBF, // NR40-NR44
+    0x00, 0x00, 0x70, // NR50-NR52
+];
+
+pub const NR10: usize = 0xFF10;
+pub const NR11: usize = 0xFF11;
+pub const NR12: usize = 0xFF12;
+pub const NR13: usize = 0xFF13;
+pub const NR14: usize = 0xFF14;
+pub const NR21: usize = 0xFF16;
+pub const NR22: usize = 0xFF17;
+pub const NR23: usize = 0xFF18;
+pub const NR24: usize = 0xFF19;
+pub const NR30: usize = 0xFF1A;
+pub const NR31: usize = 0xFF1B;
+pub const NR32: usize = 0xFF1C;
+pub const NR33: usize = 0xFF1D;
+pub const NR34: usize = 0xFF1E;
+pub const NR41: usize = 0xFF20;
+pub const NR42: usize = 0xFF21;
+pub const NR43: usize = 0xFF22;
+pub const NR44: usize = 0xFF23;
+pub const NR50: usize = 0xFF24;
+pub const NR51: usize = 0xFF25;
+pub const NR52: usize = 0xFF26;
+pub const WAVE_RAM_START: usize = 0xFF30;
+pub const WAVE_RAM_END: usize = 0xFF3F;
+
+#[derive(Debug)]
+struct LengthCounter {
+    enabled: bool,
+    counter: u16,
+    full: u16,
+}
+
+impl LengthCounter {
+    fn new(full: u16) -> LengthCounter {
+        LengthCounter {
+            enabled: false,
+            counter: 0,
+            full,
+        }
+    }
+
+    fn load(&mut self, value: u16) {
+        self.counter = self.full - value;
+    }
+
+    fn clock(&mut self, channel_enabled: &mut bool) {
+        if self.enabled && self.counter > 0 {
+            self.counter -= 1;
+            if self.counter == 0 {
+                *channel_enabled = false;
+            }
+        }
+    }
+
+    // Enabling the length counter while the next frame sequencer step does not
+    // clock length gives it one extra clock.
+    fn write_enable(&mut self, enable: bool, frame_step: u8, channel_enabled: &mut bool) {
+        let was_enabled = self.enabled;
+        self.enabled = enable;
+        if !was_enabled && enable && frame_step & 1 == 1 {
+            self.clock(channel_enabled);
+        }
+    }
+
+    fn trigger(&mut self, frame_step: u8) {
+        if self.counter == 0 {
+            self.counter = self.full;
+            if self.enabled && frame_step & 1 == 1 {
+                self.counter -= 1;
+            }
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u16(self.counter);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.enabled = reader.read_bool()?;
+        self.counter = reader.read_u16()?.min(self.full);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Envelope {
+    initial_volume: u8,
+    increase: bool,
+    period: u8,
+    volume: u8,
+    timer: u8,
+}
+
+impl Envelope {
+    fn new() -> Envelope {
+        Envelope {
+            initial_volume: 0,
+            increase: false,
+            period: 0,
+            volume: 0,
+            timer: 0,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        (self.initial_volume << 4) | ((self.increase as u8) << 3) | self.period
+    }
+
+    fn write(&mut self, value: u8) {
+        self.initial_volume = value >> 4;
+        self.increase = value & 0x08 != 0;
+        self.period = value & 0x07;
+    }
+
+    // The DAC is powered whenever the upper five bits of NRx2 are not all zero
+    fn dac_enabled(&self) -> bool {
+        self.read() & 0xF8 != 0
+    }
+
+    fn trigger(&mut self) {
+        self.volume = self.initial_volume;
+        self.timer = self.period;
+    }
+
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.initial_volume);
+        writer.write_bool(self.increase);
+        writer.write_u8(self.period);
+        writer.write_u8(self.volume);
+        writer.write_u8(self.timer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.initial_volume = reader.read_u8()? & 0x0F;
+        self.increase = reader.read_bool()?;
+        self.period = reader.read_u8()? & 0x07;
+        self.volume = reader.read_u8()? & 0x0F;
+        self.timer = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct Sweep {
+    period: u8,
+    negate: bool,
+    shift: u8,
+    timer: u8,
+    shadow: u16,
+    enabled: bool,
+    negate_used: bool,
+}
+
+impl Sweep {
+    fn new() -> Sweep {
+        Sweep {
+            period: 0,
+            negate: false,
+            shift: 0,
+            timer: 0,
+            shadow: 0,
+            enabled: false,
+            negate_used: false,
+        }
+    }
+
+    fn read(&self) -> u8 {
+        (self.period << 4) | ((self.negate as u8) << 3) | self.shift
+    }
+
+    fn write(&mut self, value: u8, channel_enabled: &mut bool) {
+        let negate = value & 0x08 != 0;
+        // Leaving negate mode after a negated calculation disables the channel
+        if self.negate && !negate && self.negate_used {
+            *channel_enabled = false;
+        }
+        self.period = (value >> 4) & 0x07;
+        self.negate = negate;
+        self.shift = value & 0x07;
+    }
+
+    fn reload_timer(&mut self) {
+        self.timer = if self.period == 0 { 8 } else { self.period };
+    }
+
+    fn calculate(&mut self) -> u16 {
+        let delta = self.shadow >> self.shift;
+        if self.negate {
+            self.negate_used = true;
+            self.shadow - delta
+        } else {
+            self.shadow + delta
+        }
+    }
+
+    fn trigger(&mut self, frequency: u16, channel_enabled: &mut bool) {
+        self.shadow = frequency;
+        self.reload_timer();
+        self.enabled = self.period != 0 || self.shift != 0;
+        self.negate_used = false;
+        if self.shift != 0 && self.calculate() > 2047 {
+            *channel_enabled = false;
+        }
+    }
+
+    // Returns the new channel frequency when the sweep updated it
+    fn clock(&mut self, channel_enabled: &mut bool) -> Option<u16> {
+        if self.timer > 0 {
+            self.timer -= 1;
+        }
+        if self.timer != 0 {
+            return None;
+        }
+        self.reload_timer();
+        if !self.enabled || self.period == 0 {
+            return None;
+        }
+
+        let frequency = self.calculate();
+        if frequency > 2047 {
+            *channel_enabled = false;
+            return None;
+        }
+        if self.shift == 0 {
+            return None;
+        }
+
+        self.shadow = frequency;
+        // The new frequency is run through the overflow check a second time
+        if self.calculate() > 2047 {
+            *channel_enabled = false;
+        }
+        Some(frequency)
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(self.period);
+        writer.write_bool(self.negate);
+        writer.write_u8(self.shift);
+        writer.write_u8(self.timer);
+        writer.write_u16(self.shadow);
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.negate_used);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.period = reader.read_u8()? & 0x07;
+        self.negate = reader.read_bool()?;
+        self.shift = reader.read_u8()? & 0x07;
+        self.timer = reader.read_u8()?;
+        self.shadow = reader.read_u16()?;
+        self.enabled = reader.read_bool()?;
+        self.negate_used = reader.read_bool()?;
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct SquareChannel {
+    enabled: bool,
+    duty: u8,
+    duty_position: u8,
+    frequency: u16,
+    timer: u16,
+    length: LengthCounter,
+    envelope: Envelope,
+    sweep: Option<Sweep>,
+}
+
+impl SquareChannel {
+    fn new(with_sweep: bool) -> SquareChannel {
+        SquareChannel {
+            enabled: false,
+            duty: 0,
+            duty_position: 0,
+            frequency: 0,
+            timer: 2048 * 4,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+            sweep: if with_sweep { Some(Sweep::new()) } else { None },
+        }
+    }
+
+    fn tick(&mut self) {
+        self.timer -= 1;
+        if self.timer == 0 {
+            self.timer = (2048 - self.frequency) * 4;
+            self.duty_position = (self.duty_position + 1) & 0x07;
+        }
+    }
+
+    fn write_length(&mut self, value: u8) {
+        self.duty = value >> 6;
+        self.length.load((value & 0x3F) as u16);
+    }
+
+    fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    fn write_control(&mut self, value: u8, frame_step: u8) {
+        self.frequency = (self.frequency & 0x00FF) | (((value & 0x07) as u16) << 8);
+        self.length
+            .write_enable(value & 0x40 != 0, frame_step, &mut self.enabled);
+        if value & 0x80 != 0 {
+            self.trigger(frame_step);
+        }
+    }
+
+    fn trigger(&mut self, frame_step: u8) {
+        self.enabled = self.envelope.dac_enabled();
+        self.length.trigger(frame_step);
+        self.timer = (2048 - self.frequency) * 4;
+        self.envelope.trigger();
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.trigger(self.frequency, &mut self.enabled);
+        }
+    }
+
+    fn clock_sweep(&mut self) {
+        if let Some(sweep) = self.sweep.as_mut() {
+            if let Some(frequency) = sweep.clock(&mut self.enabled) {
+                self.frequency = frequency;
+            }
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        DUTY_TABLE[self.duty as usize][self.duty_position as usize] * self.envelope.volume
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u8(self.duty);
+        writer.write_u8(self.duty_position);
+        writer.write_u16(self.frequency);
+        writer.write_u16(self.timer);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+        if let Some(sweep) = self.sweep.as_ref() {
+            sweep.save_state(writer);
+        }
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.enabled = reader.read_bool()?;
+        self.duty = reader.read_u8()? & 0x03;
+        self.duty_position = reader.read_u8()? & 0x07;
+        self.frequency = reader.read_u16()? & 0x07FF;
+        // A zero timer would underflow on the next tick
+        self.timer = reader.read_u16()?.max(1);
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        if let Some(sweep) = self.sweep.as_mut() {
+            sweep.load_state(reader)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct WaveChannel {
+    enabled: bool,
+    dac_enabled: bool,
+    volume_code: u8,
+    frequency: u16,
+    timer: u16,
+    position: u8,
+    sample_buffer: u8,
+    length: LengthCounter,
+    ram: [u8; 16],
+}
+
+impl WaveChannel {
+    fn new() -> WaveChannel {
+        WaveChannel {
+            enabled: false,
+            dac_enabled: false,
+            volume_code: 0,
+            frequency: 0,
+            timer: 2048 * 2,
+            position: 0,
+            sample_buffer: 0,
+            length: LengthCounter::new(256),
+            ram: [0; 16],
+        }
+    }
+
+    fn tick(&mut self) {
+        self.timer -= 1;
+        if self.timer == 0 {
+            self.timer = (2048 - self.frequency) * 2;
+            self.position = (self.position + 1) & 0x1F;
+            let byte = self.ram[(self.position / 2) as usize];
+            self.sample_buffer = if self.position & 1 == 0 {
+                byte >> 4
+            } else {
+                byte & 0x0F
+            };
+        }
+    }
+
+    // While the channel is playing, wave RAM accesses land on the byte the
+    // channel is currently reading instead of the requested one.
+    fn ram_index(&self, address: usize) -> usize {
+        if self.enabled {
+            (self.position / 2) as usize
+        } else {
+            address - WAVE_RAM_START
+        }
+    }
+
+    fn write_control(&mut self, value: u8, frame_step: u8) {
+        self.frequency = (self.frequency & 0x00FF) | (((value & 0x07) as u16) << 8);
+        self.length
+            .write_enable(value & 0x40 != 0, frame_step, &mut self.enabled);
+        if value & 0x80 != 0 {
+            self.enabled = self.dac_enabled;
+            self.length.trigger(frame_step);
+            self.timer = (2048 - self.frequency) * 2;
+            self.position = 0;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled {
+            return 0;
+        }
+        match self.volume_code {
+            0 => 0,
+            1 => self.sample_buffer,
+            2 => self.sample_buffer >> 1,
+            _ => self.sample_buffer >> 2,
+        }
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_bool(self.dac_enabled);
+        writer.write_u8(self.volume_code);
+        writer.write_u16(self.frequency);
+        writer.write_u16(self.timer);
+        writer.write_u8(self.position);
+        writer.write_u8(self.sample_buffer);
+        self.length.save_state(writer);
+        writer.write_bytes(&self.ram);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.enabled = reader.read_bool()?;
+        self.dac_enabled = reader.read_bool()?;
+        self.volume_code = reader.read_u8()? & 0x03;
+        self.frequency = reader.read_u16()? & 0x07FF;
+        self.timer = reader.read_u16()?.max(1);
+        self.position = reader.read_u8()? & 0x1F;
+        self.sample_buffer = reader.read_u8()? & 0x0F;
+        self.length.load_state(reader)?;
+        let ram = reader.read_bytes()?;
+        if ram.len() != self.ram.len() {
+            return Err("APU section has a bad wave RAM size".to_string());
+        }
+        self.ram.copy_from_slice(ram);
+        Ok(())
+    }
+}
+
+#[derive(Debug)]
+struct NoiseChannel {
+    enabled: bool,
+    clock_shift: u8,
+    width_mode: bool,
+    divisor_code: u8,
+    timer: u32,
+    lfsr: u16,
+    length: LengthCounter,
+    envelope: Envelope,
+}
+
+impl NoiseChannel {
+    fn new() -> NoiseChannel {
+        NoiseChannel {
+            enabled: false,
+            clock_shift: 0,
+            width_mode: false,
+            divisor_code: 0,
+            timer: NOISE_DIVISORS[0],
+            lfsr: 0x7FFF,
+            length: LengthCounter::new(64),
+            envelope: Envelope::new(),
+        }
+    }
+
+    fn period(&self) -> u32 {
+        NOISE_DIVISORS[self.divisor_code as usize] << self.clock_shift
+    }
+
+    fn tick(&mut self) {
+        self.timer -= 1;
+        if self.timer == 0 {
+            self.timer = self.period();
+            // Shift clocks 14 and 15 never clock the LFSR
+            if self.clock_shift < 14 {
+                self.clock_lfsr();
+            }
+        }
+    }
+
+    fn clock_lfsr(&mut self) {
+        let bit = (self.lfsr ^ (self.lfsr >> 1)) & 0x01;
+        self.lfsr = (self.lfsr >> 1) | (bit << 14);
+        if self.width_mode {
+            self.lfsr = (self.lfsr & !0x40) | (bit << 6);
+        }
+    }
+
+    fn read_polynomial(&self) -> u8 {
+        (self.clock_shift << 4) | ((self.width_mode as u8) << 3) | self.divisor_code
+    }
+
+    fn write_polynomial(&mut self, value: u8) {
+        self.clock_shift = value >> 4;
+        self.width_mode = value & 0x08 != 0;
+        self.divisor_code = value & 0x07;
+    }
+
+    fn write_envelope(&mut self, value: u8) {
+        self.envelope.write(value);
+        if !self.envelope.dac_enabled() {
+            self.enabled = false;
+        }
+    }
+
+    fn write_control(&mut self, value: u8, frame_step: u8) {
+        self.length
+            .write_enable(value & 0x40 != 0, frame_step, &mut self.enabled);
+        if value & 0x80 != 0 {
+            self.enabled = self.envelope.dac_enabled();
+            self.length.trigger(frame_step);
+            self.timer = self.period();
+            self.envelope.trigger();
+            self.lfsr = 0x7FFF;
+        }
+    }
+
+    fn output(&self) -> u8 {
+        if !self.enabled || self.lfsr & 0x01 != 0 {
+            return 0;
+        }
+        self.envelope.volume
+    }
+
+    fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_bool(self.enabled);
+        writer.write_u8(self.clock_shift);
+        writer.write_bool(self.width_mode);
+        writer.write_u8(self.divisor_code);
+        writer.write_u32(self.timer);
+        writer.write_u16(self.lfsr);
+        self.length.save_state(writer);
+        self.envelope.save_state(writer);
+    }
+
+    fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        self.enabled = reader.read_bool()?;
+        self.clock_shift = reader.read_u8()? & 0x0F;
+        self.width_mode = reader.read_bool()?;
+        self.divisor_code = reader.read_u8()? & 0x07;
+        self.timer = reader.read_u32()?.max(1);
+        self.lfsr = reader.read_u16()? & 0x7FFF;
+        self.length.load_state(reader)?;
+        self.envelope.load_state(reader)?;
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Channel {
+    Square1,
+    Square2,
+    Wave,
+    Noise,
+}
+
+impl Channel {
+    pub const ALL: [Channel; 4] = [
+        Channel::Square1,
+        Channel::Square2,
+        Channel::Wave,
+        Channel::Noise,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Channel::Square1 => "square1",
+            Channel::Square2 => "square2",
+            Channel::Wave => "wave",
+            Channel::Noise => "noise",
+        }
+    }
+}
+
+impl std::str::FromStr for Channel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Channel, String> {
+        match s {
+            "1" | "square1" => Ok(Channel::Square1),
+            "2" | "square2" => Ok(Channel::Square2),
+            "3" | "wave" => Ok(Channel::Wave),
+            "4" | "noise" => Ok(Channel::Noise),
+            _ => Err(format!("unknown channel '{}'", s)),
+        }
+    }
+}
+
+// A write to an APU register, timestamped in clock cycles since power on
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RegisterWrite {
+    pub cycle: u64,
+    pub address: usize,
+    pub value: u8,
+}
+
+// Converts a 4 bit channel output to the -1.0..1.0 range. A disabled DAC
+// contributes nothing, while an enabled DAC with a silent channel sits at
+// full negative offset just like the hardware.
+fn dac(enabled: bool, digital: u8) -> f32 {
+    if enabled {
+        digital as f32 / 7.5 - 1.0
+    } else {
+        0.0
+    }
+}
+
+#[derive(Debug)]
+pub struct Apu {
+    square1: SquareChannel,
+    square2: SquareChannel,
+    wave: WaveChannel,
+    noise: NoiseChannel,
+    nr50: u8,
+    nr51: u8,
+    powered: bool,
+    frame_step: u8,
+    div: u16,
+    cycles: u64,
+    muted: [bool; 4],
+    solo: Option<Channel>,
+    sample_output: bool,
+    samples: Vec<(f32, f32)>,
+    stem_output: bool,
+    stems: [Vec<(f32, f32)>; 4],
+    register_logging: bool,
+    register_writes: Vec<RegisterWrite>,
+}
+
+impl Apu {
+    pub fn new() -> Apu {
+        Apu {
+            square1: SquareChannel::new(true),
+            square2: SquareChannel::new(false),
+            wave: WaveChannel::new(),
+            noise: NoiseChannel::new(),
+            nr50: 0,
+            nr51: 0,
+            powered: false,
+            frame_step: 0,
+            div: 0,
+            cycles: 0,
+            muted: [false; 4],
+            solo: None,
+            sample_output: false,
+            samples: Vec::new(),
+            stem_output: false,
+            stems: [Vec::new(), Vec::new(), Vec::new(), Vec::new()],
+            register_logging: false,
+            register_writes: Vec::new(),
+        }
+    }
+
+    // Back to the power on state, keeping the output and mixing settings
+    pub fn reset(&mut self) {
+        let mut apu = Apu::new();
+        apu.muted = self.muted;
+        apu.solo = self.solo;
+        apu.sample_output = self.sample_output;
+        apu.stem_output = self.stem_output;
+        apu.register_logging = self.register_logging;
+        *self = apu;
+    }
+
+    pub fn powered(&self) -> bool {
+        self.powered
+    }
+
+    // When enabled, the mixed output is recorded every 4 cycles (APU_SAMPLE_RATE)
+    // until collected with take_samples.
+    pub fn set_sample_output(&mut self, enabled: bool) {
+        self.sample_output = enabled;
+        if !enabled {
+            self.samples.clear();
+        }
+    }
+
+    pub fn take_samples(&mut self) -> Vec<(f32, f32)> {
+        std::mem::take(&mut self.samples)
+    }
+
+    // Same as sample output, but every channel is recorded on its own with
+    // panning and master volume applied, ignoring mute and solo.
+    pub fn set_stem_output(&mut self, enabled: bool) {
+        self.stem_output = enabled;
+        if !enabled {
+            self.stems.iter_mut().for_each(|stem| stem.clear());
+        }
+    }
+
+    pub fn take_stems(&mut self) -> [Vec<(f32, f32)>; 4] {
+        std::mem::take(&mut self.stems)
+    }
+
+    // Records every register and wave RAM write until collected with
+    // take_register_writes.
+    pub fn set_register_logging(&mut self, enabled: bool) {
+        self.register_logging = enabled;
+        if !enabled {
+            self.register_writes.clear();
+        }
+    }
+
+    pub fn take_register_writes(&mut self) -> Vec<RegisterWrite> {
+        std::mem::take(&mut self.register_writes)
+    }
+
+    // Clock cycles elapsed since the APU was created
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    pub fn set_muted(&mut self, channel: Channel, muted: bool) {
+        self.muted[channel as usize] = muted;
+    }
+
+    pub fn muted(&self, channel: Channel) -> bool {
+        self.muted[channel as usize]
+    }
+
+    // While a channel is soloed every other channel is left out of the mix
+    pub fn set_solo(&mut self, channel: Option<Channel>) {
+        self.solo = channel;
+    }
+
+    pub fn solo(&self) -> Option<Channel> {
+        self.solo
+    }
+
+    fn audible(&self, channel: Channel) -> bool {
+        match self.solo {
+            Some(solo) => solo == channel,
+            None => !self.muted[channel as usize],
+        }
+    }
+
+    pub fn step(&mut self, cycles: u32) {
+        for _ in 0..cycles {
+            self.tick();
+        }
+    }
+
+    fn tick(&mut self) {
+        let old_div = self.div;
+        self.div = self.div.wrapping_add(1);
+        self.cycles += 1;
+        if old_div & DIV_APU_BIT != 0 && self.div & DIV_APU_BIT == 0 {
+            self.clock_frame_sequencer();
+        }
+
+        if self.powered {
+            self.square1.tick();
+            self.square2.tick();
+            self.wave.tick();
+            self.noise.tick();
+        }
+
+        if self.div & 0x03 == 0 {
+            if self.sample_output {
+                let sample = self.output();
+                self.samples.push(sample);
+            }
+            if self.stem_output {
+                for channel in Channel::ALL.iter() {
+                    let sample = self.channel_output(*channel);
+                    self.stems[*channel as usize].push(sample);
+                }
+            }
+        }
+    }
+
+    // The 16 bit divider, whose upper byte is the DIV register
+    pub fn div(&self) -> u16 {
+        self.div
+    }
+
+    // Writing DIV resets the divider, which counts as a falling edge when
+    // the sequencer bit was set.
+    pub fn reset_div(&mut self) {
+        if self.div & DIV_APU_BIT != 0 {
+            self.clock_frame_sequencer();
+        }
+        self.div = 0;
+    }
+
+    fn clock_frame_sequencer(&mut self) {
+        if !self.powered {
+            return;
+        }
+        let step = self.frame_step;
+
+        if step & 1 == 0 {
+            self.square1.length.clock(&mut self.square1.enabled);
+            self.square2.length.clock(&mut self.square2.enabled);
+            self.wave.length.clock(&mut self.wave.enabled);
+            self.noise.length.clock(&mut self.noise.enabled);
+        }
+        if step == 2 || step == 6 {
+            self.square1.clock_sweep();
+        }
+        if step == 7 {
+            self.square1.envelope.clock();
+            self.square2.envelope.clock();
+            self.noise.envelope.clock();
+        }
+
+        self.frame_step = (step + 1) & 0x07;
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        match address {
+            WAVE_RAM_START..=WAVE_RAM_END => self.wave.ram[self.wave.ram_index(address)],
+            NR10..=NR52 => self.read_register(address) | READ_MASK[address - NR10],
+            _ => 0xFF,
+        }
+    }
+
+    fn read_register(&self, address: usize) -> u8 {
+        match address {
+            NR10 => self.square1.sweep.as_ref().map_or(0, |sweep| sweep.read()),
+            NR11 => self.square1.duty << 6,
+            NR12 => self.square1.envelope.read(),
+            NR14 => (self.square1.length.enabled as u8) << 6,
+            NR21 => self.square2.duty << 6,
+            NR22 => self.square2.envelope.read(),
+            NR24 => (self.square2.length.enabled as u8) << 6,
+            NR30 => (self.wave.dac_enabled as u8) << 7,
+            NR32 => self.wave.volume_code << 5,
+            NR34 => (self.wave.length.enabled as u8) << 6,
+            NR42 => self.noise.envelope.read(),
+            NR43 => self.noise.read_polynomial(),
+            NR44 => (self.noise.length.enabled as u8) << 6,
+            NR50 => self.nr50,
+            NR51 => self.nr51,
+            NR52 => {
+                ((self.powered as u8) << 7)
+                    | ((self.noise.enabled as u8) << 3)
+                    | ((self.wave.enabled as u8) << 2)
+                    | ((self.square2.enabled as u8) << 1)
+                    | (self.square1.enabled as u8)
+            }
+            _ => 0,
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        if self.register_logging {
+            if let NR10..=WAVE_RAM_END = address {
+                self.register_writes.push(RegisterWrite {
+                    cycle: self.cycles,
+                    address,
+                    value,
+                });
+            }
+        }
+
+        match address {
+            WAVE_RAM_START..=WAVE_RAM_END => {
+                let index = self.wave.ram_index(address);
+                self.wave.ram[index] = value;
+            }
+            NR52 => self.write_power(value & 0x80 != 0),
+            NR10..=NR51 => {
+                if self.powered {
+                    self.write_register(address, value);
+                } else {
+                    self.write_length_while_off(address, value);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn write_register(&mut self, address: usize, value: u8) {
+        let frame_step = self.frame_step;
+        match address {
+            NR10 => {
+                if let Some(sweep) = self.square1.sweep.as_mut() {
+                    sweep.write(value, &mut self.square1.enabled);
+                }
+            }
+            NR11 => self.square1.write_length(value),
+            NR12 => self.square1.write_envelope(value),
+            NR13 => self.square1.frequency = (self.square1.frequency & 0x0700) | value as u16,
+            NR14 => self.square1.write_control(value, frame_step),
+            NR21 => self.square2.write_length(value),
+            NR22 => self.square2.write_envelope(value),
+            NR23 => self.square2.frequency = (self.square2.frequency & 0x0700) | value as u16,
+            NR24 => self.square2.write_control(value, frame_step),
+            NR30 => {
+                self.wave.dac_enabled = value & 0x80 != 0;
+                if !self.wave.dac_enabled {
+                    self.wave.enabled = false;
+                }
+            }
+            NR31 => self.wave.length.load(value as u16),
+            NR32 => self.wave.volume_code = (value >> 5) & 0x03,
+            NR33 => self.wave.frequency = (self.wave.frequency & 0x0700) | value as u16,
+            NR34 => self.wave.write_control(value, frame_step),
+            NR41 => self.noise.length.load((value & 0x3F) as u16),
+            NR42 => self.noise.write_envelope(value),
+            NR43 => self.noise.write_polynomial(value),
+            NR44 => self.noise.write_control(value, frame_step),
+            NR50 => self.nr50 = value,
+            NR51 => self.nr51 = value,
+            _ => {}
+        }
+    }
+
+    // On the DMG the length counters stay writable while the APU is off
+    fn write_length_while_off(&mut self, address: usize, value: u8) {
+        match address {
+            NR11 => self.square1.length.load((value & 0x3F) as u16),
+            NR21 => self.square2.length.load((value & 0x3F) as u16),
+            NR31 => self.wave.length.load(value as u16),
+            NR41 => self.noise.length.load((value & 0x3F) as u16),
+            _ => {}
+        }
+    }
+
+    fn write_power(&mut self, on: bool) {
+        if self.powered && !on {
+            // Powering off clears every register except the length counters
+            for address in NR10..=NR51 {
+                match address {
+                    NR11 => self.square1.duty = 0,
+                    NR21 => self.square2.duty = 0,
+                    NR31 | NR41 => {}
+                    _ => self.write_register(address, 0),
+                }
+            }
+            self.square1.enabled = false;
+            self.square2.enabled = false;
+            self.wave.enabled = false;
+            self.noise.enabled = false;
+        } else if !self.powered && on {
+            self.frame_step = 0;
+            self.square1.duty_position = 0;
+            self.square2.duty_position = 0;
+            self.wave.sample_buffer = 0;
+        }
+        self.powered = on;
+    }
+
+    // Register writes that bring a freshly powered APU to the current state,
+    // including the write-only frequency bits. Channels that are playing are
+    // retriggered, which restarts their envelope and length counters.
+    pub fn register_state(&self) -> Vec<(usize, u8)> {
+        let mut state = vec![(NR52, 0x80), (NR50, self.nr50), (NR51, self.nr51)];
+        for (index, value) in self.wave.ram.iter().enumerate() {
+            state.push((WAVE_RAM_START + index, *value));
+        }
+
+        let control = |frequency: u16, length_enabled: bool, playing: bool| {
+            ((playing as u8) << 7) | ((length_enabled as u8) << 6) | (frequency >> 8) as u8
+        };
+        let length = |length: &LengthCounter| (length.full - length.counter) as u8;
+
+        let square1 = &self.square1;
+        state.push((NR10, self.read_register(NR10)));
+        state.push((NR11, (square1.duty << 6) | (length(&square1.length) & 0x3F)));
+        state.push((NR12, square1.envelope.read()));
+        state.push((NR13, square1.frequency as u8));
+        state.push((
+            NR14,
+            control(square1.frequency, square1.length.enabled, square1.enabled),
+        ));
+
+        let square2 = &self.square2;
+        state.push((NR21, (square2.duty << 6) | (length(&square2.length) & 0x3F)));
+        state.push((NR22, square2.envelope.read()));
+        state.push((NR23, square2.frequency as u8));
+        state.push((
+            NR24,
+            control(square2.frequency, square2.length.enabled, square2.enabled),
+        ));
+
+        let wave = &self.wave;
+        state.push((NR30, (wave.dac_enabled as u8) << 7));
+        state.push((NR31, length(&wave.length)));
+        state.push((NR32, wave.volume_code << 5));
+        state.push((NR33, wave.frequency as u8));
+        state.push((
+            NR34,
+            control(wave.frequency, wave.length.enabled, wave.enabled),
+        ));
+
+        let noise = &self.noise;
+        state.push((NR41, length(&noise.length) & 0x3F));
+        state.push((NR42, noise.envelope.read()));
+        state.push((NR43, noise.read_polynomial()));
+        state.push((NR44, control(0, noise.length.enabled, noise.enabled)));
+
+        state
+    }
+
+    fn dac_output(&self, channel: Channel) -> f32 {
+        match channel {
+            Channel::Square1 => dac(self.square1.envelope.dac_enabled(), self.square1.output()),
+            Channel::Square2 => dac(self.square2.envelope.dac_enabled(), self.square2.output()),
+            Channel::Wave => dac(self.wave.dac_enabled, self.wave.output()),
+            Channel::Noise => dac(self.noise.envelope.dac_enabled(), self.noise.output()),
+        }
+    }
+
+    fn master_volume(&self) -> (f32, f32) {
+        let left = (((self.nr50 >> 4) & 0x07) + 1) as f32 / 8.0;
+        let right = ((self.nr50 & 0x07) + 1) as f32 / 8.0;
+        (left, right)
+    }
+
+    // Output of a single channel after panning and master volume, scaled the
+    // same as its contribution to the mixed output.
+    pub fn channel_output(&self, channel: Channel) -> (f32, f32) {
+        if !self.powered {
+            return (0.0, 0.0);
+        }
+
+        let sample = self.dac_output(channel);
+        let (left_volume, right_volume) = self.master_volume();
+        let index = channel as usize;
+        let left = if self.nr51 & (0x10 << index) != 0 {
+            sample / 4.0 * left_volume
+        } else {
+            0.0
+        };
+        let right = if self.nr51 & (0x01 << index) != 0 {
+            sample / 4.0 * right_volume
+        } else {
+            0.0
+        };
+        (left, right)
+    }
+
+    // Mixed (left, right) output in the -1.0..1.0 range
+    pub fn output(&self) -> (f32, f32) {
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for channel in Channel::ALL.iter() {
+            if self.audible(*channel) {
+                let (channel_left, channel_right) = self.channel_output(*channel);
+                left += channel_left;
+                right += channel_right;
+            }
+        }
+        (left, right)
+    }
+
+    // Hardware state only; output and mixing settings belong to the frontend
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(STATE_VERSION);
+        self.square1.save_state(writer);
+        self.square2.save_state(writer);
+        self.wave.save_state(writer);
+        self.noise.save_state(writer);
+        writer.write_u8(self.nr50);
+        writer.write_u8(self.nr51);
+        writer.write_bool(self.powered);
+        writer.write_u8(self.frame_step);
+        writer.write_u16(self.div);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_version("APU", STATE_VERSION)?;
+        self.square1.load_state(reader)?;
+        self.square2.load_state(reader)?;
+        self.wave.load_state(reader)?;
+        self.noise.load_state(reader)?;
+        self.nr50 = reader.read_u8()?;
+        self.nr51 = reader.read_u8()?;
+        self.powered = reader.read_bool()?;
+        self.frame_step = reader.read_u8()? & 0x07;
+        self.div = reader.read_u16()?;
+        Ok(())
+    }
+}
+
+impl Default for Apu {
+    fn default() -> Apu {
+        Apu::new()
+    }
+}
+
+#[cfg(test)]
+mod test;
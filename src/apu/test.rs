@@ -0,0 +1,395 @@
+use super::*;
+
+fn powered_apu() -> Apu {
+    let mut apu = Apu::new();
+    apu.write(NR52, 0x80);
+    apu
+}
+
+#[test]
+fn read_masks() {
+    let apu = powered_apu();
+
+    assert_eq!(apu.read(NR10), 0x80);
+    assert_eq!(apu.read(NR13), 0xFF);
+    assert_eq!(apu.read(NR30), 0x7F);
+    assert_eq!(apu.read(0xFF15), 0xFF);
+    assert_eq!(apu.read(NR52), 0xF0);
+}
+
+#[test]
+fn registers_read_back() {
+    let mut apu = powered_apu();
+
+    apu.write(NR10, 0x7A);
+    apu.write(NR11, 0xBF);
+    apu.write(NR12, 0xF3);
+    apu.write(NR50, 0x77);
+    apu.write(NR51, 0xF3);
+
+    assert_eq!(apu.read(NR10), 0xFA);
+    assert_eq!(apu.read(NR11), 0xBF);
+    assert_eq!(apu.read(NR12), 0xF3);
+    assert_eq!(apu.read(NR50), 0x77);
+    assert_eq!(apu.read(NR51), 0xF3);
+}
+
+#[test]
+fn writes_ignored_while_off() {
+    let mut apu = Apu::new();
+
+    apu.write(NR12, 0xF0);
+    apu.write(NR50, 0x77);
+    assert_eq!(apu.read(NR12), 0x00);
+    assert_eq!(apu.read(NR50), 0x00);
+}
+
+#[test]
+fn power_off_clears_registers() {
+    let mut apu = powered_apu();
+
+    apu.write(NR12, 0xF0);
+    apu.write(NR14, 0x80);
+    apu.write(NR51, 0xFF);
+    assert_eq!(apu.read(NR52), 0xF1);
+
+    apu.write(NR52, 0x00);
+    assert_eq!(apu.read(NR12), 0x00);
+    assert_eq!(apu.read(NR51), 0x00);
+    assert_eq!(apu.read(NR52), 0x70);
+}
+
+#[test]
+fn length_counter_survives_power_off() {
+    let mut apu = powered_apu();
+
+    apu.write(NR52, 0x00);
+    apu.write(NR41, 0x3F);
+    apu.write(NR52, 0x80);
+
+    apu.write(NR42, 0xF0);
+    apu.write(NR44, 0xC0);
+    assert_eq!(apu.read(NR52) & 0x08, 0x08);
+
+    // One frame sequencer length clock is enough to expire a length of 1
+    apu.step(DIV_APU_BIT as u32 * 2);
+    assert_eq!(apu.read(NR52) & 0x08, 0x00);
+}
+
+#[test]
+fn trigger_requires_dac() {
+    let mut apu = powered_apu();
+
+    apu.write(NR22, 0x00);
+    apu.write(NR24, 0x80);
+    assert_eq!(apu.read(NR52) & 0x02, 0x00);
+
+    apu.write(NR22, 0x08);
+    apu.write(NR24, 0x80);
+    assert_eq!(apu.read(NR52) & 0x02, 0x02);
+
+    apu.write(NR22, 0x00);
+    assert_eq!(apu.read(NR52) & 0x02, 0x00);
+}
+
+#[test]
+fn length_counter_disables_channel() {
+    let mut apu = powered_apu();
+
+    apu.write(NR30, 0x80);
+    apu.write(NR31, 0xFE);
+    apu.write(NR34, 0xC0);
+    assert_eq!(apu.read(NR52) & 0x04, 0x04);
+
+    // Length is clocked on the first and third frame sequencer steps
+    apu.step(DIV_APU_BIT as u32 * 2 * 3);
+    assert_eq!(apu.read(NR52) & 0x04, 0x00);
+}
+
+#[test]
+fn frame_sequencer_follows_div_reset() {
+    let mut apu = powered_apu();
+
+    apu.write(NR42, 0xF0);
+    apu.write(NR41, 0x3F);
+    apu.write(NR44, 0xC0);
+
+    apu.step(DIV_APU_BIT as u32);
+    apu.reset_div();
+    assert_eq!(apu.read(NR52) & 0x08, 0x00);
+}
+
+#[test]
+fn envelope_decreases_volume() {
+    let mut envelope = Envelope::new();
+
+    envelope.write(0x51);
+    envelope.trigger();
+    assert_eq!(envelope.volume, 5);
+
+    envelope.clock();
+    assert_eq!(envelope.volume, 4);
+
+    for _ in 0..10 {
+        envelope.clock();
+    }
+    assert_eq!(envelope.volume, 0);
+}
+
+#[test]
+fn envelope_increases_volume() {
+    let mut envelope = Envelope::new();
+
+    envelope.write(0xE9);
+    envelope.trigger();
+    assert_eq!(envelope.volume, 14);
+
+    envelope.clock();
+    envelope.clock();
+    envelope.clock();
+    assert_eq!(envelope.volume, 15);
+}
+
+#[test]
+fn sweep_overflow_disables_square1() {
+    let mut apu = powered_apu();
+
+    apu.write(NR10, 0x11);
+    apu.write(NR12, 0xF0);
+    apu.write(NR13, 0xFF);
+    apu.write(NR14, 0x87);
+    assert_eq!(apu.read(NR52) & 0x01, 0x00);
+}
+
+#[test]
+fn sweep_updates_frequency() {
+    let mut apu = powered_apu();
+
+    apu.write(NR10, 0x11);
+    apu.write(NR12, 0xF0);
+    apu.write(NR13, 0x00);
+    apu.write(NR14, 0x82);
+
+    // Sweep is clocked on the third frame sequencer step
+    apu.step(DIV_APU_BIT as u32 * 2 * 3);
+    assert_eq!(apu.square1.frequency, 0x300);
+    assert_eq!(apu.read(NR52) & 0x01, 0x01);
+}
+
+#[test]
+fn sweep_negate_exit_disables_square1() {
+    let mut apu = powered_apu();
+
+    apu.write(NR10, 0x19);
+    apu.write(NR12, 0xF0);
+    apu.write(NR13, 0x00);
+    apu.write(NR14, 0x82);
+    assert_eq!(apu.read(NR52) & 0x01, 0x01);
+
+    apu.write(NR10, 0x11);
+    assert_eq!(apu.read(NR52) & 0x01, 0x00);
+}
+
+#[test]
+fn square_duty_output() {
+    let mut apu = powered_apu();
+
+    apu.write(NR21, 0x80);
+    apu.write(NR22, 0xF0);
+    apu.write(NR23, 0xFF);
+    apu.write(NR24, 0x87);
+
+    let mut high = 0;
+    for _ in 0..8 {
+        apu.step(4);
+        if apu.square2.output() == 15 {
+            high += 1;
+        }
+    }
+    assert_eq!(high, 4);
+}
+
+#[test]
+fn noise_15_bit_lfsr() {
+    let mut noise = NoiseChannel::new();
+
+    noise.clock_lfsr();
+    assert_eq!(noise.lfsr, 0x3FFF);
+
+    noise.lfsr = 0x0001;
+    noise.clock_lfsr();
+    assert_eq!(noise.lfsr, 0x4000);
+}
+
+#[test]
+fn noise_7_bit_lfsr_period() {
+    let mut noise = NoiseChannel::new();
+    noise.write_polynomial(0x08);
+
+    noise.clock_lfsr();
+    let start = noise.lfsr & 0x7F;
+    let mut period = 1;
+    noise.clock_lfsr();
+    while noise.lfsr & 0x7F != start {
+        noise.clock_lfsr();
+        period += 1;
+    }
+    assert_eq!(period, 127);
+}
+
+#[test]
+fn wave_ram_access() {
+    let mut apu = Apu::new();
+
+    apu.write(WAVE_RAM_START, 0x12);
+    apu.write(WAVE_RAM_END, 0xEF);
+    assert_eq!(apu.read(WAVE_RAM_START), 0x12);
+    assert_eq!(apu.read(WAVE_RAM_END), 0xEF);
+}
+
+#[test]
+fn wave_volume_shift() {
+    let mut apu = powered_apu();
+
+    for address in WAVE_RAM_START..=WAVE_RAM_END {
+        apu.write(address, 0xCC);
+    }
+    apu.write(NR30, 0x80);
+    apu.write(NR32, 0x20);
+    apu.write(NR33, 0xFF);
+    apu.write(NR34, 0x87);
+
+    apu.step(2);
+    assert_eq!(apu.wave.output(), 0x0C);
+
+    apu.write(NR32, 0x60);
+    assert_eq!(apu.wave.output(), 0x03);
+}
+
+#[test]
+fn mixer_panning() {
+    let mut apu = powered_apu();
+
+    apu.write(NR50, 0x77);
+    apu.write(NR51, 0x01);
+    apu.write(NR12, 0xF0);
+    apu.write(NR14, 0x80);
+
+    let (left, right) = apu.output();
+    assert_eq!(left, 0.0);
+    assert!(right != 0.0);
+}
+
+#[test]
+fn output_silent_while_off() {
+    let apu = Apu::new();
+
+    assert_eq!(apu.output(), (0.0, 0.0));
+}
+
+#[test]
+fn sample_output_rate() {
+    let mut apu = powered_apu();
+
+    apu.step(400);
+    assert_eq!(apu.take_samples().len(), 0);
+
+    apu.set_sample_output(true);
+    apu.step(400);
+    assert_eq!(apu.take_samples().len(), 100);
+    assert_eq!(apu.take_samples().len(), 0);
+}
+
+#[test]
+fn mute_and_solo() {
+    let mut apu = powered_apu();
+    apu.write(NR51, 0x33);
+    apu.write(NR12, 0xF0);
+    apu.write(NR14, 0x80);
+    apu.write(NR22, 0xF0);
+    apu.write(NR24, 0x80);
+
+    let square1 = apu.channel_output(Channel::Square1);
+    let square2 = apu.channel_output(Channel::Square2);
+    assert_eq!(apu.output(), (square1.0 + square2.0, square1.1 + square2.1));
+
+    apu.set_muted(Channel::Square1, true);
+    assert_eq!(apu.output(), square2);
+
+    apu.set_solo(Some(Channel::Square1));
+    assert_eq!(apu.output(), square1);
+
+    apu.set_solo(None);
+    apu.set_muted(Channel::Square1, false);
+    assert_eq!(apu.output(), (square1.0 + square2.0, square1.1 + square2.1));
+}
+
+#[test]
+fn stem_output() {
+    let mut apu = powered_apu();
+
+    apu.set_stem_output(true);
+    apu.step(40);
+    let stems = apu.take_stems();
+    assert!(stems.iter().all(|stem| stem.len() == 10));
+    assert!(apu.take_stems().iter().all(|stem| stem.is_empty()));
+}
+
+#[test]
+fn register_logging() {
+    let mut apu = Apu::new();
+
+    apu.write(NR52, 0x80);
+    apu.set_register_logging(true);
+    apu.step(100);
+    apu.write(NR12, 0xF0);
+    apu.write(WAVE_RAM_START, 0x12);
+    apu.write(0xFF00, 0x12);
+
+    assert_eq!(
+        apu.take_register_writes(),
+        vec![
+            RegisterWrite {
+                cycle: 100,
+                address: NR12,
+                value: 0xF0
+            },
+            RegisterWrite {
+                cycle: 100,
+                address: WAVE_RAM_START,
+                value: 0x12
+            },
+        ]
+    );
+}
+
+#[test]
+fn register_state_restores_apu() {
+    let mut apu = powered_apu();
+    apu.write(NR50, 0x35);
+    apu.write(NR51, 0x5A);
+    apu.write(WAVE_RAM_START + 3, 0x9C);
+    apu.write(NR10, 0x23);
+    apu.write(NR11, 0x80);
+    apu.write(NR12, 0xF3);
+    apu.write(NR13, 0x34);
+    apu.write(NR14, 0x86);
+    apu.write(NR43, 0x5B);
+
+    let mut restored = Apu::new();
+    for (address, value) in apu.register_state() {
+        restored.write(address, value);
+    }
+
+    for address in NR10..=WAVE_RAM_END {
+        assert_eq!(restored.read(address), apu.read(address));
+    }
+    assert_eq!(restored.square1.frequency, 0x634);
+}
+
+#[test]
+fn parse_channel() {
+    assert_eq!("1".parse::<Channel>(), Ok(Channel::Square1));
+    assert_eq!("wave".parse::<Channel>(), Ok(Channel::Wave));
+    assert!("5".parse::<Channel>().is_err());
+}
@@ -0,0 +1,359 @@
+// Audio output pipeline: resamples the APU output down to the host rate,
+// removes the DC offset and hands the samples to the audio thread through a
+// lock-free ring buffer.
+
+use super::apu::{APU_SAMPLE_RATE, CPU_CLOCK_HZ};
+use std::f64::consts::PI;
+use std::sync::atomic::{AtomicU32, AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// The first stage averages this many APU samples before the sinc filter
+const DECIMATION: u32 = 8;
+const SINC_HALF_WIDTH: usize = 16;
+const SINC_PHASES: usize = 128;
+
+// Largest output rate change the resampler accepts, as a fraction
+pub const MAX_RATE_ADJUSTMENT: f64 = 0.05;
+
+// Per cycle charge factor of the output capacitor on the DMG
+const CAPACITOR_CHARGE_FACTOR: f64 = 0.999958;
+
+// Single producer, single consumer ring of stereo frames. The emulator thread
+// pushes and the audio callback pops; samples are stored as raw f32 bits so
+// no locking is needed on either side.
+#[derive(Debug)]
+pub struct SampleBuffer {
+    samples: Vec<AtomicU32>,
+    capacity: usize,
+    read: AtomicUsize,
+    write: AtomicUsize,
+    underruns: AtomicU64,
+    overruns: AtomicU64,
+}
+
+impl SampleBuffer {
+    // Holds at least one frame, as push and pop index modulo the capacity
+    pub fn new(capacity: usize) -> SampleBuffer {
+        let capacity = capacity.max(1);
+        let mut samples = Vec::with_capacity(capacity * 2);
+        for _ in 0..capacity * 2 {
+            samples.push(AtomicU32::new(0));
+        }
+        SampleBuffer {
+            samples,
+            capacity,
+            read: AtomicUsize::new(0),
+            write: AtomicUsize::new(0),
+            underruns: AtomicU64::new(0),
+            overruns: AtomicU64::new(0),
+        }
+    }
+
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    // Number of frames waiting to be played
+    pub fn len(&self) -> usize {
+        let write = self.write.load(Ordering::Acquire);
+        let read = self.read.load(Ordering::Acquire);
+        write.wrapping_sub(read)
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Frames dropped because the buffer was full
+    pub fn overruns(&self) -> u64 {
+        self.overruns.load(Ordering::Relaxed)
+    }
+
+    // Callbacks that ran out of frames before filling their output
+    pub fn underruns(&self) -> u64 {
+        self.underruns.load(Ordering::Relaxed)
+    }
+
+    pub fn push(&self, left: f32, right: f32) -> bool {
+        let write = self.write.load(Ordering::Relaxed);
+        let read = self.read.load(Ordering::Acquire);
+        if write.wrapping_sub(read) == self.capacity {
+            self.overruns.fetch_add(1, Ordering::Relaxed);
+            return false;
+        }
+
+        let index = (write % self.capacity) * 2;
+        self.samples[index].store(left.to_bits(), Ordering::Relaxed);
+        self.samples[index + 1].store(right.to_bits(), Ordering::Relaxed);
+        self.write.store(write.wrapping_add(1), Ordering::Release);
+        true
+    }
+
+    pub fn pop(&self) -> Option<(f32, f32)> {
+        let read = self.read.load(Ordering::Relaxed);
+        let write = self.write.load(Ordering::Acquire);
+        if read == write {
+            return None;
+        }
+
+        let index = (read % self.capacity) * 2;
+        let left = f32::from_bits(self.samples[index].load(Ordering::Relaxed));
+        let right = f32::from_bits(self.samples[index + 1].load(Ordering::Relaxed));
+        self.read.store(read.wrapping_add(1), Ordering::Release);
+        Some((left, right))
+    }
+
+    // Fills an interleaved stereo output, padding with silence on underrun
+    pub fn fill(&self, out: &mut [f32]) {
+        let mut starved = false;
+        for frame in out.chunks_mut(2) {
+            let (left, right) = match self.pop() {
+                Some(sample) => sample,
+                None => {
+                    starved = true;
+                    (0.0, 0.0)
+                }
+            };
+            frame[0] = left;
+            if frame.len() > 1 {
+                frame[1] = right;
+            }
+        }
+        if starved {
+            self.underruns.fetch_add(1, Ordering::Relaxed);
+        }
+    }
+}
+
+fn sinc(x: f64) -> f64 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+fn blackman(x: f64) -> f64 {
+    // x runs from -1.0 to 1.0 across the window
+    let n = (x + 1.0) / 2.0;
+    0.42 - 0.5 * (2.0 * PI * n).cos() + 0.08 * (4.0 * PI * n).cos()
+}
+
+// Band-limited resampler from APU_SAMPLE_RATE to the host rate. A box filter
+// first decimates by DECIMATION, then a windowed sinc interpolates the
+// fractional positions of the output samples.
+#[derive(Debug)]
+pub struct Resampler {
+    accumulator: (f32, f32),
+    accumulated: u32,
+    history: Vec<(f32, f32)>,
+    history_pos: usize,
+    // Position of the next output sample relative to the newest input sample
+    time: f64,
+    base_step: f64,
+    step: f64,
+    kernel: Vec<f32>,
+}
+
+impl Resampler {
+    pub fn new(output_rate: u32) -> Resampler {
+        let input_rate = (APU_SAMPLE_RATE / DECIMATION) as f64;
+        let step = input_rate / output_rate as f64;
+        let cutoff = (output_rate as f64 / input_rate).min(1.0) * 0.9;
+        let taps = SINC_HALF_WIDTH * 2;
+
+        let mut kernel = Vec::with_capacity(SINC_PHASES * taps);
+        for phase in 0..SINC_PHASES {
+            let frac = phase as f64 / SINC_PHASES as f64;
+            let mut row: Vec<f64> = (0..taps)
+                .map(|tap| {
+                    let x = tap as f64 - (SINC_HALF_WIDTH - 1) as f64 - frac;
+                    cutoff * sinc(cutoff * x) * blackman(x / SINC_HALF_WIDTH as f64)
+                })
+                .collect();
+            // Normalise every phase to unity gain so DC passes unchanged
+            let sum: f64 = row.iter().sum();
+            for value in row.iter_mut() {
+                *value /= sum;
+            }
+            kernel.extend(row.iter().map(|value| *value as f32));
+        }
+
+        // Leave room for the step to grow under dynamic rate control
+        let history_len = taps + (step / (1.0 - MAX_RATE_ADJUSTMENT)).ceil() as usize + 2;
+        Resampler {
+            accumulator: (0.0, 0.0),
+            accumulated: 0,
+            history: vec![(0.0, 0.0); history_len],
+            history_pos: 0,
+            time: -(SINC_HALF_WIDTH as f64),
+            base_step: step,
+            step,
+            kernel,
+        }
+    }
+
+    // Scales the output rate, e.g. 1.005 produces 0.5% more samples per
+    // second of emulated audio. Clamped to MAX_RATE_ADJUSTMENT.
+    pub fn set_ratio(&mut self, ratio: f64) {
+        let ratio = ratio.clamp(1.0 - MAX_RATE_ADJUSTMENT, 1.0 + MAX_RATE_ADJUSTMENT);
+        self.step = self.base_step / ratio;
+    }
+
+    // Feeds one APU sample, calling output for every host sample produced
+    pub fn push<F: FnMut(f32, f32)>(&mut self, left: f32, right: f32, mut output: F) {
+        self.accumulator.0 += left;
+        self.accumulator.1 += right;
+        self.accumulated += 1;
+        if self.accumulated < DECIMATION {
+            return;
+        }
+
+        let scale = 1.0 / DECIMATION as f32;
+        let sample = (self.accumulator.0 * scale, self.accumulator.1 * scale);
+        self.accumulator = (0.0, 0.0);
+        self.accumulated = 0;
+
+        self.history_pos = (self.history_pos + 1) % self.history.len();
+        self.history[self.history_pos] = sample;
+        self.time -= 1.0;
+
+        while self.time <= -(SINC_HALF_WIDTH as f64) {
+            let (left, right) = self.interpolate(self.time);
+            output(left, right);
+            self.time += self.step;
+        }
+    }
+
+    fn interpolate(&self, time: f64) -> (f32, f32) {
+        let center = time.floor();
+        let frac = time - center;
+        let phase = ((frac * SINC_PHASES as f64) as usize).min(SINC_PHASES - 1);
+        let taps = SINC_HALF_WIDTH * 2;
+        let row = &self.kernel[phase * taps..(phase + 1) * taps];
+
+        let len = self.history.len();
+        // Offset of the first tap from the newest sample
+        let first = -(center as isize) + SINC_HALF_WIDTH as isize - 1;
+        let mut left = 0.0;
+        let mut right = 0.0;
+        for (tap, weight) in row.iter().enumerate() {
+            let back = (first - tap as isize) as usize;
+            let (l, r) = self.history[(self.history_pos + len - back) % len];
+            left += l * weight;
+            right += r * weight;
+        }
+        (left, right)
+    }
+}
+
+// Models the output capacitor that removes the DC offset of the DACs
+#[derive(Debug)]
+pub struct HighPass {
+    capacitor: f32,
+    charge_factor: f32,
+}
+
+impl HighPass {
+    pub fn new(sample_rate: u32) -> HighPass {
+        let cycles_per_sample = CPU_CLOCK_HZ as f64 / sample_rate as f64;
+        HighPass {
+            capacitor: 0.0,
+            charge_factor: CAPACITOR_CHARGE_FACTOR.powf(cycles_per_sample) as f32,
+        }
+    }
+
+    pub fn filter(&mut self, input: f32) -> f32 {
+        let output = input - self.capacitor;
+        self.capacitor = input - output * self.charge_factor;
+        output
+    }
+}
+
+// Fits the audio of an emulation running speed times too fast into real
+// time by averaging runs of speed APU samples into one. Nothing is dropped,
+// the pitch rises with the speed and the average doubles as the
+// anti-aliasing filter.
+#[derive(Debug, Default)]
+pub struct FastForward {
+    sum: (f32, f32),
+    // Input samples in sum, plus the fraction left over from the last run
+    weight: f64,
+}
+
+impl FastForward {
+    pub fn new() -> FastForward {
+        FastForward::default()
+    }
+
+    pub fn process(&mut self, samples: &[(f32, f32)], speed: f64) -> Vec<(f32, f32)> {
+        let speed = speed.max(1.0);
+        let mut out = Vec::with_capacity((samples.len() as f64 / speed) as usize + 1);
+        let mut count = 0.0;
+        for (left, right) in samples.iter() {
+            self.sum.0 += left;
+            self.sum.1 += right;
+            self.weight += 1.0;
+            count += 1.0;
+            if self.weight >= speed {
+                let scale = 1.0 / count as f32;
+                out.push((self.sum.0 * scale, self.sum.1 * scale));
+                self.sum = (0.0, 0.0);
+                self.weight -= speed;
+                count = 0.0;
+            }
+        }
+        out
+    }
+}
+
+// Takes raw APU samples and delivers filtered host rate samples to a buffer
+#[derive(Debug)]
+pub struct AudioOutput {
+    resampler: Resampler,
+    left_filter: HighPass,
+    right_filter: HighPass,
+    fast_forward: FastForward,
+    buffer: Arc<SampleBuffer>,
+}
+
+impl AudioOutput {
+    pub fn new(sample_rate: u32, buffer: Arc<SampleBuffer>) -> AudioOutput {
+        AudioOutput {
+            resampler: Resampler::new(sample_rate),
+            left_filter: HighPass::new(sample_rate),
+            right_filter: HighPass::new(sample_rate),
+            fast_forward: FastForward::new(),
+            buffer,
+        }
+    }
+
+    pub fn buffer(&self) -> &Arc<SampleBuffer> {
+        &self.buffer
+    }
+
+    pub fn set_rate_adjustment(&mut self, ratio: f64) {
+        self.resampler.set_ratio(ratio);
+    }
+
+    // Samples of an emulation running speed times faster than real time,
+    // sped up to match
+    pub fn push_fast_forward(&mut self, samples: &[(f32, f32)], speed: f64) {
+        let samples = self.fast_forward.process(samples, speed);
+        self.push_samples(&samples);
+    }
+
+    pub fn push_samples(&mut self, samples: &[(f32, f32)]) {
+        let left_filter = &mut self.left_filter;
+        let right_filter = &mut self.right_filter;
+        let buffer = &self.buffer;
+        for (left, right) in samples.iter() {
+            self.resampler.push(*left, *right, |left, right| {
+                buffer.push(left_filter.filter(left), right_filter.filter(right));
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,149 @@
+use super::*;
+
+#[test]
+fn buffer_push_pop() {
+    let buffer = SampleBuffer::new(4);
+
+    assert!(buffer.is_empty());
+    assert!(buffer.push(0.25, -0.25));
+    assert!(buffer.push(0.5, -0.5));
+    assert_eq!(buffer.len(), 2);
+
+    assert_eq!(buffer.pop(), Some((0.25, -0.25)));
+    assert_eq!(buffer.pop(), Some((0.5, -0.5)));
+    assert_eq!(buffer.pop(), None);
+}
+
+#[test]
+fn buffer_overrun() {
+    let buffer = SampleBuffer::new(2);
+
+    assert!(buffer.push(0.1, 0.1));
+    assert!(buffer.push(0.2, 0.2));
+    assert!(!buffer.push(0.3, 0.3));
+    assert_eq!(buffer.overruns(), 1);
+    assert_eq!(buffer.len(), 2);
+}
+
+#[test]
+fn buffer_zero_capacity() {
+    let buffer = SampleBuffer::new(0);
+
+    assert_eq!(buffer.capacity(), 1);
+    assert!(buffer.push(0.1, 0.1));
+    assert!(!buffer.push(0.2, 0.2));
+    assert_eq!(buffer.pop(), Some((0.1, 0.1)));
+}
+
+#[test]
+fn buffer_wraps() {
+    let buffer = SampleBuffer::new(3);
+
+    for i in 0..10 {
+        assert!(buffer.push(i as f32, -(i as f32)));
+        assert_eq!(buffer.pop(), Some((i as f32, -(i as f32))));
+    }
+}
+
+#[test]
+fn buffer_fill_underrun() {
+    let buffer = SampleBuffer::new(8);
+    buffer.push(0.5, -0.5);
+
+    let mut out = [1.0; 6];
+    buffer.fill(&mut out);
+    assert_eq!(out, [0.5, -0.5, 0.0, 0.0, 0.0, 0.0]);
+    assert_eq!(buffer.underruns(), 1);
+
+    buffer.push(0.5, -0.5);
+    let mut out = [1.0; 2];
+    buffer.fill(&mut out);
+    assert_eq!(buffer.underruns(), 1);
+}
+
+#[test]
+fn resampler_output_rate() {
+    let mut resampler = Resampler::new(44_100);
+
+    let mut produced = 0;
+    for _ in 0..APU_SAMPLE_RATE {
+        resampler.push(0.0, 0.0, |_, _| produced += 1);
+    }
+    // One second of input, minus the filter delay
+    assert!(produced > 44_000 && produced <= 44_100);
+}
+
+#[test]
+fn resampler_rate_adjustment() {
+    let mut resampler = Resampler::new(44_100);
+    resampler.set_ratio(1.01);
+
+    let mut produced = 0;
+    for _ in 0..APU_SAMPLE_RATE {
+        resampler.push(0.0, 0.0, |_, _| produced += 1);
+    }
+    assert!(produced > 44_400 && produced < 44_600);
+}
+
+#[test]
+fn resampler_passes_dc() {
+    let mut resampler = Resampler::new(44_100);
+
+    let mut last = (0.0, 0.0);
+    for _ in 0..APU_SAMPLE_RATE / 10 {
+        resampler.push(0.5, -0.25, |left, right| last = (left, right));
+    }
+    assert!((last.0 - 0.5).abs() < 0.001);
+    assert!((last.1 + 0.25).abs() < 0.001);
+}
+
+#[test]
+fn resampler_rejects_high_frequencies() {
+    let mut resampler = Resampler::new(44_100);
+
+    // A square wave at the APU Nyquist rate must not alias into the output
+    let mut peak: f32 = 0.0;
+    for i in 0..APU_SAMPLE_RATE / 10 {
+        let sample = if i & 1 == 0 { 1.0 } else { -1.0 };
+        resampler.push(sample, sample, |left, _| peak = peak.max(left.abs()));
+    }
+    assert!(peak < 0.01);
+}
+
+#[test]
+fn high_pass_removes_dc() {
+    let mut filter = HighPass::new(44_100);
+
+    let first = filter.filter(1.0);
+    assert_eq!(first, 1.0);
+
+    let mut last = first;
+    for _ in 0..44_100 {
+        last = filter.filter(1.0);
+    }
+    assert!(last.abs() < 0.001);
+}
+
+#[test]
+fn output_fills_buffer() {
+    let buffer = Arc::new(SampleBuffer::new(1024));
+    let mut output = AudioOutput::new(44_100, buffer.clone());
+
+    output.push_samples(&vec![(0.0, 0.0); APU_SAMPLE_RATE as usize / 100]);
+    assert!(buffer.len() > 400 && buffer.len() <= 441);
+}
+
+#[test]
+fn fast_forward_averages_runs() {
+    let mut fast_forward = FastForward::new();
+    let samples: Vec<(f32, f32)> = (0..1000).map(|i| (i as f32, -(i as f32))).collect();
+
+    let out = fast_forward.process(&samples, 2.5);
+    assert_eq!(out.len(), 400);
+    assert_eq!(out[0], (1.0, -1.0));
+    // At normal speed samples pass unchanged
+    assert_eq!(
+        FastForward::new().process(&samples[..3], 1.0),
+        samples[..3].to_vec()
+    );
+}
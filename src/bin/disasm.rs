@@ -0,0 +1,157 @@
+// Linear sweep disassembly of a ROM file, one instruction per line:
+//
+//   ROM0:0150  3E 01     ld a, $01
+//
+// Bank 0 is shown at 0x0000-0x3FFF and every other bank at 0x4000-0x7FFF,
+// where the bank controller would map it. Labels from the RGBDS symbol file
+// next to the ROM are printed on lines of their own and replace jump targets
+// and memory operands:
+//
+//   Main.loop:
+//   ROM0:0158  FA 00 C0  ld a, [wCounter]
+
+extern crate gba;
+
+use gba::disasm::decode;
+use gba::memory::reset_bank;
+use gba::symbols::Symbols;
+use std::io::{self, BufWriter, Write};
+use std::path::PathBuf;
+
+const BANK_SIZE: usize = 0x4000;
+
+const USAGE: &str = "usage: disasm [options] <rom>
+
+options:
+  --bank <n>          only disassemble ROM bank n
+  --start <address>   first address, hex (default the start of the bank)
+  --end <address>     stop before this address, hex (default the end of the bank)
+  --sym <file>        RGBDS symbol file (default the .sym file next to the ROM)
+  --no-sym            do not load symbols";
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address '{}'", text))
+}
+
+fn disassemble_bank<W: Write>(
+    out: &mut W,
+    rom: &[u8],
+    symbols: &Symbols,
+    bank: usize,
+    start: Option<u16>,
+    end: Option<u16>,
+) -> io::Result<()> {
+    let base: u16 = if bank == 0 { 0x0000 } else { 0x4000 };
+    let offset = bank * BANK_SIZE;
+    let read = |address: u16| {
+        // Operands running off the end of the bank read as zero
+        if address >= base && ((address - base) as usize) < BANK_SIZE {
+            rom.get(offset + (address - base) as usize)
+                .copied()
+                .unwrap_or(0)
+        } else {
+            0
+        }
+    };
+
+    let mut address = start.unwrap_or(base).max(base) as u32;
+    let end = end
+        .map(|end| end as u32)
+        .unwrap_or(base as u32 + BANK_SIZE as u32);
+    let label = if bank == 0 {
+        "ROM0".to_string()
+    } else {
+        format!("ROMX[{:02X}]", bank)
+    };
+    // Code in a switchable bank sees its own bank there, code in bank 0
+    // whatever is mapped after reset
+    let mapped = |target: u16| {
+        if bank != 0 && (0x4000..0x8000).contains(&target) {
+            bank as u16
+        } else {
+            reset_bank(target as usize)
+        }
+    };
+    while address < end.min(base as u32 + BANK_SIZE as u32) {
+        let instruction = decode(read, address as u16);
+        if let Some(name) = symbols.label(bank as u16, address as u16) {
+            writeln!(out, "{}:", name)?;
+        }
+        let bytes: Vec<String> = instruction
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        writeln!(
+            out,
+            "{}:{:04X}  {:<9} {}",
+            label,
+            address,
+            bytes.join(" "),
+            symbols.format_instruction(&instruction, mapped)
+        )?;
+        address += instruction.length() as u32;
+    }
+    Ok(())
+}
+
+fn run() -> Result<(), String> {
+    let mut rom_path = None;
+    let mut bank = None;
+    let mut start = None;
+    let mut end = None;
+    let mut sym_path = None;
+    let mut no_sym = false;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--bank" => {
+                bank = Some(
+                    value()?
+                        .parse::<usize>()
+                        .map_err(|_| "--bank needs a whole number".to_string())?,
+                )
+            }
+            "--start" => start = Some(parse_address(&value()?)?),
+            "--end" => end = Some(parse_address(&value()?)?),
+            "--sym" => sym_path = Some(PathBuf::from(value()?)),
+            "--no-sym" => no_sym = true,
+            _ if arg.starts_with("--") => return Err(format!("unknown argument '{}'", arg)),
+            _ if rom_path.is_none() => rom_path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+
+    let rom_path = rom_path.ok_or_else(|| USAGE.to_string())?;
+    let rom = std::fs::read(&rom_path)
+        .map_err(|e| format!("could not read {}: {}", rom_path.display(), e))?;
+    let banks = (rom.len() + BANK_SIZE - 1) / BANK_SIZE;
+    let symbols = match sym_path {
+        _ if no_sym => Symbols::new(),
+        Some(path) => Symbols::load(&path)?,
+        None => Symbols::load_for_rom(&rom_path)?.unwrap_or_default(),
+    };
+
+    let stdout = io::stdout();
+    let mut out = BufWriter::new(stdout.lock());
+    let selected: Vec<usize> = match bank {
+        Some(bank) if bank >= banks => {
+            return Err(format!("the ROM only has {} banks", banks));
+        }
+        Some(bank) => vec![bank],
+        None => (0..banks).collect(),
+    };
+    for bank in selected {
+        disassemble_bank(&mut out, &rom, &symbols, bank, start, end).map_err(|e| e.to_string())?;
+    }
+    out.flush().map_err(|e| e.to_string())
+}
+
+fn main() {
+    if let Err(e) = run() {
+        eprintln!("{}", e);
+        std::process::exit(1);
+    }
+}
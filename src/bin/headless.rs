@@ -0,0 +1,534 @@
+// Runs a ROM without a window or audio device, for test suites and CI.
+//
+// Exit codes: 0 when the stop condition was met (or all frames ran when there
+// is none), 1 when a failure condition hit or the frame limit ran out first,
+// 2 on bad arguments or I/O errors.
+
+extern crate gba;
+
+use gba::cdl::CodeDataLog;
+use gba::cpu::Registers;
+use gba::debugger::Debugger;
+use gba::gameboy::GameBoy;
+use gba::gdb::{GdbEvent, GdbStub};
+use gba::memory::MemoryMap;
+use gba::model::Model;
+use gba::movie::Movie;
+use gba::pacing::CYCLES_PER_FRAME;
+use gba::profiler::Profiler;
+use gba::recording::VideoRecorder;
+use gba::serial::CaptureLink;
+use gba::symbols::Symbols;
+use gba::trace::{parse_range, Trace};
+use gba::vram;
+use gba::watchpoint::{WatchAction, Watchpoint};
+use std::any::Any;
+use std::io::{self, Write};
+use std::ops::RangeInclusive;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::PathBuf;
+use std::time::Duration;
+
+// One minute of emulated time
+const DEFAULT_FRAMES: u64 = 3600;
+const LD_B_B: u8 = 0x40;
+// Functions listed after a profiling run
+const PROFILE_REPORT_LINES: usize = 20;
+const RECORDING_SAMPLE_RATE: u32 = 44_100;
+
+const USAGE: &str = "usage: headless [options] <rom>
+
+options:
+  --model <dmg|cgb>        hardware to emulate (default dmg)
+  --boot-rom <file>        run this boot ROM instead of starting at 0x0100
+  --frames <n>             give up after n frames (default 3600, or the length of
+                           the movie)
+  --until-serial <text>    pass once the serial output contains text
+  --fail-serial <text>     fail once the serial output contains text
+  --until-pc <address>     stop when PC reaches a hex address
+  --until-ld-bb            stop at the LD B,B software breakpoint; passes when
+                           BC DE HL hold the Mooneye Fibonacci values
+  --screenshot <file>      write the final frame as PNG, or PPM when the file
+                           name ends in .ppm, for golden image tests
+  --screenshot-scale <n>   enlarge the screenshot n times (default 1)
+  --dump-vram <prefix>     write the final tiles, background maps, sprites and
+                           palettes as prefix-tiles.png, prefix-map0.png,
+                           prefix-map1.png, prefix-oam.png and prefix-palettes.png,
+                           and the OAM entries to prefix-oam.txt
+  --play-movie <file>      replay a movie and fail when the emulation desyncs from
+                           it
+  --record-video <file>    record every frame to file.y4m and the audio to file.wav
+  --print-serial           echo the serial output to stdout
+  --trace <file>           write a Gameboy Doctor execution trace
+  --trace-range <from-to>  only trace these addresses, hex, can be repeated
+  --trace-labels           end trace lines with the label from the symbol file
+  --debug                  start paused in the debugger, reading commands from stdin
+  --break <address>        enter the debugger at a label or hex address, can be
+                           repeated
+  --watch <[kind:]target>  enter the debugger on accesses to an address, range or
+                           I/O register; kind is read, write (default), change
+                           or access, e.g. change:LCDC or c000-c0ff
+  --watch-log <[kind:]target>
+                           print accesses without stopping
+  --gdb <port>             wait for a gdb remote connection on localhost and let
+                           it control the emulation
+  --sym <file>             RGBDS symbol file (default the .sym file next to the ROM)
+  --profile <file>         count cycles per function, write folded stacks for
+                           flamegraph tools and print the busiest functions
+  --cdl <file>             log which ROM bytes run as code or are read as data,
+                           adding to the file if it exists (see src/cdl.rs)";
+
+#[derive(Debug, PartialEq)]
+enum Outcome {
+    Passed,
+    Failed(String),
+}
+
+#[derive(Default)]
+struct Options {
+    rom_path: Option<PathBuf>,
+    model: Option<Model>,
+    boot_rom_path: Option<PathBuf>,
+    frames: Option<u64>,
+    until_serial: Option<String>,
+    fail_serial: Option<String>,
+    until_pc: Option<u16>,
+    until_ld_bb: bool,
+    screenshot: Option<PathBuf>,
+    screenshot_scale: Option<usize>,
+    dump_vram: Option<String>,
+    record_video: Option<PathBuf>,
+    play_movie: Option<PathBuf>,
+    print_serial: bool,
+    trace: Option<PathBuf>,
+    trace_ranges: Vec<RangeInclusive<u16>>,
+    trace_labels: bool,
+    debug: bool,
+    // Resolved once the symbols are loaded
+    breakpoints: Vec<String>,
+    watchpoints: Vec<Watchpoint>,
+    gdb_port: Option<u16>,
+    sym_path: Option<PathBuf>,
+    profile: Option<PathBuf>,
+    cdl: Option<PathBuf>,
+}
+
+fn parse_address(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address '{}'", text))
+}
+
+fn parse_options() -> Result<Options, String> {
+    let mut options = Options::default();
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--model" => options.model = Some(value()?.parse()?),
+            "--boot-rom" => options.boot_rom_path = Some(PathBuf::from(value()?)),
+            "--frames" => {
+                options.frames = Some(
+                    value()?
+                        .parse()
+                        .map_err(|_| "--frames needs a whole number".to_string())?,
+                )
+            }
+            "--until-serial" => options.until_serial = Some(value()?),
+            "--fail-serial" => options.fail_serial = Some(value()?),
+            "--until-pc" => options.until_pc = Some(parse_address(&value()?)?),
+            "--until-ld-bb" => options.until_ld_bb = true,
+            "--screenshot" => options.screenshot = Some(PathBuf::from(value()?)),
+            "--screenshot-scale" => {
+                options.screenshot_scale = Some(
+                    value()?
+                        .parse()
+                        .ok()
+                        .filter(|scale| *scale > 0)
+                        .ok_or("--screenshot-scale needs a positive whole number")?,
+                )
+            }
+            "--play-movie" => options.play_movie = Some(PathBuf::from(value()?)),
+            "--record-video" => options.record_video = Some(PathBuf::from(value()?)),
+            "--dump-vram" => options.dump_vram = Some(value()?),
+            "--print-serial" => options.print_serial = true,
+            "--trace" => options.trace = Some(PathBuf::from(value()?)),
+            "--trace-range" => options.trace_ranges.push(parse_range(&value()?)?),
+            "--trace-labels" => options.trace_labels = true,
+            "--debug" => options.debug = true,
+            "--break" => options.breakpoints.push(value()?),
+            "--gdb" => {
+                options.gdb_port = Some(
+                    value()?
+                        .parse()
+                        .map_err(|_| "--gdb needs a port number".to_string())?,
+                )
+            }
+            "--watch" => options
+                .watchpoints
+                .push(Watchpoint::parse(&value()?, WatchAction::Pause)?),
+            "--watch-log" => options
+                .watchpoints
+                .push(Watchpoint::parse(&value()?, WatchAction::Log)?),
+            "--sym" => options.sym_path = Some(PathBuf::from(value()?)),
+            "--profile" => options.profile = Some(PathBuf::from(value()?)),
+            "--cdl" => options.cdl = Some(PathBuf::from(value()?)),
+            _ if arg.starts_with("--") => return Err(format!("unknown argument '{}'", arg)),
+            _ if options.rom_path.is_none() => options.rom_path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'", arg)),
+        }
+    }
+    Ok(options)
+}
+
+// Mooneye test ROMs signal success with these in B, C, D, E, H and L
+fn mooneye_passed(registers: &Registers) -> bool {
+    [
+        registers.b,
+        registers.c,
+        registers.d,
+        registers.e,
+        registers.h,
+        registers.l,
+    ] == [3, 5, 8, 13, 21, 34]
+}
+
+fn dump_vram(memory: &MemoryMap, prefix: &str) -> Result<(), String> {
+    let path = |name: &str| PathBuf::from(format!("{}-{}", prefix, name));
+    vram::tiles(memory).save(&path("tiles.png"))?;
+    vram::background_map(memory, 0).save(&path("map0.png"))?;
+    vram::background_map(memory, 1).save(&path("map1.png"))?;
+    vram::oam(memory).save(&path("oam.png"))?;
+    vram::palettes(memory).save(&path("palettes.png"))?;
+    let text: String = vram::sprites(memory)
+        .iter()
+        .map(|sprite| format!("{}\n", sprite))
+        .collect();
+    let oam = path("oam.txt");
+    std::fs::write(&oam, text).map_err(|e| format!("could not write {}: {}", oam.display(), e))
+}
+
+// Reads debugger commands from stdin until the emulation resumes. Returns
+// false when the user quits or stdin ends.
+fn debug_prompt(debugger: &mut Debugger, gameboy: &mut GameBoy) -> io::Result<bool> {
+    let stdin = io::stdin();
+    let mut stdout = io::stdout();
+    debugger.print_location(gameboy, &mut stdout)?;
+    while debugger.paused() {
+        print!("(debug) ");
+        stdout.flush()?;
+        let mut line = String::new();
+        if stdin.read_line(&mut line)? == 0 {
+            return Ok(false);
+        }
+        if !debugger.execute(gameboy, &line, &mut stdout)? {
+            return Ok(false);
+        }
+    }
+    Ok(true)
+}
+
+fn report_gdb(stub: &mut GdbStub) {
+    for event in stub.take_events() {
+        match event {
+            GdbEvent::Error { .. } => eprintln!("{}", event),
+            _ => println!("{}", event),
+        }
+    }
+}
+
+// Lets gdb drive while the emulation is stopped. Returns false when gdb
+// killed the target.
+fn gdb_session(
+    stub: &mut GdbStub,
+    debugger: &mut Debugger,
+    gameboy: &mut GameBoy,
+) -> io::Result<bool> {
+    while debugger.paused() {
+        let keep_going = stub.poll(debugger, gameboy)?;
+        report_gdb(stub);
+        if !keep_going {
+            return Ok(false);
+        }
+        std::thread::sleep(Duration::from_millis(1));
+    }
+    Ok(true)
+}
+
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic"
+    }
+}
+
+fn run(options: Options) -> Result<Outcome, String> {
+    let rom_path = options.rom_path.ok_or_else(|| USAGE.to_string())?;
+    let rom = std::fs::read(&rom_path)
+        .map_err(|e| format!("could not read {}: {}", rom_path.display(), e))?;
+    let symbols = match options.sym_path.as_ref() {
+        Some(path) => Symbols::load(path)?,
+        None => Symbols::load_for_rom(&rom_path)?.unwrap_or_default(),
+    };
+    let mut gameboy = GameBoy::new(options.model.unwrap_or(Model::Dmg));
+    if let Some(path) = options.boot_rom_path {
+        let boot_rom = std::fs::read(&path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        gameboy
+            .set_boot_rom(Some(boot_rom))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    gameboy.load_rom(rom)?;
+    if let Some(path) = options.cdl.as_ref() {
+        let cdl = CodeDataLog::load_or_new(path, gameboy.rom().len())?;
+        *gameboy.memory().cdl.borrow_mut() = Some(cdl);
+    }
+    // Samples are only needed for recording
+    gameboy
+        .memory()
+        .apu
+        .borrow_mut()
+        .set_sample_output(options.record_video.is_some());
+    let link = CaptureLink::new();
+    let serial_output = link.output();
+    gameboy
+        .memory()
+        .serial
+        .borrow_mut()
+        .set_link(Box::new(link));
+    if let Some(path) = options.trace.as_ref() {
+        let mut trace = Trace::create(path)
+            .map_err(|e| format!("could not create {}: {}", path.display(), e))?;
+        for range in options.trace_ranges.iter() {
+            trace.add_range(range.clone());
+        }
+        if options.trace_labels {
+            trace.set_symbols(Some(symbols.clone()));
+        }
+        gameboy.cpu_mut().set_trace(Some(trace));
+    }
+
+    let mut gdb = match options.gdb_port {
+        Some(port) => {
+            let stub = GdbStub::listen(("127.0.0.1", port)).map_err(|e| e.to_string())?;
+            println!("waiting for gdb on port {}", port);
+            Some(stub)
+        }
+        None => None,
+    };
+    let needs_debugger = options.debug
+        || gdb.is_some()
+        || !options.breakpoints.is_empty()
+        || !options.watchpoints.is_empty();
+    // The debugger shows the profiler's call stack
+    if options.profile.is_some() || needs_debugger {
+        gameboy.set_profiler(Some(Profiler::new(symbols.clone())));
+    }
+    let mut debugger = if needs_debugger {
+        let mut debugger = Debugger::new();
+        for text in options.breakpoints.iter() {
+            match symbols.resolve(text)? {
+                (address, Some(bank)) => debugger.add_bank_breakpoint(address, bank),
+                (address, None) => debugger.add_breakpoint(address),
+            }
+        }
+        debugger.set_symbols(symbols);
+        for watchpoint in options.watchpoints.iter() {
+            debugger.add_watchpoint(&gameboy, watchpoint.clone());
+        }
+        // Nothing runs before gdb connected
+        if options.debug || gdb.is_some() {
+            debugger.pause();
+        }
+        Some(debugger)
+    } else {
+        None
+    };
+
+    let has_condition =
+        options.until_serial.is_some() || options.until_pc.is_some() || options.until_ld_bb;
+    let movie = match options.play_movie.as_ref() {
+        Some(path) => {
+            let movie = Movie::load(path)?;
+            movie.start(&mut gameboy)?;
+            Some(movie)
+        }
+        None => None,
+    };
+    let frames = options.frames.unwrap_or_else(|| match movie.as_ref() {
+        Some(movie) => movie.len() as u64,
+        None => DEFAULT_FRAMES,
+    });
+    let mut printed = 0;
+    let mut outcome = None;
+    let mut video = match options.record_video.as_ref() {
+        Some(path) => {
+            Some(VideoRecorder::create(path, RECORDING_SAMPLE_RATE).map_err(|e| e.to_string())?)
+        }
+        None => None,
+    };
+
+    'frames: for frame in 0..frames {
+        // Catches interrupts from gdb while the emulation runs
+        if let (Some(stub), Some(debugger)) = (gdb.as_mut(), debugger.as_mut()) {
+            let keep_going = stub
+                .poll(debugger, &mut gameboy)
+                .map_err(|e| e.to_string())?;
+            report_gdb(stub);
+            if !keep_going {
+                outcome = Some(Outcome::Failed("killed by gdb".to_string()));
+                break 'frames;
+            }
+        }
+
+        // Frames end on the same boundaries as GameBoy::run_frame and a
+        // movie's recording, with the buttons released once the movie is over
+        if let Some(movie) = movie.as_ref() {
+            gameboy.set_buttons(movie.input(frame as usize).unwrap_or(0));
+        }
+        let length = CYCLES_PER_FRAME as u64;
+        let end = (gameboy.cycles() / length + 1) * length;
+        while gameboy.cycles() < end {
+            let cpu = gameboy.cpu();
+            if options.until_pc == Some(cpu.pc()) {
+                outcome = Some(Outcome::Passed);
+                break 'frames;
+            }
+            if options.until_ld_bb && gameboy.memory().peek(cpu.pc() as usize) == LD_B_B {
+                outcome = Some(if mooneye_passed(&cpu.registers()) {
+                    Outcome::Passed
+                } else {
+                    Outcome::Failed(format!("LD B,B with {:X?}", cpu.registers()))
+                });
+                break 'frames;
+            }
+
+            // An opcode the CPU does not implement panics; that fails the
+            // run instead of aborting it, so the screenshot is still taken
+            let pc = cpu.pc();
+            let stepped = panic::catch_unwind(AssertUnwindSafe(|| match debugger.as_mut() {
+                Some(debugger) => debugger.step(&mut gameboy),
+                None => {
+                    gameboy.step_instruction();
+                    false
+                }
+            }));
+            let stopped = match stepped {
+                Ok(stopped) => stopped,
+                Err(payload) => {
+                    outcome = Some(Outcome::Failed(format!(
+                        "emulator panicked at {:04X}: {}",
+                        pc,
+                        panic_message(payload.as_ref())
+                    )));
+                    break 'frames;
+                }
+            };
+            if let Some(debugger) = debugger.as_mut() {
+                debugger
+                    .write_log(&mut io::stdout())
+                    .map_err(|e| e.to_string())?;
+                if stopped {
+                    let keep_going = match gdb.as_mut() {
+                        Some(stub) => gdb_session(stub, debugger, &mut gameboy),
+                        None => debug_prompt(debugger, &mut gameboy),
+                    };
+                    if !keep_going.map_err(|e| e.to_string())? {
+                        outcome = Some(Outcome::Failed("quit in the debugger".to_string()));
+                        break 'frames;
+                    }
+                }
+            }
+        }
+
+        if let Some(movie) = movie.as_ref() {
+            if let Err(desync) = movie.verify(frame as usize + 1, &gameboy) {
+                outcome = Some(Outcome::Failed(desync.to_string()));
+                break 'frames;
+            }
+        }
+        if let Some(video) = video.as_mut() {
+            let samples = gameboy.audio_samples();
+            video
+                .push_frame(gameboy.framebuffer(), &samples)
+                .map_err(|e| e.to_string())?;
+        }
+
+        let output = serial_output.borrow();
+        if options.print_serial {
+            print!("{}", String::from_utf8_lossy(&output[printed..]));
+            printed = output.len();
+        }
+        let text = String::from_utf8_lossy(&output);
+        if let Some(fail) = options.fail_serial.as_ref() {
+            if text.contains(fail.as_str()) {
+                outcome = Some(Outcome::Failed(format!(
+                    "serial output contains '{}'",
+                    fail
+                )));
+                break;
+            }
+        }
+        if let Some(until) = options.until_serial.as_ref() {
+            if text.contains(until.as_str()) {
+                outcome = Some(Outcome::Passed);
+                break;
+            }
+        }
+    }
+
+    if let Some(video) = video {
+        video.finish().map_err(|e| e.to_string())?;
+    }
+    if let Some(trace) = gameboy.cpu_mut().set_trace(None) {
+        trace.finish().map_err(|e| e.to_string())?;
+    }
+    if let (Some(path), Some(cdl)) = (options.cdl.as_ref(), gameboy.memory().cdl.borrow().as_ref())
+    {
+        cdl.save(path)?;
+        let summary = cdl.summary();
+        println!(
+            "code/data log: {} code bytes, {} data bytes, {} untouched",
+            summary.code, summary.data, summary.unused
+        );
+    }
+    if let (Some(path), Some(profiler)) = (options.profile.as_ref(), gameboy.profiler()) {
+        profiler
+            .save_folded(path)
+            .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+        profiler
+            .write_report(&mut io::stdout(), PROFILE_REPORT_LINES)
+            .map_err(|e| e.to_string())?;
+    }
+    if let Some(path) = options.screenshot.as_ref() {
+        gameboy
+            .framebuffer()
+            .save(path, options.screenshot_scale.unwrap_or(1))?;
+    }
+    if let Some(prefix) = options.dump_vram.as_ref() {
+        dump_vram(gameboy.memory(), prefix)?;
+    }
+
+    Ok(outcome.unwrap_or(if has_condition {
+        Outcome::Failed(format!("no stop condition met within {} frames", frames))
+    } else {
+        Outcome::Passed
+    }))
+}
+
+fn main() {
+    let code = match parse_options().and_then(run) {
+        Ok(Outcome::Passed) => 0,
+        Ok(Outcome::Failed(reason)) => {
+            eprintln!("failed: {}", reason);
+            1
+        }
+        Err(e) => {
+            eprintln!("{}", e);
+            2
+        }
+    };
+    std::process::exit(code);
+}
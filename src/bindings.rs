@@ -0,0 +1,102 @@
+// Input bindings, loaded from a text file with one binding per line:
+//
+//   # comments start with a hash
+//   key Z = b
+//   key Right Shift = select
+//   controller dpup = up
+//
+// Key names are the SDL key names and controller buttons use the SDL game
+// controller mapping names. Keys the frontend uses as hotkeys cannot be bound.
+
+use super::joypad::Button;
+use std::fs;
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Bindings {
+    pub keys: Vec<(String, Button)>,
+    pub controller: Vec<(String, Button)>,
+}
+
+impl Bindings {
+    pub fn parse(text: &str) -> Result<Bindings, String> {
+        let mut bindings = Bindings {
+            keys: Vec::new(),
+            controller: Vec::new(),
+        };
+
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+
+            let (kind, rest) = match line.find(char::is_whitespace) {
+                Some(index) => (&line[..index], &line[index..]),
+                None => return Err(error(format!("expected a binding, found '{}'", line))),
+            };
+            let (input, button) = match rest.rfind('=') {
+                Some(index) => (rest[..index].trim(), rest[index + 1..].trim()),
+                None => return Err(error("expected '='".to_string())),
+            };
+            if input.is_empty() {
+                return Err(error("missing input name".to_string()));
+            }
+            let button = button.parse::<Button>().map_err(error)?;
+
+            match kind {
+                "key" => bindings.keys.push((input.to_string(), button)),
+                "controller" => bindings.controller.push((input.to_string(), button)),
+                _ => return Err(error(format!("unknown input type '{}'", kind))),
+            }
+        }
+
+        Ok(bindings)
+    }
+
+    pub fn load(path: &Path) -> Result<Bindings, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        Bindings::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+impl Default for Bindings {
+    fn default() -> Bindings {
+        let keys = [
+            ("Right", Button::Right),
+            ("Left", Button::Left),
+            ("Up", Button::Up),
+            ("Down", Button::Down),
+            ("X", Button::A),
+            ("Z", Button::B),
+            ("Backspace", Button::Select),
+            ("Return", Button::Start),
+        ];
+        let controller = [
+            ("dpright", Button::Right),
+            ("dpleft", Button::Left),
+            ("dpup", Button::Up),
+            ("dpdown", Button::Down),
+            ("a", Button::A),
+            ("b", Button::B),
+            ("back", Button::Select),
+            ("start", Button::Start),
+        ];
+
+        Bindings {
+            keys: keys
+                .iter()
+                .map(|(name, button)| (name.to_string(), *button))
+                .collect(),
+            controller: controller
+                .iter()
+                .map(|(name, button)| (name.to_string(), *button))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
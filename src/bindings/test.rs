@@ -0,0 +1,62 @@
+use super::*;
+
+#[test]
+fn parse_bindings() {
+    let bindings = Bindings::parse(
+        "# arrows and letters\n\
+         key Up = up\n\
+         \n\
+         key Right Shift = select\n\
+         controller  dpdown=down\n",
+    )
+    .unwrap();
+
+    assert_eq!(
+        bindings.keys,
+        vec![
+            ("Up".to_string(), Button::Up),
+            ("Right Shift".to_string(), Button::Select)
+        ]
+    );
+    assert_eq!(
+        bindings.controller,
+        vec![("dpdown".to_string(), Button::Down)]
+    );
+}
+
+#[test]
+fn parse_keypad_equals() {
+    let bindings = Bindings::parse("key Keypad = = start").unwrap();
+
+    assert_eq!(bindings.keys, vec![("Keypad =".to_string(), Button::Start)]);
+}
+
+#[test]
+fn parse_errors() {
+    assert_eq!(
+        Bindings::parse("key Z = b\nmouse Left = a"),
+        Err("line 2: unknown input type 'mouse'".to_string())
+    );
+    assert_eq!(
+        Bindings::parse("key Z = turbo"),
+        Err("line 1: unknown button 'turbo'".to_string())
+    );
+    assert_eq!(
+        Bindings::parse("key Z"),
+        Err("line 1: expected '='".to_string())
+    );
+    assert_eq!(
+        Bindings::parse("key = a"),
+        Err("line 1: missing input name".to_string())
+    );
+}
+
+#[test]
+fn default_binds_every_button() {
+    let bindings = Bindings::default();
+
+    for button in Button::ALL.iter() {
+        assert!(bindings.keys.iter().any(|(_, bound)| bound == button));
+        assert!(bindings.controller.iter().any(|(_, bound)| bound == button));
+    }
+}
@@ -0,0 +1,68 @@
+// Cartridge header at 0x0100-0x014F
+
+pub const HEADER_END: usize = 0x0150;
+const TITLE_START: usize = 0x0134;
+const TITLE_END: usize = 0x0143;
+const CGB_FLAG: usize = 0x0143;
+const CARTRIDGE_TYPE: usize = 0x0147;
+const HEADER_CHECKSUM: usize = 0x014D;
+const GLOBAL_CHECKSUM: usize = 0x014E;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Header {
+    pub title: String,
+    pub cgb_flag: u8,
+    pub cartridge_type: u8,
+    pub header_checksum: u8,
+    pub global_checksum: u16,
+}
+
+impl Header {
+    pub fn parse(rom: &[u8]) -> Result<Header, String> {
+        if rom.len() < HEADER_END {
+            return Err(format!(
+                "ROM is {} bytes, too small to hold a cartridge header",
+                rom.len()
+            ));
+        }
+
+        let title = rom[TITLE_START..TITLE_END]
+            .iter()
+            .take_while(|byte| **byte != 0)
+            .map(|byte| *byte as char)
+            .collect::<String>()
+            .trim_end()
+            .to_string();
+
+        Ok(Header {
+            title,
+            cgb_flag: rom[CGB_FLAG],
+            cartridge_type: rom[CARTRIDGE_TYPE],
+            header_checksum: rom[HEADER_CHECKSUM],
+            global_checksum: ((rom[GLOBAL_CHECKSUM] as u16) << 8) | rom[GLOBAL_CHECKSUM + 1] as u16,
+        })
+    }
+
+    // Whether the cartridge keeps its RAM alive with a battery
+    pub fn has_battery(&self) -> bool {
+        matches!(
+            self.cartridge_type,
+            0x03 | 0x06 | 0x09 | 0x0D | 0x0F | 0x10 | 0x13 | 0x1B | 0x1E | 0x22 | 0xFF
+        )
+    }
+
+    // The game uses CGB features, either exclusively or with DMG fallback
+    pub fn supports_cgb(&self) -> bool {
+        self.cgb_flag & 0x80 != 0
+    }
+}
+
+// The checksum the boot ROM verifies over 0x0134-0x014C
+pub fn header_checksum(rom: &[u8]) -> u8 {
+    rom[TITLE_START..HEADER_CHECKSUM]
+        .iter()
+        .fold(0u8, |sum, byte| sum.wrapping_sub(*byte).wrapping_sub(1))
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,43 @@
+use super::*;
+
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0; 0x8000];
+    rom[TITLE_START..TITLE_START + 6].copy_from_slice(b"TETRIS");
+    rom[CARTRIDGE_TYPE] = 0x03;
+    rom[GLOBAL_CHECKSUM] = 0x16;
+    rom[GLOBAL_CHECKSUM + 1] = 0xBF;
+    rom[HEADER_CHECKSUM] = header_checksum(&rom);
+    rom
+}
+
+#[test]
+fn parse_header() {
+    let header = Header::parse(&rom()).unwrap();
+
+    assert_eq!(header.title, "TETRIS");
+    assert_eq!(header.global_checksum, 0x16BF);
+    assert!(header.has_battery());
+    assert!(!header.supports_cgb());
+}
+
+#[test]
+fn cgb_title_is_shorter() {
+    let mut rom = rom();
+    rom[TITLE_START..TITLE_END].copy_from_slice(b"POKEMON CRYSTAL");
+    rom[CGB_FLAG] = 0xC0;
+
+    let header = Header::parse(&rom).unwrap();
+    assert_eq!(header.title, "POKEMON CRYSTAL");
+    assert!(header.supports_cgb());
+}
+
+#[test]
+fn checksum_of_blank_header() {
+    // 25 bytes of zero each subtract one
+    assert_eq!(header_checksum(&vec![0; HEADER_END]), 0xE7);
+}
+
+#[test]
+fn too_small() {
+    assert!(Header::parse(&[0; 0x100]).is_err());
+}
@@ -0,0 +1,152 @@
+// Code/data log of the cartridge ROM: which bytes ran as code, which were
+// read as data and which were never touched, as a disassembly aid and to find
+// dead code and unused assets.
+//
+// The file holds one flag byte per ROM byte and nothing else, so it is as
+// long as the ROM and byte n describes ROM byte n (bank n / 0x4000, address
+// 0x4000 + n % 0x4000 for banks other than 0), like the CDL files of FCEUX,
+// BizHawk and Mesen:
+//
+//   bit 0  0x01  executed as the first byte of an instruction
+//   bit 1  0x02  executed as an operand, including the second byte of a
+//                CB-prefixed opcode
+//   bit 2  0x04  read as data
+//   bit 3  0x08  copied to VRAM: read as data, with the value being the next
+//                thing the CPU wrote to 0x8000-0x9FFF, as tile and map
+//                copying loops do
+//   bits 4-7     reserved, zero
+//
+// Flags only accumulate, so loading an existing log and running again adds
+// to it.
+
+use std::fs;
+use std::path::Path;
+
+pub const CDL_OPCODE: u8 = 0x01;
+pub const CDL_OPERAND: u8 = 0x02;
+pub const CDL_DATA: u8 = 0x04;
+pub const CDL_VRAM_SOURCE: u8 = 0x08;
+
+const VRAM_START: u16 = 0x8000;
+const VRAM_END: u16 = 0x9FFF;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Summary {
+    pub code: usize,
+    pub data: usize,
+    pub unused: usize,
+}
+
+#[derive(Debug, Clone)]
+pub struct CodeDataLog {
+    flags: Vec<u8>,
+    // Address and length of the instruction executing, whose own bytes are
+    // fetched rather than read as data
+    instruction: (u16, u16),
+    // ROM offset and value of the last data read, until the next write
+    last_data: Option<(usize, u8)>,
+}
+
+impl CodeDataLog {
+    pub fn new(rom_size: usize) -> CodeDataLog {
+        CodeDataLog::from_flags(vec![0; rom_size])
+    }
+
+    fn from_flags(flags: Vec<u8>) -> CodeDataLog {
+        CodeDataLog {
+            flags,
+            instruction: (0, 0),
+            last_data: None,
+        }
+    }
+
+    // Continues an earlier log of the same ROM, or starts a new one when
+    // there is no file yet
+    pub fn load_or_new(path: &Path, rom_size: usize) -> Result<CodeDataLog, String> {
+        if !path.exists() {
+            return Ok(CodeDataLog::new(rom_size));
+        }
+        let flags =
+            fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        if flags.len() != rom_size {
+            return Err(format!(
+                "{} is {} bytes, the log of a {} byte ROM has one byte per ROM byte",
+                path.display(),
+                flags.len(),
+                rom_size
+            ));
+        }
+        Ok(CodeDataLog::from_flags(flags))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, &self.flags)
+            .map_err(|e| format!("could not write {}: {}", path.display(), e))
+    }
+
+    pub fn flags(&self) -> &[u8] {
+        &self.flags
+    }
+
+    fn mark(&mut self, offset: usize, flag: u8) {
+        if let Some(flags) = self.flags.get_mut(offset) {
+            *flags |= flag;
+        }
+    }
+
+    // Called by the CPU before it runs the instruction at address. offset
+    // maps addresses to ROM offsets, None outside the ROM.
+    pub fn log_instruction<F: Fn(u16) -> Option<usize>>(
+        &mut self,
+        address: u16,
+        length: u16,
+        offset: F,
+    ) {
+        self.instruction = (address, length);
+        for index in 0..length {
+            let flag = if index == 0 { CDL_OPCODE } else { CDL_OPERAND };
+            if let Some(offset) = offset(address.wrapping_add(index)) {
+                self.mark(offset, flag);
+            }
+        }
+    }
+
+    // A CPU read from the ROM at offset
+    pub fn log_read(&mut self, address: u16, offset: usize, value: u8) {
+        let (start, length) = self.instruction;
+        if address.wrapping_sub(start) < length {
+            return;
+        }
+        self.mark(offset, CDL_DATA);
+        self.last_data = Some((offset, value));
+    }
+
+    // Any CPU write
+    pub fn log_write(&mut self, address: u16, value: u8) {
+        if let Some((offset, data)) = self.last_data.take() {
+            if (VRAM_START..=VRAM_END).contains(&address) && data == value {
+                self.mark(offset, CDL_VRAM_SOURCE);
+            }
+        }
+    }
+
+    pub fn summary(&self) -> Summary {
+        let code = CDL_OPCODE | CDL_OPERAND;
+        Summary {
+            code: self
+                .flags
+                .iter()
+                .filter(|flags| **flags & code != 0)
+                .count(),
+            data: self
+                .flags
+                .iter()
+                .filter(|flags| **flags & code == 0 && **flags & CDL_DATA != 0)
+                .count(),
+            unused: self.flags.iter().filter(|flags| **flags == 0).count(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
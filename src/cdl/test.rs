@@ -0,0 +1,74 @@
+use super::*;
+use std::path::PathBuf;
+
+// ROM offsets for a flat 32KB ROM
+fn offset(address: u16) -> Option<usize> {
+    if address < 0x8000 {
+        Some(address as usize)
+    } else {
+        None
+    }
+}
+
+#[test]
+fn logs_code_and_data() {
+    let mut cdl = CodeDataLog::new(0x8000);
+
+    // ld a, [$4000] at 0x0150
+    cdl.log_instruction(0x0150, 3, offset);
+    cdl.log_read(0x0150, 0x0150, 0xFA);
+    cdl.log_read(0x0151, 0x0151, 0x00);
+    cdl.log_read(0x4000, 0x4000, 0x12);
+
+    let flags = cdl.flags();
+    assert_eq!(flags[0x0150], CDL_OPCODE);
+    assert_eq!(flags[0x0151], CDL_OPERAND);
+    assert_eq!(flags[0x0152], CDL_OPERAND);
+    assert_eq!(flags[0x4000], CDL_DATA);
+    assert_eq!(flags[0x0153], 0);
+    assert_eq!(
+        cdl.summary(),
+        Summary {
+            code: 3,
+            data: 1,
+            unused: 0x8000 - 4
+        }
+    );
+}
+
+#[test]
+fn logs_data_copied_to_vram() {
+    let mut cdl = CodeDataLog::new(0x8000);
+    cdl.log_instruction(0x0150, 1, offset);
+    cdl.log_read(0x5000, 0x5000, 0x3C);
+    cdl.log_write(0x8000, 0x3C);
+    cdl.log_read(0x5001, 0x5001, 0x7E);
+    // Written elsewhere, or changed on the way
+    cdl.log_write(0xC000, 0x7E);
+    cdl.log_read(0x5002, 0x5002, 0x7E);
+    cdl.log_write(0x8001, 0x00);
+
+    let flags = cdl.flags();
+    assert_eq!(flags[0x5000], CDL_DATA | CDL_VRAM_SOURCE);
+    assert_eq!(flags[0x5001], CDL_DATA);
+    assert_eq!(flags[0x5002], CDL_DATA);
+}
+
+#[test]
+fn continues_saved_logs() {
+    let path = std::env::temp_dir().join(format!("gba-cdl-{}.cdl", std::process::id()));
+    let mut cdl = CodeDataLog::load_or_new(&path, 0x10).unwrap();
+    cdl.log_instruction(0x0002, 2, offset);
+    cdl.save(&path).unwrap();
+
+    let cdl = CodeDataLog::load_or_new(&path, 0x10).unwrap();
+    assert_eq!(&cdl.flags()[..4], &[0, 0, CDL_OPCODE, CDL_OPERAND]);
+    assert!(CodeDataLog::load_or_new(&path, 0x20).is_err());
+    std::fs::remove_file(&path).unwrap();
+
+    let missing = PathBuf::from("/nonexistent/game.cdl");
+    assert_eq!(
+        CodeDataLog::load_or_new(&missing, 4).unwrap().flags(),
+        &[0; 4]
+    );
+}
@@ -1,5 +1,12 @@
+use super::disasm::decode;
 use super::memory::MemoryMap;
+use super::model::Model;
+use super::savestate::{StateReader, StateWriter};
+use super::trace::Trace;
 use std::num::Wrapping;
+use std::ops::Deref;
+
+const STATE_VERSION: u8 = 1;
 
 enum OperationType {
     B8,
@@ -137,23 +144,180 @@ impl Flag {
     }
 }
 
-pub struct Cpu<'m> {
+// Copy of the register file, with the flags packed into F
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct Registers {
+    pub a: u8,
+    pub f: u8,
+    pub b: u8,
+    pub c: u8,
+    pub d: u8,
+    pub e: u8,
+    pub h: u8,
+    pub l: u8,
+    pub sp: u16,
+    pub pc: u16,
+}
+
+// The memory is anything that derefs to the map, a plain reference or a
+// shared handle when the CPU lives next to the map in the same struct.
+pub struct Cpu<M> {
     flag: Flag,
     reg: Reg,
-    cycles: u32,
-    memory: &'m MemoryMap,
+    cycles: u64,
+    memory: M,
+    trace: Option<Trace>,
 }
 
-impl Cpu<'_> {
-    pub fn new(memory: &MemoryMap) -> Cpu<'_> {
+impl<M: Deref<Target = MemoryMap>> Cpu<M> {
+    pub fn new(memory: M) -> Cpu<M> {
         Cpu {
             flag: Flag::new(),
             reg: Reg::new(),
             cycles: 0,
             memory: memory,
+            trace: None,
+        }
+    }
+
+    // Starts or stops writing an execution trace, returning the old one
+    pub fn set_trace(&mut self, trace: Option<Trace>) -> Option<Trace> {
+        std::mem::replace(&mut self.trace, trace)
+    }
+
+    pub fn trace_mut(&mut self) -> Option<&mut Trace> {
+        self.trace.as_mut()
+    }
+
+    fn write_trace(&mut self) {
+        let registers = self.registers();
+        let pc = self.reg.pc as usize;
+        let mut pcmem = [0; 4];
+        for (offset, byte) in pcmem.iter_mut().enumerate() {
+            *byte = self.memory.peek((pc + offset) & 0xFFFF);
+        }
+        if let Some(trace) = self.trace.as_mut() {
+            trace.record(&registers, pcmem, self.memory.bank(pc));
         }
     }
 
+    pub fn pc(&self) -> u16 {
+        self.reg.pc
+    }
+
+    pub fn registers(&self) -> Registers {
+        let mut f = 0;
+        if self.flag.z {
+            f |= 0x80;
+        }
+        if self.flag.n {
+            f |= 0x40;
+        }
+        if self.flag.h {
+            f |= 0x20;
+        }
+        if self.flag.c {
+            f |= 0x10;
+        }
+        Registers {
+            a: self.reg.a,
+            f,
+            b: self.reg.b,
+            c: self.reg.c,
+            d: self.reg.d,
+            e: self.reg.e,
+            h: self.reg.h,
+            l: self.reg.l,
+            sp: self.reg.sp,
+            pc: self.reg.pc,
+        }
+    }
+
+    // Overwrites the register file, unpacking the flags from F
+    pub fn set_registers(&mut self, registers: &Registers) {
+        self.reg.a = registers.a;
+        self.reg.f = registers.f;
+        self.reg.b = registers.b;
+        self.reg.c = registers.c;
+        self.reg.d = registers.d;
+        self.reg.e = registers.e;
+        self.reg.h = registers.h;
+        self.reg.l = registers.l;
+        self.reg.sp = registers.sp;
+        self.reg.pc = registers.pc;
+        self.flag.z = registers.f & 0x80 != 0;
+        self.flag.n = registers.f & 0x40 != 0;
+        self.flag.h = registers.f & 0x20 != 0;
+        self.flag.c = registers.f & 0x10 != 0;
+    }
+
+    // Clock cycles executed since power on
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    // Puts the registers in the state the boot ROM leaves them in, for
+    // starting a cartridge directly at 0x0100.
+    pub fn skip_boot(&mut self, model: Model) {
+        let (a, f, b, c, d, e, h, l) = match model {
+            Model::Dmg => (0x01, 0xB0, 0x00, 0x13, 0x00, 0xD8, 0x01, 0x4D),
+            Model::Cgb => (0x11, 0x80, 0x00, 0x00, 0xFF, 0x56, 0x00, 0x0D),
+        };
+        self.reg.a = a;
+        self.reg.f = f;
+        self.reg.b = b;
+        self.reg.c = c;
+        self.reg.d = d;
+        self.reg.e = e;
+        self.reg.h = h;
+        self.reg.l = l;
+        self.reg.sp = 0xFFFE;
+        self.reg.pc = 0x0100;
+        self.flag.z = f & 0x80 != 0;
+        self.flag.n = f & 0x40 != 0;
+        self.flag.h = f & 0x20 != 0;
+        self.flag.c = f & 0x10 != 0;
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(STATE_VERSION);
+        for value in [
+            self.reg.a, self.reg.b, self.reg.c, self.reg.d, self.reg.e, self.reg.f, self.reg.h,
+            self.reg.l,
+        ]
+        .iter()
+        {
+            writer.write_u8(*value);
+        }
+        writer.write_u16(self.reg.sp);
+        writer.write_u16(self.reg.pc);
+        writer.write_bool(self.flag.z);
+        writer.write_bool(self.flag.n);
+        writer.write_bool(self.flag.h);
+        writer.write_bool(self.flag.c);
+        writer.write_u64(self.cycles);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_version("CPU", STATE_VERSION)?;
+        self.reg.a = reader.read_u8()?;
+        self.reg.b = reader.read_u8()?;
+        self.reg.c = reader.read_u8()?;
+        self.reg.d = reader.read_u8()?;
+        self.reg.e = reader.read_u8()?;
+        self.reg.f = reader.read_u8()?;
+        self.reg.h = reader.read_u8()?;
+        self.reg.l = reader.read_u8()?;
+        self.reg.sp = reader.read_u16()?;
+        self.reg.pc = reader.read_u16()?;
+        self.flag.z = reader.read_bool()?;
+        self.flag.n = reader.read_bool()?;
+        self.flag.h = reader.read_bool()?;
+        self.flag.c = reader.read_bool()?;
+        self.cycles = reader.read_u64()?;
+        Ok(())
+    }
+
     fn push_to_stack(&mut self, value: u8) {
         self.memory.write(self.reg.sp as usize - 1, value);
 
@@ -166,6 +330,22 @@ impl Cpu<'_> {
     }
 
     pub fn step(&mut self) {
+        // Only gather the registers and PCMEM when a line will be written
+        if let Some(true) = self.trace.as_ref().map(|trace| trace.traces(self.reg.pc)) {
+            self.write_trace();
+        }
+        if !self.memory.watchpoints.borrow().is_empty() {
+            self.memory
+                .watchpoints
+                .borrow_mut()
+                .set_context(self.reg.pc, self.cycles);
+        }
+        if self.memory.cdl.borrow().is_some() {
+            let memory = &self.memory;
+            let length = decode(|address| memory.peek(address as usize), self.reg.pc).length();
+            self.memory.log_instruction(self.reg.pc, length);
+        }
+
         match self.memory.read(self.reg.pc as usize) {
             // 8 bit loads (Immediate)
             0x06 => {
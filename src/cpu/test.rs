@@ -1755,4 +1755,87 @@ fn push_hl() {
     assert_eq!(cpu.reg.sp, 0x03);
     assert_eq!(cpu.reg.pc, 0x01);
     assert_eq!(cpu.cycles, 16);
-}
\ No newline at end of file
+}
+#[test]
+fn test_skip_boot() {
+    let mem = MemoryMap::new(0xFFFF);
+    let mut cpu = Cpu::new(&mem);
+
+    cpu.skip_boot(Model::Dmg);
+    assert_eq!(cpu.pc(), 0x0100);
+    assert_eq!(cpu.reg.sp, 0xFFFE);
+    assert_eq!(cpu.reg.a, 0x01);
+    assert_eq!(cpu.flag.z, true);
+    assert_eq!(cpu.flag.n, false);
+    assert_eq!(cpu.flag.c, true);
+
+    cpu.skip_boot(Model::Cgb);
+    assert_eq!(cpu.reg.a, 0x11);
+    assert_eq!(cpu.flag.c, false);
+}
+
+#[test]
+fn test_registers_snapshot() {
+    let mem = MemoryMap::new(0xFFFF);
+    let mut cpu = Cpu::new(&mem);
+
+    cpu.skip_boot(Model::Dmg);
+    let registers = cpu.registers();
+    assert_eq!(registers.a, 0x01);
+    assert_eq!(registers.f, 0xB0);
+    assert_eq!(registers.c, 0x13);
+    assert_eq!(registers.sp, 0xFFFE);
+    assert_eq!(registers.pc, 0x0100);
+
+    cpu.flag.n = true;
+    assert_eq!(cpu.registers().f, 0xF0);
+}
+
+#[test]
+fn test_set_registers() {
+    let mem = MemoryMap::new(0xFFFF);
+    let mut cpu = Cpu::new(&mem);
+
+    cpu.skip_boot(Model::Dmg);
+    let mut registers = cpu.registers();
+    registers.h = 0xC0;
+    registers.f = 0x40;
+    cpu.set_registers(&registers);
+    assert_eq!(cpu.reg.h, 0xC0);
+    assert_eq!(cpu.flag.z, false);
+    assert_eq!(cpu.flag.n, true);
+    assert_eq!(cpu.flag.c, false);
+    assert_eq!(cpu.registers(), registers);
+}
+
+#[test]
+fn test_trace_before_step() {
+    let mem = MemoryMap::new(0x10000);
+    let mut cpu = Cpu::new(&mem);
+    let output = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+    struct Shared(std::rc::Rc<std::cell::RefCell<Vec<u8>>>);
+    impl std::io::Write for Shared {
+        fn write(&mut self, data: &[u8]) -> std::io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(data);
+            Ok(data.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    cpu.skip_boot(Model::Dmg);
+    cpu.memory.write(0x100, 0x3E);
+    cpu.memory.write(0x101, 0x42);
+    cpu.memory.write(0x102, 0x47);
+    cpu.set_trace(Some(Trace::new(Box::new(Shared(output.clone())))));
+    cpu.step();
+    cpu.set_trace(None).unwrap().finish().unwrap();
+    cpu.step();
+
+    let text = String::from_utf8(output.borrow().clone()).unwrap();
+    assert_eq!(
+        text,
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:3E,42,47,00\n"
+    );
+}
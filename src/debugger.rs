@@ -0,0 +1,752 @@
+// Interactive command line debugger. It takes one command per line and
+// writes to any writer, and it runs the console itself so breakpoints are
+// checked between instructions. Frontends only feed it lines and call step or
+// run_frame instead of the GameBoy methods while it is attached.
+
+use super::cpu::Registers;
+use super::disasm::{decode, Instruction};
+use super::gameboy::GameBoy;
+use super::pacing::CYCLES_PER_FRAME;
+use super::profiler::{function_name, FrameKind};
+use super::symbols::Symbols;
+use super::watchpoint::{WatchAction, WatchHit, Watchpoint};
+use std::collections::BTreeSet;
+use std::io::{self, Write};
+
+pub const HELP: &str = "commands:
+  break <addr>           stop before the instruction at addr (b). A label or
+                         bank:addr only stops while that bank is mapped
+  delete [addr]          remove one breakpoint, or all of them (d). A label or
+                         bank:addr removes the one set for that bank
+  breakpoints            list the breakpoints
+  step [n]               execute n instructions (s)
+  next                   execute one instruction, stepping over calls (n)
+  finish                 run until the current routine returns
+  backtrace              show the calls that led here, while profiling (bt)
+  continue               resume emulation (c)
+  regs                   show the registers and flags (r)
+  mem <addr> [len]       hex dump of memory (x)
+  disasm [addr] [n]      disassemble n instructions, around PC by default (l)
+  set <reg> <value>      change a register: a f b c d e h l af bc de hl sp pc
+  write <addr> <bytes>   change memory, one hex value per byte (w)
+  watch [kind] <target> [log]
+                         stop on accesses to an address, range (c000-c0ff) or
+                         I/O register (LCDC). kind is read, write (default),
+                         change or access; log reports hits without stopping
+  unwatch [n]            remove watchpoint n, or all of them
+  watchpoints            list the watchpoints
+  quit                   exit the emulator (q)
+addresses are labels from the symbol file or hex ($ forces hex), values are
+hex and counts are decimal. An empty line repeats the last command.";
+
+// Instructions shown before and after PC when disassembling around it
+const CONTEXT_BEFORE: usize = 4;
+const CONTEXT_AFTER: usize = 6;
+const DEFAULT_DUMP_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Command {
+    Break { address: u16, bank: Option<u16> },
+    // An address and the bank it was set for, or all breakpoints
+    Delete(Option<(u16, Option<u16>)>),
+    Breakpoints,
+    Step(u32),
+    Next,
+    Finish,
+    Backtrace,
+    Continue,
+    Registers,
+    Memory { address: u16, length: usize },
+    Disassemble { address: Option<u16>, count: usize },
+    Set { register: String, value: u16 },
+    Write { address: u16, bytes: Vec<u8> },
+    Watch(Watchpoint),
+    Unwatch(Option<usize>),
+    Watchpoints,
+    Help,
+    Quit,
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hex value '{}'", text))
+}
+
+fn parse_count(text: &str) -> Result<usize, String> {
+    text.parse()
+        .map_err(|_| format!("invalid count '{}'", text))
+}
+
+impl Command {
+    pub fn parse(line: &str) -> Result<Command, String> {
+        Command::parse_with(line, &Symbols::new())
+    }
+
+    // Parses with labels from symbols accepted as addresses
+    pub fn parse_with(line: &str, symbols: &Symbols) -> Result<Command, String> {
+        let address = |text: &str| symbols.resolve(text).map(|(address, _)| address);
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let argument = |index: usize| {
+            words
+                .get(index)
+                .copied()
+                .ok_or_else(|| format!("{} needs more arguments", words[0]))
+        };
+        let command = match words.first().copied().unwrap_or("") {
+            "break" | "b" => {
+                let (address, bank) = symbols.resolve(argument(1)?)?;
+                Command::Break { address, bank }
+            }
+            "delete" | "d" => Command::Delete(match words.get(1) {
+                Some(text) => Some(symbols.resolve(text)?),
+                None => None,
+            }),
+            "breakpoints" => Command::Breakpoints,
+            "step" | "s" => Command::Step(match words.get(1) {
+                Some(count) => parse_count(count)? as u32,
+                None => 1,
+            }),
+            "next" | "n" => Command::Next,
+            "finish" => Command::Finish,
+            "backtrace" | "bt" => Command::Backtrace,
+            "continue" | "c" => Command::Continue,
+            "regs" | "r" => Command::Registers,
+            "mem" | "x" => Command::Memory {
+                address: address(argument(1)?)?,
+                length: match words.get(2) {
+                    Some(length) => parse_count(length)?,
+                    None => DEFAULT_DUMP_LENGTH,
+                },
+            },
+            "disasm" | "l" => Command::Disassemble {
+                address: match words.get(1) {
+                    Some(text) => Some(address(text)?),
+                    None => None,
+                },
+                count: match words.get(2) {
+                    Some(count) => parse_count(count)?,
+                    None => CONTEXT_BEFORE + CONTEXT_AFTER + 1,
+                },
+            },
+            "set" => Command::Set {
+                register: argument(1)?.to_lowercase(),
+                value: parse_hex(argument(2)?)?,
+            },
+            "write" | "w" => {
+                let address = address(argument(1)?)?;
+                argument(2)?;
+                let mut bytes = Vec::new();
+                for word in &words[2..] {
+                    let value = parse_hex(word)?;
+                    if value > 0xFF {
+                        return Err(format!("'{}' does not fit in a byte", word));
+                    }
+                    bytes.push(value as u8);
+                }
+                Command::Write { address, bytes }
+            }
+            "watch" => {
+                let mut rest = &words[1..];
+                let action = if rest.last() == Some(&"log") {
+                    rest = &rest[..rest.len() - 1];
+                    WatchAction::Log
+                } else {
+                    WatchAction::Pause
+                };
+                let watchpoint = match rest {
+                    [kind, target] => Watchpoint::parse(&format!("{}:{}", kind, target), action)?,
+                    [target] => Watchpoint::parse(target, action)?,
+                    _ => return Err("usage: watch [kind] <target> [log]".to_string()),
+                };
+                Command::Watch(watchpoint)
+            }
+            "unwatch" => Command::Unwatch(match words.get(1) {
+                Some(index) => Some(parse_count(index)?),
+                None => None,
+            }),
+            "watchpoints" => Command::Watchpoints,
+            "help" | "h" | "?" => Command::Help,
+            "quit" | "q" => Command::Quit,
+            other => return Err(format!("unknown command '{}', try help", other)),
+        };
+        Ok(command)
+    }
+}
+
+// Register display, with the flags spelled out
+pub fn format_registers(registers: &Registers) -> String {
+    let flag = |mask: u8, name: char| if registers.f & mask != 0 { name } else { '-' };
+    format!(
+        "A:{:02X} F:{:02X} [{}{}{}{}] BC:{:02X}{:02X} DE:{:02X}{:02X} HL:{:02X}{:02X} SP:{:04X} PC:{:04X}",
+        registers.a,
+        registers.f,
+        flag(0x80, 'Z'),
+        flag(0x40, 'N'),
+        flag(0x20, 'H'),
+        flag(0x10, 'C'),
+        registers.b,
+        registers.c,
+        registers.d,
+        registers.e,
+        registers.h,
+        registers.l,
+        registers.sp,
+        registers.pc
+    )
+}
+
+fn set_register(registers: &mut Registers, name: &str, value: u16) -> Result<(), String> {
+    let byte = || {
+        if value > 0xFF {
+            Err(format!("{} only holds a byte", name))
+        } else {
+            Ok(value as u8)
+        }
+    };
+    match name {
+        "a" => registers.a = byte()?,
+        // The low nibble of F always reads as zero
+        "f" => registers.f = byte()? & 0xF0,
+        "b" => registers.b = byte()?,
+        "c" => registers.c = byte()?,
+        "d" => registers.d = byte()?,
+        "e" => registers.e = byte()?,
+        "h" => registers.h = byte()?,
+        "l" => registers.l = byte()?,
+        "af" => {
+            registers.a = (value >> 8) as u8;
+            registers.f = value as u8 & 0xF0;
+        }
+        "bc" => {
+            registers.b = (value >> 8) as u8;
+            registers.c = value as u8;
+        }
+        "de" => {
+            registers.d = (value >> 8) as u8;
+            registers.e = value as u8;
+        }
+        "hl" => {
+            registers.h = (value >> 8) as u8;
+            registers.l = value as u8;
+        }
+        "sp" => registers.sp = value,
+        "pc" => registers.pc = value,
+        _ => return Err(format!("unknown register '{}'", name)),
+    }
+    Ok(())
+}
+
+fn instruction_at(gameboy: &GameBoy, address: u16) -> Instruction {
+    let memory = gameboy.memory();
+    decode(|address| memory.peek(address as usize), address)
+}
+
+// Pending stop of next and finish
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Until {
+    // Back at this address with the stack no deeper, after stepping over a call
+    Address { pc: u16, sp: u16 },
+    // A return that pops the stack above sp
+    Return { sp: u16 },
+}
+
+#[derive(Debug)]
+pub struct Debugger {
+    // Address and bank. Breakpoints from labels only stop in their own bank,
+    // several banks can have one at the same address.
+    breakpoints: BTreeSet<(u16, Option<u16>)>,
+    paused: bool,
+    until: Option<Until>,
+    // Resuming on a breakpoint executes it instead of stopping right away
+    resumed_at: Option<u16>,
+    last_command: Option<Command>,
+    // Watchpoint hits that stopped the emulation, and the logged ones not
+    // written out yet
+    stop_hits: Vec<WatchHit>,
+    log: Vec<WatchHit>,
+    symbols: Symbols,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: BTreeSet::new(),
+            paused: false,
+            until: None,
+            resumed_at: None,
+            last_command: None,
+            stop_hits: Vec::new(),
+            log: Vec::new(),
+            symbols: Symbols::new(),
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    // Stops in front of the next instruction, e.g. from a hotkey
+    pub fn pause(&mut self) {
+        self.paused = true;
+        self.until = None;
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    pub fn set_symbols(&mut self, symbols: Symbols) {
+        self.symbols = symbols;
+    }
+
+    pub fn add_breakpoint(&mut self, address: u16) {
+        self.breakpoints.insert((address, None));
+    }
+
+    // A breakpoint that only stops while bank is mapped at address
+    pub fn add_bank_breakpoint(&mut self, address: u16, bank: u16) {
+        self.breakpoints.insert((address, Some(bank)));
+    }
+
+    // Removes the breakpoint set for bank, or the one for any bank with None
+    pub fn remove_breakpoint(&mut self, address: u16, bank: Option<u16>) -> bool {
+        self.breakpoints.remove(&(address, bank))
+    }
+
+    pub fn breakpoints(&self) -> Vec<(u16, Option<u16>)> {
+        self.breakpoints.iter().copied().collect()
+    }
+
+    fn breakpoint_at(&self, gameboy: &GameBoy, pc: u16) -> bool {
+        self.breakpoints
+            .range((pc, None)..=(pc, Some(u16::MAX)))
+            .any(|(_, bank)| match bank {
+                Some(bank) => gameboy.memory().bank(pc as usize) == *bank,
+                None => true,
+            })
+    }
+
+    // "0158 (Main.loop)", or just the address without a label for it
+    fn describe(&self, bank: u16, address: u16) -> String {
+        match self.symbols.describe(bank, address) {
+            Some(label) => format!("{:04X} ({})", address, label),
+            None => format!("{:04X}", address),
+        }
+    }
+
+    // Watchpoints live in the memory map so they also catch accesses made
+    // while the debugger is not stepping
+    pub fn add_watchpoint(&self, gameboy: &GameBoy, watchpoint: Watchpoint) {
+        gameboy.memory().watchpoints.borrow_mut().add(watchpoint);
+    }
+
+    fn collect_hits(&mut self, gameboy: &GameBoy) {
+        let hits = gameboy.memory().watchpoints.borrow_mut().take_hits();
+        for hit in hits {
+            match hit.action {
+                WatchAction::Pause => {
+                    self.stop_hits.push(hit);
+                    self.pause();
+                }
+                WatchAction::Log => self.log.push(hit),
+            }
+        }
+    }
+
+    // Writes out the hits of logging watchpoints
+    pub fn write_log(&mut self, out: &mut dyn Write) -> io::Result<()> {
+        for hit in self.log.drain(..) {
+            writeln!(out, "watchpoint: {}", hit)?;
+        }
+        Ok(())
+    }
+
+    // Watchpoint hits behind the last stop
+    pub fn stop_hits(&self) -> &[WatchHit] {
+        &self.stop_hits
+    }
+
+    // Lets the emulation run until the next breakpoint or watchpoint
+    pub fn resume(&mut self, gameboy: &GameBoy) {
+        self.resume_until(gameboy, None);
+    }
+
+    fn resume_until(&mut self, gameboy: &GameBoy, until: Option<Until>) {
+        self.stop_hits.clear();
+        self.paused = false;
+        self.until = until;
+        self.resumed_at = Some(gameboy.cpu().pc());
+    }
+
+    // Executes one instruction unless a breakpoint or a pending next or
+    // finish stops in front of it. Returns true when the debugger is paused.
+    pub fn step(&mut self, gameboy: &mut GameBoy) -> bool {
+        if self.paused {
+            return true;
+        }
+
+        let registers = gameboy.cpu().registers();
+        let resuming = self.resumed_at.take() == Some(registers.pc);
+        if !resuming && self.breakpoint_at(gameboy, registers.pc) {
+            self.pause();
+            return true;
+        }
+        if let Some(Until::Address { pc, sp }) = self.until {
+            if registers.pc == pc && registers.sp >= sp {
+                self.pause();
+                return true;
+            }
+        }
+
+        let returning = match self.until {
+            Some(Until::Return { .. }) => instruction_at(gameboy, registers.pc).is_return(),
+            _ => false,
+        };
+        gameboy.step_instruction();
+        self.collect_hits(gameboy);
+        if let Some(Until::Return { sp }) = self.until {
+            // A conditional return that is not taken leaves SP alone
+            if returning && gameboy.cpu().registers().sp > sp {
+                self.pause();
+            }
+        }
+        self.paused
+    }
+
+    // Executes one instruction while paused, ignoring breakpoints. Returns
+    // false when a watchpoint stopped it.
+    pub fn single_step(&mut self, gameboy: &mut GameBoy) -> bool {
+        self.pause();
+        self.stop_hits.clear();
+        gameboy.step_instruction();
+        self.collect_hits(gameboy);
+        self.stop_hits.is_empty()
+    }
+
+    // Like GameBoy::run_frame, but stops early when the debugger pauses.
+    // Returns true when it did.
+    pub fn run_frame(&mut self, gameboy: &mut GameBoy) -> bool {
+        let frame = CYCLES_PER_FRAME as u64;
+        let end = (gameboy.cycles() / frame + 1) * frame;
+        while gameboy.cycles() < end {
+            if self.step(gameboy) {
+                return true;
+            }
+        }
+        false
+    }
+
+    // Current instruction and registers, shown whenever the debugger stops
+    pub fn print_location(&self, gameboy: &GameBoy, out: &mut dyn Write) -> io::Result<()> {
+        let registers = gameboy.cpu().registers();
+        for hit in self.stop_hits.iter() {
+            writeln!(out, "watchpoint: {}", hit)?;
+        }
+        if self.stop_hits.is_empty() && self.breakpoint_at(gameboy, registers.pc) {
+            let bank = gameboy.memory().bank(registers.pc as usize);
+            writeln!(out, "breakpoint at {}", self.describe(bank, registers.pc))?;
+        }
+        writeln!(out, "{}", format_registers(&registers))?;
+        let instruction = instruction_at(gameboy, registers.pc);
+        self.write_instruction(gameboy, out, &instruction, true)
+    }
+
+    // One disassembled line, below the label of the address if it has one
+    fn write_instruction(
+        &self,
+        gameboy: &GameBoy,
+        out: &mut dyn Write,
+        instruction: &Instruction,
+        current: bool,
+    ) -> io::Result<()> {
+        let memory = gameboy.memory();
+        let bank = |address: u16| memory.bank(address as usize);
+        if let Some(label) = self
+            .symbols
+            .label(bank(instruction.address), instruction.address)
+        {
+            writeln!(out, "  {}:", label)?;
+        }
+        let bytes: Vec<String> = instruction
+            .bytes
+            .iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect();
+        writeln!(
+            out,
+            "{} {:04X}  {:<9} {}",
+            if current { '>' } else { ' ' },
+            instruction.address,
+            bytes.join(" "),
+            self.symbols.format_instruction(instruction, bank)
+        )
+    }
+
+    // Runs one command line. Returns false when the user asked to quit.
+    pub fn execute(
+        &mut self,
+        gameboy: &mut GameBoy,
+        line: &str,
+        out: &mut dyn Write,
+    ) -> io::Result<bool> {
+        let command = if line.trim().is_empty() {
+            match self.last_command.clone() {
+                Some(command) => command,
+                None => return Ok(true),
+            }
+        } else {
+            match Command::parse_with(line, &self.symbols) {
+                Ok(command) => command,
+                Err(e) => {
+                    writeln!(out, "{}", e)?;
+                    return Ok(true);
+                }
+            }
+        };
+        self.last_command = Some(command.clone());
+        self.run_command(gameboy, command, out)
+    }
+
+    fn run_command(
+        &mut self,
+        gameboy: &mut GameBoy,
+        command: Command,
+        out: &mut dyn Write,
+    ) -> io::Result<bool> {
+        match command {
+            Command::Break { address, bank } => {
+                match bank {
+                    Some(bank) => self.add_bank_breakpoint(address, bank),
+                    None => self.add_breakpoint(address),
+                }
+                let shown = bank.unwrap_or_else(|| gameboy.memory().bank(address as usize));
+                writeln!(out, "breakpoint at {}", self.describe(shown, address))?;
+            }
+            Command::Delete(Some((address, bank))) => {
+                if !self.remove_breakpoint(address, bank) {
+                    match bank {
+                        Some(bank) => {
+                            writeln!(out, "no breakpoint at {:02X}:{:04X}", bank, address)?
+                        }
+                        None => writeln!(out, "no breakpoint at {:04X}", address)?,
+                    }
+                }
+            }
+            Command::Delete(None) => self.breakpoints.clear(),
+            Command::Breakpoints => {
+                if self.breakpoints.is_empty() {
+                    writeln!(out, "no breakpoints")?;
+                }
+                for (address, bank) in self.breakpoints.iter() {
+                    match bank {
+                        Some(bank) => {
+                            writeln!(out, "{:02X}:{}", bank, self.describe(*bank, *address))?
+                        }
+                        None => {
+                            let shown = gameboy.memory().bank(*address as usize);
+                            writeln!(out, "{}", self.describe(shown, *address))?
+                        }
+                    }
+                }
+            }
+            Command::Step(count) => {
+                for _ in 0..count {
+                    if !self.single_step(gameboy) {
+                        break;
+                    }
+                }
+                self.write_log(out)?;
+                self.print_location(gameboy, out)?;
+            }
+            Command::Next => {
+                let registers = gameboy.cpu().registers();
+                let instruction = instruction_at(gameboy, registers.pc);
+                if instruction.is_call() {
+                    let until = Until::Address {
+                        pc: instruction.next_address(),
+                        sp: registers.sp,
+                    };
+                    self.resume_until(gameboy, Some(until));
+                } else {
+                    return self.run_command(gameboy, Command::Step(1), out);
+                }
+            }
+            Command::Finish => {
+                let sp = gameboy.cpu().registers().sp;
+                self.resume_until(gameboy, Some(Until::Return { sp }));
+            }
+            Command::Backtrace => self.backtrace(gameboy, out)?,
+            Command::Continue => self.resume(gameboy),
+            Command::Registers => {
+                let registers = gameboy.cpu().registers();
+                writeln!(
+                    out,
+                    "{}  cycles:{}",
+                    format_registers(&registers),
+                    gameboy.cycles()
+                )?;
+            }
+            Command::Memory { address, length } => {
+                self.dump_memory(gameboy, address, length, out)?
+            }
+            Command::Disassemble { address, count } => {
+                self.disassemble(gameboy, address, count, out)?
+            }
+            Command::Set { register, value } => {
+                let mut registers = gameboy.cpu().registers();
+                match set_register(&mut registers, &register, value) {
+                    Ok(()) => gameboy.cpu_mut().set_registers(&registers),
+                    Err(e) => writeln!(out, "{}", e)?,
+                }
+            }
+            Command::Write { address, bytes } => {
+                // Goes through the memory map like a CPU write, so ROM and
+                // I/O registers behave as they would for the game
+                let memory = gameboy.memory();
+                for (offset, byte) in bytes.iter().enumerate() {
+                    memory.write(address.wrapping_add(offset as u16) as usize, *byte);
+                }
+                // Changes made from the debugger are not the game's accesses
+                memory.watchpoints.borrow_mut().take_hits();
+            }
+            Command::Watch(watchpoint) => {
+                writeln!(out, "watching {}", watchpoint)?;
+                self.add_watchpoint(gameboy, watchpoint);
+            }
+            Command::Unwatch(Some(index)) => {
+                let removed = gameboy.memory().watchpoints.borrow_mut().remove(index);
+                if removed.is_none() {
+                    writeln!(out, "no watchpoint {}", index)?;
+                }
+            }
+            Command::Unwatch(None) => gameboy.memory().watchpoints.borrow_mut().clear(),
+            Command::Watchpoints => {
+                let watchpoints = gameboy.memory().watchpoints.borrow();
+                if watchpoints.is_empty() {
+                    writeln!(out, "no watchpoints")?;
+                }
+                for (index, watchpoint) in watchpoints.list().iter().enumerate() {
+                    writeln!(out, "{}: {}", index, watchpoint)?;
+                }
+            }
+            Command::Help => writeln!(out, "{}", HELP)?,
+            Command::Quit => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // Innermost first, from the profiler's shadow stack
+    fn backtrace(&self, gameboy: &GameBoy, out: &mut dyn Write) -> io::Result<()> {
+        let profiler = match gameboy.profiler() {
+            Some(profiler) => profiler,
+            None => {
+                return writeln!(
+                    out,
+                    "no call stack, calls are only followed while profiling"
+                )
+            }
+        };
+        let memory = gameboy.memory();
+        let frames = profiler.call_stack().frames();
+        let mut address = gameboy.cpu().pc();
+        for (depth, frame) in frames.iter().rev().enumerate() {
+            let name = self
+                .symbols
+                .describe(memory.bank(address as usize), address)
+                .unwrap_or_else(|| function_name(&self.symbols, frame.function));
+            let entered = match frame.kind {
+                FrameKind::Call => "",
+                FrameKind::Interrupt => "  (interrupt)",
+            };
+            writeln!(out, "#{:<3} {:04X}  {}{}", depth, address, name, entered)?;
+            address = frame.caller;
+        }
+        let name = self
+            .symbols
+            .describe(memory.bank(address as usize), address)
+            .unwrap_or_else(|| "(root)".to_string());
+        writeln!(out, "#{:<3} {:04X}  {}", frames.len(), address, name)
+    }
+
+    fn dump_memory(
+        &self,
+        gameboy: &GameBoy,
+        address: u16,
+        length: usize,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let memory = gameboy.memory();
+        let mut offset = 0;
+        while offset < length {
+            let line = address.wrapping_add(offset as u16);
+            let bytes: Vec<u8> = (0..16.min(length - offset))
+                .map(|index| memory.peek(line.wrapping_add(index as u16) as usize))
+                .collect();
+            let hex: Vec<String> = bytes.iter().map(|byte| format!("{:02X}", byte)).collect();
+            let text: String = bytes
+                .iter()
+                .map(|byte| {
+                    if byte.is_ascii_graphic() || *byte == b' ' {
+                        *byte as char
+                    } else {
+                        '.'
+                    }
+                })
+                .collect();
+            writeln!(out, "{:04X}  {:<47}  {}", line, hex.join(" "), text)?;
+            offset += 16;
+        }
+        Ok(())
+    }
+
+    fn disassemble(
+        &self,
+        gameboy: &GameBoy,
+        address: Option<u16>,
+        count: usize,
+        out: &mut dyn Write,
+    ) -> io::Result<()> {
+        let pc = gameboy.cpu().pc();
+        let start = match address {
+            Some(address) => address,
+            None => context_start(gameboy, pc),
+        };
+        let mut address = start;
+        for _ in 0..count {
+            let instruction = instruction_at(gameboy, address);
+            self.write_instruction(gameboy, out, &instruction, instruction.address == pc)?;
+            address = instruction.next_address();
+        }
+        Ok(())
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+// Instructions have different lengths, so there is no telling where the ones
+// before PC start. Decode from a few bytes back and keep the furthest start
+// whose instructions line up with PC.
+fn context_start(gameboy: &GameBoy, pc: u16) -> u16 {
+    let mut best: Vec<u16> = Vec::new();
+    for distance in (1..=CONTEXT_BEFORE as u16 * 3).rev() {
+        let start = pc.wrapping_sub(distance);
+        let mut address = start;
+        let mut addresses = Vec::new();
+        while address.wrapping_sub(start) < distance {
+            addresses.push(address);
+            address = instruction_at(gameboy, address).next_address();
+        }
+        if address == pc && addresses.len() > best.len() {
+            best = addresses;
+        }
+    }
+    let skip = best.len().saturating_sub(CONTEXT_BEFORE);
+    best.get(skip).copied().unwrap_or(pc)
+}
+
+#[cfg(test)]
+mod test;
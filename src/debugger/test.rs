@@ -0,0 +1,270 @@
+use super::*;
+use crate::model::Model;
+use crate::profiler::Profiler;
+
+// Loads B at 0x0100, then PUSH BC all the way
+fn gameboy() -> GameBoy {
+    let mut rom = vec![0xC5; 0x8000];
+    rom[0x0100] = 0x06;
+    rom[0x0101] = 0x42;
+    let mut gameboy = GameBoy::new(Model::Dmg);
+    gameboy.load_rom(rom).unwrap();
+    gameboy
+}
+
+fn run(debugger: &mut Debugger, gameboy: &mut GameBoy, line: &str) -> String {
+    let mut out = Vec::new();
+    assert!(debugger.execute(gameboy, line, &mut out).unwrap());
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn parses_commands() {
+    assert_eq!(
+        Command::parse("b $0150"),
+        Ok(Command::Break {
+            address: 0x0150,
+            bank: None
+        })
+    );
+    assert_eq!(Command::parse("delete"), Ok(Command::Delete(None)));
+    assert_eq!(Command::parse("step 10"), Ok(Command::Step(10)));
+    assert_eq!(
+        Command::parse("x c000 32"),
+        Ok(Command::Memory {
+            address: 0xC000,
+            length: 32
+        })
+    );
+    assert_eq!(
+        Command::parse("set HL 0xC000"),
+        Ok(Command::Set {
+            register: "hl".to_string(),
+            value: 0xC000
+        })
+    );
+    assert_eq!(
+        Command::parse("w ff80 1 2"),
+        Ok(Command::Write {
+            address: 0xFF80,
+            bytes: vec![0x01, 0x02]
+        })
+    );
+    assert!(Command::parse("w ff80 100").is_err());
+    assert!(Command::parse("break").is_err());
+    assert!(Command::parse("jump").is_err());
+}
+
+#[test]
+fn stops_at_breakpoints() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+    run(&mut debugger, &mut gameboy, "break 104");
+
+    assert!(debugger.run_frame(&mut gameboy));
+    assert!(debugger.paused());
+    assert_eq!(gameboy.cpu().pc(), 0x0104);
+    let mut out = Vec::new();
+    debugger.print_location(&gameboy, &mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.starts_with("breakpoint at 0104\n"));
+    assert!(text.ends_with("> 0104  C5        push bc\n"));
+
+    // Continuing runs the instruction under the breakpoint
+    run(&mut debugger, &mut gameboy, "c");
+    assert!(!debugger.paused());
+    assert!(!debugger.step(&mut gameboy));
+    assert_eq!(gameboy.cpu().pc(), 0x0105);
+
+    run(&mut debugger, &mut gameboy, "d 104");
+    assert!(debugger.breakpoints().is_empty());
+}
+
+#[test]
+fn breaks_on_labels_in_their_bank() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+    debugger.set_symbols(Symbols::parse("00:0104 Main.loop\n02:4000 Other").unwrap());
+
+    assert_eq!(
+        run(&mut debugger, &mut gameboy, "break Main.loop"),
+        "breakpoint at 0104 (Main.loop)\n"
+    );
+    // Bank 2 is never mapped without a bank controller
+    run(&mut debugger, &mut gameboy, "b Other");
+    assert_eq!(
+        run(&mut debugger, &mut gameboy, "breakpoints"),
+        "00:0104 (Main.loop)\n02:4000 (Other)\n"
+    );
+    assert!(Command::parse_with("b Nowhere", debugger.symbols()).is_err());
+
+    assert!(debugger.run_frame(&mut gameboy));
+    let mut out = Vec::new();
+    debugger.print_location(&gameboy, &mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    assert!(text.starts_with("breakpoint at 0104 (Main.loop)\n"));
+    assert!(text.ends_with("  Main.loop:\n> 0104  C5        push bc\n"));
+
+    run(&mut debugger, &mut gameboy, "set pc 3fff");
+    run(&mut debugger, &mut gameboy, "c");
+    assert!(!debugger.step(&mut gameboy));
+    assert!(!debugger.step(&mut gameboy));
+    assert_eq!(gameboy.cpu().pc(), 0x4001);
+}
+
+#[test]
+fn keeps_breakpoints_per_bank() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+    debugger.set_symbols(Symbols::parse("01:4000 First\n02:4000 Second").unwrap());
+
+    run(&mut debugger, &mut gameboy, "b First");
+    run(&mut debugger, &mut gameboy, "b 02:4000");
+    run(&mut debugger, &mut gameboy, "b 4000");
+    assert_eq!(
+        debugger.breakpoints(),
+        [(0x4000, None), (0x4000, Some(1)), (0x4000, Some(2))]
+    );
+
+    run(&mut debugger, &mut gameboy, "d Second");
+    run(&mut debugger, &mut gameboy, "d 4000");
+    assert_eq!(debugger.breakpoints(), [(0x4000, Some(1))]);
+    assert_eq!(
+        run(&mut debugger, &mut gameboy, "d 03:4000"),
+        "no breakpoint at 03:4000\n"
+    );
+    run(&mut debugger, &mut gameboy, "d 01:4000");
+    assert!(debugger.breakpoints().is_empty());
+}
+
+#[test]
+fn shows_the_call_stack() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+    assert!(run(&mut debugger, &mut gameboy, "bt").starts_with("no call stack"));
+
+    debugger.set_symbols(Symbols::parse("00:0100 Main").unwrap());
+    gameboy.set_profiler(Some(Profiler::new(Symbols::new())));
+    run(&mut debugger, &mut gameboy, "s 2");
+    assert_eq!(
+        run(&mut debugger, &mut gameboy, "bt"),
+        "#0   0103  Main+3\n"
+    );
+}
+
+#[test]
+fn steps_and_repeats() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+
+    let text = run(&mut debugger, &mut gameboy, "s");
+    assert_eq!(gameboy.cpu().pc(), 0x0102);
+    assert!(debugger.paused());
+    assert!(text.contains("BC:4213"));
+
+    // An empty line steps again, next steps like step outside of calls
+    run(&mut debugger, &mut gameboy, "");
+    assert_eq!(gameboy.cpu().pc(), 0x0103);
+    run(&mut debugger, &mut gameboy, "n");
+    assert_eq!(gameboy.cpu().pc(), 0x0104);
+}
+
+#[test]
+fn shows_registers() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+
+    assert_eq!(
+        run(&mut debugger, &mut gameboy, "regs"),
+        "A:01 F:B0 [Z-HC] BC:0013 DE:00D8 HL:014D SP:FFFE PC:0100  cycles:0\n"
+    );
+}
+
+#[test]
+fn modifies_registers_and_memory() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+
+    run(&mut debugger, &mut gameboy, "set hl c000");
+    run(&mut debugger, &mut gameboy, "set f ff");
+    let registers = gameboy.cpu().registers();
+    assert_eq!((registers.h, registers.l), (0xC0, 0x00));
+    assert_eq!(registers.f, 0xF0);
+    assert!(run(&mut debugger, &mut gameboy, "set a 100").contains("only holds a byte"));
+
+    run(&mut debugger, &mut gameboy, "write c000 48 69");
+    assert_eq!(gameboy.memory().read(0xC001), 0x69);
+    assert_eq!(
+        run(&mut debugger, &mut gameboy, "mem c000 4"),
+        "C000  48 69 00 00                                      Hi..\n"
+    );
+}
+
+#[test]
+fn disassembles_around_pc() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+    run(&mut debugger, &mut gameboy, "step 4");
+
+    let text = run(&mut debugger, &mut gameboy, "disasm");
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 11);
+    assert_eq!(lines[0], "  0100  06 42     ld b, $42");
+    assert_eq!(lines[4], "> 0105  C5        push bc");
+
+    let text = run(&mut debugger, &mut gameboy, "disasm 101 2");
+    assert_eq!(
+        text,
+        "  0101  42        ld b, d\n  0102  C5        push bc\n"
+    );
+}
+
+#[test]
+fn quits() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+
+    assert!(!debugger
+        .execute(&mut gameboy, "quit", &mut Vec::new())
+        .unwrap());
+}
+
+#[test]
+fn stops_on_watchpoints() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+    run(&mut debugger, &mut gameboy, "watch change fffd");
+    run(&mut debugger, &mut gameboy, "watch fffc-fffc log");
+    assert_eq!(
+        run(&mut debugger, &mut gameboy, "watchpoints"),
+        "0: change FFFD\n1: write FFFC (log)\n"
+    );
+
+    // The first PUSH BC stores 42 over the zero at FFFD, then 13 at FFFC
+    assert!(debugger.run_frame(&mut gameboy));
+    assert_eq!(gameboy.cpu().pc(), 0x0103);
+    let mut out = Vec::new();
+    debugger.write_log(&mut out).unwrap();
+    debugger.print_location(&gameboy, &mut out).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(
+        lines[0],
+        "watchpoint: write FFFC 00 -> 13 at PC 0102, cycle 8"
+    );
+    assert_eq!(
+        lines[1],
+        "watchpoint: change FFFD 00 -> 42 at PC 0102, cycle 8"
+    );
+
+    // Writing from the debugger does not trigger anything
+    run(&mut debugger, &mut gameboy, "unwatch 1");
+    run(&mut debugger, &mut gameboy, "write fffd 7");
+    run(&mut debugger, &mut gameboy, "c");
+    assert!(!debugger.step(&mut gameboy));
+    run(&mut debugger, &mut gameboy, "unwatch");
+    assert_eq!(
+        run(&mut debugger, &mut gameboy, "watchpoints"),
+        "no watchpoints\n"
+    );
+}
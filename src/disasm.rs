@@ -0,0 +1,354 @@
+// SM83 disassembler. Decodes one instruction at a time into a structured
+// form and prints it in RGBDS syntax.
+
+use std::fmt;
+
+const R8: [&str; 8] = ["b", "c", "d", "e", "h", "l", "[hl]", "a"];
+const R16: [&str; 4] = ["bc", "de", "hl", "sp"];
+const R16_STACK: [&str; 4] = ["bc", "de", "hl", "af"];
+const R16_MEMORY: [&str; 4] = ["[bc]", "[de]", "[hl+]", "[hl-]"];
+const CONDITIONS: [&str; 4] = ["nz", "z", "nc", "c"];
+const ALU: [&str; 8] = ["add", "adc", "sub", "sbc", "and", "xor", "or", "cp"];
+const ROTATE: [&str; 8] = ["rlc", "rrc", "rl", "rr", "sla", "sra", "swap", "srl"];
+const ACCUMULATOR: [&str; 8] = ["rlca", "rrca", "rla", "rra", "daa", "cpl", "scf", "ccf"];
+
+pub const CB_PREFIX: u8 = 0xCB;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operand {
+    // Register, register pair or register indirect such as [hl] or [hl+]
+    Register(&'static str),
+    Condition(&'static str),
+    Immediate8(u8),
+    Immediate16(u16),
+    // [n16]
+    Address(u16),
+    // [$FF00 + n8], written as the full address
+    HighAddress(u8),
+    // Relative jump, kept with the address it lands on
+    Relative { offset: i8, target: u16 },
+    // The signed offset of add sp, e8 and ld hl, sp + e8
+    SpOffset(i8),
+    Bit(u8),
+    Vector(u8),
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Register(name) | Operand::Condition(name) => write!(f, "{}", name),
+            Operand::Immediate8(value) => write!(f, "${:02X}", value),
+            Operand::Immediate16(value) => write!(f, "${:04X}", value),
+            Operand::Address(address) => write!(f, "[${:04X}]", address),
+            Operand::HighAddress(offset) => write!(f, "[$FF{:02X}]", offset),
+            Operand::Relative { target, .. } => write!(f, "${:04X}", target),
+            Operand::SpOffset(offset) => {
+                let sign = if *offset < 0 { "-" } else { "" };
+                write!(f, "{}${:02X}", sign, offset.unsigned_abs())
+            }
+            Operand::Bit(bit) => write!(f, "{}", bit),
+            Operand::Vector(vector) => write!(f, "${:02X}", vector),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Instruction {
+    pub address: u16,
+    pub bytes: Vec<u8>,
+    pub mnemonic: &'static str,
+    pub operands: Vec<Operand>,
+    // Clock cycles, when a condition holds for conditional instructions
+    pub cycles: u8,
+    // Clock cycles of a conditional instruction whose condition fails
+    pub cycles_not_taken: Option<u8>,
+}
+
+impl Instruction {
+    pub fn length(&self) -> u16 {
+        self.bytes.len() as u16
+    }
+
+    // Address of the instruction that follows in memory
+    pub fn next_address(&self) -> u16 {
+        self.address.wrapping_add(self.length())
+    }
+
+    // Where a jump, call or rst goes when taken, if known statically
+    pub fn branch_target(&self) -> Option<u16> {
+        match self.mnemonic {
+            "jp" | "call" | "jr" => self.operands.iter().find_map(|operand| match operand {
+                Operand::Immediate16(target) | Operand::Relative { target, .. } => Some(*target),
+                _ => None,
+            }),
+            "rst" => match self.operands[0] {
+                Operand::Vector(vector) => Some(vector as u16),
+                _ => None,
+            },
+            _ => None,
+        }
+    }
+
+    pub fn is_call(&self) -> bool {
+        self.mnemonic == "call" || self.mnemonic == "rst"
+    }
+
+    pub fn is_return(&self) -> bool {
+        self.mnemonic == "ret" || self.mnemonic == "reti"
+    }
+
+    // RGBDS syntax with a custom printer for operands, which is how symbol
+    // names replace raw addresses.
+    pub fn format_with<F: Fn(&Operand) -> Option<String>>(&self, operand_name: F) -> String {
+        let mut text = self.mnemonic.to_string();
+        for (index, operand) in self.operands.iter().enumerate() {
+            text.push_str(if index == 0 { " " } else { ", " });
+            if let Operand::SpOffset(offset) = operand {
+                if self.mnemonic == "ld" {
+                    let sign = if *offset < 0 { '-' } else { '+' };
+                    text.push_str(&format!("sp {} ${:02X}", sign, offset.unsigned_abs()));
+                    continue;
+                }
+            }
+            match operand_name(operand) {
+                Some(name) => text.push_str(&name),
+                None => text.push_str(&operand.to_string()),
+            }
+        }
+        text
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format_with(|_| None))
+    }
+}
+
+// Decodes the instruction at address, fetching bytes through read so the
+// same code works on a live memory map and on a ROM image.
+pub fn decode<F: Fn(u16) -> u8>(read: F, address: u16) -> Instruction {
+    let opcode = read(address);
+    let byte = |offset: u16| read(address.wrapping_add(offset));
+    let word = || ((byte(2) as u16) << 8) | byte(1) as u16;
+    let relative = || {
+        let offset = byte(1) as i8;
+        Operand::Relative {
+            offset,
+            target: address.wrapping_add(2).wrapping_add(offset as u16),
+        }
+    };
+
+    let x = opcode >> 6;
+    let y = ((opcode >> 3) & 0x07) as usize;
+    let z = (opcode & 0x07) as usize;
+    let p = y >> 1;
+    let q = y & 1;
+    // Memory operands cost an extra access
+    let r8_cycles = |index: usize, register: u8, memory: u8| {
+        if index == 6 {
+            memory
+        } else {
+            register
+        }
+    };
+
+    use Operand::*;
+    let (mnemonic, operands, length, cycles, cycles_not_taken): (
+        &'static str,
+        Vec<Operand>,
+        u16,
+        u8,
+        Option<u8>,
+    ) = match (x, z) {
+        (0, 0) => match y {
+            0 => ("nop", vec![], 1, 4, None),
+            1 => ("ld", vec![Address(word()), Register("sp")], 3, 20, None),
+            2 => ("stop", vec![], 2, 4, None),
+            3 => ("jr", vec![relative()], 2, 12, None),
+            _ => (
+                "jr",
+                vec![Condition(CONDITIONS[y - 4]), relative()],
+                2,
+                12,
+                Some(8),
+            ),
+        },
+        (0, 1) if q == 0 => (
+            "ld",
+            vec![Register(R16[p]), Immediate16(word())],
+            3,
+            12,
+            None,
+        ),
+        (0, 1) => ("add", vec![Register("hl"), Register(R16[p])], 1, 8, None),
+        (0, 2) if q == 0 => (
+            "ld",
+            vec![Register(R16_MEMORY[p]), Register("a")],
+            1,
+            8,
+            None,
+        ),
+        (0, 2) => (
+            "ld",
+            vec![Register("a"), Register(R16_MEMORY[p])],
+            1,
+            8,
+            None,
+        ),
+        (0, 3) => (
+            if q == 0 { "inc" } else { "dec" },
+            vec![Register(R16[p])],
+            1,
+            8,
+            None,
+        ),
+        (0, 4) => ("inc", vec![Register(R8[y])], 1, r8_cycles(y, 4, 12), None),
+        (0, 5) => ("dec", vec![Register(R8[y])], 1, r8_cycles(y, 4, 12), None),
+        (0, 6) => (
+            "ld",
+            vec![Register(R8[y]), Immediate8(byte(1))],
+            2,
+            r8_cycles(y, 8, 12),
+            None,
+        ),
+        (0, _) => (ACCUMULATOR[y], vec![], 1, 4, None),
+        (1, 6) if y == 6 => ("halt", vec![], 1, 4, None),
+        (1, _) => (
+            "ld",
+            vec![Register(R8[y]), Register(R8[z])],
+            1,
+            if y == 6 || z == 6 { 8 } else { 4 },
+            None,
+        ),
+        (2, _) => (
+            ALU[y],
+            vec![Register("a"), Register(R8[z])],
+            1,
+            r8_cycles(z, 4, 8),
+            None,
+        ),
+        (3, 0) => match y {
+            0..=3 => ("ret", vec![Condition(CONDITIONS[y])], 1, 20, Some(8)),
+            4 => (
+                "ldh",
+                vec![HighAddress(byte(1)), Register("a")],
+                2,
+                12,
+                None,
+            ),
+            5 => (
+                "add",
+                vec![Register("sp"), SpOffset(byte(1) as i8)],
+                2,
+                16,
+                None,
+            ),
+            6 => (
+                "ldh",
+                vec![Register("a"), HighAddress(byte(1))],
+                2,
+                12,
+                None,
+            ),
+            _ => (
+                "ld",
+                vec![Register("hl"), SpOffset(byte(1) as i8)],
+                2,
+                12,
+                None,
+            ),
+        },
+        (3, 1) if q == 0 => ("pop", vec![Register(R16_STACK[p])], 1, 12, None),
+        (3, 1) => match p {
+            0 => ("ret", vec![], 1, 16, None),
+            1 => ("reti", vec![], 1, 16, None),
+            2 => ("jp", vec![Register("hl")], 1, 4, None),
+            _ => ("ld", vec![Register("sp"), Register("hl")], 1, 8, None),
+        },
+        (3, 2) => match y {
+            0..=3 => (
+                "jp",
+                vec![Condition(CONDITIONS[y]), Immediate16(word())],
+                3,
+                16,
+                Some(12),
+            ),
+            4 => ("ldh", vec![Register("[c]"), Register("a")], 1, 8, None),
+            5 => ("ld", vec![Address(word()), Register("a")], 3, 16, None),
+            6 => ("ldh", vec![Register("a"), Register("[c]")], 1, 8, None),
+            _ => ("ld", vec![Register("a"), Address(word())], 3, 16, None),
+        },
+        (3, 3) => match y {
+            0 => ("jp", vec![Immediate16(word())], 3, 16, None),
+            1 => return decode_prefixed(address, byte(1)),
+            6 => ("di", vec![], 1, 4, None),
+            7 => ("ei", vec![], 1, 4, None),
+            _ => ("db", vec![Immediate8(opcode)], 1, 4, None),
+        },
+        (3, 4) if y < 4 => (
+            "call",
+            vec![Condition(CONDITIONS[y]), Immediate16(word())],
+            3,
+            24,
+            Some(12),
+        ),
+        (3, 5) if q == 0 => ("push", vec![Register(R16_STACK[p])], 1, 16, None),
+        (3, 5) if p == 0 => ("call", vec![Immediate16(word())], 3, 24, None),
+        (3, 6) => (ALU[y], vec![Register("a"), Immediate8(byte(1))], 2, 8, None),
+        (3, 7) => ("rst", vec![Vector((y * 8) as u8)], 1, 16, None),
+        // The holes in the opcode table
+        _ => ("db", vec![Immediate8(opcode)], 1, 4, None),
+    };
+
+    Instruction {
+        address,
+        bytes: (0..length).map(byte).collect(),
+        mnemonic,
+        operands,
+        cycles,
+        cycles_not_taken,
+    }
+}
+
+fn decode_prefixed(address: u16, opcode: u8) -> Instruction {
+    let y = ((opcode >> 3) & 0x07) as usize;
+    let z = (opcode & 0x07) as usize;
+    let register = Operand::Register(R8[z]);
+
+    let (mnemonic, operands) = match opcode >> 6 {
+        0 => (ROTATE[y], vec![register]),
+        1 => ("bit", vec![Operand::Bit(y as u8), register]),
+        2 => ("res", vec![Operand::Bit(y as u8), register]),
+        _ => ("set", vec![Operand::Bit(y as u8), register]),
+    };
+    let cycles = match (opcode >> 6, z) {
+        (1, 6) => 12,
+        (_, 6) => 16,
+        _ => 8,
+    };
+
+    Instruction {
+        address,
+        bytes: vec![CB_PREFIX, opcode],
+        mnemonic,
+        operands,
+        cycles,
+        cycles_not_taken: None,
+    }
+}
+
+// Decodes consecutive instructions from start until end (exclusive)
+pub fn disassemble<F: Fn(u16) -> u8>(read: F, start: u16, end: u16) -> Vec<Instruction> {
+    let mut instructions = Vec::new();
+    let mut address = start as u32;
+    while address < end as u32 {
+        let instruction = decode(&read, address as u16);
+        address += instruction.length() as u32;
+        instructions.push(instruction);
+    }
+    instructions
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,138 @@
+use super::*;
+
+fn decode_bytes(bytes: &[u8]) -> Instruction {
+    let bytes = bytes.to_vec();
+    decode(
+        move |address| bytes.get(address as usize).copied().unwrap_or(0),
+        0,
+    )
+}
+
+fn text(bytes: &[u8]) -> String {
+    decode_bytes(bytes).to_string()
+}
+
+#[test]
+fn loads() {
+    assert_eq!(text(&[0x00]), "nop");
+    assert_eq!(text(&[0x3E, 0x12]), "ld a, $12");
+    assert_eq!(text(&[0x21, 0x34, 0x12]), "ld hl, $1234");
+    assert_eq!(text(&[0x7E]), "ld a, [hl]");
+    assert_eq!(text(&[0x22]), "ld [hl+], a");
+    assert_eq!(text(&[0x3A]), "ld a, [hl-]");
+    assert_eq!(text(&[0x08, 0x00, 0xC0]), "ld [$C000], sp");
+    assert_eq!(text(&[0xEA, 0x00, 0xD0]), "ld [$D000], a");
+    assert_eq!(text(&[0xE0, 0x40]), "ldh [$FF40], a");
+    assert_eq!(text(&[0xF2]), "ldh a, [c]");
+    assert_eq!(text(&[0xF8, 0xFE]), "ld hl, sp - $02");
+    assert_eq!(text(&[0xF8, 0x05]), "ld hl, sp + $05");
+    assert_eq!(text(&[0xE8, 0x80]), "add sp, -$80");
+    assert_eq!(text(&[0xF5]), "push af");
+}
+
+#[test]
+fn arithmetic() {
+    assert_eq!(text(&[0x80]), "add a, b");
+    assert_eq!(text(&[0xAF]), "xor a, a");
+    assert_eq!(text(&[0xFE, 0x90]), "cp a, $90");
+    assert_eq!(text(&[0x09]), "add hl, bc");
+    assert_eq!(text(&[0x34]), "inc [hl]");
+    assert_eq!(text(&[0x1B]), "dec de");
+    assert_eq!(text(&[0x27]), "daa");
+}
+
+#[test]
+fn control_flow() {
+    let bytes = [0x00, 0x00, 0x18, 0xFC];
+    let instruction = decode(|address| bytes[address as usize], 2);
+    assert_eq!(instruction.to_string(), "jr $0000");
+    assert_eq!(instruction.branch_target(), Some(0x0000));
+
+    assert_eq!(text(&[0xC3, 0x50, 0x01]), "jp $0150");
+    assert_eq!(text(&[0xE9]), "jp hl");
+    assert_eq!(text(&[0xCC, 0x00, 0x40]), "call z, $4000");
+    assert_eq!(text(&[0xD0]), "ret nc");
+    assert_eq!(text(&[0xFF]), "rst $38");
+    assert_eq!(decode_bytes(&[0xFF]).branch_target(), Some(0x38));
+    assert!(decode_bytes(&[0xD9]).is_return());
+    assert!(decode_bytes(&[0xCD, 0, 0]).is_call());
+}
+
+#[test]
+fn prefixed() {
+    assert_eq!(text(&[0xCB, 0x37]), "swap a");
+    assert_eq!(text(&[0xCB, 0x7C]), "bit 7, h");
+    assert_eq!(text(&[0xCB, 0x86]), "res 0, [hl]");
+    assert_eq!(text(&[0xCB, 0xFF]), "set 7, a");
+
+    let instruction = decode_bytes(&[0xCB, 0x46]);
+    assert_eq!(instruction.length(), 2);
+    assert_eq!(instruction.cycles, 12);
+    assert_eq!(decode_bytes(&[0xCB, 0x06]).cycles, 16);
+}
+
+#[test]
+fn holes_are_data() {
+    for opcode in [
+        0xD3, 0xDB, 0xDD, 0xE3, 0xE4, 0xEB, 0xEC, 0xED, 0xF4, 0xFC, 0xFD,
+    ]
+    .iter()
+    {
+        let instruction = decode_bytes(&[*opcode]);
+        assert_eq!(instruction.mnemonic, "db");
+        assert_eq!(instruction.length(), 1);
+    }
+    assert_eq!(text(&[0xD3]), "db $D3");
+}
+
+#[test]
+fn lengths_and_cycles() {
+    let cases: [(&[u8], u16, u8, Option<u8>); 10] = [
+        (&[0x00], 1, 4, None),
+        (&[0x10, 0x00], 2, 4, None),
+        (&[0x20, 0x00], 2, 12, Some(8)),
+        (&[0x46], 1, 8, None),
+        (&[0x36, 0x00], 2, 12, None),
+        (&[0xC0], 1, 20, Some(8)),
+        (&[0xC2, 0, 0], 3, 16, Some(12)),
+        (&[0xC4, 0, 0], 3, 24, Some(12)),
+        (&[0xCD, 0, 0], 3, 24, None),
+        (&[0xF1], 1, 12, None),
+    ];
+    for (bytes, length, cycles, not_taken) in cases.iter() {
+        let instruction = decode_bytes(bytes);
+        assert_eq!(instruction.length(), *length, "{}", instruction);
+        assert_eq!(instruction.cycles, *cycles, "{}", instruction);
+        assert_eq!(instruction.cycles_not_taken, *not_taken, "{}", instruction);
+    }
+}
+
+#[test]
+fn every_opcode_decodes() {
+    for opcode in 0..=255u8 {
+        let instruction = decode_bytes(&[opcode, 0x00, 0x00]);
+        assert!(instruction.length() >= 1 && instruction.length() <= 3);
+        assert_eq!(instruction.bytes[0], opcode);
+    }
+}
+
+#[test]
+fn sequence() {
+    let bytes = [0x3E, 0x01, 0xCB, 0x37, 0xC9];
+    let instructions = disassemble(|address| bytes[address as usize], 0, 5);
+
+    let addresses: Vec<u16> = instructions.iter().map(|i| i.address).collect();
+    assert_eq!(addresses, vec![0, 2, 4]);
+    assert_eq!(instructions[2].next_address(), 5);
+}
+
+#[test]
+fn custom_operand_names() {
+    let instruction = decode_bytes(&[0xCD, 0x50, 0x01]);
+
+    let text = instruction.format_with(|operand| match operand {
+        Operand::Immediate16(0x0150) => Some("Main".to_string()),
+        _ => None,
+    });
+    assert_eq!(text, "call Main");
+}
@@ -0,0 +1,5 @@
+// SDL side of the emulator, only used by the main binary
+
+pub mod console;
+pub mod input;
+pub mod video;
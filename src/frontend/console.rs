@@ -0,0 +1,37 @@
+use std::io::{self, BufRead, Write};
+use std::sync::mpsc::{channel, Receiver};
+use std::thread;
+
+// Debugger commands typed on stdin. A thread blocks on the terminal so the
+// window keeps handling events while the emulation is paused.
+pub struct Console {
+    lines: Receiver<String>,
+}
+
+impl Console {
+    pub fn spawn() -> Console {
+        let (sender, lines) = channel();
+        thread::spawn(move || {
+            for line in io::stdin().lock().lines() {
+                let sent = match line {
+                    Ok(line) => sender.send(line).is_ok(),
+                    Err(_) => false,
+                };
+                if !sent {
+                    break;
+                }
+            }
+        });
+        Console { lines }
+    }
+
+    pub fn prompt(&self) {
+        print!("(debug) ");
+        let _ = io::stdout().flush();
+    }
+
+    // Next line typed, without blocking
+    pub fn try_line(&self) -> Option<String> {
+        self.lines.try_recv().ok()
+    }
+}
@@ -0,0 +1,106 @@
+use gba::bindings::Bindings;
+use gba::joypad::Button;
+use sdl2::controller::{Button as ControllerButton, GameController};
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::GameControllerSubsystem;
+use std::collections::HashMap;
+
+// Turns keyboard and game controller events into a joypad button mask
+pub struct Input {
+    keys: HashMap<Keycode, Button>,
+    buttons: HashMap<ControllerButton, Button>,
+    subsystem: GameControllerSubsystem,
+    controllers: Vec<GameController>,
+    keyboard: u8,
+    controller: u8,
+}
+
+impl Input {
+    // Keys in hotkeys are taken by the frontend and cannot be bound
+    pub fn new(
+        subsystem: GameControllerSubsystem,
+        bindings: &Bindings,
+        hotkeys: &[Keycode],
+    ) -> Result<Input, String> {
+        let mut keys = HashMap::new();
+        for (name, button) in bindings.keys.iter() {
+            let key = Keycode::from_name(name).ok_or_else(|| format!("unknown key '{}'", name))?;
+            if hotkeys.contains(&key) {
+                return Err(format!(
+                    "key '{}' is a hotkey and cannot be bound to {:?}",
+                    name, button
+                ));
+            }
+            keys.insert(key, *button);
+        }
+
+        let mut buttons = HashMap::new();
+        for (name, button) in bindings.controller.iter() {
+            let controller_button = ControllerButton::from_string(name)
+                .ok_or_else(|| format!("unknown controller button '{}'", name))?;
+            buttons.insert(controller_button, *button);
+        }
+
+        Ok(Input {
+            keys,
+            buttons,
+            subsystem,
+            controllers: Vec::new(),
+            keyboard: 0,
+            controller: 0,
+        })
+    }
+
+    // Currently pressed buttons, see Button::mask
+    pub fn pressed(&self) -> u8 {
+        self.keyboard | self.controller
+    }
+
+    pub fn handle_event(&mut self, event: &Event) {
+        match event {
+            Event::KeyDown {
+                keycode: Some(key),
+                repeat: false,
+                ..
+            } => {
+                if let Some(button) = self.keys.get(key) {
+                    self.keyboard |= button.mask();
+                }
+            }
+            Event::KeyUp {
+                keycode: Some(key), ..
+            } => {
+                if let Some(button) = self.keys.get(key) {
+                    self.keyboard &= !button.mask();
+                }
+            }
+            Event::ControllerButtonDown { button, .. } => {
+                if let Some(button) = self.buttons.get(button) {
+                    self.controller |= button.mask();
+                }
+            }
+            Event::ControllerButtonUp { button, .. } => {
+                if let Some(button) = self.buttons.get(button) {
+                    self.controller &= !button.mask();
+                }
+            }
+            // Also sent at startup for controllers that are already plugged in
+            Event::ControllerDeviceAdded { which, .. } => match self.subsystem.open(*which) {
+                Ok(controller) => {
+                    println!("controller connected: {}", controller.name());
+                    self.controllers.push(controller);
+                }
+                Err(e) => eprintln!("could not open controller {}: {}", which, e),
+            },
+            Event::ControllerDeviceRemoved { which, .. } => {
+                self.controllers
+                    .retain(|controller| controller.instance_id() != *which);
+                if self.controllers.is_empty() {
+                    self.controller = 0;
+                }
+            }
+            _ => {}
+        }
+    }
+}
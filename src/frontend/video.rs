@@ -0,0 +1,179 @@
+use gba::screen::{Framebuffer, SCREEN_HEIGHT, SCREEN_WIDTH};
+use gba::vram::View;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::Rect;
+use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::video::{Window, WindowContext};
+use sdl2::VideoSubsystem;
+
+// Opens a window of scale times the screen size. The picture keeps whole
+// multiples of 160x144 when the window is resized, letterboxing the rest.
+pub fn open_window(
+    video: &VideoSubsystem,
+    title: &str,
+    scale: u32,
+    vsync: bool,
+) -> Result<Canvas<Window>, String> {
+    let window = video
+        .window(
+            title,
+            SCREEN_WIDTH as u32 * scale,
+            SCREEN_HEIGHT as u32 * scale,
+        )
+        .position_centered()
+        .resizable()
+        .build()
+        .map_err(|e| e.to_string())?;
+
+    let mut builder = window.into_canvas().accelerated();
+    if vsync {
+        builder = builder.present_vsync();
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+// Draws framebuffers into a window through a streaming texture
+pub struct Display<'t> {
+    canvas: Canvas<Window>,
+    texture: Texture<'t>,
+}
+
+impl<'t> Display<'t> {
+    pub fn new(
+        canvas: Canvas<Window>,
+        creator: &'t TextureCreator<WindowContext>,
+    ) -> Result<Display<'t>, String> {
+        let texture = creator
+            .create_texture_streaming(
+                PixelFormatEnum::RGB24,
+                SCREEN_WIDTH as u32,
+                SCREEN_HEIGHT as u32,
+            )
+            .map_err(|e| e.to_string())?;
+        Ok(Display { canvas, texture })
+    }
+
+    // Refresh rate of the display the window is on in Hz, 0 when unknown
+    pub fn refresh_rate(&self) -> Result<i32, String> {
+        Ok(self.canvas.window().display_mode()?.refresh_rate)
+    }
+
+    // The whole multiple of 160x144 the window currently shows
+    pub fn scale(&self) -> Result<u32, String> {
+        let (width, height) = self.canvas.output_size()?;
+        Ok((width / SCREEN_WIDTH as u32)
+            .min(height / SCREEN_HEIGHT as u32)
+            .max(1))
+    }
+
+    pub fn present(&mut self, framebuffer: &Framebuffer) -> Result<(), String> {
+        self.texture
+            .update(None, &framebuffer.to_rgb(), SCREEN_WIDTH * 3)
+            .map_err(|e| e.to_string())?;
+        let (width, height) = self.canvas.output_size()?;
+        let scale = self.scale()?;
+        let target_width = SCREEN_WIDTH as u32 * scale;
+        let target_height = SCREEN_HEIGHT as u32 * scale;
+        let target = Rect::new(
+            (width as i32 - target_width as i32) / 2,
+            (height as i32 - target_height as i32) / 2,
+            target_width,
+            target_height,
+        );
+
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+        self.canvas.clear();
+        self.canvas.copy(&self.texture, None, target)?;
+        self.canvas.present();
+        Ok(())
+    }
+}
+
+// A window for a debug view, opened hidden. Its texture has to come from its
+// own canvas, so the caller makes the creator and hands it to ViewWindow::new.
+pub fn open_view_window(video: &VideoSubsystem, title: &str) -> Result<Canvas<Window>, String> {
+    let window = video
+        .window(title, 1, 1)
+        .resizable()
+        .hidden()
+        .build()
+        .map_err(|e| e.to_string())?;
+    window
+        .into_canvas()
+        .accelerated()
+        .build()
+        .map_err(|e| e.to_string())
+}
+
+// Shows a debug view stretched to fill the window, through a streaming
+// texture that is only recreated when the view changes size
+pub struct ViewWindow<'t> {
+    canvas: Canvas<Window>,
+    creator: &'t TextureCreator<WindowContext>,
+    texture: Option<Texture<'t>>,
+    visible: bool,
+}
+
+impl<'t> ViewWindow<'t> {
+    pub fn new(
+        canvas: Canvas<Window>,
+        creator: &'t TextureCreator<WindowContext>,
+    ) -> ViewWindow<'t> {
+        ViewWindow {
+            canvas,
+            creator,
+            texture: None,
+            visible: false,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    pub fn visible(&self) -> bool {
+        self.visible
+    }
+
+    // Opens the window at the size of view
+    pub fn show(&mut self, view: &View) -> Result<(), String> {
+        self.canvas
+            .window_mut()
+            .set_size(view.width as u32, view.height as u32)
+            .map_err(|e| e.to_string())?;
+        self.canvas.window_mut().show();
+        self.visible = true;
+        self.present(view)
+    }
+
+    pub fn hide(&mut self) {
+        self.canvas.window_mut().hide();
+        self.visible = false;
+    }
+
+    pub fn present(&mut self, view: &View) -> Result<(), String> {
+        let size = (view.width as u32, view.height as u32);
+        let resized = match self.texture.as_ref() {
+            Some(texture) => {
+                let query = texture.query();
+                (query.width, query.height) != size
+            }
+            None => true,
+        };
+        if resized {
+            self.texture = Some(
+                self.creator
+                    .create_texture_streaming(PixelFormatEnum::RGB24, size.0, size.1)
+                    .map_err(|e| e.to_string())?,
+            );
+        }
+        let texture = self.texture.as_mut().unwrap();
+        texture
+            .update(None, &view.rgb, view.width * 3)
+            .map_err(|e| e.to_string())?;
+        self.canvas.clear();
+        self.canvas.copy(texture, None, None)?;
+        self.canvas.present();
+        Ok(())
+    }
+}
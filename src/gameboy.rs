@@ -0,0 +1,333 @@
+// The whole console: CPU, memory map and peripherals behind one type, which
+// is what frontends are expected to drive.
+
+use super::cartridge::Header;
+use super::cpu::Cpu;
+use super::disasm::decode;
+use super::image::crc32;
+use super::memory::MemoryMap;
+use super::model::Model;
+use super::pacing::CYCLES_PER_FRAME;
+use super::profiler::Profiler;
+use super::savestate::{
+    read_sections, StateHeader, StateReader, StateWriter, SECTION_APU, SECTION_CPU, SECTION_JOYPAD,
+    SECTION_MEMORY, SECTION_PALETTE, SECTION_SERIAL, STATE_VERSION,
+};
+use super::screen::Framebuffer;
+use std::rc::Rc;
+
+const MEMORY_SIZE: usize = 0x10000;
+// FNV-1a, for state hashes
+const FNV_OFFSET: u64 = 0xCBF2_9CE4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01B3;
+
+pub struct GameBoy {
+    model: Model,
+    memory: Rc<MemoryMap>,
+    cpu: Cpu<Rc<MemoryMap>>,
+    rom: Vec<u8>,
+    rom_crc: u32,
+    header: Option<Header>,
+    boot_rom: Option<Vec<u8>>,
+    // Stays blank until there is a PPU drawing into it
+    framebuffer: Framebuffer,
+    profiler: Option<Profiler>,
+}
+
+impl GameBoy {
+    // A console with no cartridge inserted, already reset
+    pub fn new(model: Model) -> GameBoy {
+        let memory = Rc::new(MemoryMap::with_io(MEMORY_SIZE));
+        memory.apu.borrow_mut().set_sample_output(true);
+        let mut gameboy = GameBoy {
+            model,
+            cpu: Cpu::new(memory.clone()),
+            memory,
+            rom: Vec::new(),
+            rom_crc: crc32(&[]),
+            header: None,
+            boot_rom: None,
+            framebuffer: Framebuffer::new(),
+            profiler: None,
+        };
+        gameboy.reset();
+        gameboy
+    }
+
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
+    // The bus and peripherals, e.g. to plug in a serial link or set up the
+    // APU outputs. Settings made here survive a reset.
+    pub fn memory(&self) -> &MemoryMap {
+        &self.memory
+    }
+
+    pub fn cpu(&self) -> &Cpu<Rc<MemoryMap>> {
+        &self.cpu
+    }
+
+    pub fn cpu_mut(&mut self) -> &mut Cpu<Rc<MemoryMap>> {
+        &mut self.cpu
+    }
+
+    pub fn header(&self) -> Option<&Header> {
+        self.header.as_ref()
+    }
+
+    pub fn rom(&self) -> &[u8] {
+        &self.rom
+    }
+
+    // Inserts a cartridge and resets the console
+    pub fn load_rom(&mut self, rom: Vec<u8>) -> Result<(), String> {
+        self.header = Some(Header::parse(&rom)?);
+        self.rom_crc = crc32(&rom);
+        self.rom = rom;
+        self.reset();
+        Ok(())
+    }
+
+    // Boot ROM to run on the next reset. Without one the console starts at
+    // 0x0100 with the registers the boot ROM would have left.
+    pub fn set_boot_rom(&mut self, boot_rom: Option<Vec<u8>>) -> Result<(), String> {
+        if let Some(boot_rom) = boot_rom.as_ref() {
+            if boot_rom.len() != self.model.boot_rom_size() {
+                return Err(format!(
+                    "boot ROM is {} bytes, a {} boot ROM is {}",
+                    boot_rom.len(),
+                    self.model.name(),
+                    self.model.boot_rom_size()
+                ));
+            }
+        }
+        self.boot_rom = boot_rom;
+        Ok(())
+    }
+
+    pub fn boot_rom(&self) -> Option<&[u8]> {
+        self.boot_rom.as_deref()
+    }
+
+    // Power cycles the console, keeping the cartridge
+    pub fn reset(&mut self) {
+        self.memory.reset();
+        self.memory.load_rom(&self.rom);
+        let trace = self.cpu.set_trace(None);
+        self.cpu = Cpu::new(self.memory.clone());
+        self.cpu.set_trace(trace);
+        match self.boot_rom.as_ref() {
+            Some(boot_rom) => self.memory.load_boot_rom(boot_rom.clone()),
+            None => self.cpu.skip_boot(self.model),
+        }
+        self.framebuffer.clear();
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.clear_stack();
+        }
+    }
+
+    // CRC-32 of the loaded ROM, identifying it in save states
+    pub fn rom_crc(&self) -> u32 {
+        self.rom_crc
+    }
+
+    // Snapshot of the whole machine, see savestate for the format
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        StateHeader {
+            version: STATE_VERSION,
+            emulator_version: env!("CARGO_PKG_VERSION").to_string(),
+            model: self.model,
+            rom_crc: self.rom_crc,
+            title: self
+                .header
+                .as_ref()
+                .map(|header| header.title.clone())
+                .unwrap_or_default(),
+            thumbnail: self.framebuffer.clone(),
+        }
+        .write(&mut writer);
+
+        self.write_sections(&mut writer);
+        writer.into_bytes()
+    }
+
+    fn write_sections(&self, writer: &mut StateWriter) {
+        let memory = &self.memory;
+        writer.section(SECTION_CPU, |writer| self.cpu.save_state(writer));
+        writer.section(SECTION_MEMORY, |writer| memory.save_state(writer));
+        writer.section(SECTION_APU, |writer| memory.apu.borrow().save_state(writer));
+        writer.section(SECTION_JOYPAD, |writer| {
+            memory.joypad.borrow().save_state(writer)
+        });
+        writer.section(SECTION_SERIAL, |writer| {
+            memory.serial.borrow().save_state(writer)
+        });
+        writer.section(SECTION_PALETTE, |writer| {
+            memory.palettes.borrow().save_state(writer)
+        });
+    }
+
+    // 64 bit hash of everything a save state holds but its header, which
+    // leaves out the version string and thumbnail
+    pub fn state_hash(&self) -> u64 {
+        let mut writer = StateWriter::new();
+        self.write_sections(&mut writer);
+        writer.into_bytes().iter().fold(FNV_OFFSET, |hash, byte| {
+            (hash ^ *byte as u64).wrapping_mul(FNV_PRIME)
+        })
+    }
+
+    // Restores a snapshot made with save_state. A state that is made for
+    // another ROM or model, or that fails to parse or apply, leaves the
+    // console as it was.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<(), String> {
+        let mut reader = StateReader::new(data);
+        let header = StateHeader::read(&mut reader)?;
+        if header.rom_crc != self.rom_crc {
+            return Err(format!(
+                "save state is for '{}' (CRC {:08X}), not the loaded ROM (CRC {:08X})",
+                header.title, header.rom_crc, self.rom_crc
+            ));
+        }
+        if header.model != self.model {
+            return Err(format!(
+                "save state is for the {} model, running as {}",
+                header.model.name(),
+                self.model.name()
+            ));
+        }
+        let sections = read_sections(&mut reader)?;
+
+        let snapshot = self.save_state();
+        if let Err(e) = self.apply_state(header, &sections) {
+            // Made by this build a moment ago, so it applies cleanly
+            let mut reader = StateReader::new(&snapshot);
+            let header = StateHeader::read(&mut reader).expect("snapshot header");
+            let sections = read_sections(&mut reader).expect("snapshot sections");
+            self.apply_state(header, &sections)
+                .expect("snapshot sections");
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    fn apply_state(
+        &mut self,
+        header: StateHeader,
+        sections: &std::collections::HashMap<[u8; 4], &[u8]>,
+    ) -> Result<(), String> {
+        self.reset();
+        self.load_sections(sections)?;
+        self.framebuffer = header.thumbnail;
+        Ok(())
+    }
+
+    fn load_sections(
+        &mut self,
+        sections: &std::collections::HashMap<[u8; 4], &[u8]>,
+    ) -> Result<(), String> {
+        let memory = &self.memory;
+        if let Some(data) = sections.get(&SECTION_CPU) {
+            self.cpu.load_state(&mut StateReader::new(data))?;
+        }
+        if let Some(data) = sections.get(&SECTION_MEMORY) {
+            memory.load_state(&mut StateReader::new(data))?;
+        }
+        if let Some(data) = sections.get(&SECTION_APU) {
+            memory
+                .apu
+                .borrow_mut()
+                .load_state(&mut StateReader::new(data))?;
+        }
+        if let Some(data) = sections.get(&SECTION_JOYPAD) {
+            memory
+                .joypad
+                .borrow_mut()
+                .load_state(&mut StateReader::new(data))?;
+        }
+        if let Some(data) = sections.get(&SECTION_SERIAL) {
+            memory
+                .serial
+                .borrow_mut()
+                .load_state(&mut StateReader::new(data))?;
+        }
+        if let Some(data) = sections.get(&SECTION_PALETTE) {
+            memory
+                .palettes
+                .borrow_mut()
+                .load_state(&mut StateReader::new(data))?;
+        }
+        Ok(())
+    }
+
+    // Clock cycles since the last reset
+    pub fn cycles(&self) -> u64 {
+        self.cpu.cycles()
+    }
+
+    // Starts or stops following calls and counting cycles per function,
+    // returning the old profiler
+    pub fn set_profiler(&mut self, profiler: Option<Profiler>) -> Option<Profiler> {
+        std::mem::replace(&mut self.profiler, profiler)
+    }
+
+    pub fn profiler(&self) -> Option<&Profiler> {
+        self.profiler.as_ref()
+    }
+
+    // Executes one instruction and returns the cycles it took
+    pub fn step_instruction(&mut self) -> u32 {
+        // Decoded up front, as the instruction may overwrite itself
+        let profiled = match self.profiler {
+            Some(_) => {
+                let memory = &self.memory;
+                let registers = self.cpu.registers();
+                let instruction = decode(|address| memory.peek(address as usize), registers.pc);
+                Some((instruction, registers))
+            }
+            None => None,
+        };
+
+        let start = self.cpu.cycles();
+        self.cpu.step();
+        let cycles = (self.cpu.cycles() - start) as u32;
+        self.memory.tick(cycles);
+
+        if let (Some(profiler), Some((instruction, before))) = (self.profiler.as_mut(), profiled) {
+            let memory = &self.memory;
+            let after = self.cpu.registers();
+            profiler.record(&instruction, &before, &after, cycles, |address| {
+                memory.bank(address as usize)
+            });
+        }
+        cycles
+    }
+
+    // Runs up to the end of the current video frame
+    pub fn run_frame(&mut self) {
+        let frame = CYCLES_PER_FRAME as u64;
+        let end = (self.cpu.cycles() / frame + 1) * frame;
+        while self.cpu.cycles() < end {
+            self.step_instruction();
+        }
+    }
+
+    pub fn framebuffer(&self) -> &Framebuffer {
+        &self.framebuffer
+    }
+
+    // Audio produced since the last call, stereo at APU_SAMPLE_RATE
+    pub fn audio_samples(&mut self) -> Vec<(f32, f32)> {
+        self.memory.apu.borrow_mut().take_samples()
+    }
+
+    // Currently pressed buttons, see joypad::Button::mask
+    pub fn set_buttons(&mut self, pressed: u8) {
+        self.memory.set_buttons(pressed);
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,161 @@
+use super::*;
+use crate::joypad::{Button, P1};
+use crate::pacing::CYCLES_PER_FRAME;
+
+// A cartridge that loads B and then pushes BC over and over, slow enough to
+// run a couple of frames without reaching the end of the ROM
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0xC5; 0x8000];
+    rom[0x0100] = 0x06;
+    rom[0x0101] = 0x42;
+    rom
+}
+
+#[test]
+fn starts_after_boot() {
+    let mut gameboy = GameBoy::new(Model::Dmg);
+    gameboy.load_rom(rom()).unwrap();
+
+    assert_eq!(gameboy.cpu().pc(), 0x0100);
+    assert_eq!(gameboy.cpu().registers().a, 0x01);
+    assert_eq!(gameboy.step_instruction(), 8);
+    assert_eq!(gameboy.cpu().registers().b, 0x42);
+    assert_eq!(gameboy.cycles(), 8);
+}
+
+#[test]
+fn rejects_bad_rom() {
+    let mut gameboy = GameBoy::new(Model::Dmg);
+
+    assert!(gameboy.load_rom(vec![0; 0x40]).is_err());
+    assert!(gameboy.header().is_none());
+}
+
+// The last instruction of a frame may run a few cycles past the boundary,
+// the next frame still ends on the following one.
+#[test]
+fn frames_end_on_frame_boundaries() {
+    let mut gameboy = GameBoy::new(Model::Dmg);
+    gameboy.load_rom(rom()).unwrap();
+    let frame = CYCLES_PER_FRAME as u64;
+
+    gameboy.run_frame();
+    assert!(gameboy.cycles() >= frame && gameboy.cycles() < frame + 16);
+    gameboy.run_frame();
+    assert!(gameboy.cycles() >= 2 * frame && gameboy.cycles() < 2 * frame + 16);
+    assert_eq!(gameboy.audio_samples().len() as u64, gameboy.cycles() / 4);
+}
+
+#[test]
+fn reset_restarts_cartridge() {
+    let mut gameboy = GameBoy::new(Model::Cgb);
+    gameboy.load_rom(rom()).unwrap();
+    gameboy.run_frame();
+    gameboy.memory().write(0xC000, 0x12);
+
+    gameboy.reset();
+    assert_eq!(gameboy.cycles(), 0);
+    assert_eq!(gameboy.cpu().pc(), 0x0100);
+    assert_eq!(gameboy.cpu().registers().a, 0x11);
+    assert_eq!(gameboy.memory().read(0xC000), 0x00);
+    assert_eq!(gameboy.memory().read(0x0101), 0x42);
+}
+
+#[test]
+fn boot_rom_runs_from_zero() {
+    let mut gameboy = GameBoy::new(Model::Dmg);
+    gameboy.load_rom(rom()).unwrap();
+
+    assert!(gameboy.set_boot_rom(Some(vec![0; 0x900])).is_err());
+    gameboy.set_boot_rom(Some(vec![0x06; 0x100])).unwrap();
+    gameboy.reset();
+    assert_eq!(gameboy.cpu().pc(), 0x0000);
+    assert!(gameboy.memory().boot_rom_mapped());
+}
+
+#[test]
+fn buttons_reach_joypad() {
+    let mut gameboy = GameBoy::new(Model::Dmg);
+
+    gameboy.memory().write(P1, 0x10);
+    gameboy.set_buttons(Button::A.mask());
+    assert_eq!(gameboy.memory().read(P1) & 0x0F, 0x0E);
+}
+
+#[test]
+fn save_state_round_trip() {
+    let mut gameboy = GameBoy::new(Model::Dmg);
+    gameboy.load_rom(rom()).unwrap();
+    gameboy.memory().write(0xFF26, 0x80);
+    gameboy.memory().write(0xFF12, 0xF3);
+    gameboy.memory().write(0xFF14, 0x87);
+    gameboy.run_frame();
+    gameboy.audio_samples();
+    let state = gameboy.save_state();
+    let registers = gameboy.cpu().registers();
+    let cycles = gameboy.cycles();
+
+    gameboy.run_frame();
+    let expected_frame = gameboy.audio_samples();
+    let expected_stack = gameboy.memory().read(0xF000);
+
+    gameboy.load_state(&state).unwrap();
+    assert_eq!(gameboy.cpu().registers(), registers);
+    assert_eq!(gameboy.cycles(), cycles);
+    gameboy.run_frame();
+    assert_eq!(gameboy.audio_samples(), expected_frame);
+    assert_eq!(gameboy.memory().read(0xF000), expected_stack);
+}
+
+#[test]
+fn state_for_other_rom_refused() {
+    let mut gameboy = GameBoy::new(Model::Dmg);
+    gameboy.load_rom(rom()).unwrap();
+    let state = gameboy.save_state();
+
+    let mut other = rom();
+    other[0x0134] = b'X';
+    gameboy.load_rom(other).unwrap();
+    let error = gameboy.load_state(&state).unwrap_err();
+    assert!(error.contains("not the loaded ROM"), "{}", error);
+
+    let mut cgb = GameBoy::new(Model::Cgb);
+    cgb.load_rom(rom()).unwrap();
+    assert!(cgb.load_state(&state).is_err());
+}
+
+#[test]
+fn truncated_state_leaves_console_alone() {
+    let mut gameboy = GameBoy::new(Model::Dmg);
+    gameboy.load_rom(rom()).unwrap();
+    gameboy.run_frame();
+    let state = gameboy.save_state();
+    let cycles = gameboy.cycles();
+
+    assert!(gameboy.load_state(&state[..state.len() - 10]).is_err());
+    assert_eq!(gameboy.cycles(), cycles);
+}
+
+#[test]
+fn corrupt_section_leaves_console_alone() {
+    let mut gameboy = GameBoy::new(Model::Dmg);
+    gameboy.load_rom(rom()).unwrap();
+    gameboy.run_frame();
+    let mut state = gameboy.save_state();
+    gameboy.run_frame();
+    let registers = gameboy.cpu().registers();
+    let cycles = gameboy.cycles();
+    let stack = gameboy.memory().read(0xF000);
+
+    // The serial section is applied after the CPU, memory and APU ones
+    let tag = state
+        .windows(4)
+        .rposition(|window| window == SECTION_SERIAL)
+        .unwrap();
+    state[tag + 8] = 0xFF;
+    let error = gameboy.load_state(&state).unwrap_err();
+    assert!(error.contains("serial section version"), "{}", error);
+    assert_eq!(gameboy.cpu().registers(), registers);
+    assert_eq!(gameboy.cycles(), cycles);
+    assert_eq!(gameboy.memory().read(0xF000), stack);
+}
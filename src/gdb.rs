@@ -0,0 +1,585 @@
+// GDB remote serial protocol stub, so gdb and other RSP clients can debug the
+// emulated CPU over a local TCP port.
+//
+// Packets look like $data#checksum with the checksum being the two digit hex
+// sum of the data bytes. Each side acknowledges a packet with + or asks for
+// it again with -, and a lone 0x03 byte interrupts a running target.
+//
+// The registers are sent in the order of TARGET_XML: the 8 bit registers
+// a f b c d e h l with the flags packed into f, then sp and pc as little
+// endian 16 bit values. Upstream gdb has no SM83 architecture, so a client
+// needs to accept the target description on its own, as SM83 aware gdb
+// builds and most other RSP front ends do.
+//
+// Breakpoints and watchpoints are the debugger's, so they are shared with the
+// command line debugger of the same session.
+
+use super::debugger::Debugger;
+use super::gameboy::GameBoy;
+use super::watchpoint::{WatchAction, WatchKind, Watchpoint};
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream, ToSocketAddrs};
+
+pub const TARGET_XML: &str = r#"<?xml version="1.0"?>
+<!DOCTYPE target SYSTEM "gdb-target.dtd">
+<target version="1.0">
+  <feature name="org.gnu.gdb.sm83.core">
+    <flags id="sm83_flags" size="1">
+      <field name="c" start="4" end="4"/>
+      <field name="h" start="5" end="5"/>
+      <field name="n" start="6" end="6"/>
+      <field name="z" start="7" end="7"/>
+    </flags>
+    <reg name="a" bitsize="8" regnum="0" type="uint8"/>
+    <reg name="f" bitsize="8" regnum="1" type="sm83_flags"/>
+    <reg name="b" bitsize="8" regnum="2" type="uint8"/>
+    <reg name="c" bitsize="8" regnum="3" type="uint8"/>
+    <reg name="d" bitsize="8" regnum="4" type="uint8"/>
+    <reg name="e" bitsize="8" regnum="5" type="uint8"/>
+    <reg name="h" bitsize="8" regnum="6" type="uint8"/>
+    <reg name="l" bitsize="8" regnum="7" type="uint8"/>
+    <reg name="sp" bitsize="16" regnum="8" type="data_ptr"/>
+    <reg name="pc" bitsize="16" regnum="9" type="code_ptr"/>
+  </feature>
+</target>
+"#;
+
+const PACKET_SIZE: usize = 0x4000;
+const INTERRUPT: u8 = 0x03;
+const ESCAPE: u8 = b'}';
+
+// Signals of the stop replies
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Event {
+    Packet(String),
+    BadChecksum,
+    // gdb asks for the last reply again
+    Nak,
+    Interrupt,
+}
+
+pub fn checksum(data: &str) -> u8 {
+    data.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte))
+}
+
+// Wraps a reply in $...#checksum, escaping the bytes that would end it early
+pub fn frame(data: &str) -> String {
+    let mut escaped = String::with_capacity(data.len());
+    for c in data.chars() {
+        if c == '$' || c == '#' || c == '}' || c == '*' {
+            escaped.push('}');
+            escaped.push((c as u8 ^ 0x20) as char);
+        } else {
+            escaped.push(c);
+        }
+    }
+    format!("${}#{:02x}", escaped, checksum(&escaped))
+}
+
+// Takes the next complete event off the front of the input, dropping the
+// positive acknowledgements and noise in between
+fn next_event(input: &mut Vec<u8>) -> Option<Event> {
+    loop {
+        match input.first()? {
+            b'$' => break,
+            &INTERRUPT => {
+                input.remove(0);
+                return Some(Event::Interrupt);
+            }
+            b'-' => {
+                input.remove(0);
+                return Some(Event::Nak);
+            }
+            _ => {
+                input.remove(0);
+            }
+        }
+    }
+    let end = input.iter().position(|byte| *byte == b'#')?;
+    if input.len() < end + 3 {
+        return None;
+    }
+    let raw: Vec<u8> = input.drain(..end + 3).collect();
+    let body = &raw[1..end];
+    let expected = std::str::from_utf8(&raw[end + 1..])
+        .ok()
+        .and_then(|digits| u8::from_str_radix(digits, 16).ok());
+    let sum = body.iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+    if expected != Some(sum) {
+        return Some(Event::BadChecksum);
+    }
+
+    let mut data = Vec::with_capacity(body.len());
+    let mut bytes = body.iter();
+    while let Some(byte) = bytes.next() {
+        if *byte == ESCAPE {
+            if let Some(escaped) = bytes.next() {
+                data.push(escaped ^ 0x20);
+            }
+        } else {
+            data.push(*byte);
+        }
+    }
+    Some(Event::Packet(String::from_utf8_lossy(&data).into_owned()))
+}
+
+fn parse_hex(text: &str) -> Result<usize, String> {
+    usize::from_str_radix(text, 16).map_err(|_| format!("invalid hex '{}'", text))
+}
+
+// Works on the bytes, as packets can hold anything the client sent
+fn decode_hex(text: &str) -> Result<Vec<u8>, String> {
+    let bytes = text.as_bytes();
+    if !bytes.len().is_multiple_of(2) {
+        return Err("odd number of hex digits".to_string());
+    }
+    if !bytes.iter().all(u8::is_ascii_hexdigit) {
+        return Err(format!("invalid hex '{}'", text));
+    }
+    Ok(bytes
+        .chunks(2)
+        .map(|pair| (hex_digit(pair[0]) << 4) | hex_digit(pair[1]))
+        .collect())
+}
+
+fn hex_digit(digit: u8) -> u8 {
+    match digit {
+        b'0'..=b'9' => digit - b'0',
+        b'a'..=b'f' => digit - b'a' + 10,
+        _ => digit - b'A' + 10,
+    }
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
+
+// "addr,length" as used by the memory and breakpoint packets
+fn parse_address_length(text: &str) -> Result<(u16, usize), String> {
+    let (address, length) = text
+        .split_once(',')
+        .ok_or_else(|| format!("expected address,length in '{}'", text))?;
+    Ok((parse_hex(address)? as u16, parse_hex(length)?))
+}
+
+fn register_bytes(gameboy: &GameBoy) -> Vec<u8> {
+    let registers = gameboy.cpu().registers();
+    let mut bytes = vec![
+        registers.a,
+        registers.f,
+        registers.b,
+        registers.c,
+        registers.d,
+        registers.e,
+        registers.h,
+        registers.l,
+    ];
+    bytes.extend_from_slice(&registers.sp.to_le_bytes());
+    bytes.extend_from_slice(&registers.pc.to_le_bytes());
+    bytes
+}
+
+fn set_register(gameboy: &mut GameBoy, number: usize, bytes: &[u8]) -> Result<(), String> {
+    let mut registers = gameboy.cpu().registers();
+    let byte = || bytes.first().copied().ok_or("missing value");
+    let word = || match bytes {
+        [low, high, ..] => Ok(u16::from_le_bytes([*low, *high])),
+        _ => Err("missing value"),
+    };
+    match number {
+        0 => registers.a = byte()?,
+        // The low nibble of F always reads as zero
+        1 => registers.f = byte()? & 0xF0,
+        2 => registers.b = byte()?,
+        3 => registers.c = byte()?,
+        4 => registers.d = byte()?,
+        5 => registers.e = byte()?,
+        6 => registers.h = byte()?,
+        7 => registers.l = byte()?,
+        8 => registers.sp = word()?,
+        9 => registers.pc = word()?,
+        _ => return Err(format!("no register {}", number)),
+    }
+    gameboy.cpu_mut().set_registers(&registers);
+    Ok(())
+}
+
+// Answer to qXfer reads: m with more to come, l for the last part
+fn transfer_chunk(data: &str, offset: usize, length: usize) -> String {
+    let start = offset.min(data.len());
+    let end = (start + length).min(data.len());
+    let marker = if end < data.len() { 'm' } else { 'l' };
+    format!("{}{}", marker, &data[start..end])
+}
+
+fn stop_reply(debugger: &Debugger, signal: u8) -> String {
+    match debugger.stop_hits().first() {
+        Some(hit) => {
+            let kind = match hit.kind {
+                WatchKind::Read => "rwatch",
+                _ => "watch",
+            };
+            format!("T{:02x}{}:{:04x};", SIGTRAP, kind, hit.address)
+        }
+        None => format!("S{:02x}", signal),
+    }
+}
+
+// What happened to the connection, for the frontend to report
+#[derive(Debug, Clone, PartialEq)]
+pub enum GdbEvent {
+    Connected(SocketAddr),
+    Disconnected,
+    // A packet that was answered with an error
+    Error { packet: String, message: String },
+}
+
+impl std::fmt::Display for GdbEvent {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            GdbEvent::Connected(address) => write!(f, "gdb connected from {}", address),
+            GdbEvent::Disconnected => write!(f, "gdb disconnected"),
+            GdbEvent::Error { packet, message } => write!(f, "gdb: {}: {}", packet, message),
+        }
+    }
+}
+
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<TcpStream>,
+    input: Vec<u8>,
+    last_reply: String,
+    // gdb sent continue and waits for a stop reply
+    running: bool,
+    events: Vec<GdbEvent>,
+}
+
+impl GdbStub {
+    // Address is usually 127.0.0.1:port; a port of 0 picks a free one
+    pub fn listen<A: ToSocketAddrs>(address: A) -> io::Result<GdbStub> {
+        let listener = TcpListener::bind(address)?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub {
+            listener,
+            client: None,
+            input: Vec::new(),
+            last_reply: String::new(),
+            running: false,
+            events: Vec::new(),
+        })
+    }
+
+    pub fn local_addr(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    pub fn connected(&self) -> bool {
+        self.client.is_some()
+    }
+
+    // Events since the last call
+    pub fn take_events(&mut self) -> Vec<GdbEvent> {
+        std::mem::take(&mut self.events)
+    }
+
+    // Accepts a connection and answers the packets that arrived, without
+    // blocking. Once the debugger stops after a continue it sends the stop
+    // reply. Returns false when gdb killed the target.
+    pub fn poll(&mut self, debugger: &mut Debugger, gameboy: &mut GameBoy) -> io::Result<bool> {
+        if self.client.is_none() {
+            match self.listener.accept() {
+                Ok((stream, address)) => {
+                    stream.set_nodelay(true)?;
+                    stream.set_nonblocking(true)?;
+                    self.events.push(GdbEvent::Connected(address));
+                    self.client = Some(stream);
+                    self.input.clear();
+                    self.running = false;
+                    // gdb expects a halted target when it attaches
+                    debugger.pause();
+                }
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return Ok(true),
+                Err(e) => return Err(e),
+            }
+        }
+
+        if !self.receive() {
+            self.disconnect(debugger, gameboy);
+            return Ok(true);
+        }
+        while let Some(event) = next_event(&mut self.input) {
+            match event {
+                Event::BadChecksum => self.send_raw(b"-")?,
+                Event::Nak => {
+                    let reply = self.last_reply.clone();
+                    self.send_raw(reply.as_bytes())?;
+                }
+                Event::Interrupt => {
+                    if self.running {
+                        debugger.pause();
+                        self.running = false;
+                        self.send(&format!("S{:02x}", SIGINT))?;
+                    }
+                }
+                Event::Packet(packet) => {
+                    self.send_raw(b"+")?;
+                    if packet == "k" {
+                        self.client = None;
+                        return Ok(false);
+                    }
+                    if let Some(reply) = self.handle_packet(&packet, debugger, gameboy) {
+                        self.send(&reply)?;
+                    }
+                    if packet.starts_with('D') {
+                        self.disconnect(debugger, gameboy);
+                        return Ok(true);
+                    }
+                }
+            }
+        }
+
+        if self.running && debugger.paused() {
+            self.running = false;
+            self.send(&stop_reply(debugger, SIGTRAP))?;
+        }
+        Ok(true)
+    }
+
+    // Pulls what arrived on the socket into the input. Returns false when the
+    // connection is gone.
+    fn receive(&mut self) -> bool {
+        let client = match self.client.as_mut() {
+            Some(client) => client,
+            None => return false,
+        };
+        let mut buffer = [0; 1024];
+        loop {
+            match client.read(&mut buffer) {
+                Ok(0) => return false,
+                Ok(read) => self.input.extend_from_slice(&buffer[..read]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return true,
+                Err(_) => return false,
+            }
+        }
+    }
+
+    // The game keeps running without its debugger
+    fn disconnect(&mut self, debugger: &mut Debugger, gameboy: &GameBoy) {
+        if self.client.take().is_some() {
+            self.events.push(GdbEvent::Disconnected);
+        }
+        self.running = false;
+        if debugger.paused() {
+            debugger.resume(gameboy);
+        }
+    }
+
+    fn send_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        let client = match self.client.as_mut() {
+            Some(client) => client,
+            None => return Ok(()),
+        };
+        let mut data = data;
+        while !data.is_empty() {
+            match client.write(data) {
+                Ok(0) => return Err(io::Error::new(ErrorKind::WriteZero, "gdb went away")),
+                Ok(written) => data = &data[written..],
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(e) => return Err(e),
+            }
+        }
+        Ok(())
+    }
+
+    fn send(&mut self, reply: &str) -> io::Result<()> {
+        self.last_reply = frame(reply);
+        let framed = self.last_reply.clone();
+        self.send_raw(framed.as_bytes())
+    }
+
+    // Answers one packet. None means the target was resumed and the reply
+    // is the stop reply sent once it halts again.
+    fn handle_packet(
+        &mut self,
+        packet: &str,
+        debugger: &mut Debugger,
+        gameboy: &mut GameBoy,
+    ) -> Option<String> {
+        match self.answer(packet, debugger, gameboy) {
+            Ok(reply) => reply,
+            Err(message) => {
+                self.events.push(GdbEvent::Error {
+                    packet: packet.to_string(),
+                    message,
+                });
+                Some("E01".to_string())
+            }
+        }
+    }
+
+    fn answer(
+        &mut self,
+        packet: &str,
+        debugger: &mut Debugger,
+        gameboy: &mut GameBoy,
+    ) -> Result<Option<String>, String> {
+        // The command is one ASCII letter; anything else is unsupported
+        let (command, rest) = match packet.as_bytes().first() {
+            Some(byte) if byte.is_ascii() => packet.split_at(1),
+            _ => ("", packet),
+        };
+        let reply = match command {
+            "?" => stop_reply(debugger, SIGTRAP),
+            "g" => encode_hex(&register_bytes(gameboy)),
+            "G" => {
+                let bytes = decode_hex(rest)?;
+                if bytes.len() < 12 {
+                    return Err("expected all 10 registers".to_string());
+                }
+                for number in 0..8 {
+                    set_register(gameboy, number, &bytes[number..number + 1])?;
+                }
+                set_register(gameboy, 8, &bytes[8..10])?;
+                set_register(gameboy, 9, &bytes[10..12])?;
+                "OK".to_string()
+            }
+            "p" => {
+                let number = parse_hex(rest)?;
+                let bytes = register_bytes(gameboy);
+                match number {
+                    0..=7 => encode_hex(&bytes[number..number + 1]),
+                    8 => encode_hex(&bytes[8..10]),
+                    9 => encode_hex(&bytes[10..12]),
+                    _ => return Err(format!("no register {}", number)),
+                }
+            }
+            "P" => {
+                let (number, value) = rest.split_once('=').ok_or("expected n=value")?;
+                set_register(gameboy, parse_hex(number)?, &decode_hex(value)?)?;
+                "OK".to_string()
+            }
+            "m" => {
+                let (address, length) = parse_address_length(rest)?;
+                let memory = gameboy.memory();
+                let bytes: Vec<u8> = (0..length.min(PACKET_SIZE / 2))
+                    .map(|offset| memory.peek(address.wrapping_add(offset as u16) as usize))
+                    .collect();
+                encode_hex(&bytes)
+            }
+            "M" => {
+                let (range, data) = rest.split_once(':').ok_or("expected addr,length:data")?;
+                let (address, _) = parse_address_length(range)?;
+                let memory = gameboy.memory();
+                for (offset, byte) in decode_hex(data)?.iter().enumerate() {
+                    memory.write(address.wrapping_add(offset as u16) as usize, *byte);
+                }
+                // Changes made by the debugger are not the game's accesses
+                memory.watchpoints.borrow_mut().take_hits();
+                "OK".to_string()
+            }
+            "c" => {
+                if !rest.is_empty() {
+                    set_register(gameboy, 9, &(parse_hex(rest)? as u16).to_le_bytes())?;
+                }
+                debugger.resume(gameboy);
+                self.running = true;
+                return Ok(None);
+            }
+            "s" => {
+                if !rest.is_empty() {
+                    set_register(gameboy, 9, &(parse_hex(rest)? as u16).to_le_bytes())?;
+                }
+                debugger.single_step(gameboy);
+                stop_reply(debugger, SIGTRAP)
+            }
+            "Z" | "z" => self.breakpoint(command == "Z", rest, debugger, gameboy)?,
+            "H" | "T" => "OK".to_string(),
+            "D" => "OK".to_string(),
+            "q" => self.query(rest)?,
+            // Everything else, including vCont and X, is unsupported which
+            // makes gdb fall back to the packets above
+            _ => String::new(),
+        };
+        Ok(Some(reply))
+    }
+
+    fn query(&mut self, query: &str) -> Result<String, String> {
+        let reply = if query.starts_with("Supported") {
+            format!("PacketSize={:x};qXfer:features:read+", PACKET_SIZE)
+        } else if let Some(annex) = query.strip_prefix("Xfer:features:read:") {
+            let (name, range) = annex
+                .split_once(':')
+                .ok_or("expected annex:offset,length")?;
+            if name != "target.xml" {
+                return Ok("E00".to_string());
+            }
+            let (offset, length) = range.split_once(',').ok_or("expected offset,length")?;
+            transfer_chunk(TARGET_XML, parse_hex(offset)?, parse_hex(length)?)
+        } else {
+            match query {
+                "Attached" => "1".to_string(),
+                "C" => "QC1".to_string(),
+                "fThreadInfo" => "m1".to_string(),
+                "sThreadInfo" => "l".to_string(),
+                _ => String::new(),
+            }
+        };
+        Ok(reply)
+    }
+
+    // Z0/Z1 are breakpoints, Z2/Z3/Z4 write, read and access watchpoints
+    fn breakpoint(
+        &mut self,
+        insert: bool,
+        arguments: &str,
+        debugger: &mut Debugger,
+        gameboy: &GameBoy,
+    ) -> Result<String, String> {
+        let mut parts = arguments.splitn(3, ',');
+        let kind = parts.next().unwrap_or("");
+        let address = parse_hex(parts.next().ok_or("missing address")?)? as u16;
+        let length = parse_hex(parts.next().ok_or("missing kind")?)?.max(1);
+        let watch_kind = match kind {
+            "0" | "1" => {
+                if insert {
+                    debugger.add_breakpoint(address);
+                } else {
+                    // Breakpoints set for one bank in the debugger stay
+                    debugger.remove_breakpoint(address, None);
+                }
+                return Ok("OK".to_string());
+            }
+            "2" => WatchKind::Write,
+            "3" => WatchKind::Read,
+            "4" => WatchKind::Access,
+            _ => return Ok(String::new()),
+        };
+        if length > 0x10000 {
+            return Err(format!("watch length {:#x} is larger than memory", length));
+        }
+        let end = (address as u32)
+            .checked_add(length as u32 - 1)
+            .filter(|end| *end <= 0xFFFF)
+            .ok_or_else(|| format!("watch at {:#06x} runs past the end of memory", address))?;
+        let watchpoint = Watchpoint {
+            range: address..=end as u16,
+            kind: watch_kind,
+            action: WatchAction::Pause,
+        };
+        if insert {
+            debugger.add_watchpoint(gameboy, watchpoint);
+        } else {
+            let mut watchpoints = gameboy.memory().watchpoints.borrow_mut();
+            if let Some(index) = watchpoints.list().iter().position(|w| *w == watchpoint) {
+                watchpoints.remove(index);
+            }
+        }
+        Ok("OK".to_string())
+    }
+}
+
+#[cfg(test)]
+mod test;
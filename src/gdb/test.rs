@@ -0,0 +1,303 @@
+use super::*;
+use crate::model::Model;
+use std::time::{Duration, Instant};
+
+// Loads B at 0x0100, then PUSH BC all the way
+fn gameboy() -> GameBoy {
+    let mut rom = vec![0xC5; 0x8000];
+    rom[0x0100] = 0x06;
+    rom[0x0101] = 0x42;
+    let mut gameboy = GameBoy::new(Model::Dmg);
+    gameboy.load_rom(rom).unwrap();
+    gameboy
+}
+
+fn stub() -> GdbStub {
+    GdbStub::listen("127.0.0.1:0").unwrap()
+}
+
+fn answer(
+    stub: &mut GdbStub,
+    debugger: &mut Debugger,
+    gameboy: &mut GameBoy,
+    packet: &str,
+) -> String {
+    stub.handle_packet(packet, debugger, gameboy).unwrap()
+}
+
+#[test]
+fn frames_packets() {
+    assert_eq!(frame("OK"), "$OK#9a");
+    assert_eq!(frame("a#b"), "$a}\x03b#43");
+
+    let mut input = b"+$g#67$m0,2#f".to_vec();
+    assert_eq!(next_event(&mut input), Some(Event::Packet("g".to_string())));
+    // Incomplete until the checksum is there
+    assert_eq!(next_event(&mut input), None);
+    input.extend_from_slice(b"b-\x03$g#00");
+    assert_eq!(
+        next_event(&mut input),
+        Some(Event::Packet("m0,2".to_string()))
+    );
+    assert_eq!(next_event(&mut input), Some(Event::Nak));
+    assert_eq!(next_event(&mut input), Some(Event::Interrupt));
+    assert_eq!(next_event(&mut input), Some(Event::BadChecksum));
+    assert!(input.is_empty());
+}
+
+#[test]
+fn reads_and_writes_registers() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+    let mut stub = stub();
+
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "g"),
+        "01b0001300d8014dfeff0001"
+    );
+    assert_eq!(answer(&mut stub, &mut debugger, &mut gameboy, "p1"), "b0");
+    assert_eq!(answer(&mut stub, &mut debugger, &mut gameboy, "p9"), "0001");
+
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "P8=00c0"),
+        "OK"
+    );
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "P1=ff"),
+        "OK"
+    );
+    let registers = gameboy.cpu().registers();
+    assert_eq!(registers.sp, 0xC000);
+    assert_eq!(registers.f, 0xF0);
+
+    assert_eq!(
+        answer(
+            &mut stub,
+            &mut debugger,
+            &mut gameboy,
+            "G11800000ff56000dfeff5001"
+        ),
+        "OK"
+    );
+    let registers = gameboy.cpu().registers();
+    assert_eq!((registers.a, registers.f, registers.d), (0x11, 0x80, 0xFF));
+    assert_eq!(registers.pc, 0x0150);
+    assert_eq!(answer(&mut stub, &mut debugger, &mut gameboy, "pa"), "E01");
+    assert_eq!(
+        stub.take_events(),
+        [GdbEvent::Error {
+            packet: "pa".to_string(),
+            message: "no register 10".to_string()
+        }]
+    );
+}
+
+#[test]
+fn reads_and_writes_memory() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+    let mut stub = stub();
+
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "m100,3"),
+        "0642c5"
+    );
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "Mc000,2:beef"),
+        "OK"
+    );
+    assert_eq!(gameboy.memory().read(0xC001), 0xEF);
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "mc000,2"),
+        "beef"
+    );
+}
+
+#[test]
+fn breakpoints_and_stepping() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+    let mut stub = stub();
+    debugger.pause();
+
+    assert_eq!(answer(&mut stub, &mut debugger, &mut gameboy, "s"), "S05");
+    assert_eq!(gameboy.cpu().pc(), 0x0102);
+
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "Z0,104,1"),
+        "OK"
+    );
+    assert!(stub
+        .handle_packet("c", &mut debugger, &mut gameboy)
+        .is_none());
+    assert!(debugger.run_frame(&mut gameboy));
+    assert_eq!(gameboy.cpu().pc(), 0x0104);
+    assert_eq!(stop_reply(&debugger, SIGTRAP), "S05");
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "z0,104,1"),
+        "OK"
+    );
+    assert!(debugger.breakpoints().is_empty());
+
+    // gdb only removes its own breakpoint, not one set for a bank
+    debugger.add_bank_breakpoint(0x4000, 1);
+    answer(&mut stub, &mut debugger, &mut gameboy, "Z0,4000,1");
+    answer(&mut stub, &mut debugger, &mut gameboy, "z0,4000,1");
+    assert_eq!(debugger.breakpoints(), [(0x4000, Some(1))]);
+}
+
+#[test]
+fn watchpoints_report_the_address() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+    let mut stub = stub();
+
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "Z2,fffc,2"),
+        "OK"
+    );
+    assert!(debugger.run_frame(&mut gameboy));
+    assert_eq!(stop_reply(&debugger, SIGTRAP), "T05watch:fffd;");
+
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "z2,fffc,2"),
+        "OK"
+    );
+    assert!(gameboy.memory().watchpoints.borrow().is_empty());
+}
+
+#[test]
+fn rejects_malformed_packets() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+    let mut stub = stub();
+
+    // Lossy decoding turns bad bytes into the 3 byte U+FFFD
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "Mc000,2:b\u{FFFD}"),
+        "E01"
+    );
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "\u{FFFD}"),
+        ""
+    );
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "Z2,0,10001"),
+        "E01"
+    );
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "Z2,ffff,2"),
+        "E01"
+    );
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "Z2,0,10000"),
+        "OK"
+    );
+}
+
+#[test]
+fn serves_the_target_description() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+    let mut stub = stub();
+
+    let supported = answer(
+        &mut stub,
+        &mut debugger,
+        &mut gameboy,
+        "qSupported:xmlRegisters=i386",
+    );
+    assert!(supported.contains("qXfer:features:read+"));
+
+    let first = answer(
+        &mut stub,
+        &mut debugger,
+        &mut gameboy,
+        "qXfer:features:read:target.xml:0,40",
+    );
+    assert_eq!(first, format!("m{}", &TARGET_XML[..0x40]));
+    let rest = answer(
+        &mut stub,
+        &mut debugger,
+        &mut gameboy,
+        "qXfer:features:read:target.xml:40,1000",
+    );
+    assert_eq!(rest, format!("l{}", &TARGET_XML[0x40..]));
+    assert_eq!(
+        answer(&mut stub, &mut debugger, &mut gameboy, "vMustReplyEmpty"),
+        ""
+    );
+}
+
+// Polls the stub, running the emulation like a frontend, until gdb has a reply
+fn run_until_reply(
+    stub: &mut GdbStub,
+    debugger: &mut Debugger,
+    gameboy: &mut GameBoy,
+    client: &mut TcpStream,
+) -> String {
+    let deadline = Instant::now() + Duration::from_secs(5);
+    client.set_nonblocking(true).unwrap();
+    let mut reply = Vec::new();
+    while Instant::now() < deadline {
+        assert!(stub.poll(debugger, gameboy).unwrap());
+        if !debugger.paused() {
+            debugger.run_frame(gameboy);
+        }
+        let mut buffer = [0; 256];
+        match client.read(&mut buffer) {
+            Ok(read) => reply.extend_from_slice(&buffer[..read]),
+            Err(ref e) if e.kind() == ErrorKind::WouldBlock => {}
+            Err(e) => panic!("{}", e),
+        }
+        let text = String::from_utf8_lossy(&reply).into_owned();
+        if let (Some(start), Some(end)) = (text.find('$'), text.rfind('#')) {
+            if end + 3 <= text.len() {
+                client.set_nonblocking(false).unwrap();
+                client.write_all(b"+").unwrap();
+                return text[start + 1..end].to_string();
+            }
+        }
+    }
+    panic!("no reply from the stub");
+}
+
+#[test]
+fn talks_over_tcp() {
+    let mut gameboy = gameboy();
+    let mut debugger = Debugger::new();
+    let mut stub = stub();
+    let mut client = TcpStream::connect(stub.local_addr().unwrap()).unwrap();
+
+    client.write_all(frame("?").as_bytes()).unwrap();
+    assert_eq!(
+        run_until_reply(&mut stub, &mut debugger, &mut gameboy, &mut client),
+        "S05"
+    );
+    assert!(stub.connected());
+    assert!(debugger.paused());
+
+    client.write_all(frame("Z0,103,1").as_bytes()).unwrap();
+    assert_eq!(
+        run_until_reply(&mut stub, &mut debugger, &mut gameboy, &mut client),
+        "OK"
+    );
+    client.write_all(frame("c").as_bytes()).unwrap();
+    assert_eq!(
+        run_until_reply(&mut stub, &mut debugger, &mut gameboy, &mut client),
+        "S05"
+    );
+    assert_eq!(gameboy.cpu().pc(), 0x0103);
+
+    // Detaching lets the game run on
+    client.write_all(frame("D").as_bytes()).unwrap();
+    assert_eq!(
+        run_until_reply(&mut stub, &mut debugger, &mut gameboy, &mut client),
+        "OK"
+    );
+    assert!(!stub.connected());
+    assert!(!debugger.paused());
+    let events = stub.take_events();
+    assert!(matches!(events[0], GdbEvent::Connected(_)));
+    assert_eq!(events[1..], [GdbEvent::Disconnected]);
+}
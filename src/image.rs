@@ -0,0 +1,167 @@
+// Image file output. PNG files are written with uncompressed deflate
+// blocks, which keeps the encoder tiny at the cost of file size. PPM (binary
+// P6) is there for tools that compare raw pixels.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+const PNG_SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A];
+const MAX_STORED_BLOCK: usize = 0xFFFF;
+
+pub(crate) fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFFu32;
+    for byte in data.iter() {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (!(crc & 1)).wrapping_add(1);
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
+
+fn adler32(data: &[u8]) -> u32 {
+    let mut a = 1u32;
+    let mut b = 0u32;
+    for byte in data.iter() {
+        a = (a + *byte as u32) % 65521;
+        b = (b + a) % 65521;
+    }
+    (b << 16) | a
+}
+
+fn write_chunk<W: Write>(writer: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    writer.write_all(&(data.len() as u32).to_be_bytes())?;
+    writer.write_all(kind)?;
+    writer.write_all(data)?;
+
+    let mut crc_input = kind.to_vec();
+    crc_input.extend_from_slice(data);
+    writer.write_all(&crc32(&crc_input).to_be_bytes())
+}
+
+// Zlib stream made of stored (uncompressed) deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let mut out = vec![0x78, 0x01];
+    let mut blocks = data.chunks(MAX_STORED_BLOCK).peekable();
+    if blocks.peek().is_none() {
+        out.extend_from_slice(&[0x01, 0x00, 0x00, 0xFF, 0xFF]);
+    }
+    while let Some(block) = blocks.next() {
+        let last = blocks.peek().is_none();
+        let len = block.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&len.to_le_bytes());
+        out.extend_from_slice(&(!len).to_le_bytes());
+        out.extend_from_slice(block);
+    }
+    out.extend_from_slice(&adler32(data).to_be_bytes());
+    out
+}
+
+// Writes 8 bit RGB pixels, three bytes per pixel, row by row
+pub fn write_png<W: Write>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+) -> io::Result<()> {
+    assert_eq!(rgb.len(), width * height * 3);
+
+    let mut header = Vec::with_capacity(13);
+    header.extend_from_slice(&(width as u32).to_be_bytes());
+    header.extend_from_slice(&(height as u32).to_be_bytes());
+    header.extend_from_slice(&[8, 2, 0, 0, 0]); // 8 bit depth, RGB, no interlace
+
+    // Every scanline starts with its filter type, always none here
+    let mut scanlines = Vec::with_capacity((width * 3 + 1) * height);
+    for row in rgb.chunks(width * 3) {
+        scanlines.push(0);
+        scanlines.extend_from_slice(row);
+    }
+
+    writer.write_all(&PNG_SIGNATURE)?;
+    write_chunk(writer, b"IHDR", &header)?;
+    write_chunk(writer, b"IDAT", &zlib_stored(&scanlines))?;
+    write_chunk(writer, b"IEND", &[])
+}
+
+// Binary PPM of 8 bit RGB pixels
+pub fn write_ppm<W: Write>(
+    writer: &mut W,
+    width: usize,
+    height: usize,
+    rgb: &[u8],
+) -> io::Result<()> {
+    assert_eq!(rgb.len(), width * height * 3);
+    write!(writer, "P6\n{} {}\n255\n", width, height)?;
+    writer.write_all(rgb)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImageFormat {
+    Png,
+    Ppm,
+}
+
+impl ImageFormat {
+    pub fn parse(name: &str) -> Result<ImageFormat, String> {
+        match name.to_ascii_lowercase().as_str() {
+            "png" => Ok(ImageFormat::Png),
+            "ppm" => Ok(ImageFormat::Ppm),
+            _ => Err(format!(
+                "unknown image format '{}', expected png or ppm",
+                name
+            )),
+        }
+    }
+
+    // From the file extension, PNG unless it says ppm
+    pub fn for_path(path: &Path) -> ImageFormat {
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some(extension) if extension.eq_ignore_ascii_case("ppm") => ImageFormat::Ppm,
+            _ => ImageFormat::Png,
+        }
+    }
+
+    pub fn extension(self) -> &'static str {
+        match self {
+            ImageFormat::Png => "png",
+            ImageFormat::Ppm => "ppm",
+        }
+    }
+}
+
+// Writes an image in the format its extension names
+pub fn save_image(path: &Path, width: usize, height: usize, rgb: &[u8]) -> Result<(), String> {
+    let file =
+        File::create(path).map_err(|e| format!("could not create {}: {}", path.display(), e))?;
+    let mut writer = BufWriter::new(file);
+    match ImageFormat::for_path(path) {
+        ImageFormat::Png => write_png(&mut writer, width, height, rgb),
+        ImageFormat::Ppm => write_ppm(&mut writer, width, height, rgb),
+    }
+    .and_then(|()| writer.flush())
+    .map_err(|e| format!("could not write {}: {}", path.display(), e))
+}
+
+// Nearest neighbour enlargement by a whole factor, as the window shows it
+pub fn scale_rgb(width: usize, height: usize, rgb: &[u8], scale: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(rgb.len() * scale * scale);
+    for row in rgb.chunks(width * 3).take(height) {
+        let mut line = Vec::with_capacity(row.len() * scale);
+        for pixel in row.chunks(3) {
+            for _ in 0..scale {
+                line.extend_from_slice(pixel);
+            }
+        }
+        for _ in 0..scale {
+            out.extend_from_slice(&line);
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod test;
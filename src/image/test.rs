@@ -0,0 +1,65 @@
+use super::*;
+
+#[test]
+fn crc32_check_value() {
+    assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    assert_eq!(crc32(b"IEND"), 0xAE42_6082);
+}
+
+#[test]
+fn adler32_check_value() {
+    assert_eq!(adler32(b"Wikipedia"), 0x11E6_0398);
+}
+
+#[test]
+fn stored_blocks() {
+    let data = vec![0xAB; MAX_STORED_BLOCK + 10];
+    let stream = zlib_stored(&data);
+
+    assert_eq!(&stream[0..2], &[0x78, 0x01]);
+    assert_eq!(&stream[2..7], &[0x00, 0xFF, 0xFF, 0x00, 0x00]);
+    let second = 7 + MAX_STORED_BLOCK;
+    assert_eq!(&stream[second..second + 5], &[0x01, 0x0A, 0x00, 0xF5, 0xFF]);
+    assert_eq!(stream.len(), 2 + 5 + MAX_STORED_BLOCK + 5 + 10 + 4);
+}
+
+#[test]
+fn png_layout() {
+    let mut out = Vec::new();
+    write_png(&mut out, 2, 1, &[255, 0, 0, 0, 0, 255]).unwrap();
+
+    assert_eq!(&out[0..8], &PNG_SIGNATURE);
+    assert_eq!(&out[8..16], &[0, 0, 0, 13, b'I', b'H', b'D', b'R']);
+    assert_eq!(&out[16..24], &[0, 0, 0, 2, 0, 0, 0, 1]);
+    assert_eq!(&out[24..29], &[8, 2, 0, 0, 0]);
+    assert_eq!(&out[37..41], b"IDAT");
+    assert_eq!(
+        &out[out.len() - 12..],
+        &[0, 0, 0, 0, b'I', b'E', b'N', b'D', 0xAE, 0x42, 0x60, 0x82]
+    );
+}
+
+#[test]
+fn ppm_layout() {
+    let mut out = Vec::new();
+    write_ppm(&mut out, 2, 1, &[255, 0, 0, 0, 0, 255]).unwrap();
+    assert_eq!(&out[..], &b"P6\n2 1\n255\n\xFF\x00\x00\x00\x00\xFF"[..]);
+}
+
+#[test]
+fn formats_follow_the_extension() {
+    assert_eq!(ImageFormat::for_path(Path::new("a.PPM")), ImageFormat::Ppm);
+    assert_eq!(ImageFormat::for_path(Path::new("a.png")), ImageFormat::Png);
+    assert_eq!(ImageFormat::for_path(Path::new("a")), ImageFormat::Png);
+    assert_eq!(ImageFormat::parse("ppm"), Ok(ImageFormat::Ppm));
+    assert!(ImageFormat::parse("gif").is_err());
+}
+
+#[test]
+fn scales_whole_pixels() {
+    let rgb = [1, 1, 1, 2, 2, 2];
+    assert_eq!(
+        scale_rgb(2, 1, &rgb, 2),
+        vec![1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2, 1, 1, 1, 1, 1, 1, 2, 2, 2, 2, 2, 2]
+    );
+}
@@ -0,0 +1,149 @@
+// P1/JOYP register: the button matrix selected through bits 4 and 5
+
+use super::savestate::{StateReader, StateWriter};
+use std::str::FromStr;
+
+pub const P1: usize = 0xFF00;
+
+const SELECT_DIRECTIONS: u8 = 0x10;
+const SELECT_BUTTONS: u8 = 0x20;
+
+const STATE_VERSION: u8 = 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Button {
+    Right,
+    Left,
+    Up,
+    Down,
+    A,
+    B,
+    Select,
+    Start,
+}
+
+impl Button {
+    pub const ALL: [Button; 8] = [
+        Button::Right,
+        Button::Left,
+        Button::Up,
+        Button::Down,
+        Button::A,
+        Button::B,
+        Button::Select,
+        Button::Start,
+    ];
+
+    // Bit of the button in a button state mask; the direction keys take the
+    // low nibble and the action buttons the high nibble.
+    pub fn mask(self) -> u8 {
+        1 << self as u8
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Button::Right => "right",
+            Button::Left => "left",
+            Button::Up => "up",
+            Button::Down => "down",
+            Button::A => "a",
+            Button::B => "b",
+            Button::Select => "select",
+            Button::Start => "start",
+        }
+    }
+}
+
+impl FromStr for Button {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Button, String> {
+        Button::ALL
+            .iter()
+            .find(|button| button.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| format!("unknown button '{}'", s))
+    }
+}
+
+#[derive(Debug)]
+pub struct Joypad {
+    select: u8,
+    pressed: u8,
+}
+
+impl Joypad {
+    pub fn new() -> Joypad {
+        Joypad {
+            select: SELECT_DIRECTIONS | SELECT_BUTTONS,
+            pressed: 0,
+        }
+    }
+
+    // Low nibble of P1, where a selected and pressed button reads as 0
+    fn lines(&self) -> u8 {
+        let mut lines = 0x0F;
+        if self.select & SELECT_DIRECTIONS == 0 {
+            lines &= !self.pressed & 0x0F;
+        }
+        if self.select & SELECT_BUTTONS == 0 {
+            lines &= !(self.pressed >> 4) & 0x0F;
+        }
+        lines
+    }
+
+    pub fn read(&self) -> u8 {
+        0xC0 | self.select | self.lines()
+    }
+
+    // Returns true when the write pulled one of the input lines low, which
+    // requests the joypad interrupt.
+    pub fn write(&mut self, value: u8) -> bool {
+        let old_lines = self.lines();
+        self.select = value & (SELECT_DIRECTIONS | SELECT_BUTTONS);
+        old_lines & !self.lines() != 0
+    }
+
+    pub fn pressed(&self) -> u8 {
+        self.pressed
+    }
+
+    // Replaces the whole button state, using the masks from Button::mask.
+    // Returns true when the joypad interrupt should be requested.
+    pub fn set_pressed(&mut self, pressed: u8) -> bool {
+        let old_lines = self.lines();
+        self.pressed = pressed;
+        old_lines & !self.lines() != 0
+    }
+
+    pub fn set_button(&mut self, button: Button, pressed: bool) -> bool {
+        let state = if pressed {
+            self.pressed | button.mask()
+        } else {
+            self.pressed & !button.mask()
+        };
+        self.set_pressed(state)
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(STATE_VERSION);
+        writer.write_u8(self.select);
+        writer.write_u8(self.pressed);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_version("joypad", STATE_VERSION)?;
+        self.select = reader.read_u8()? & (SELECT_DIRECTIONS | SELECT_BUTTONS);
+        self.pressed = reader.read_u8()?;
+        Ok(())
+    }
+}
+
+impl Default for Joypad {
+    fn default() -> Joypad {
+        Joypad::new()
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,66 @@
+use super::*;
+
+#[test]
+fn nothing_selected_reads_high() {
+    let mut joypad = Joypad::new();
+
+    joypad.set_pressed(0xFF);
+    assert_eq!(joypad.read(), 0xFF);
+}
+
+#[test]
+fn direction_matrix() {
+    let mut joypad = Joypad::new();
+
+    joypad.write(0x20);
+    joypad.set_button(Button::Left, true);
+    joypad.set_button(Button::Start, true);
+    assert_eq!(joypad.read(), 0xED);
+}
+
+#[test]
+fn button_matrix() {
+    let mut joypad = Joypad::new();
+
+    joypad.write(0x10);
+    joypad.set_button(Button::Left, true);
+    joypad.set_button(Button::Start, true);
+    assert_eq!(joypad.read(), 0xD7);
+}
+
+#[test]
+fn both_rows_selected() {
+    let mut joypad = Joypad::new();
+
+    joypad.write(0x00);
+    joypad.set_button(Button::Right, true);
+    joypad.set_button(Button::B, true);
+    assert_eq!(joypad.read(), 0xCC);
+}
+
+#[test]
+fn interrupt_on_press_of_selected_row() {
+    let mut joypad = Joypad::new();
+    joypad.write(0x20);
+
+    assert!(joypad.set_button(Button::Down, true));
+    assert!(!joypad.set_button(Button::Down, true));
+    assert!(!joypad.set_button(Button::Down, false));
+    assert!(!joypad.set_button(Button::A, true));
+}
+
+#[test]
+fn interrupt_on_select_with_button_held() {
+    let mut joypad = Joypad::new();
+
+    assert!(!joypad.set_button(Button::A, true));
+    assert!(!joypad.write(0x20));
+    assert!(joypad.write(0x10));
+}
+
+#[test]
+fn parse_button() {
+    assert_eq!("Start".parse::<Button>(), Ok(Button::Start));
+    assert_eq!("up".parse::<Button>(), Ok(Button::Up));
+    assert!("turbo".parse::<Button>().is_err());
+}
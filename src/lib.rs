@@ -1,3 +1,33 @@
 
+pub mod apu;
+pub mod audio;
+pub mod bindings;
+pub mod cartridge;
+pub mod cdl;
 pub mod cpu;
-pub mod memory;
\ No newline at end of file
+pub mod debugger;
+pub mod disasm;
+pub mod gameboy;
+pub mod gdb;
+pub mod image;
+pub mod joypad;
+pub mod memory;
+pub mod model;
+pub mod movie;
+pub mod pacing;
+pub mod palette;
+pub mod printer;
+pub mod profiler;
+pub mod recording;
+pub mod rewind;
+pub mod savestate;
+pub mod screen;
+pub mod serial;
+pub mod symbols;
+pub mod tcp_link;
+pub mod trace;
+pub mod vgm;
+pub mod vram;
+pub mod watchpoint;
+pub mod wav;
+pub mod y4m;
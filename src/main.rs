@@ -1,61 +1,900 @@
-extern crate rand;
+extern crate gba;
 extern crate sdl2;
 
+mod frontend;
+
+use frontend::console::Console;
+use frontend::input::Input;
+use frontend::video::{open_view_window, open_window, Display, ViewWindow};
+use gba::apu::Channel;
+use gba::audio::{AudioOutput, SampleBuffer};
+use gba::bindings::Bindings;
+use gba::cdl::CodeDataLog;
+use gba::debugger::Debugger;
+use gba::gameboy::GameBoy;
+use gba::gdb::{GdbEvent, GdbStub};
+use gba::image::ImageFormat;
+use gba::model::Model;
+use gba::movie::Movie;
+use gba::pacing::{frame_duration, FramePacer, SyncMode, TurboAudio, CYCLES_PER_FRAME, FRAME_RATE};
+use gba::printer::Printer;
+use gba::profiler::Profiler;
+use gba::recording::{StemRecorder, VideoRecorder, WavRecorder};
+use gba::rewind::Rewind;
+use gba::serial::DisconnectedLink;
+use gba::symbols::Symbols;
+use gba::tcp_link::TcpLink;
+use gba::trace::{parse_range, Trace};
+use gba::vgm::VgmWriter;
+use gba::vram;
 use sdl2::audio::{AudioCallback, AudioSpecDesired};
-use std::time::Duration;
+use sdl2::event::{Event, WindowEvent};
+use sdl2::keyboard::{Keycode, Mod};
+use std::cell::RefCell;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+// Roughly 185ms of audio at 44.1 kHz
+const SAMPLE_BUFFER_FRAMES: usize = 8192;
+// Queue about three video frames of audio
+const TARGET_BUFFER_FRAMES: usize = 2048;
+const RECORDING_SAMPLE_RATE: u32 = 44_100;
+const DEFAULT_SCALE: u32 = 3;
+const DEFAULT_REWIND_BUDGET_MB: usize = 64;
+// Snapshot every other frame, a keyframe once a second
+const REWIND_INTERVAL: u64 = 2;
+const REWIND_KEYFRAME_INTERVAL: usize = 30;
+// Functions listed after a profiling run
+const PROFILE_REPORT_LINES: usize = 20;
+// The VRAM viewer redraws 15 times a second while the game runs
+const VRAM_VIEW_INTERVAL: u64 = 4;
+
+const USAGE: &str = "usage: main [options] <rom>
+
+options:
+  --model <dmg|cgb>          hardware to emulate (default dmg)
+  --boot-rom <file>          run this boot ROM instead of starting at 0x0100
+  --scale <n>                initial window size as a multiple of 160x144 (default 3)
+  --save-dir <dir>           where battery saves go (default: next to the ROM)
+  --screenshot-dir <dir>     where F8 screenshots go (default: the save directory)
+  --screenshot-format <png|ppm>
+                             screenshot file format (default png)
+  --sync <audio|video>       pace on the audio queue or the display refresh
+  --turbo                    start in turbo, running as fast as possible
+  --turbo-audio <mute|stretch>
+                             silence turbo, or speed its audio up to match (default mute)
+  --mute <channel>           silence an APU channel (1-4), can be repeated
+  --solo <channel>           only play one APU channel
+  --record-wav <file>        record the audio output
+  --record-stems <prefix>    record every APU channel to its own file
+  --record-vgm <file>        log APU register writes
+  --record-movie <file>      record the input of every frame to a movie, from power-on
+  --movie-state <file>       start the recorded movie from this save state instead
+  --play-movie <file>        replay a movie, reporting when the emulation desyncs from it
+  --record-video <file>      record every emulated frame to file.y4m and the audio to
+                             file.wav, in sync however fast the emulation runs
+  --bindings <file>          keyboard and controller bindings
+  --link-listen <address>    wait for a link cable connection
+  --link-connect <address>   connect the link cable to another emulator
+  --printer <dir>            attach a Game Boy Printer saving to dir, not with a link cable
+  --trace <file>             write a Gameboy Doctor execution trace
+  --trace-range <from-to>    only trace these addresses, hex, can be repeated
+  --trace-labels             end trace lines with the label from the symbol file
+  --rewind-budget <MB>       memory for the rewind buffer, 0 disables (default 64)
+  --debug                    start paused in the debugger
+  --gdb <port>               accept a gdb remote connection on localhost
+  --sym <file>               RGBDS symbol file (default the .sym file next to the ROM)
+  --profile <file>           count cycles per function and write folded stacks for
+                             flamegraph tools on exit
+  --cdl <file>               log which ROM bytes run as code or are read as data,
+                             adding to the file if it exists (see src/cdl.rs)
 
-struct WhiteNoiseCallback {
-    volume: f32,
+keys:
+  0-9                        select the save state slot
+  F8                         save a screenshot of the 160x144 screen
+  Shift+F8                   save a screenshot at the window's scale
+  F5 / F7                    save / load the state in the current slot
+  R (hold)                   rewind
+  Tab (hold) / `             turbo while held / turn turbo on or off
+  T                          pause or resume the execution trace
+  F9                         show or hide the VRAM viewer: tiles, both background maps
+                             with the screen outlined, sprites and palettes
+  F10                        list the 40 OAM entries in the terminal
+  F12                        pause in the debugger, commands are typed in the terminal
+                             (see help there for breakpoints and watchpoints)
+  Escape                     quit";
+
+struct ApuCallback {
+    buffer: Arc<SampleBuffer>,
 }
-impl AudioCallback for WhiteNoiseCallback {
+impl AudioCallback for ApuCallback {
     type Channel = f32;
 
     fn callback(&mut self, out: &mut [f32]) {
-        use self::rand::{thread_rng, Rng};
-        let mut rng = thread_rng();
+        self.buffer.fill(out);
+    }
+}
 
-        // Generate white noise
-        for x in out.iter_mut() {
-            *x = (rng.gen_range(0.0, 2.0) - 1.0) * self.volume;
+// Matched before the bindings, so none of them can be bound to a button
+const HOTKEYS: [Keycode; 21] = [
+    Keycode::Num0,
+    Keycode::Num1,
+    Keycode::Num2,
+    Keycode::Num3,
+    Keycode::Num4,
+    Keycode::Num5,
+    Keycode::Num6,
+    Keycode::Num7,
+    Keycode::Num8,
+    Keycode::Num9,
+    Keycode::F5,
+    Keycode::F7,
+    Keycode::F8,
+    Keycode::F9,
+    Keycode::F10,
+    Keycode::F12,
+    Keycode::R,
+    Keycode::T,
+    Keycode::Tab,
+    Keycode::Backquote,
+    Keycode::Escape,
+];
+
+// Which end of the link cable to open once the ROM has loaded
+enum LinkRole {
+    Listen(String),
+    Connect(String),
+}
+
+fn report_prints(prints: &RefCell<Vec<Result<PathBuf, String>>>) {
+    for saved in prints.borrow_mut().drain(..) {
+        match saved {
+            Ok(path) => println!("printed {}", path.display()),
+            Err(e) => eprintln!("{}", e),
         }
     }
 }
 
+fn slot_key(key: Keycode) -> Option<u32> {
+    let slot = key as i32 - Keycode::Num0 as i32;
+    if (0..=9).contains(&slot) {
+        Some(slot as u32)
+    } else {
+        None
+    }
+}
+
+fn state_path(save_dir: &Path, rom_path: &Path, slot: u32) -> PathBuf {
+    save_dir.join(
+        rom_path
+            .with_extension(format!("ss{}", slot))
+            .file_name()
+            .unwrap(),
+    )
+}
+
+// The first of rom-1.png, rom-2.png and so on that does not exist yet
+fn screenshot_path(dir: &Path, rom_path: &Path, format: ImageFormat) -> PathBuf {
+    let stem = rom_path.file_stem().unwrap().to_string_lossy();
+    (1..)
+        .map(|number| dir.join(format!("{}-{}.{}", stem, number, format.extension())))
+        .find(|path| !path.exists())
+        .unwrap()
+}
+
+fn save_state(gameboy: &GameBoy, path: &Path) -> Result<(), String> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir).map_err(|e| e.to_string())?;
+    }
+    std::fs::write(path, gameboy.save_state()).map_err(|e| e.to_string())
+}
+
+fn load_state(gameboy: &mut GameBoy, path: &Path) -> Result<(), String> {
+    let data = std::fs::read(path).map_err(|e| e.to_string())?;
+    gameboy.load_state(&data)
+}
+
 fn main() -> Result<(), String> {
+    let mut rom_path = None;
+    let mut model = Model::Dmg;
+    let mut boot_rom_path = None;
+    let mut scale = DEFAULT_SCALE;
+    let mut save_dir = None;
+    let mut screenshot_dir = None;
+    let mut screenshot_format = ImageFormat::Png;
+    let mut sync_mode = SyncMode::Audio;
+    let mut turbo = false;
+    let mut turbo_audio = TurboAudio::Mute;
+    let mut muted = Vec::new();
+    let mut solo = None;
+    let mut wav_path = None;
+    let mut video_path = None;
+    let mut record_movie = None;
+    let mut movie_state = None;
+    let mut play_movie = None;
+    let mut stems_prefix = None;
+    let mut vgm_path = None;
+    let mut bindings = Bindings::default();
+    let mut link = None;
+    let mut printer_dir = None;
+    let mut rewind_budget = DEFAULT_REWIND_BUDGET_MB;
+    let mut trace_path = None;
+    let mut trace_ranges = Vec::new();
+    let mut trace_labels = false;
+    let mut debug = false;
+    let mut gdb_port = None;
+    let mut sym_path = None;
+    let mut profile_path = None;
+    let mut cdl_path = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || args.next().ok_or_else(|| format!("{} needs a value", arg));
+        match arg.as_str() {
+            "--model" => model = value()?.parse()?,
+            "--boot-rom" => boot_rom_path = Some(PathBuf::from(value()?)),
+            "--scale" => {
+                scale = value()?
+                    .parse()
+                    .ok()
+                    .filter(|scale| *scale > 0)
+                    .ok_or("--scale needs a positive whole number")?
+            }
+            "--save-dir" => save_dir = Some(PathBuf::from(value()?)),
+            "--screenshot-dir" => screenshot_dir = Some(PathBuf::from(value()?)),
+            "--screenshot-format" => screenshot_format = ImageFormat::parse(&value()?)?,
+            "--sync" => sync_mode = value()?.parse()?,
+            "--turbo" => turbo = true,
+            "--turbo-audio" => turbo_audio = value()?.parse()?,
+            "--mute" => muted.push(value()?.parse::<Channel>()?),
+            "--solo" => solo = Some(value()?.parse::<Channel>()?),
+            "--record-wav" => wav_path = Some(PathBuf::from(value()?)),
+            "--record-movie" => record_movie = Some(PathBuf::from(value()?)),
+            "--movie-state" => movie_state = Some(PathBuf::from(value()?)),
+            "--play-movie" => play_movie = Some(PathBuf::from(value()?)),
+            "--record-video" => video_path = Some(PathBuf::from(value()?)),
+            "--record-stems" => stems_prefix = Some(PathBuf::from(value()?)),
+            "--record-vgm" => vgm_path = Some(PathBuf::from(value()?)),
+            "--bindings" => bindings = Bindings::load(&PathBuf::from(value()?))?,
+            "--link-listen" => link = Some(LinkRole::Listen(value()?)),
+            "--link-connect" => link = Some(LinkRole::Connect(value()?)),
+            "--printer" => printer_dir = Some(PathBuf::from(value()?)),
+            "--trace" => trace_path = Some(PathBuf::from(value()?)),
+            "--trace-range" => trace_ranges.push(parse_range(&value()?)?),
+            "--trace-labels" => trace_labels = true,
+            "--rewind-budget" => {
+                rewind_budget = value()?
+                    .parse()
+                    .map_err(|_| "--rewind-budget needs a whole number of MB".to_string())?
+            }
+            "--debug" => debug = true,
+            "--gdb" => {
+                gdb_port = Some(
+                    value()?
+                        .parse::<u16>()
+                        .map_err(|_| "--gdb needs a port number".to_string())?,
+                )
+            }
+            "--sym" => sym_path = Some(PathBuf::from(value()?)),
+            "--profile" => profile_path = Some(PathBuf::from(value()?)),
+            "--cdl" => cdl_path = Some(PathBuf::from(value()?)),
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                return Ok(());
+            }
+            _ if arg.starts_with("--") => return Err(format!("unknown argument '{}'", arg)),
+            _ if rom_path.is_none() => rom_path = Some(PathBuf::from(arg)),
+            _ => return Err(format!("unexpected argument '{}'\n\n{}", arg, USAGE)),
+        }
+    }
+
+    let rom_path = rom_path.ok_or_else(|| USAGE.to_string())?;
+    if (record_movie.is_some() || play_movie.is_some()) && link.is_some() {
+        return Err("movies cannot be recorded or played over a link cable".to_string());
+    }
+    if printer_dir.is_some() && link.is_some() {
+        return Err("--printer and a link cable cannot share the serial port".to_string());
+    }
+    if record_movie.is_some() && play_movie.is_some() {
+        return Err("--record-movie and --play-movie cannot be combined".to_string());
+    }
+    if movie_state.is_some() && record_movie.is_none() {
+        return Err("--movie-state needs --record-movie".to_string());
+    }
+    let rom = std::fs::read(&rom_path)
+        .map_err(|e| format!("could not read {}: {}", rom_path.display(), e))?;
+    let symbols = match sym_path {
+        Some(path) => Symbols::load(&path)?,
+        None => Symbols::load_for_rom(&rom_path)?.unwrap_or_default(),
+    };
+
+    let save_dir = save_dir.unwrap_or_else(|| {
+        rom_path
+            .parent()
+            .map(|dir| dir.to_path_buf())
+            .unwrap_or_default()
+    });
+    let screenshot_dir = screenshot_dir.unwrap_or_else(|| save_dir.clone());
+    let save_path = save_dir.join(rom_path.with_extension("sav").file_name().unwrap());
+
+    let mut gameboy = GameBoy::new(model);
+    if let Some(path) = boot_rom_path {
+        let boot_rom = std::fs::read(&path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        gameboy
+            .set_boot_rom(Some(boot_rom))
+            .map_err(|e| format!("{}: {}", path.display(), e))?;
+    }
+    gameboy.load_rom(rom)?;
+    let header = gameboy.header().cloned().unwrap();
+    if let Some(path) = cdl_path.as_ref() {
+        let cdl = CodeDataLog::load_or_new(path, gameboy.rom().len())?;
+        *gameboy.memory().cdl.borrow_mut() = Some(cdl);
+    }
+    if let Some(path) = trace_path.as_ref() {
+        let mut trace = Trace::create(path)
+            .map_err(|e| format!("could not create {}: {}", path.display(), e))?;
+        for range in trace_ranges {
+            trace.add_range(range);
+        }
+        if trace_labels {
+            trace.set_symbols(Some(symbols.clone()));
+        }
+        gameboy.cpu_mut().set_trace(Some(trace));
+    }
+    let memory = gameboy.memory();
+    if header.has_battery() && save_path.exists() {
+        let ram = std::fs::read(&save_path)
+            .map_err(|e| format!("could not read {}: {}", save_path.display(), e))?;
+        memory.load_cartridge_ram(&ram);
+    }
+
+    {
+        let mut apu = memory.apu.borrow_mut();
+        apu.set_sample_output(true);
+        for channel in muted {
+            apu.set_muted(channel, true);
+        }
+        apu.set_solo(solo);
+        apu.set_stem_output(stems_prefix.is_some());
+        apu.set_register_logging(vgm_path.is_some());
+    }
+    let mut link_events = None;
+    if let Some(role) = link {
+        let link = match role {
+            LinkRole::Listen(address) => {
+                println!("waiting for the other emulator on {}", address);
+                TcpLink::listen(address)
+            }
+            LinkRole::Connect(address) => TcpLink::connect(address),
+        }
+        .map_err(|e| e.to_string())?;
+        link_events = Some(link.events());
+        memory.serial.borrow_mut().set_link(Box::new(link));
+    }
+    let mut prints = None;
+    if let Some(dir) = printer_dir {
+        let printer = Printer::with_output_dir(dir);
+        prints = Some(printer.saved());
+        memory.serial.borrow_mut().set_link(Box::new(printer));
+    }
+
+    let mut wav = match wav_path {
+        Some(path) => {
+            Some(WavRecorder::create(&path, RECORDING_SAMPLE_RATE).map_err(|e| e.to_string())?)
+        }
+        None => None,
+    };
+    let mut video = match video_path {
+        Some(path) => {
+            Some(VideoRecorder::create(&path, RECORDING_SAMPLE_RATE).map_err(|e| e.to_string())?)
+        }
+        None => None,
+    };
+    let mut stems = match stems_prefix {
+        Some(prefix) => {
+            Some(StemRecorder::create(&prefix, RECORDING_SAMPLE_RATE).map_err(|e| e.to_string())?)
+        }
+        None => None,
+    };
+
     let sdl_context = sdl2::init()?;
+    let video_subsystem = sdl_context.video()?;
     let audio_subsystem = sdl_context.audio()?;
+    let mut input = Input::new(sdl_context.game_controller()?, &bindings, &HOTKEYS)?;
+    let mut event_pump = sdl_context.event_pump()?;
+
+    let title = if header.title.is_empty() {
+        rom_path.display().to_string()
+    } else {
+        header.title.clone()
+    };
+    let vsync = matches!(sync_mode, SyncMode::Video(_));
+    let canvas = open_window(&video_subsystem, &title, scale, vsync)?;
+    let creator = canvas.texture_creator();
+    let mut display = Display::new(canvas, &creator)?;
+    let vram_canvas = open_view_window(&video_subsystem, "VRAM viewer")?;
+    let vram_creator = vram_canvas.texture_creator();
+    let mut vram_window = ViewWindow::new(vram_canvas, &vram_creator);
+    // While paused the viewer only redraws after a debugger command
+    let mut vram_stale = false;
 
     let desired_spec = AudioSpecDesired {
         freq: Some(44_100),
-        channels: Some(1), // mono
+        channels: Some(2), // stereo
         samples: None,     // default sample size
     };
 
-    // None: use default device
-    let mut device = audio_subsystem.open_playback(None, &desired_spec, |spec| {
-        // Show obtained AudioSpec
-        println!("{:?}", spec);
+    let buffer = Arc::new(SampleBuffer::new(SAMPLE_BUFFER_FRAMES));
 
-        WhiteNoiseCallback { volume: 0.5 }
+    // None: use default device
+    let device = audio_subsystem.open_playback(None, &desired_spec, |_| ApuCallback {
+        buffer: buffer.clone(),
     })?;
 
+    let mut output = AudioOutput::new(device.spec().freq as u32, buffer.clone());
+
     // Start playback
     device.resume();
 
-    // Play for 1 second
-    std::thread::sleep(Duration::from_millis(1_000));
+    let sync_mode = match sync_mode {
+        // Refresh rates SDL does not know come back as 0
+        SyncMode::Video(default) => match display.refresh_rate()? {
+            0 => SyncMode::Video(default),
+            rate => SyncMode::Video(rate as f64),
+        },
+        mode => mode,
+    };
+    let mut pacer = FramePacer::new(sync_mode, TARGET_BUFFER_FRAMES);
+    pacer.set_turbo(turbo);
+    pacer.set_turbo_audio(turbo_audio);
+    let mut turbo_held = false;
+    let mut slot = 1;
+    let mut rewind = Rewind::new(
+        REWIND_INTERVAL,
+        REWIND_KEYFRAME_INTERVAL,
+        rewind_budget << 20,
+    );
+    let mut rewinding = false;
+    let mut frame = 0;
+    // The debugger shows the profiler's call stack
+    if profile_path.is_some() || debug || gdb_port.is_some() {
+        gameboy.set_profiler(Some(Profiler::new(symbols.clone())));
+    }
+    let mut debugger = Debugger::new();
+    debugger.set_symbols(symbols);
+    let console = Console::spawn();
+    let mut gdb = match gdb_port {
+        Some(port) => {
+            let stub = GdbStub::listen(("127.0.0.1", port)).map_err(|e| e.to_string())?;
+            println!("waiting for gdb on port {}", port);
+            Some(stub)
+        }
+        None => None,
+    };
+    if debug {
+        debugger.pause();
+        debugger
+            .print_location(&gameboy, &mut io::stdout())
+            .map_err(|e| e.to_string())?;
+        console.prompt();
+    }
 
-    {
-        // Acquire a lock. This lets us read and modify callback data.
-        let mut lock = device.lock();
-        (*lock).volume = 0.15;
-        // Lock guard is dropped here
+    // The movie being recorded or played back, its path and the frames run
+    let mut recording = None;
+    if let Some(path) = record_movie {
+        if let Some(state) = movie_state.as_ref() {
+            load_state(&mut gameboy, state)?;
+        }
+        recording = Some((Movie::record(&mut gameboy, movie_state.is_some()), path));
+    }
+    let mut playback = match play_movie {
+        Some(path) => {
+            let movie = Movie::load(&path)?;
+            movie.start(&mut gameboy)?;
+            println!("playing {} frames from {}", movie.len(), path.display());
+            Some(movie)
+        }
+        None => None,
+    };
+
+    // After the movie start, which can reset the APU or load its state
+    let mut vgm = match vgm_path {
+        Some(path) => Some(
+            VgmWriter::create(&path, &gameboy.memory().apu.borrow()).map_err(|e| e.to_string())?,
+        ),
+        None => None,
+    };
+    let mut movie_frame = 0;
+    let mut desynced = false;
+    // A frame the debugger stopped in finishes with the buttons it started
+    // with, so the input only changes at frame boundaries
+    let mut frame_end = 0;
+    let mut buttons = 0;
+    let mut video_samples = Vec::new();
+
+    'running: loop {
+        for event in event_pump.poll_iter() {
+            match event {
+                Event::Window {
+                    window_id,
+                    win_event: WindowEvent::Close,
+                    ..
+                } if window_id == vram_window.id() => vram_window.hide(),
+                // With the viewer open, closing the main window no longer
+                // sends Quit
+                Event::Quit { .. }
+                | Event::Window {
+                    win_event: WindowEvent::Close,
+                    ..
+                }
+                | Event::KeyDown {
+                    keycode: Some(Keycode::Escape),
+                    ..
+                } => break 'running,
+                Event::KeyDown {
+                    keycode: Some(key),
+                    repeat: false,
+                    ..
+                } if slot_key(key).is_some() => {
+                    slot = slot_key(key).unwrap();
+                    println!("state slot {}", slot);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F5),
+                    repeat: false,
+                    ..
+                } => {
+                    let path = state_path(&save_dir, &rom_path, slot);
+                    match save_state(&gameboy, &path) {
+                        Ok(()) => println!("saved state to {}", path.display()),
+                        Err(e) => eprintln!("could not save {}: {}", path.display(), e),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    repeat: false,
+                    ..
+                } if recording.is_some() || playback.is_some() => {
+                    println!("states cannot be loaded while a movie records or plays")
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F7),
+                    repeat: false,
+                    ..
+                } => {
+                    let path = state_path(&save_dir, &rom_path, slot);
+                    match load_state(&mut gameboy, &path) {
+                        Ok(()) => {
+                            // The buffered snapshots belong to another timeline
+                            rewind.clear();
+                            // The next frame starts from the loaded cycle count
+                            frame_end = 0;
+                            video_samples.clear();
+                            if let Some(vgm) = vgm.as_mut() {
+                                vgm.restart(&gameboy.memory().apu.borrow())
+                                    .map_err(|e| e.to_string())?;
+                            }
+                            println!("loaded state from {}", path.display())
+                        }
+                        Err(e) => eprintln!("could not load {}: {}", path.display(), e),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::T),
+                    repeat: false,
+                    ..
+                } => {
+                    if let Some(trace) = gameboy.cpu_mut().trace_mut() {
+                        trace.set_enabled(!trace.enabled());
+                        println!(
+                            "trace {}",
+                            if trace.enabled() { "resumed" } else { "paused" }
+                        );
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F8),
+                    keymod,
+                    repeat: false,
+                    ..
+                } => {
+                    let scale = if keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD) {
+                        display.scale()?
+                    } else {
+                        1
+                    };
+                    let path = screenshot_path(&screenshot_dir, &rom_path, screenshot_format);
+                    let saved = std::fs::create_dir_all(&screenshot_dir)
+                        .map_err(|e| e.to_string())
+                        .and_then(|()| gameboy.framebuffer().save(&path, scale as usize));
+                    match saved {
+                        Ok(()) => println!("saved screenshot to {}", path.display()),
+                        Err(e) => eprintln!("could not save screenshot: {}", e),
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F9),
+                    repeat: false,
+                    ..
+                } => {
+                    if vram_window.visible() {
+                        vram_window.hide();
+                    } else {
+                        vram_window.show(&vram::overview(gameboy.memory()))?;
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F10),
+                    repeat: false,
+                    ..
+                } => {
+                    for sprite in vram::sprites(gameboy.memory()) {
+                        println!("{}", sprite);
+                    }
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::F12),
+                    repeat: false,
+                    ..
+                } if !debugger.paused() => {
+                    debugger.pause();
+                    debugger
+                        .print_location(&gameboy, &mut io::stdout())
+                        .map_err(|e| e.to_string())?;
+                    console.prompt();
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::R),
+                    repeat: false,
+                    ..
+                } => rewinding = rewind_budget > 0 && recording.is_none() && playback.is_none(),
+                Event::KeyUp {
+                    keycode: Some(Keycode::R),
+                    ..
+                } => rewinding = false,
+                Event::KeyDown {
+                    keycode: Some(Keycode::Tab),
+                    repeat: false,
+                    ..
+                } => {
+                    turbo_held = true;
+                    pacer.set_turbo(true);
+                }
+                Event::KeyUp {
+                    keycode: Some(Keycode::Tab),
+                    ..
+                } => {
+                    turbo_held = false;
+                    pacer.set_turbo(turbo);
+                }
+                Event::KeyDown {
+                    keycode: Some(Keycode::Backquote),
+                    repeat: false,
+                    ..
+                } => {
+                    turbo = !turbo;
+                    pacer.set_turbo(turbo || turbo_held);
+                    println!("turbo {}", if turbo { "on" } else { "off" });
+                }
+                _ => input.handle_event(&event),
+            }
+        }
+
+        if rewinding {
+            if let Some((previous, state)) = rewind.step_back(frame) {
+                match gameboy.load_state(&state) {
+                    Ok(()) => {
+                        frame = previous;
+                        frame_end = 0;
+                        video_samples.clear();
+                        if let Some(vgm) = vgm.as_mut() {
+                            vgm.restart(&gameboy.memory().apu.borrow())
+                                .map_err(|e| e.to_string())?;
+                        }
+                    }
+                    Err(e) => {
+                        // The console is left as it was, carry on from there
+                        eprintln!("could not rewind: {}", e);
+                        rewind.clear();
+                        rewinding = false;
+                    }
+                }
+            }
+            display.present(gameboy.framebuffer())?;
+            std::thread::sleep(frame_duration(FRAME_RATE));
+            continue;
+        }
+
+        if let Some(stub) = gdb.as_mut() {
+            let keep_going = stub
+                .poll(&mut debugger, &mut gameboy)
+                .map_err(|e| e.to_string())?;
+            for event in stub.take_events() {
+                match event {
+                    GdbEvent::Error { .. } => eprintln!("{}", event),
+                    _ => println!("{}", event),
+                }
+            }
+            if !keep_going {
+                break 'running;
+            }
+        }
+
+        if debugger.paused() {
+            while let Some(line) = console.try_line() {
+                let keep_going = debugger
+                    .execute(&mut gameboy, &line, &mut io::stdout())
+                    .map_err(|e| e.to_string())?;
+                if !keep_going {
+                    break 'running;
+                }
+                vram_stale = true;
+                if debugger.paused() {
+                    console.prompt();
+                }
+            }
+            display.present(gameboy.framebuffer())?;
+            if vram_window.visible() && vram_stale {
+                vram_window.present(&vram::overview(gameboy.memory()))?;
+                vram_stale = false;
+            }
+            std::thread::sleep(frame_duration(FRAME_RATE));
+            continue;
+        }
+
+        if gameboy.cycles() >= frame_end {
+            let frame = CYCLES_PER_FRAME as u64;
+            frame_end = (gameboy.cycles() / frame + 1) * frame;
+            buttons = match playback.as_ref().map(|movie| movie.input(movie_frame)) {
+                Some(Some(movie_buttons)) => movie_buttons,
+                Some(None) => {
+                    println!("movie ended after {} frames", movie_frame);
+                    playback = None;
+                    input.pressed()
+                }
+                None => input.pressed(),
+            };
+            if let Some((movie, _)) = recording.as_mut() {
+                movie.push_input(buttons);
+            }
+        }
+        gameboy.set_buttons(buttons);
+        let stopped = debugger.run_frame(&mut gameboy);
+        let frame_done = gameboy.cycles() >= frame_end;
+        if frame_done {
+            movie_frame += 1;
+            if let Some((movie, _)) = recording.as_mut() {
+                movie.end_frame(&gameboy);
+            }
+            if let (Some(movie), false) = (playback.as_ref(), desynced) {
+                if let Err(desync) = movie.verify(movie_frame, &gameboy) {
+                    eprintln!("{}, playback goes on", desync);
+                    desynced = true;
+                }
+            }
+        }
+        debugger
+            .write_log(&mut io::stdout())
+            .map_err(|e| e.to_string())?;
+        if stopped {
+            debugger
+                .print_location(&gameboy, &mut io::stdout())
+                .map_err(|e| e.to_string())?;
+            console.prompt();
+        }
+        frame += 1;
+        if rewind_budget > 0 && frame % REWIND_INTERVAL == 0 {
+            rewind.capture(frame, &gameboy.save_state());
+        }
+        let samples = gameboy.audio_samples();
+        let memory = gameboy.memory();
+        if let Some(wav) = wav.as_mut() {
+            wav.push_samples(&samples).map_err(|e| e.to_string())?;
+        }
+        if let Some(video) = video.as_mut() {
+            // A frame the debugger stopped in is recorded once it finishes,
+            // with the audio of all its parts
+            video_samples.extend_from_slice(&samples);
+            if frame_done {
+                video
+                    .push_frame(gameboy.framebuffer(), &video_samples)
+                    .map_err(|e| e.to_string())?;
+                video_samples.clear();
+            }
+        }
+        if let Some(events) = link_events.as_ref() {
+            for event in events.lock().unwrap().drain(..) {
+                eprintln!("{}", event);
+            }
+        }
+        if let Some(prints) = prints.as_ref() {
+            report_prints(prints);
+        }
+        if let Some(stems) = stems.as_mut() {
+            let channels = memory.apu.borrow_mut().take_stems();
+            stems.push_stems(&channels).map_err(|e| e.to_string())?;
+        }
+        if let Some(vgm) = vgm.as_mut() {
+            let writes = memory.apu.borrow_mut().take_register_writes();
+            vgm.write_registers(&writes).map_err(|e| e.to_string())?;
+        }
+        if pacer.should_queue_audio(&buffer) {
+            output.set_rate_adjustment(pacer.rate_adjustment(&buffer));
+            if pacer.turbo() {
+                output.push_fast_forward(&samples, pacer.speed());
+            } else {
+                output.push_samples(&samples);
+            }
+        }
+
+        display.present(gameboy.framebuffer())?;
+        if vram_window.visible() && frame % VRAM_VIEW_INTERVAL == 0 {
+            vram_window.present(&vram::overview(gameboy.memory()))?;
+        }
+        pacer.wait(&buffer);
     }
 
-    // Play for another second
-    std::thread::sleep(Duration::from_millis(1_000));
+    device.pause();
 
-    // Device is automatically closed when dropped
+    if let Some(trace) = gameboy.cpu_mut().set_trace(None) {
+        trace.finish().map_err(|e| e.to_string())?;
+    }
+    if let (Some(path), Some(cdl)) = (cdl_path.as_ref(), gameboy.memory().cdl.borrow().as_ref()) {
+        cdl.save(path)?;
+    }
+    if let (Some(path), Some(profiler)) = (profile_path.as_ref(), gameboy.profiler()) {
+        profiler
+            .save_folded(path)
+            .map_err(|e| format!("could not write {}: {}", path.display(), e))?;
+        profiler
+            .write_report(&mut io::stdout(), PROFILE_REPORT_LINES)
+            .map_err(|e| e.to_string())?;
+    }
+    let memory = gameboy.memory();
+    if let Some(prints) = prints.as_ref() {
+        // Unplugging the printer tears off the strip still in it
+        memory
+            .serial
+            .borrow_mut()
+            .set_link(Box::new(DisconnectedLink));
+        report_prints(prints);
+    }
+    if header.has_battery() {
+        std::fs::create_dir_all(&save_dir).map_err(|e| e.to_string())?;
+        std::fs::write(&save_path, memory.cartridge_ram())
+            .map_err(|e| format!("could not write {}: {}", save_path.display(), e))?;
+    }
+    if let Some(wav) = wav {
+        wav.finish().map_err(|e| e.to_string())?;
+    }
+    if let Some((movie, path)) = recording {
+        movie.save(&path)?;
+        println!(
+            "recorded {} frames of input to {}",
+            movie.len(),
+            path.display()
+        );
+    }
+    if let Some(video) = video {
+        println!("recorded {} frames", video.frames());
+        video.finish().map_err(|e| e.to_string())?;
+    }
+    if let Some(stems) = stems {
+        stems.finish().map_err(|e| e.to_string())?;
+    }
+    if let Some(vgm) = vgm {
+        let end = memory.apu.borrow().cycles();
+        vgm.finish(end).map_err(|e| e.to_string())?;
+    }
+
+    if buffer.underruns() > 0 || buffer.overruns() > 0 {
+        println!(
+            "audio underruns: {}, overruns: {}",
+            buffer.underruns(),
+            buffer.overruns()
+        );
+    }
 
     Ok(())
 }
@@ -1,8 +1,55 @@
+use super::apu::Apu;
+use super::apu::{NR10, WAVE_RAM_END};
+use super::cdl::CodeDataLog;
+use super::joypad::{Joypad, P1};
+use super::palette::{PaletteRam, BCPS, OCPD};
+use super::savestate::{StateReader, StateWriter};
+use super::serial::{Serial, SB, SC};
+use super::watchpoint::Watchpoints;
 use std::cell::RefCell;
 
+// Upper byte of the divider the APU frame sequencer runs off
+pub const DIV: usize = 0xFF04;
+pub const IF: usize = 0xFF0F;
+// Writing a non-zero value unmaps the boot ROM until the next reset
+pub const BOOT: usize = 0xFF50;
+
+pub const ROM_END: usize = 0x7FFF;
+pub const CARTRIDGE_RAM_START: usize = 0xA000;
+pub const CARTRIDGE_RAM_END: usize = 0xBFFF;
+
+// The CGB boot ROM is split around the cartridge header
+const BOOT_ROM_HEADER_START: usize = 0x0100;
+const BOOT_ROM_HEADER_END: usize = 0x01FF;
+
+const STATE_VERSION: u8 = 1;
+
+// Interrupt request bits in IF
+pub const INT_SERIAL: u8 = 0x08;
+pub const INT_JOYPAD: u8 = 0x10;
+
+// Bank mapped at location after a reset: bank 1 in the switchable ROM and
+// WRAM areas and bank 0 everywhere else
+pub fn reset_bank(location: usize) -> u16 {
+    match location {
+        0x4000..=ROM_END | 0xD000..=0xDFFF => 1,
+        _ => 0,
+    }
+}
+
 #[derive(Debug)]
 pub struct MemoryMap {
     pub mem: RefCell<Vec<u8>>,
+    pub apu: RefCell<Apu>,
+    pub joypad: RefCell<Joypad>,
+    pub serial: RefCell<Serial>,
+    // CGB palette RAM behind BCPS/BCPD and OCPS/OCPD
+    pub palettes: RefCell<PaletteRam>,
+    pub watchpoints: RefCell<Watchpoints>,
+    // Code/data log of the ROM, while one is being recorded
+    pub cdl: RefCell<Option<CodeDataLog>>,
+    boot_rom: RefCell<Option<Vec<u8>>>,
+    io_mapped: bool,
 }
 
 impl MemoryMap {
@@ -11,16 +58,251 @@ impl MemoryMap {
         mem.resize(memory_size, 0);
         MemoryMap {
             mem: RefCell::new(mem),
+            apu: RefCell::new(Apu::new()),
+            joypad: RefCell::new(Joypad::new()),
+            serial: RefCell::new(Serial::new()),
+            palettes: RefCell::new(PaletteRam::new()),
+            watchpoints: RefCell::new(Watchpoints::new()),
+            cdl: RefCell::new(None),
+            boot_rom: RefCell::new(None),
+            io_mapped: false,
+        }
+    }
+
+    // Same as new, but accesses to the I/O registers are routed to the
+    // peripherals instead of plain memory.
+    pub fn with_io(memory_size: usize) -> MemoryMap {
+        let mut map = MemoryMap::new(memory_size);
+        map.io_mapped = true;
+        map
+    }
+
+    // Clears memory and peripheral state as on power on. Links and output
+    // settings of the peripherals are kept, and so are the watchpoints and
+    // the code/data log.
+    pub fn reset(&self) {
+        for byte in self.mem.borrow_mut().iter_mut() {
+            *byte = 0;
+        }
+        *self.boot_rom.borrow_mut() = None;
+        self.apu.borrow_mut().reset();
+        *self.joypad.borrow_mut() = Joypad::new();
+        self.serial.borrow_mut().reset();
+        *self.palettes.borrow_mut() = PaletteRam::new();
+    }
+
+    // Copies the ROM into the cartridge area. Only the first 32KB is visible
+    // as there is no bank controller.
+    pub fn load_rom(&self, rom: &[u8]) {
+        let mut my_ref = self.mem.borrow_mut();
+        let len = rom.len().min(ROM_END + 1).min(my_ref.len());
+        my_ref[..len].copy_from_slice(&rom[..len]);
+    }
+
+    // Maps a boot ROM over the start of the cartridge until BOOT is written
+    pub fn load_boot_rom(&self, boot_rom: Vec<u8>) {
+        *self.boot_rom.borrow_mut() = Some(boot_rom);
+    }
+
+    pub fn boot_rom_mapped(&self) -> bool {
+        self.boot_rom.borrow().is_some()
+    }
+
+    // Contents of the external RAM, for battery saves
+    pub fn cartridge_ram(&self) -> Vec<u8> {
+        self.mem.borrow()[CARTRIDGE_RAM_START..=CARTRIDGE_RAM_END].to_vec()
+    }
+
+    pub fn load_cartridge_ram(&self, ram: &[u8]) {
+        let mut my_ref = self.mem.borrow_mut();
+        let len = ram.len().min(CARTRIDGE_RAM_END - CARTRIDGE_RAM_START + 1);
+        my_ref[CARTRIDGE_RAM_START..CARTRIDGE_RAM_START + len].copy_from_slice(&ram[..len]);
+    }
+
+    // Everything past the cartridge ROM, which is not part of the state.
+    // Peripherals save their own state.
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(STATE_VERSION);
+        writer.write_bool(self.boot_rom_mapped());
+        let my_ref = self.mem.borrow();
+        writer.write_bytes(&my_ref[(ROM_END + 1).min(my_ref.len())..]);
+    }
+
+    pub fn load_state(&self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_version("memory", STATE_VERSION)?;
+        if reader.read_bool()? {
+            if !self.boot_rom_mapped() {
+                return Err("save state was made while the boot ROM was running".to_string());
+            }
+        } else {
+            *self.boot_rom.borrow_mut() = None;
+        }
+
+        let bytes = reader.read_bytes()?;
+        let mut my_ref = self.mem.borrow_mut();
+        let start = (ROM_END + 1).min(my_ref.len());
+        if bytes.len() != my_ref.len() - start {
+            return Err(format!(
+                "save state holds {} bytes of memory, expected {}",
+                bytes.len(),
+                my_ref.len() - start
+            ));
         }
+        my_ref[start..].copy_from_slice(bytes);
+        Ok(())
     }
 
     pub fn write(&self, location: usize, value: u8) {
+        if let Some(cdl) = self.cdl.borrow_mut().as_mut() {
+            cdl.log_write(location as u16, value);
+        }
+        if self.watchpoints.borrow().is_empty() {
+            self.store(location, value);
+            return;
+        }
+
+        let old = self.peek(location);
+        self.store(location, value);
+        let after = self.peek(location);
+        self.watchpoints
+            .borrow_mut()
+            .check_write(location as u16, old, value, after);
+    }
+
+    fn store(&self, location: usize, value: u8) {
+        if self.io_mapped {
+            match location {
+                // Bank controller writes, ignored without one
+                0..=ROM_END => return,
+                BOOT => {
+                    if value != 0 {
+                        *self.boot_rom.borrow_mut() = None;
+                    }
+                    return;
+                }
+                P1 => {
+                    if self.joypad.borrow_mut().write(value) {
+                        self.request_interrupt(INT_JOYPAD);
+                    }
+                    return;
+                }
+                SB | SC => {
+                    self.serial.borrow_mut().write(location, value);
+                    return;
+                }
+                DIV => {
+                    self.apu.borrow_mut().reset_div();
+                    return;
+                }
+                NR10..=WAVE_RAM_END => {
+                    self.apu.borrow_mut().write(location, value);
+                    return;
+                }
+                BCPS..=OCPD => {
+                    self.palettes.borrow_mut().write(location, value);
+                    return;
+                }
+                _ => {}
+            }
+        }
+
         let mut my_ref = self.mem.borrow_mut();
         my_ref[location] = value;
     }
 
     pub fn read(&self, location: usize) -> u8 {
+        let value = self.peek(location);
+        if let Some(cdl) = self.cdl.borrow_mut().as_mut() {
+            if let Some(offset) = self.rom_offset(location) {
+                cdl.log_read(location as u16, offset, value);
+            }
+        }
+        let mut watchpoints = self.watchpoints.borrow_mut();
+        if !watchpoints.is_empty() {
+            watchpoints.check_read(location as u16, value);
+        }
+        value
+    }
+
+    // Reads without triggering watchpoints, for debugging tools
+    pub fn peek(&self, location: usize) -> u8 {
+        if let Some(boot_rom) = self.boot_rom.borrow().as_ref() {
+            let in_header = (BOOT_ROM_HEADER_START..=BOOT_ROM_HEADER_END).contains(&location);
+            if location < boot_rom.len() && !in_header {
+                return boot_rom[location];
+            }
+        }
+
+        if self.io_mapped {
+            match location {
+                P1 => return self.joypad.borrow().read(),
+                BOOT => return 0xFF,
+                SB | SC => return self.serial.borrow().read(location),
+                DIV => return (self.apu.borrow().div() >> 8) as u8,
+                NR10..=WAVE_RAM_END => return self.apu.borrow().read(location),
+                BCPS..=OCPD => return self.palettes.borrow().read(location),
+                _ => {}
+            }
+        }
+
         let my_ref = self.mem.borrow();
         return my_ref[location];
     }
+
+    // Position in the ROM file of the byte mapped at location, None outside
+    // the ROM and where the boot ROM covers it
+    pub fn rom_offset(&self, location: usize) -> Option<usize> {
+        if location > ROM_END {
+            return None;
+        }
+        if let Some(boot_rom) = self.boot_rom.borrow().as_ref() {
+            let in_header = (BOOT_ROM_HEADER_START..=BOOT_ROM_HEADER_END).contains(&location);
+            if location < boot_rom.len() && !in_header {
+                return None;
+            }
+        }
+        Some(match location {
+            0..=0x3FFF => location,
+            _ => self.bank(location) as usize * 0x4000 + location - 0x4000,
+        })
+    }
+
+    // Marks the instruction the CPU is about to run in the code/data log
+    pub fn log_instruction(&self, address: u16, length: u16) {
+        if let Some(cdl) = self.cdl.borrow_mut().as_mut() {
+            cdl.log_instruction(address, length, |address| self.rom_offset(address as usize));
+        }
+    }
+
+    // Bank mapped at location, numbered as in RGBDS symbol files
+    pub fn bank(&self, location: usize) -> u16 {
+        // There is no bank controller or SVBK yet, so the reset mapping holds
+        reset_bank(location)
+    }
+
+    pub fn request_interrupt(&self, interrupt: u8) {
+        let mut my_ref = self.mem.borrow_mut();
+        my_ref[IF] |= interrupt;
+    }
+
+    // Updates the pressed buttons (see joypad::Button::mask), requesting the
+    // joypad interrupt when a selected line goes low.
+    pub fn set_buttons(&self, pressed: u8) {
+        if self.joypad.borrow_mut().set_pressed(pressed) {
+            self.request_interrupt(INT_JOYPAD);
+        }
+    }
+
+    // Advances the peripherals by the given number of clock cycles
+    pub fn tick(&self, cycles: u32) {
+        if self.io_mapped {
+            self.apu.borrow_mut().step(cycles);
+            if self.serial.borrow_mut().step(cycles) {
+                self.request_interrupt(INT_SERIAL);
+            }
+        }
+    }
 }
+
+#[cfg(test)]
+mod test;
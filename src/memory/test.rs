@@ -0,0 +1,135 @@
+use super::*;
+use crate::apu::{NR41, NR42, NR44, NR52};
+use crate::cdl::{CodeDataLog, CDL_DATA, CDL_OPCODE, CDL_OPERAND};
+use crate::joypad::Button;
+use crate::serial::TRANSFER_CYCLES;
+
+#[test]
+fn flat_memory_without_io() {
+    let mem = MemoryMap::new(0x10000);
+
+    mem.write(P1, 0x12);
+    mem.write(NR10, 0x55);
+    assert_eq!(mem.read(P1), 0x12);
+    assert_eq!(mem.read(NR10), 0x55);
+}
+
+#[test]
+fn io_routed_to_peripherals() {
+    let mem = MemoryMap::with_io(0x10000);
+
+    mem.write(NR10, 0x55);
+    assert_eq!(mem.read(NR10), 0x80);
+    assert_eq!(mem.read(P1), 0xFF);
+}
+
+#[test]
+fn div_is_the_apu_divider() {
+    let mem = MemoryMap::with_io(0x10000);
+
+    mem.tick(0x1234);
+    assert_eq!(mem.read(DIV), 0x12);
+    assert_eq!(mem.apu.borrow().div(), 0x1234);
+    mem.write(DIV, 0x99);
+    assert_eq!(mem.read(DIV), 0x00);
+    assert_eq!(mem.apu.borrow().div(), 0);
+}
+
+// Resetting DIV while bit 12 of the divider is set clocks the frame
+// sequencer, here expiring a length counter of 1
+#[test]
+fn div_write_clocks_the_frame_sequencer() {
+    let mem = MemoryMap::with_io(0x10000);
+    mem.write(NR52, 0x80);
+    mem.write(NR42, 0xF0);
+    mem.write(NR41, 0x3F);
+    mem.write(NR44, 0xC0);
+
+    mem.tick(0x1000);
+    assert_eq!(mem.read(NR52) & 0x08, 0x08);
+    mem.write(DIV, 0);
+    assert_eq!(mem.read(NR52) & 0x08, 0x00);
+}
+
+#[test]
+fn joypad_interrupt() {
+    let mem = MemoryMap::with_io(0x10000);
+
+    mem.write(P1, 0x10);
+    mem.set_buttons(Button::Up.mask());
+    assert_eq!(mem.read(IF) & INT_JOYPAD, 0);
+
+    mem.set_buttons(Button::Up.mask() | Button::Start.mask());
+    assert_eq!(mem.read(IF) & INT_JOYPAD, INT_JOYPAD);
+    assert_eq!(mem.read(P1), 0xD7);
+}
+
+#[test]
+fn serial_interrupt() {
+    let mem = MemoryMap::with_io(0x10000);
+
+    mem.write(SB, 0x42);
+    mem.write(SC, 0x81);
+    mem.tick(TRANSFER_CYCLES);
+    assert_eq!(mem.read(IF) & INT_SERIAL, INT_SERIAL);
+    assert_eq!(mem.read(SB), 0xFF);
+}
+
+#[test]
+fn rom_is_read_only_with_io() {
+    let mem = MemoryMap::with_io(0x10000);
+
+    mem.load_rom(&[0x31, 0xFE, 0xFF]);
+    mem.write(0x2000, 0x01);
+    assert_eq!(mem.read(0x0000), 0x31);
+    assert_eq!(mem.read(0x2000), 0x00);
+}
+
+#[test]
+fn boot_rom_overlay() {
+    let mem = MemoryMap::with_io(0x10000);
+    let mut rom = vec![0xAA; 0x8000];
+    rom[0x0100] = 0x00;
+    mem.load_rom(&rom);
+    let mut boot_rom = vec![0x11; 0x900];
+    boot_rom[0x0000] = 0x31;
+
+    mem.load_boot_rom(boot_rom);
+    assert!(mem.boot_rom_mapped());
+    assert_eq!(mem.read(0x0000), 0x31);
+    assert_eq!(mem.read(0x0100), 0x00);
+    assert_eq!(mem.read(0x0200), 0x11);
+    assert_eq!(mem.read(0x0900), 0xAA);
+
+    mem.write(BOOT, 0x01);
+    assert!(!mem.boot_rom_mapped());
+    assert_eq!(mem.read(0x0000), 0xAA);
+}
+
+#[test]
+fn cartridge_ram_round_trip() {
+    let mem = MemoryMap::with_io(0x10000);
+
+    mem.load_cartridge_ram(&[1, 2, 3]);
+    assert_eq!(mem.read(CARTRIDGE_RAM_START + 2), 3);
+    let ram = mem.cartridge_ram();
+    assert_eq!(ram.len(), 0x2000);
+    assert_eq!(&ram[..3], &[1, 2, 3]);
+}
+
+#[test]
+fn code_data_log() {
+    let mem = MemoryMap::with_io(0x10000);
+    *mem.cdl.borrow_mut() = Some(CodeDataLog::new(0x8000));
+
+    mem.log_instruction(0x4100, 2);
+    mem.read(0x4101);
+    mem.read(0x0200);
+    // Outside the ROM
+    mem.read(0xC000);
+    let cdl = mem.cdl.borrow();
+    let flags = cdl.as_ref().unwrap().flags();
+    assert_eq!(flags[0x4100], CDL_OPCODE);
+    assert_eq!(flags[0x4101], CDL_OPERAND);
+    assert_eq!(flags[0x0200], CDL_DATA);
+}
@@ -0,0 +1,43 @@
+// Hardware revision being emulated
+
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Model {
+    Dmg,
+    Cgb,
+}
+
+impl Model {
+    pub const ALL: [Model; 2] = [Model::Dmg, Model::Cgb];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Model::Dmg => "dmg",
+            Model::Cgb => "cgb",
+        }
+    }
+
+    // Size of the boot ROM image; the CGB one skips the header at 0x100-0x1FF
+    pub fn boot_rom_size(self) -> usize {
+        match self {
+            Model::Dmg => 0x100,
+            Model::Cgb => 0x900,
+        }
+    }
+}
+
+impl FromStr for Model {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Model, String> {
+        Model::ALL
+            .iter()
+            .find(|model| model.name() == s.to_lowercase())
+            .copied()
+            .ok_or_else(|| format!("unknown model '{}', expected dmg or cgb", s))
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,8 @@
+use super::*;
+
+#[test]
+fn parse_names() {
+    assert_eq!("dmg".parse::<Model>(), Ok(Model::Dmg));
+    assert_eq!("CGB".parse::<Model>(), Ok(Model::Cgb));
+    assert!("gba".parse::<Model>().is_err());
+}
@@ -0,0 +1,289 @@
+// Input movies: the buttons held in every frame from power-on or from a save
+// state, replayed bit for bit. The emulation is deterministic given its
+// start and the input, since nothing reads the host clock or a random source
+// and input is only applied at frame boundaries. An RTC or anything else
+// that follows real time has to follow emulated cycles while a movie runs.
+//
+// Playback compares a hash of the machine state with the one recorded every
+// hash_interval frames, which catches a desync close to where it happened.
+//
+// File layout, integers little endian, strings and byte arrays prefixed with
+// a u32 length:
+//
+//   "GBMV"  magic
+//   u16     format version (MOVIE_VERSION)
+//   string  emulator version that wrote the file
+//   u8      model (0 DMG, 1 CGB)
+//   u32     CRC-32 of the whole ROM
+//   string  ROM title from the cartridge header
+//   u32     CRC-32 of the boot ROM, 0 when starting past it
+//   u32     hash interval in frames
+//   u8      start: 0 power-on, 1 save state
+//   bytes   cartridge RAM at power-on, or the save state
+//   bytes   one byte per frame, the buttons as joypad::Button masks
+//   u32     number of hashes, then per hash
+//     u32   frames run when it was taken
+//     u64   GameBoy::state_hash
+
+use super::gameboy::GameBoy;
+use super::image::crc32;
+use super::model::Model;
+use super::savestate::{StateReader, StateWriter};
+use std::fs;
+use std::path::Path;
+
+pub const MOVIE_MAGIC: [u8; 4] = *b"GBMV";
+pub const MOVIE_VERSION: u16 = 1;
+// Once a second
+pub const DEFAULT_HASH_INTERVAL: u32 = 60;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum MovieStart {
+    // Battery backed RAM is part of power-on, as the game sees it
+    PowerOn { cartridge_ram: Vec<u8> },
+    State(Vec<u8>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Movie {
+    pub emulator_version: String,
+    pub model: Model,
+    pub rom_crc: u32,
+    pub title: String,
+    pub boot_rom_crc: Option<u32>,
+    pub hash_interval: u32,
+    pub start: MovieStart,
+    inputs: Vec<u8>,
+    hashes: Vec<(u32, u64)>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Desync {
+    pub frame: u32,
+    pub expected: u64,
+    pub actual: u64,
+}
+
+impl std::fmt::Display for Desync {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "movie desynced by frame {}: state hash {:016X}, recorded {:016X}",
+            self.frame, self.actual, self.expected
+        )
+    }
+}
+
+impl Movie {
+    // A movie starting from where gameboy is now: from power-on with its
+    // current cartridge RAM, or from a save state of it. Starting from
+    // power-on resets the console.
+    pub fn record(gameboy: &mut GameBoy, from_state: bool) -> Movie {
+        let start = if from_state {
+            MovieStart::State(gameboy.save_state())
+        } else {
+            let cartridge_ram = gameboy.memory().cartridge_ram();
+            gameboy.reset();
+            gameboy.memory().load_cartridge_ram(&cartridge_ram);
+            MovieStart::PowerOn { cartridge_ram }
+        };
+        Movie {
+            emulator_version: env!("CARGO_PKG_VERSION").to_string(),
+            model: gameboy.model(),
+            rom_crc: gameboy.rom_crc(),
+            title: gameboy
+                .header()
+                .map(|header| header.title.clone())
+                .unwrap_or_default(),
+            boot_rom_crc: gameboy.boot_rom().map(crc32),
+            hash_interval: DEFAULT_HASH_INTERVAL,
+            start,
+            inputs: Vec::new(),
+            hashes: Vec::new(),
+        }
+    }
+
+    // Puts gameboy where the movie starts, refusing a console set up
+    // differently from the one it was recorded on
+    pub fn start(&self, gameboy: &mut GameBoy) -> Result<(), String> {
+        if self.rom_crc != gameboy.rom_crc() {
+            return Err(format!(
+                "movie is for '{}' (CRC {:08X}), not the loaded ROM (CRC {:08X})",
+                self.title,
+                self.rom_crc,
+                gameboy.rom_crc()
+            ));
+        }
+        if self.model != gameboy.model() {
+            return Err(format!(
+                "movie is for the {} model, running as {}",
+                self.model.name(),
+                gameboy.model().name()
+            ));
+        }
+        match &self.start {
+            MovieStart::PowerOn { cartridge_ram } => {
+                let boot_rom_crc = gameboy.boot_rom().map(crc32);
+                if self.boot_rom_crc != boot_rom_crc {
+                    return Err(match self.boot_rom_crc {
+                        Some(crc) => format!("movie needs the boot ROM with CRC {:08X}", crc),
+                        None => "movie was recorded without a boot ROM".to_string(),
+                    });
+                }
+                gameboy.reset();
+                gameboy.memory().load_cartridge_ram(cartridge_ram);
+                Ok(())
+            }
+            MovieStart::State(state) => gameboy.load_state(state),
+        }
+    }
+
+    // Frames of input
+    pub fn len(&self) -> usize {
+        self.inputs.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.inputs.is_empty()
+    }
+
+    // Buttons held in frame, counting from 0
+    pub fn input(&self, frame: usize) -> Option<u8> {
+        self.inputs.get(frame).copied()
+    }
+
+    // Adds the buttons of the next frame
+    pub fn push_input(&mut self, buttons: u8) {
+        self.inputs.push(buttons);
+    }
+
+    // Call after running each recorded frame
+    pub fn end_frame(&mut self, gameboy: &GameBoy) {
+        let frame = self.inputs.len() as u32;
+        if frame.is_multiple_of(self.hash_interval) {
+            self.hashes.push((frame, gameboy.state_hash()));
+        }
+    }
+
+    // Call after playing back frames frames. Fails when a hash was recorded
+    // at this point and the state differs.
+    pub fn verify(&self, frames: usize, gameboy: &GameBoy) -> Result<(), Desync> {
+        let frame = frames as u32;
+        match self
+            .hashes
+            .binary_search_by_key(&frame, |(frame, _)| *frame)
+        {
+            Ok(index) => {
+                let expected = self.hashes[index].1;
+                let actual = gameboy.state_hash();
+                if actual == expected {
+                    Ok(())
+                } else {
+                    Err(Desync {
+                        frame,
+                        expected,
+                        actual,
+                    })
+                }
+            }
+            Err(_) => Ok(()),
+        }
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut writer = StateWriter::new();
+        writer.write_u32(u32::from_le_bytes(MOVIE_MAGIC));
+        writer.write_u16(MOVIE_VERSION);
+        writer.write_str(&self.emulator_version);
+        writer.write_u8(match self.model {
+            Model::Dmg => 0,
+            Model::Cgb => 1,
+        });
+        writer.write_u32(self.rom_crc);
+        writer.write_str(&self.title);
+        writer.write_u32(self.boot_rom_crc.unwrap_or(0));
+        writer.write_u32(self.hash_interval);
+        match &self.start {
+            MovieStart::PowerOn { cartridge_ram } => {
+                writer.write_u8(0);
+                writer.write_bytes(cartridge_ram);
+            }
+            MovieStart::State(state) => {
+                writer.write_u8(1);
+                writer.write_bytes(state);
+            }
+        }
+        writer.write_bytes(&self.inputs);
+        writer.write_u32(self.hashes.len() as u32);
+        for (frame, hash) in self.hashes.iter() {
+            writer.write_u32(*frame);
+            writer.write_u64(*hash);
+        }
+        writer.into_bytes()
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Movie, String> {
+        let mut reader = StateReader::new(data);
+        if reader.read_u32().ok() != Some(u32::from_le_bytes(MOVIE_MAGIC)) {
+            return Err("not a movie file".to_string());
+        }
+        let version = reader.read_u16()?;
+        if version > MOVIE_VERSION {
+            return Err(format!(
+                "movie format {} is newer than this build supports ({})",
+                version, MOVIE_VERSION
+            ));
+        }
+        let emulator_version = reader.read_str()?;
+        let model = match reader.read_u8()? {
+            0 => Model::Dmg,
+            1 => Model::Cgb,
+            other => return Err(format!("movie has unknown model {}", other)),
+        };
+        let rom_crc = reader.read_u32()?;
+        let title = reader.read_str()?;
+        let boot_rom_crc = match reader.read_u32()? {
+            0 => None,
+            crc => Some(crc),
+        };
+        let hash_interval = reader.read_u32()?.max(1);
+        let start = match reader.read_u8()? {
+            0 => MovieStart::PowerOn {
+                cartridge_ram: reader.read_bytes()?.to_vec(),
+            },
+            1 => MovieStart::State(reader.read_bytes()?.to_vec()),
+            other => return Err(format!("movie has unknown start {}", other)),
+        };
+        let inputs = reader.read_bytes()?.to_vec();
+        let count = reader.read_u32()?;
+        let mut hashes = Vec::new();
+        for _ in 0..count {
+            hashes.push((reader.read_u32()?, reader.read_u64()?));
+        }
+        Ok(Movie {
+            emulator_version,
+            model,
+            rom_crc,
+            title,
+            boot_rom_crc,
+            hash_interval,
+            start,
+            inputs,
+            hashes,
+        })
+    }
+
+    pub fn load(path: &Path) -> Result<Movie, String> {
+        let data =
+            fs::read(path).map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        Movie::parse(&data).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        fs::write(path, self.to_bytes())
+            .map_err(|e| format!("could not write {}: {}", path.display(), e))
+    }
+}
+
+#[cfg(test)]
+mod test;
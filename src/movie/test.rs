@@ -0,0 +1,117 @@
+use super::*;
+use crate::joypad::Button;
+
+fn rom() -> Vec<u8> {
+    let mut rom = vec![0xC5; 0x8000];
+    rom[0x0100] = 0x06;
+    rom[0x0101] = 0x42;
+    rom
+}
+
+fn console() -> GameBoy {
+    let mut gameboy = GameBoy::new(Model::Dmg);
+    gameboy.load_rom(rom()).unwrap();
+    gameboy
+}
+
+// Records frames frames holding A on every other one
+fn record(gameboy: &mut GameBoy, from_state: bool, frames: usize) -> Movie {
+    let mut movie = Movie::record(gameboy, from_state);
+    movie.hash_interval = 2;
+    for frame in 0..frames {
+        let buttons = if frame % 2 == 0 { Button::A.mask() } else { 0 };
+        movie.push_input(buttons);
+        gameboy.set_buttons(buttons);
+        gameboy.run_frame();
+        movie.end_frame(gameboy);
+    }
+    movie
+}
+
+// Plays the whole movie back, returning the first desync
+fn play(movie: &Movie, gameboy: &mut GameBoy) -> Result<(), Desync> {
+    movie.start(gameboy).unwrap();
+    for frame in 0..movie.len() {
+        gameboy.set_buttons(movie.input(frame).unwrap());
+        gameboy.run_frame();
+        movie.verify(frame + 1, gameboy)?;
+    }
+    Ok(())
+}
+
+#[test]
+fn plays_back_from_power_on() {
+    let mut gameboy = console();
+    gameboy.run_frame();
+    let movie = record(&mut gameboy, false, 4);
+    let hash = gameboy.state_hash();
+
+    let mut other = console();
+    assert_eq!(play(&movie, &mut other), Ok(()));
+    assert_eq!(other.state_hash(), hash);
+}
+
+#[test]
+fn plays_back_from_a_state() {
+    let mut gameboy = console();
+    gameboy.run_frame();
+    let movie = record(&mut gameboy, true, 3);
+    let hash = gameboy.state_hash();
+
+    let mut other = console();
+    assert_eq!(play(&movie, &mut other), Ok(()));
+    assert_eq!(other.state_hash(), hash);
+    assert_eq!(
+        other.cycles(),
+        gameboy.cycles(),
+        "the state carries the cycle count"
+    );
+}
+
+#[test]
+fn detects_desyncs() {
+    let mut gameboy = console();
+    let mut movie = record(&mut gameboy, false, 4);
+    // Start held in the last frame is still held when the hash is taken
+    movie.inputs[3] = Button::Start.mask();
+
+    let desync = play(&movie, &mut console()).unwrap_err();
+    assert_eq!(desync.frame, 4);
+    assert_ne!(desync.expected, desync.actual);
+}
+
+#[test]
+fn file_round_trip() {
+    let mut gameboy = console();
+    let movie = record(&mut gameboy, true, 3);
+    let data = movie.to_bytes();
+    assert_eq!(&data[..4], b"GBMV");
+    assert_eq!(Movie::parse(&data), Ok(movie));
+
+    assert_eq!(Movie::parse(b"GBST").unwrap_err(), "not a movie file");
+}
+
+#[test]
+fn refuses_other_consoles() {
+    let movie = record(&mut console(), false, 1);
+
+    let mut other = rom();
+    other[0x0134] = b'X';
+    let mut gameboy = GameBoy::new(Model::Dmg);
+    gameboy.load_rom(other).unwrap();
+    assert!(movie
+        .start(&mut gameboy)
+        .unwrap_err()
+        .contains("not the loaded ROM"));
+
+    let mut cgb = GameBoy::new(Model::Cgb);
+    cgb.load_rom(rom()).unwrap();
+    assert!(movie.start(&mut cgb).is_err());
+
+    let mut booting = console();
+    booting.set_boot_rom(Some(vec![0; 0x100])).unwrap();
+    assert_eq!(
+        movie.start(&mut booting).unwrap_err(),
+        "movie was recorded without a boot ROM"
+    );
+}
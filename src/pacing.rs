@@ -0,0 +1,189 @@
+// Frame pacing: keeps the emulator running at the speed of the real hardware
+// by syncing either to the audio queue or to the display refresh.
+
+use super::apu::CPU_CLOCK_HZ;
+use super::audio::SampleBuffer;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+pub const CYCLES_PER_FRAME: u32 = 70_224;
+pub const FRAME_RATE: f64 = CPU_CLOCK_HZ as f64 / CYCLES_PER_FRAME as f64;
+
+// How far dynamic rate control may bend the audio rate. Small enough that
+// the pitch change is inaudible.
+const MAX_RATE_DELTA: f64 = 0.005;
+// Display refresh assumed by --sync video until the frontend asks the
+// display for its real rate
+pub const DEFAULT_REFRESH_RATE: f64 = 60.0;
+// Weight of the newest frame in the measured turbo speed
+const SPEED_SMOOTHING: f64 = 0.1;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SyncMode {
+    // Block on the audio queue and steer its fill level with dynamic rate control
+    Audio,
+    // One emulated frame per display refresh at the given rate in Hz
+    Video(f64),
+}
+
+impl FromStr for SyncMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<SyncMode, String> {
+        match s {
+            "audio" => Ok(SyncMode::Audio),
+            "video" => Ok(SyncMode::Video(DEFAULT_REFRESH_RATE)),
+            _ => Err(format!(
+                "unknown sync mode '{}', expected audio or video",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TurboAudio {
+    Mute,
+    // Keep all of the audio by resampling it to the turbo speed, see
+    // audio::FastForward
+    TimeStretch,
+}
+
+impl FromStr for TurboAudio {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TurboAudio, String> {
+        match s {
+            "mute" => Ok(TurboAudio::Mute),
+            "stretch" => Ok(TurboAudio::TimeStretch),
+            _ => Err(format!(
+                "unknown turbo audio '{}', expected mute or stretch",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct FramePacer {
+    mode: SyncMode,
+    turbo: bool,
+    turbo_audio: TurboAudio,
+    // Audio frames the queue should hold to ride out host scheduling jitter
+    target_fill: usize,
+    next_frame: Instant,
+    // Emulated frames per frame of real time while in turbo, smoothed
+    speed: f64,
+    last_frame: Instant,
+}
+
+impl FramePacer {
+    pub fn new(mode: SyncMode, target_fill: usize) -> FramePacer {
+        FramePacer {
+            mode,
+            turbo: false,
+            turbo_audio: TurboAudio::Mute,
+            target_fill,
+            next_frame: Instant::now(),
+            speed: 1.0,
+            last_frame: Instant::now(),
+        }
+    }
+
+    pub fn mode(&self) -> SyncMode {
+        self.mode
+    }
+
+    pub fn set_mode(&mut self, mode: SyncMode) {
+        self.mode = mode;
+        self.next_frame = Instant::now();
+    }
+
+    pub fn turbo(&self) -> bool {
+        self.turbo
+    }
+
+    pub fn set_turbo(&mut self, turbo: bool) {
+        self.turbo = turbo;
+        self.next_frame = Instant::now();
+        self.last_frame = Instant::now();
+        self.speed = 1.0;
+    }
+
+    pub fn turbo_audio(&self) -> TurboAudio {
+        self.turbo_audio
+    }
+
+    pub fn set_turbo_audio(&mut self, turbo_audio: TurboAudio) {
+        self.turbo_audio = turbo_audio;
+    }
+
+    // How many times faster than the real hardware the emulation runs, 1
+    // outside turbo
+    pub fn speed(&self) -> f64 {
+        self.speed
+    }
+
+    // Ratio to apply to the audio output rate. A queue above its target
+    // produces slightly fewer samples so the audio device catches up, and a
+    // starving queue slightly more.
+    pub fn rate_adjustment(&self, buffer: &SampleBuffer) -> f64 {
+        let fill = buffer.len() as f64 / (self.target_fill * 2) as f64;
+        1.0 + MAX_RATE_DELTA * (1.0 - 2.0 * fill.min(1.0))
+    }
+
+    // Whether the audio of the frame just emulated should be queued
+    pub fn should_queue_audio(&self, buffer: &SampleBuffer) -> bool {
+        if !self.turbo {
+            return true;
+        }
+        match self.turbo_audio {
+            TurboAudio::Mute => false,
+            // Only guards against overruns while the speed estimate settles
+            TurboAudio::TimeStretch => buffer.len() < self.target_fill * 2,
+        }
+    }
+
+    // Blocks until the next frame should be emulated
+    pub fn wait(&mut self, buffer: &SampleBuffer) {
+        if self.turbo {
+            let now = Instant::now();
+            let elapsed = (now - self.last_frame).as_secs_f64();
+            self.last_frame = now;
+            if elapsed > 0.0 {
+                let speed = (1.0 / FRAME_RATE / elapsed).max(1.0);
+                self.speed += (speed - self.speed) * SPEED_SMOOTHING;
+            }
+            return;
+        }
+
+        match self.mode {
+            SyncMode::Audio => {
+                // Give up after a couple of frames in case the device stalled
+                let deadline = Instant::now() + frame_duration(FRAME_RATE) * 2;
+                while buffer.len() > self.target_fill && Instant::now() < deadline {
+                    std::thread::sleep(Duration::from_millis(1));
+                }
+            }
+            SyncMode::Video(refresh_rate) => {
+                // With vsync the present already blocked and the deadline has
+                // passed; otherwise sleep out the rest of the refresh.
+                self.next_frame += frame_duration(refresh_rate);
+                let now = Instant::now();
+                if self.next_frame > now {
+                    std::thread::sleep(self.next_frame - now);
+                } else if now - self.next_frame > frame_duration(refresh_rate) {
+                    // Fell more than a frame behind, don't try to catch up
+                    self.next_frame = now;
+                }
+            }
+        }
+    }
+}
+
+pub fn frame_duration(rate: f64) -> Duration {
+    Duration::from_secs_f64(1.0 / rate)
+}
+
+#[cfg(test)]
+mod test;
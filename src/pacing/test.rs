@@ -0,0 +1,97 @@
+use super::*;
+
+fn buffer_with(frames: usize) -> SampleBuffer {
+    let buffer = SampleBuffer::new(4096);
+    for _ in 0..frames {
+        buffer.push(0.0, 0.0);
+    }
+    buffer
+}
+
+#[test]
+fn frame_rate() {
+    assert!((FRAME_RATE - 59.7275).abs() < 0.0001);
+}
+
+#[test]
+fn parse_sync_mode() {
+    assert_eq!("audio".parse::<SyncMode>(), Ok(SyncMode::Audio));
+    assert_eq!(
+        "video".parse::<SyncMode>(),
+        Ok(SyncMode::Video(DEFAULT_REFRESH_RATE))
+    );
+    assert!("vsync".parse::<SyncMode>().is_err());
+}
+
+#[test]
+fn rate_adjustment_centered_on_target() {
+    let pacer = FramePacer::new(SyncMode::Audio, 1024);
+
+    assert_eq!(pacer.rate_adjustment(&buffer_with(1024)), 1.0);
+    assert!(pacer.rate_adjustment(&buffer_with(0)) > 1.0);
+    assert!(pacer.rate_adjustment(&buffer_with(2048)) < 1.0);
+    assert_eq!(
+        pacer.rate_adjustment(&buffer_with(4000)),
+        1.0 - MAX_RATE_DELTA
+    );
+}
+
+#[test]
+fn turbo_audio() {
+    let mut pacer = FramePacer::new(SyncMode::Audio, 1024);
+    let full = buffer_with(2048);
+    let empty = buffer_with(0);
+
+    assert!(pacer.should_queue_audio(&full));
+
+    pacer.set_turbo(true);
+    assert!(!pacer.should_queue_audio(&empty));
+
+    pacer.set_turbo_audio(TurboAudio::TimeStretch);
+    assert!(pacer.should_queue_audio(&empty));
+    assert!(!pacer.should_queue_audio(&full));
+}
+
+#[test]
+fn parse_turbo_audio() {
+    assert_eq!("mute".parse::<TurboAudio>(), Ok(TurboAudio::Mute));
+    assert_eq!("stretch".parse::<TurboAudio>(), Ok(TurboAudio::TimeStretch));
+    assert!("loud".parse::<TurboAudio>().is_err());
+}
+
+#[test]
+fn measures_turbo_speed() {
+    let mut pacer = FramePacer::new(SyncMode::Audio, 16);
+    let empty = buffer_with(0);
+    assert_eq!(pacer.speed(), 1.0);
+
+    pacer.set_turbo(true);
+    // Frames come far faster than 60 a second
+    for _ in 0..50 {
+        pacer.wait(&empty);
+    }
+    assert!(pacer.speed() > 2.0);
+    pacer.set_turbo(false);
+    assert_eq!(pacer.speed(), 1.0);
+}
+
+#[test]
+fn turbo_does_not_block() {
+    let mut pacer = FramePacer::new(SyncMode::Audio, 16);
+    let full = buffer_with(2048);
+    pacer.set_turbo(true);
+
+    let start = Instant::now();
+    pacer.wait(&full);
+    assert!(start.elapsed() < Duration::from_millis(10));
+}
+
+#[test]
+fn audio_sync_gives_up_on_stalled_device() {
+    let mut pacer = FramePacer::new(SyncMode::Audio, 16);
+    let full = buffer_with(2048);
+
+    let start = Instant::now();
+    pacer.wait(&full);
+    assert!(start.elapsed() >= frame_duration(FRAME_RATE) * 2);
+}
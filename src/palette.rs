@@ -0,0 +1,135 @@
+// CGB palette RAM: 8 background and 8 object palettes of 4 colors, each color
+// a little endian RGB555 word. The CPU reaches it one byte at a time through
+// an index register (BCPS/OCPS) and a data register (BCPD/OCPD); with bit 7
+// of the index set, every data write steps the index to the next byte.
+
+use super::savestate::{StateReader, StateWriter};
+
+pub const BCPS: usize = 0xFF68;
+pub const BCPD: usize = 0xFF69;
+pub const OCPS: usize = 0xFF6A;
+pub const OCPD: usize = 0xFF6B;
+
+pub const PALETTE_RAM_SIZE: usize = 64;
+pub const PALETTE_COUNT: usize = 8;
+
+const AUTO_INCREMENT: u8 = 0x80;
+const INDEX_MASK: u8 = 0x3F;
+
+const STATE_VERSION: u8 = 1;
+
+// One of the two palette memories and its index register
+#[derive(Debug, Clone, PartialEq)]
+struct Bank {
+    ram: [u8; PALETTE_RAM_SIZE],
+    index: u8,
+}
+
+impl Bank {
+    fn new() -> Bank {
+        Bank {
+            ram: [0; PALETTE_RAM_SIZE],
+            index: 0,
+        }
+    }
+
+    fn write_data(&mut self, value: u8) {
+        self.ram[(self.index & INDEX_MASK) as usize] = value;
+        if self.index & AUTO_INCREMENT != 0 {
+            self.index = AUTO_INCREMENT | (self.index.wrapping_add(1) & INDEX_MASK);
+        }
+    }
+
+    fn color(&self, palette: usize, color: usize) -> u16 {
+        let offset = (palette * 4 + color) * 2;
+        u16::from_le_bytes([self.ram[offset], self.ram[offset + 1]])
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct PaletteRam {
+    background: Bank,
+    objects: Bank,
+}
+
+impl PaletteRam {
+    pub fn new() -> PaletteRam {
+        PaletteRam {
+            background: Bank::new(),
+            objects: Bank::new(),
+        }
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        match address {
+            // Bit 6 is unused and reads back set
+            BCPS => self.background.index | 0x40,
+            BCPD => self.background.ram[(self.background.index & INDEX_MASK) as usize],
+            OCPS => self.objects.index | 0x40,
+            OCPD => self.objects.ram[(self.objects.index & INDEX_MASK) as usize],
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        match address {
+            BCPS => self.background.index = value & (AUTO_INCREMENT | INDEX_MASK),
+            BCPD => self.background.write_data(value),
+            OCPS => self.objects.index = value & (AUTO_INCREMENT | INDEX_MASK),
+            OCPD => self.objects.write_data(value),
+            _ => {}
+        }
+    }
+
+    // RGB555 value of color 0-3 in background palette 0-7
+    pub fn background_color(&self, palette: usize, color: usize) -> u16 {
+        self.background.color(palette, color)
+    }
+
+    pub fn object_color(&self, palette: usize, color: usize) -> u16 {
+        self.objects.color(palette, color)
+    }
+
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(STATE_VERSION);
+        for bank in [&self.background, &self.objects].iter() {
+            writer.write_u8(bank.index);
+            writer.write_bytes(&bank.ram);
+        }
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_version("palette", STATE_VERSION)?;
+        for bank in [&mut self.background, &mut self.objects].iter_mut() {
+            bank.index = reader.read_u8()? & (AUTO_INCREMENT | INDEX_MASK);
+            let ram = reader.read_bytes()?;
+            if ram.len() != PALETTE_RAM_SIZE {
+                return Err(format!(
+                    "save state holds {} bytes of palette RAM, expected {}",
+                    ram.len(),
+                    PALETTE_RAM_SIZE
+                ));
+            }
+            bank.ram.copy_from_slice(ram);
+        }
+        Ok(())
+    }
+}
+
+impl Default for PaletteRam {
+    fn default() -> PaletteRam {
+        PaletteRam::new()
+    }
+}
+
+// 8 bits per channel, with the top bits repeated below so 31 becomes 255
+pub fn rgb555_to_rgb(color: u16) -> [u8; 3] {
+    let channel = |shift: u16| {
+        let value = ((color >> shift) & 0x1F) as u8;
+        (value << 3) | (value >> 2)
+    };
+    [channel(0), channel(5), channel(10)]
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,54 @@
+use super::*;
+
+#[test]
+fn writes_step_the_index() {
+    let mut palettes = PaletteRam::new();
+
+    palettes.write(BCPS, 0x80 | 0x3E);
+    palettes.write(BCPD, 0x1F);
+    palettes.write(BCPD, 0x7C);
+    // Wrapped around to the first byte, keeping auto increment on
+    palettes.write(BCPD, 0xE0);
+    assert_eq!(palettes.read(BCPS), 0xC1);
+    assert_eq!(palettes.background_color(7, 3), 0x7C1F);
+    assert_eq!(palettes.background_color(0, 0), 0x00E0);
+
+    palettes.write(BCPS, 0x3E);
+    assert_eq!(palettes.read(BCPD), 0x1F);
+    palettes.write(BCPD, 0x00);
+    assert_eq!(palettes.read(BCPS), 0x7E);
+    assert_eq!(palettes.background_color(7, 3), 0x7C00);
+}
+
+#[test]
+fn object_palettes_are_separate() {
+    let mut palettes = PaletteRam::new();
+
+    palettes.write(OCPS, 0x82);
+    palettes.write(OCPD, 0xFF);
+    palettes.write(OCPD, 0x7F);
+    assert_eq!(palettes.object_color(0, 1), 0x7FFF);
+    assert_eq!(palettes.background_color(0, 1), 0);
+    assert_eq!(palettes.read(OCPS), 0xC4);
+}
+
+#[test]
+fn converts_colors() {
+    assert_eq!(rgb555_to_rgb(0x7FFF), [0xFF, 0xFF, 0xFF]);
+    assert_eq!(rgb555_to_rgb(0x001F), [0xFF, 0x00, 0x00]);
+    assert_eq!(rgb555_to_rgb(0x0200), [0x00, 0x84, 0x00]);
+}
+
+#[test]
+fn state_round_trip() {
+    let mut palettes = PaletteRam::new();
+    palettes.write(OCPS, 0x85);
+    palettes.write(OCPD, 0x12);
+    let mut writer = StateWriter::new();
+    palettes.save_state(&mut writer);
+
+    let mut loaded = PaletteRam::new();
+    let bytes = writer.into_bytes();
+    loaded.load_state(&mut StateReader::new(&bytes)).unwrap();
+    assert_eq!(loaded, palettes);
+}
@@ -0,0 +1,372 @@
+// Game Boy Printer, plugged into the serial port as a SerialLink.
+//
+// Packets from the Game Boy look like
+//   0x88 0x33 command compression length(2, LE) data checksum(2, LE) 0x00 0x00
+// The checksum is the 16 bit sum of the command through the data bytes. The
+// printer answers 0x81 to the first trailing byte and its status to the
+// second one.
+
+use super::image::write_png;
+use super::serial::SerialLink;
+use std::cell::RefCell;
+use std::fs;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+pub const CMD_INIT: u8 = 0x01;
+pub const CMD_PRINT: u8 = 0x02;
+pub const CMD_DATA: u8 = 0x04;
+pub const CMD_STATUS: u8 = 0x0F;
+
+pub const STATUS_CHECKSUM_ERROR: u8 = 0x01;
+pub const STATUS_BUSY: u8 = 0x02;
+pub const STATUS_IMAGE_FULL: u8 = 0x04;
+pub const STATUS_UNPROCESSED: u8 = 0x08;
+
+const DEVICE_ID: u8 = 0x81;
+
+pub const PRINT_WIDTH: usize = 160;
+// A band is two rows of 20 tiles
+const BAND_BYTES: usize = 640;
+const BAND_HEIGHT: usize = 16;
+const MAX_BANDS: usize = 9;
+
+// Status inquiries that report busy after a print, like the real mechanism
+const PRINT_BUSY_INQUIRIES: u8 = 4;
+
+const SHADES: [u8; 4] = [0xFF, 0xAA, 0x55, 0x00];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum PacketState {
+    Magic1,
+    Magic2,
+    Command,
+    Compression,
+    LengthLow,
+    LengthHigh,
+    Data,
+    ChecksumLow,
+    ChecksumHigh,
+    Alive,
+    Status,
+}
+
+// A finished strip of paper, one shade (0 white to 3 black) per pixel
+#[derive(Debug, Clone, PartialEq)]
+pub struct PrintedImage {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<u8>,
+}
+
+impl PrintedImage {
+    pub fn save_png(&self, path: &Path) -> io::Result<()> {
+        let rgb: Vec<u8> = self
+            .pixels
+            .iter()
+            .flat_map(|shade| {
+                let value = SHADES[*shade as usize];
+                vec![value, value, value]
+            })
+            .collect();
+        let mut writer = BufWriter::new(File::create(path)?);
+        write_png(&mut writer, self.width, self.height, &rgb)
+    }
+}
+
+// Expands the printer's run length encoding. A control byte with bit 7 set
+// repeats the next byte (control & 0x7F) + 2 times, otherwise it is followed
+// by control + 1 literal bytes.
+pub fn decompress(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut index = 0;
+    while index < data.len() {
+        let control = data[index];
+        index += 1;
+        if control & 0x80 != 0 {
+            let count = (control & 0x7F) as usize + 2;
+            if let Some(byte) = data.get(index) {
+                out.extend(std::iter::repeat_n(*byte, count));
+            }
+            index += 1;
+        } else {
+            let count = control as usize + 1;
+            let end = (index + count).min(data.len());
+            out.extend_from_slice(&data[index..end]);
+            index = end;
+        }
+    }
+    out
+}
+
+#[derive(Debug)]
+pub struct Printer {
+    state: PacketState,
+    command: u8,
+    compressed: bool,
+    length: usize,
+    data: Vec<u8>,
+    checksum: u16,
+    received_checksum: u16,
+    status: u8,
+    busy_inquiries: u8,
+    image_data: Vec<u8>,
+    strip: Vec<u8>,
+    output_dir: Option<PathBuf>,
+    printed: Rc<RefCell<Vec<PrintedImage>>>,
+    saved: Rc<RefCell<Vec<Result<PathBuf, String>>>>,
+}
+
+impl Printer {
+    pub fn new() -> Printer {
+        Printer {
+            state: PacketState::Magic1,
+            command: 0,
+            compressed: false,
+            length: 0,
+            data: Vec::new(),
+            checksum: 0,
+            received_checksum: 0,
+            status: 0,
+            busy_inquiries: 0,
+            image_data: Vec::new(),
+            strip: Vec::new(),
+            output_dir: None,
+            printed: Rc::new(RefCell::new(Vec::new())),
+            saved: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    // Finished strips are also written to the first free print_001.png,
+    // print_002.png... in output_dir, which is created when missing
+    pub fn with_output_dir(output_dir: PathBuf) -> Printer {
+        let mut printer = Printer::new();
+        printer.output_dir = Some(output_dir);
+        printer
+    }
+
+    // Shared handle to the finished strips that stays valid after the
+    // printer is plugged into the serial port.
+    pub fn printed(&self) -> Rc<RefCell<Vec<PrintedImage>>> {
+        self.printed.clone()
+    }
+
+    // Shared handle to where each strip was saved, or why it could not be,
+    // for the frontend to collect and report. Only filled with an output
+    // directory.
+    pub fn saved(&self) -> Rc<RefCell<Vec<Result<PathBuf, String>>>> {
+        self.saved.clone()
+    }
+
+    fn status(&self) -> u8 {
+        let mut status = self.status;
+        if self.busy_inquiries > 0 {
+            status |= STATUS_BUSY;
+        }
+        if self.image_data.len() >= BAND_BYTES * MAX_BANDS {
+            status |= STATUS_IMAGE_FULL;
+        }
+        status
+    }
+
+    fn receive_byte(&mut self, byte: u8) -> u8 {
+        let mut response = 0x00;
+        self.state = match self.state {
+            PacketState::Magic1 => {
+                if byte == 0x88 {
+                    PacketState::Magic2
+                } else {
+                    PacketState::Magic1
+                }
+            }
+            PacketState::Magic2 => {
+                if byte == 0x33 {
+                    PacketState::Command
+                } else {
+                    PacketState::Magic1
+                }
+            }
+            PacketState::Command => {
+                self.command = byte;
+                self.checksum = byte as u16;
+                self.data.clear();
+                PacketState::Compression
+            }
+            PacketState::Compression => {
+                self.compressed = byte & 0x01 != 0;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthLow
+            }
+            PacketState::LengthLow => {
+                self.length = byte as usize;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                PacketState::LengthHigh
+            }
+            PacketState::LengthHigh => {
+                self.length |= (byte as usize) << 8;
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.length == 0 {
+                    PacketState::ChecksumLow
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::Data => {
+                self.data.push(byte);
+                self.checksum = self.checksum.wrapping_add(byte as u16);
+                if self.data.len() == self.length {
+                    PacketState::ChecksumLow
+                } else {
+                    PacketState::Data
+                }
+            }
+            PacketState::ChecksumLow => {
+                self.received_checksum = byte as u16;
+                PacketState::ChecksumHigh
+            }
+            PacketState::ChecksumHigh => {
+                self.received_checksum |= (byte as u16) << 8;
+                PacketState::Alive
+            }
+            PacketState::Alive => {
+                response = DEVICE_ID;
+                if self.received_checksum == self.checksum {
+                    self.status &= !STATUS_CHECKSUM_ERROR;
+                    self.run_command();
+                } else {
+                    self.status |= STATUS_CHECKSUM_ERROR;
+                }
+                PacketState::Status
+            }
+            PacketState::Status => {
+                response = self.status();
+                if self.busy_inquiries > 0 {
+                    self.busy_inquiries -= 1;
+                }
+                PacketState::Magic1
+            }
+        };
+        response
+    }
+
+    fn run_command(&mut self) {
+        match self.command {
+            CMD_INIT => {
+                self.image_data.clear();
+                self.status = 0;
+                self.busy_inquiries = 0;
+            }
+            CMD_DATA => {
+                let data = if self.compressed {
+                    decompress(&self.data)
+                } else {
+                    self.data.clone()
+                };
+                let capacity = BAND_BYTES * MAX_BANDS;
+                let room = capacity - self.image_data.len().min(capacity);
+                self.image_data
+                    .extend_from_slice(&data[..data.len().min(room)]);
+                if !self.image_data.is_empty() {
+                    self.status |= STATUS_UNPROCESSED;
+                }
+            }
+            CMD_PRINT if self.data.len() == 4 => {
+                let margins = self.data[1];
+                let palette = self.data[2];
+                self.print(margins, palette);
+            }
+            _ => {}
+        }
+    }
+
+    fn print(&mut self, margins: u8, palette: u8) {
+        // A zero palette is treated as the standard one by the hardware
+        let palette = if palette == 0 { 0xE4 } else { palette };
+
+        for band in self.image_data.chunks(BAND_BYTES) {
+            let mut pixels = vec![0; PRINT_WIDTH * BAND_HEIGHT];
+            for (tile_index, tile) in band.chunks(16).enumerate() {
+                let tile_x = (tile_index % 20) * 8;
+                let tile_y = (tile_index / 20) * 8;
+                for row in 0..tile.len() / 2 {
+                    let low = tile[row * 2];
+                    let high = tile[row * 2 + 1];
+                    for bit in 0..8 {
+                        let color = (((high >> (7 - bit)) & 1) << 1) | ((low >> (7 - bit)) & 1);
+                        let shade = (palette >> (color * 2)) & 0x03;
+                        pixels[(tile_y + row) * PRINT_WIDTH + tile_x + bit] = shade;
+                    }
+                }
+            }
+            self.strip.extend_from_slice(&pixels);
+        }
+
+        self.image_data.clear();
+        self.status &= !STATUS_UNPROCESSED;
+        self.busy_inquiries = PRINT_BUSY_INQUIRIES;
+
+        // A feed after the image means the paper gets torn off here
+        if margins & 0x0F != 0 {
+            self.finish_strip();
+        }
+    }
+
+    // Ends the current strip of paper, saving it when an output directory is set
+    pub fn finish_strip(&mut self) {
+        if self.strip.is_empty() {
+            return;
+        }
+
+        let image = PrintedImage {
+            width: PRINT_WIDTH,
+            height: self.strip.len() / PRINT_WIDTH,
+            pixels: std::mem::take(&mut self.strip),
+        };
+        if let Some(dir) = self.output_dir.as_ref() {
+            let saved = save_strip(&image, dir);
+            self.saved.borrow_mut().push(saved);
+        }
+        self.printed.borrow_mut().push(image);
+    }
+}
+
+// Skips names taken by earlier sessions instead of overwriting them
+fn save_strip(image: &PrintedImage, dir: &Path) -> Result<PathBuf, String> {
+    fs::create_dir_all(dir).map_err(|e| format!("could not create {}: {}", dir.display(), e))?;
+    let path = (1..)
+        .map(|number| dir.join(format!("print_{:03}.png", number)))
+        .find(|path| !path.exists())
+        .unwrap();
+    image
+        .save_png(&path)
+        .map_err(|e| format!("could not save {}: {}", path.display(), e))?;
+    Ok(path)
+}
+
+impl Default for Printer {
+    fn default() -> Printer {
+        Printer::new()
+    }
+}
+
+impl Drop for Printer {
+    fn drop(&mut self) {
+        self.finish_strip();
+    }
+}
+
+impl SerialLink for Printer {
+    fn transfer(&mut self, byte: u8) -> Option<u8> {
+        Some(self.receive_byte(byte))
+    }
+
+    // The printer never drives the clock
+    fn receive(&mut self, _outgoing: u8) -> Option<u8> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod test;
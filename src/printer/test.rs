@@ -0,0 +1,182 @@
+use super::*;
+
+fn packet(command: u8, compression: u8, data: &[u8]) -> Vec<u8> {
+    let mut bytes = vec![
+        0x88,
+        0x33,
+        command,
+        compression,
+        data.len() as u8,
+        (data.len() >> 8) as u8,
+    ];
+    bytes.extend_from_slice(data);
+    let checksum = bytes[2..]
+        .iter()
+        .fold(0u16, |sum, byte| sum.wrapping_add(*byte as u16));
+    bytes.push(checksum as u8);
+    bytes.push((checksum >> 8) as u8);
+    bytes.push(0x00);
+    bytes.push(0x00);
+    bytes
+}
+
+// Sends a packet and returns the two response bytes
+fn send(printer: &mut Printer, bytes: &[u8]) -> (u8, u8) {
+    let responses: Vec<u8> = bytes
+        .iter()
+        .map(|byte| printer.transfer(*byte).unwrap())
+        .collect();
+    (
+        responses[responses.len() - 2],
+        responses[responses.len() - 1],
+    )
+}
+
+#[test]
+fn status_inquiry() {
+    let mut printer = Printer::new();
+
+    assert_eq!(
+        send(&mut printer, &packet(CMD_STATUS, 0, &[])),
+        (0x81, 0x00)
+    );
+}
+
+#[test]
+fn ignores_bytes_until_magic() {
+    let mut printer = Printer::new();
+    let mut bytes = vec![0x00, 0x33, 0x88, 0x00];
+    bytes.extend(packet(CMD_STATUS, 0, &[]));
+
+    assert_eq!(send(&mut printer, &bytes), (0x81, 0x00));
+}
+
+#[test]
+fn checksum_error() {
+    let mut printer = Printer::new();
+    let mut bytes = packet(CMD_DATA, 0, &[0x12; 4]);
+    bytes[7] ^= 0xFF;
+
+    assert_eq!(send(&mut printer, &bytes), (0x81, STATUS_CHECKSUM_ERROR));
+    let (_, status) = send(&mut printer, &packet(CMD_STATUS, 0, &[]));
+    assert_eq!(status, 0x00);
+}
+
+#[test]
+fn data_marks_unprocessed() {
+    let mut printer = Printer::new();
+
+    let (_, status) = send(&mut printer, &packet(CMD_DATA, 0, &[0; BAND_BYTES]));
+    assert_eq!(status, STATUS_UNPROCESSED);
+    let (_, status) = send(&mut printer, &packet(CMD_INIT, 0, &[]));
+    assert_eq!(status, 0x00);
+}
+
+#[test]
+fn full_buffer() {
+    let mut printer = Printer::new();
+
+    for _ in 0..MAX_BANDS {
+        send(&mut printer, &packet(CMD_DATA, 0, &[0; BAND_BYTES]));
+    }
+    let (_, status) = send(&mut printer, &packet(CMD_STATUS, 0, &[]));
+    assert_eq!(status, STATUS_UNPROCESSED | STATUS_IMAGE_FULL);
+}
+
+#[test]
+fn run_length_decoding() {
+    assert_eq!(decompress(&[0x81, 0xAA]), vec![0xAA; 3]);
+    assert_eq!(
+        decompress(&[0x01, 0x01, 0x02, 0x80, 0x03]),
+        vec![1, 2, 3, 3]
+    );
+}
+
+#[test]
+fn prints_strip() {
+    let mut printer = Printer::new();
+    let printed = printer.printed();
+
+    // Every tile row is color 1 on the left half and color 3 on the right
+    let mut band = Vec::new();
+    for _ in 0..BAND_BYTES / 2 {
+        band.push(0xFF);
+        band.push(0x0F);
+    }
+    send(&mut printer, &packet(CMD_INIT, 0, &[]));
+    // A blank band as five runs of 128 zero bytes
+    send(&mut printer, &packet(CMD_DATA, 1, &[0xFE, 0x00].repeat(5)));
+    assert_eq!(printed.borrow().len(), 0);
+
+    send(&mut printer, &packet(CMD_DATA, 0, &band));
+    let (_, status) = send(&mut printer, &packet(CMD_PRINT, 0, &[1, 0x01, 0xE4, 0x40]));
+    assert_eq!(status & STATUS_UNPROCESSED, 0);
+    assert_eq!(status & STATUS_BUSY, STATUS_BUSY);
+
+    let printed = printed.borrow();
+    assert_eq!(printed.len(), 1);
+    assert_eq!(printed[0].width, PRINT_WIDTH);
+    assert_eq!(printed[0].height, BAND_HEIGHT * 2);
+    assert!(printed[0].pixels[..PRINT_WIDTH * BAND_HEIGHT]
+        .iter()
+        .all(|shade| *shade == 0));
+    let second = PRINT_WIDTH * BAND_HEIGHT;
+    assert_eq!(
+        &printed[0].pixels[second..second + 8],
+        &[1, 1, 1, 1, 3, 3, 3, 3]
+    );
+    assert_eq!(printed[0].pixels[second * 2 - 1], 3);
+}
+
+#[test]
+fn busy_clears_after_inquiries() {
+    let mut printer = Printer::new();
+
+    send(&mut printer, &packet(CMD_DATA, 0, &[0; BAND_BYTES]));
+    send(&mut printer, &packet(CMD_PRINT, 0, &[1, 0x00, 0xE4, 0x40]));
+    for _ in 1..PRINT_BUSY_INQUIRIES {
+        let (_, status) = send(&mut printer, &packet(CMD_STATUS, 0, &[]));
+        assert_eq!(status, STATUS_BUSY);
+    }
+    let (_, status) = send(&mut printer, &packet(CMD_STATUS, 0, &[]));
+    assert_eq!(status, 0x00);
+}
+
+#[test]
+fn strip_continues_without_margin() {
+    let mut printer = Printer::new();
+    let printed = printer.printed();
+
+    send(&mut printer, &packet(CMD_DATA, 0, &[0; BAND_BYTES]));
+    send(&mut printer, &packet(CMD_PRINT, 0, &[1, 0x10, 0xE4, 0x40]));
+    send(&mut printer, &packet(CMD_DATA, 0, &[0; BAND_BYTES]));
+    send(&mut printer, &packet(CMD_PRINT, 0, &[1, 0x03, 0xE4, 0x40]));
+
+    let printed = printed.borrow();
+    assert_eq!(printed.len(), 1);
+    assert_eq!(printed[0].height, BAND_HEIGHT * 2);
+}
+
+#[test]
+fn saves_into_a_new_directory() {
+    let dir = std::env::temp_dir()
+        .join(format!("gba-printer-{}", std::process::id()))
+        .join("prints");
+    let mut printer = Printer::with_output_dir(dir.clone());
+    let saved = printer.saved();
+
+    send(&mut printer, &packet(CMD_DATA, 0, &[0; BAND_BYTES]));
+    send(&mut printer, &packet(CMD_PRINT, 0, &[1, 0x03, 0xE4, 0x40]));
+
+    let path = dir.join("print_001.png");
+    assert_eq!(saved.borrow()[..], [Ok(path.clone())]);
+    assert!(path.exists());
+
+    // A later session keeps the earlier print
+    let mut printer = Printer::with_output_dir(dir.clone());
+    let saved = printer.saved();
+    send(&mut printer, &packet(CMD_DATA, 0, &[0; BAND_BYTES]));
+    send(&mut printer, &packet(CMD_PRINT, 0, &[1, 0x03, 0xE4, 0x40]));
+    assert_eq!(saved.borrow()[..], [Ok(dir.join("print_002.png"))]);
+    std::fs::remove_dir_all(dir.parent().unwrap()).unwrap();
+}
@@ -0,0 +1,353 @@
+// Shadow call stack and cycle profiler. The stack follows CALL, RST and
+// interrupt dispatch as they happen and unwinds on RET and RETI by stack
+// pointer, so code that drops return addresses or jumps through pushed ones
+// only leaves it wrong until the stack gets back above those frames.
+//
+// Cycles are charged to the whole stack of the instruction that spent them
+// and exported as folded stacks, one line per stack with its cycles, which
+// flamegraph.pl, inferno and speedscope read:
+//
+//   (root);Main;UpdateActors;MoveActor 123456
+//
+// Functions are named by the symbol at their entry point when there is one
+// and "BB:AAAA" otherwise. Interrupt handlers without a symbol are named
+// after the interrupt, such as "[vblank]".
+
+use super::cpu::Registers;
+use super::disasm::Instruction;
+use super::symbols::{Location, Symbols};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub const INTERRUPT_VECTORS: [(u16, &str); 5] = [
+    (0x0040, "vblank"),
+    (0x0048, "stat"),
+    (0x0050, "timer"),
+    (0x0058, "serial"),
+    (0x0060, "joypad"),
+];
+
+// Code that calls without ever returning, such as a scheduler switching
+// stacks, would otherwise grow the shadow stack forever
+const MAX_DEPTH: usize = 256;
+
+const ROOT: usize = 0;
+
+fn interrupt_name(address: u16) -> Option<&'static str> {
+    INTERRUPT_VECTORS
+        .iter()
+        .find(|(vector, _)| *vector == address)
+        .map(|(_, name)| *name)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FrameKind {
+    Call,
+    Interrupt,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    // Entry point of the routine
+    pub function: Location,
+    pub kind: FrameKind,
+    // The call instruction, or the instruction the interrupt came after
+    pub caller: u16,
+    // SP once the routine has returned
+    pub return_sp: u16,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct CallStack {
+    frames: Vec<Frame>,
+}
+
+impl CallStack {
+    pub fn new() -> CallStack {
+        CallStack::default()
+    }
+
+    // Outermost first
+    pub fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    // Follows one executed instruction given the registers around it.
+    // Returns how many frames were popped and the frame pushed, if any.
+    pub fn update<F: Fn(u16) -> u16>(
+        &mut self,
+        instruction: &Instruction,
+        before: &Registers,
+        after: &Registers,
+        bank: F,
+    ) -> (usize, Option<Frame>) {
+        let pushed_return = after.sp == before.sp.wrapping_sub(2);
+        let mut popped = 0;
+        let mut pushed = None;
+
+        if instruction.is_call() {
+            // Conditional calls that are not taken fall through
+            if pushed_return && instruction.branch_target() == Some(after.pc) {
+                pushed = Some(Frame {
+                    function: Location {
+                        bank: bank(after.pc),
+                        address: after.pc,
+                    },
+                    kind: FrameKind::Call,
+                    caller: before.pc,
+                    return_sp: before.sp,
+                });
+            }
+        } else if instruction.is_return() && after.sp > before.sp {
+            // Everything the return address belonged to is gone, which also
+            // catches up after routines that popped their own return address
+            while let Some(frame) = self.frames.last() {
+                if frame.return_sp > after.sp {
+                    break;
+                }
+                self.frames.pop();
+                popped += 1;
+            }
+        }
+
+        // Interrupt dispatch pushes PC and lands on a vector the instruction
+        // itself could not have gone to
+        let expected = instruction.branch_target() == Some(after.pc)
+            || instruction.next_address() == after.pc
+            || instruction.is_return();
+        if pushed.is_none()
+            && !expected
+            && after.sp < before.sp
+            && interrupt_name(after.pc).is_some()
+        {
+            pushed = Some(Frame {
+                function: Location {
+                    bank: bank(after.pc),
+                    address: after.pc,
+                },
+                kind: FrameKind::Interrupt,
+                caller: before.pc,
+                return_sp: after.sp.wrapping_add(2),
+            });
+        }
+
+        if let Some(frame) = pushed {
+            if self.frames.len() >= MAX_DEPTH {
+                self.frames.remove(0);
+            }
+            self.frames.push(frame);
+        }
+        (popped, pushed)
+    }
+}
+
+// Display name of a routine
+pub fn function_name(symbols: &Symbols, function: Location) -> String {
+    if let Some(name) = symbols.label(function.bank, function.address) {
+        return name.to_string();
+    }
+    if let Some(name) = interrupt_name(function.address) {
+        return format!("[{}]", name);
+    }
+    // Entry points in the middle of a labelled routine
+    match symbols.describe(function.bank, function.address) {
+        Some(name) => name,
+        None => format!("{:02X}:{:04X}", function.bank, function.address),
+    }
+}
+
+// One distinct stack, as a node in the tree of all stacks seen
+#[derive(Debug)]
+struct Node {
+    function: Option<Location>,
+    parent: usize,
+    children: HashMap<Location, usize>,
+    // Cycles spent with this node on top of the stack
+    cycles: u64,
+    calls: u64,
+}
+
+impl Node {
+    fn new(function: Option<Location>, parent: usize) -> Node {
+        Node {
+            function,
+            parent,
+            children: HashMap::new(),
+            cycles: 0,
+            calls: 0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Profiler {
+    stack: CallStack,
+    symbols: Symbols,
+    nodes: Vec<Node>,
+    // Node of every frame on the stack, the root first
+    path: Vec<usize>,
+    total: u64,
+}
+
+impl Profiler {
+    pub fn new(symbols: Symbols) -> Profiler {
+        Profiler {
+            stack: CallStack::new(),
+            symbols,
+            nodes: vec![Node::new(None, ROOT)],
+            path: vec![ROOT],
+            total: 0,
+        }
+    }
+
+    pub fn call_stack(&self) -> &CallStack {
+        &self.stack
+    }
+
+    pub fn symbols(&self) -> &Symbols {
+        &self.symbols
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.total
+    }
+
+    // Forgets the call stack, e.g. after a reset or loading a state, but
+    // keeps the cycles counted so far
+    pub fn clear_stack(&mut self) {
+        self.stack.clear();
+        self.path.truncate(1);
+    }
+
+    // Accounts for one executed instruction. Its cycles count for the stack
+    // it ran on, a call's own cycles for the caller.
+    pub fn record<F: Fn(u16) -> u16>(
+        &mut self,
+        instruction: &Instruction,
+        before: &Registers,
+        after: &Registers,
+        cycles: u32,
+        bank: F,
+    ) {
+        let top = *self.path.last().unwrap();
+        self.nodes[top].cycles += cycles as u64;
+        self.total += cycles as u64;
+
+        let (popped, pushed) = self.stack.update(instruction, before, after, bank);
+        let keep = self.path.len().saturating_sub(popped).max(1);
+        self.path.truncate(keep);
+        if let Some(frame) = pushed {
+            if self.path.len() > MAX_DEPTH {
+                self.path.remove(1);
+            }
+            let parent = *self.path.last().unwrap();
+            let node = match self.nodes[parent].children.get(&frame.function) {
+                Some(node) => *node,
+                None => {
+                    let node = self.nodes.len();
+                    self.nodes.push(Node::new(Some(frame.function), parent));
+                    self.nodes[parent].children.insert(frame.function, node);
+                    node
+                }
+            };
+            self.nodes[node].calls += 1;
+            self.path.push(node);
+        }
+    }
+
+    fn node_name(&self, node: usize) -> String {
+        match self.nodes[node].function {
+            Some(function) => function_name(&self.symbols, function),
+            None => "(root)".to_string(),
+        }
+    }
+
+    fn stack_names(&self, mut node: usize) -> Vec<String> {
+        let mut names = vec![self.node_name(node)];
+        while node != ROOT {
+            node = self.nodes[node].parent;
+            names.push(self.node_name(node));
+        }
+        names.reverse();
+        names
+    }
+
+    // Folded stacks with their cycles, for flamegraph tools
+    pub fn write_folded(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut lines: Vec<(String, u64)> = (0..self.nodes.len())
+            .filter(|node| self.nodes[*node].cycles > 0)
+            .map(|node| (self.stack_names(node).join(";"), self.nodes[node].cycles))
+            .collect();
+        lines.sort();
+        for (stack, cycles) in lines {
+            writeln!(out, "{} {}", stack, cycles)?;
+        }
+        Ok(())
+    }
+
+    pub fn save_folded(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        self.write_folded(&mut out)?;
+        out.flush()
+    }
+
+    // Cycles spent in each function itself and in what it called, busiest
+    // first. Recursion counts the outermost call only.
+    fn functions(&self) -> Vec<(String, u64, u64, u64)> {
+        let mut totals: HashMap<String, (u64, u64, u64)> = HashMap::new();
+        for node in 0..self.nodes.len() {
+            let name = self.node_name(node);
+            let names = self.stack_names(node);
+            let cycles = self.nodes[node].cycles;
+            let entry = totals.entry(name.clone()).or_insert((0, 0, 0));
+            entry.0 += cycles;
+            entry.2 += self.nodes[node].calls;
+            // Inclusive time goes to every distinct function on the stack
+            let mut seen: Vec<&String> = Vec::new();
+            for caller in names.iter() {
+                if !seen.contains(&caller) {
+                    seen.push(caller);
+                    totals.entry(caller.clone()).or_insert((0, 0, 0)).1 += cycles;
+                }
+            }
+        }
+        let mut functions: Vec<(String, u64, u64, u64)> = totals
+            .into_iter()
+            .map(|(name, (own, total, calls))| (name, own, total, calls))
+            .collect();
+        functions.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        functions
+    }
+
+    // Flat profile of the limit busiest functions
+    pub fn write_report(&self, out: &mut dyn Write, limit: usize) -> io::Result<()> {
+        let total = self.total.max(1) as f64;
+        writeln!(
+            out,
+            "{:>12} {:>6} {:>12} {:>6} {:>8}  function",
+            "self", "%", "total", "%", "calls"
+        )?;
+        for (name, own, inclusive, calls) in self.functions().into_iter().take(limit) {
+            writeln!(
+                out,
+                "{:>12} {:>5.1}% {:>12} {:>5.1}% {:>8}  {}",
+                own,
+                own as f64 * 100.0 / total,
+                inclusive,
+                inclusive as f64 * 100.0 / total,
+                calls,
+                name
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test;
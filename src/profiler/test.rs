@@ -0,0 +1,201 @@
+use super::*;
+use crate::disasm::decode;
+
+fn registers(pc: u16, sp: u16) -> Registers {
+    Registers {
+        a: 0,
+        f: 0,
+        b: 0,
+        c: 0,
+        d: 0,
+        e: 0,
+        h: 0,
+        l: 0,
+        sp,
+        pc,
+    }
+}
+
+// Feeds the profiler one instruction made of bytes at pc, as if the CPU had
+// gone from (pc, sp) to (after_pc, after_sp)
+fn execute(
+    profiler: &mut Profiler,
+    bytes: &[u8],
+    (pc, sp): (u16, u16),
+    (after_pc, after_sp): (u16, u16),
+    cycles: u32,
+) {
+    let instruction = decode(|address| bytes[(address - pc) as usize], pc);
+    profiler.record(
+        &instruction,
+        &registers(pc, sp),
+        &registers(after_pc, after_sp),
+        cycles,
+        |address| if address >= 0x4000 { 2 } else { 0 },
+    );
+}
+
+const NOP: [u8; 1] = [0x00];
+const RET: [u8; 1] = [0xC9];
+
+fn folded(profiler: &Profiler) -> String {
+    let mut out = Vec::new();
+    profiler.write_folded(&mut out).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn follows_calls_and_returns() {
+    let symbols = Symbols::parse("02:4000 Update\n02:5000 Draw").unwrap();
+    let mut profiler = Profiler::new(symbols);
+
+    execute(
+        &mut profiler,
+        &[0xCD, 0x00, 0x40],
+        (0x0150, 0xFFFE),
+        (0x4000, 0xFFFC),
+        24,
+    );
+    execute(&mut profiler, &NOP, (0x4000, 0xFFFC), (0x4001, 0xFFFC), 4);
+    execute(
+        &mut profiler,
+        &[0xCD, 0x00, 0x50],
+        (0x4001, 0xFFFC),
+        (0x5000, 0xFFFA),
+        24,
+    );
+    let frames = profiler.call_stack().frames();
+    assert_eq!(frames.len(), 2);
+    assert_eq!(frames[1].caller, 0x4001);
+    assert_eq!(
+        frames[1].function,
+        Location {
+            bank: 2,
+            address: 0x5000
+        }
+    );
+
+    execute(&mut profiler, &NOP, (0x5000, 0xFFFA), (0x5001, 0xFFFA), 4);
+    execute(&mut profiler, &RET, (0x5001, 0xFFFA), (0x4004, 0xFFFC), 16);
+    // A return that is not taken stays in the routine
+    execute(
+        &mut profiler,
+        &[0xC0],
+        (0x4004, 0xFFFC),
+        (0x4005, 0xFFFC),
+        8,
+    );
+    assert_eq!(profiler.call_stack().frames().len(), 1);
+    execute(&mut profiler, &RET, (0x4005, 0xFFFC), (0x0153, 0xFFFE), 16);
+    assert!(profiler.call_stack().frames().is_empty());
+
+    assert_eq!(
+        folded(&profiler),
+        "(root) 24\n(root);Update 52\n(root);Update;Draw 20\n"
+    );
+    assert_eq!(profiler.total_cycles(), 96);
+}
+
+#[test]
+fn follows_interrupts() {
+    let mut profiler = Profiler::new(Symbols::new());
+
+    // The interrupt is dispatched after the nop
+    execute(&mut profiler, &NOP, (0x0150, 0xFFFE), (0x0040, 0xFFFC), 24);
+    let frames = profiler.call_stack().frames();
+    assert_eq!(frames[0].kind, FrameKind::Interrupt);
+    assert_eq!(frames[0].caller, 0x0150);
+    // Not taken as an interrupt when the instruction jumps there itself
+    execute(
+        &mut profiler,
+        &[0x18, 0xFE],
+        (0x0040, 0xFFFC),
+        (0x0040, 0xFFFC),
+        12,
+    );
+    assert_eq!(profiler.call_stack().frames().len(), 1);
+    execute(
+        &mut profiler,
+        &[0xD9],
+        (0x0040, 0xFFFC),
+        (0x0151, 0xFFFE),
+        16,
+    );
+    assert!(profiler.call_stack().frames().is_empty());
+
+    assert_eq!(folded(&profiler), "(root) 24\n(root);[vblank] 28\n");
+}
+
+#[test]
+fn unwinds_dropped_return_addresses() {
+    let mut profiler = Profiler::new(Symbols::new());
+
+    execute(
+        &mut profiler,
+        &[0xCD, 0x00, 0x20],
+        (0x0150, 0xFFFE),
+        (0x2000, 0xFFFC),
+        24,
+    );
+    execute(
+        &mut profiler,
+        &[0xCD, 0x00, 0x30],
+        (0x2000, 0xFFFC),
+        (0x3000, 0xFFFA),
+        24,
+    );
+    // The inner routine pops its return address and returns for its caller
+    execute(
+        &mut profiler,
+        &[0xE1],
+        (0x3000, 0xFFFA),
+        (0x3001, 0xFFFC),
+        12,
+    );
+    execute(&mut profiler, &RET, (0x3001, 0xFFFC), (0x0153, 0xFFFE), 16);
+    assert!(profiler.call_stack().frames().is_empty());
+    assert_eq!(
+        folded(&profiler),
+        "(root) 24\n(root);00:2000 24\n(root);00:2000;00:3000 28\n"
+    );
+}
+
+#[test]
+fn reports_functions() {
+    let symbols = Symbols::parse("02:4000 Update").unwrap();
+    let mut profiler = Profiler::new(symbols);
+    execute(&mut profiler, &NOP, (0x0150, 0xFFFE), (0x0151, 0xFFFE), 20);
+    execute(
+        &mut profiler,
+        &[0xCD, 0x00, 0x40],
+        (0x0151, 0xFFFE),
+        (0x4000, 0xFFFC),
+        24,
+    );
+    execute(&mut profiler, &NOP, (0x4000, 0xFFFC), (0x4001, 0xFFFC), 40);
+    execute(&mut profiler, &RET, (0x4001, 0xFFFC), (0x0154, 0xFFFE), 16);
+
+    let mut out = Vec::new();
+    profiler.write_report(&mut out, 10).unwrap();
+    let text = String::from_utf8(out).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 3);
+    assert_eq!(
+        lines[1],
+        "          56  56.0%           56  56.0%        1  Update"
+    );
+    assert_eq!(
+        lines[2],
+        "          44  44.0%          100 100.0%        0  (root)"
+    );
+}
+
+#[test]
+fn names_functions() {
+    let symbols = Symbols::parse("00:0038 Crash\n02:4000 Update").unwrap();
+    let name = |bank, address| function_name(&symbols, Location { bank, address });
+    assert_eq!(name(2, 0x4000), "Update");
+    assert_eq!(name(2, 0x4010), "Update+16");
+    assert_eq!(name(0, 0x0048), "[stat]");
+    assert_eq!(name(3, 0x4000), "03:4000");
+}
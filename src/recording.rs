@@ -0,0 +1,136 @@
+// Audio recorders for ripping music: the mixed output or one WAV file per
+// channel, resampled and filtered the same way as the live audio. Video is
+// recorded as Y4M with the WAV alongside.
+
+use super::apu::{Channel, CPU_CLOCK_HZ};
+use super::audio::{HighPass, Resampler};
+use super::pacing::CYCLES_PER_FRAME;
+use super::screen::{Framebuffer, SCREEN_HEIGHT, SCREEN_WIDTH};
+use super::wav::WavWriter;
+use super::y4m::Y4mWriter;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+pub struct WavRecorder {
+    resampler: Resampler,
+    left_filter: HighPass,
+    right_filter: HighPass,
+    writer: WavWriter<BufWriter<File>>,
+    pending: Vec<(f32, f32)>,
+}
+
+impl WavRecorder {
+    pub fn create(path: &Path, sample_rate: u32) -> io::Result<WavRecorder> {
+        Ok(WavRecorder {
+            resampler: Resampler::new(sample_rate),
+            left_filter: HighPass::new(sample_rate),
+            right_filter: HighPass::new(sample_rate),
+            writer: WavWriter::create(path, sample_rate)?,
+            pending: Vec::new(),
+        })
+    }
+
+    // Takes samples at the APU rate
+    pub fn push_samples(&mut self, samples: &[(f32, f32)]) -> io::Result<()> {
+        let pending = &mut self.pending;
+        for (left, right) in samples.iter() {
+            self.resampler
+                .push(*left, *right, |left, right| pending.push((left, right)));
+        }
+
+        for (left, right) in self.pending.drain(..) {
+            let left = self.left_filter.filter(left);
+            let right = self.right_filter.filter(right);
+            self.writer.write_sample(left, right)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.writer.finish().map(|_| ())
+    }
+}
+
+// Writes <prefix>_square1.wav, <prefix>_square2.wav and so on
+pub struct StemRecorder {
+    stems: Vec<WavRecorder>,
+}
+
+impl StemRecorder {
+    pub fn create(prefix: &Path, sample_rate: u32) -> io::Result<StemRecorder> {
+        let mut stems = Vec::new();
+        for channel in Channel::ALL.iter() {
+            stems.push(WavRecorder::create(
+                &stem_path(prefix, *channel),
+                sample_rate,
+            )?);
+        }
+        Ok(StemRecorder { stems })
+    }
+
+    pub fn push_stems(&mut self, stems: &[Vec<(f32, f32)>; 4]) -> io::Result<()> {
+        for (recorder, samples) in self.stems.iter_mut().zip(stems.iter()) {
+            recorder.push_samples(samples)?;
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        for recorder in self.stems {
+            recorder.finish()?;
+        }
+        Ok(())
+    }
+}
+
+pub fn stem_path(prefix: &Path, channel: Channel) -> PathBuf {
+    let mut name = prefix.file_name().unwrap_or_default().to_os_string();
+    name.push(format!("_{}.wav", channel.name()));
+    prefix.with_file_name(name)
+}
+
+// Writes every emulated frame to <file>.y4m and its audio to <file>.wav.
+// Both are fed per emulated frame, so they stay in sync at the exact
+// 4194304/70224 (about 59.73) frames per second however fast the host
+// runs, and nothing is dropped when it falls behind.
+pub struct VideoRecorder {
+    video: Y4mWriter<BufWriter<File>>,
+    audio: WavRecorder,
+}
+
+impl VideoRecorder {
+    pub fn create(path: &Path, sample_rate: u32) -> io::Result<VideoRecorder> {
+        Ok(VideoRecorder {
+            video: Y4mWriter::create(
+                &path.with_extension("y4m"),
+                SCREEN_WIDTH,
+                SCREEN_HEIGHT,
+                (CPU_CLOCK_HZ, CYCLES_PER_FRAME),
+            )?,
+            audio: WavRecorder::create(&path.with_extension("wav"), sample_rate)?,
+        })
+    }
+
+    // One emulated frame and the audio produced while it ran
+    pub fn push_frame(
+        &mut self,
+        framebuffer: &Framebuffer,
+        samples: &[(f32, f32)],
+    ) -> io::Result<()> {
+        self.video.write_frame(&framebuffer.to_rgb())?;
+        self.audio.push_samples(samples)
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.video.frames()
+    }
+
+    pub fn finish(self) -> io::Result<()> {
+        self.video.finish()?;
+        self.audio.finish()
+    }
+}
+
+#[cfg(test)]
+mod test;
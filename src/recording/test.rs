@@ -0,0 +1,43 @@
+use super::*;
+
+#[test]
+fn stem_paths() {
+    assert_eq!(
+        stem_path(Path::new("rips/title"), Channel::Square1),
+        PathBuf::from("rips/title_square1.wav")
+    );
+    assert_eq!(
+        stem_path(Path::new("title"), Channel::Noise),
+        PathBuf::from("title_noise.wav")
+    );
+}
+
+#[test]
+fn video_and_audio_stay_in_sync() {
+    let path = std::env::temp_dir().join(format!("gba-video-{}", std::process::id()));
+    let mut recorder = VideoRecorder::create(&path, 44_100).unwrap();
+    let framebuffer = Framebuffer::new();
+    // A second of emulated time at the APU rate
+    let samples = vec![(0.0, 0.0); (CYCLES_PER_FRAME / 4) as usize];
+    let frames = 60;
+    for _ in 0..frames {
+        recorder.push_frame(&framebuffer, &samples).unwrap();
+    }
+    assert_eq!(recorder.frames(), frames);
+    recorder.finish().unwrap();
+
+    let video = std::fs::read(path.with_extension("y4m")).unwrap();
+    let audio = std::fs::read(path.with_extension("wav")).unwrap();
+    std::fs::remove_file(path.with_extension("y4m")).unwrap();
+    std::fs::remove_file(path.with_extension("wav")).unwrap();
+
+    assert!(video.starts_with(b"YUV4MPEG2 W160 H144 F262144:4389 "));
+    let frame_size = 6 + SCREEN_WIDTH * SCREEN_HEIGHT * 3 / 2;
+    assert_eq!(
+        video.len() % frame_size,
+        video.iter().position(|b| *b == b'\n').unwrap() + 1
+    );
+    let seconds = frames as f64 * CYCLES_PER_FRAME as f64 / CPU_CLOCK_HZ as f64;
+    let audio_samples = (audio.len() - 44) / 4;
+    assert!((audio_samples as f64 - seconds * 44_100.0).abs() < 16.0);
+}
@@ -0,0 +1,251 @@
+// Rewind buffer: a ring of save states taken every few frames. Every
+// keyframe_interval-th snapshot is stored whole, the ones in between as the
+// XOR against the previous keyframe. Both are run length encoded, so the
+// long stretches of unchanged bytes in a delta cost almost nothing.
+
+use super::pacing::FRAME_RATE;
+use std::collections::VecDeque;
+
+#[derive(Debug)]
+struct Snapshot {
+    frame: u64,
+    keyframe: bool,
+    len: usize,
+    data: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub struct Rewind {
+    // Frames between snapshots
+    interval: u64,
+    keyframe_interval: usize,
+    budget: usize,
+    used: usize,
+    snapshots: VecDeque<Snapshot>,
+    // Uncompressed copy of the newest keyframe, the base for new deltas
+    keyframe: Vec<u8>,
+    since_keyframe: usize,
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &[u8], position: &mut usize) -> usize {
+    let mut value = 0;
+    let mut shift = 0;
+    while let Some(byte) = data.get(*position) {
+        *position += 1;
+        value |= ((byte & 0x7F) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        shift += 7;
+    }
+    value
+}
+
+// Encodes data as pairs of (zero run length, literal length, literals)
+pub fn encode_zero_runs(data: &[u8]) -> Vec<u8> {
+    let mut out = Vec::new();
+    let mut position = 0;
+    while position < data.len() {
+        let zeros = data[position..]
+            .iter()
+            .take_while(|byte| **byte == 0)
+            .count();
+        position += zeros;
+
+        // Literals run until the next stretch of zeros worth a new pair
+        let start = position;
+        while position < data.len() {
+            let run = data[position..]
+                .iter()
+                .take(3)
+                .take_while(|byte| **byte == 0)
+                .count();
+            if run == 3 || position + run == data.len() && run > 0 {
+                break;
+            }
+            position += 1;
+        }
+
+        write_varint(&mut out, zeros);
+        write_varint(&mut out, position - start);
+        out.extend_from_slice(&data[start..position]);
+    }
+    out
+}
+
+pub fn decode_zero_runs(encoded: &[u8], len: usize) -> Vec<u8> {
+    let mut out = Vec::with_capacity(len);
+    let mut position = 0;
+    while position < encoded.len() {
+        let zeros = read_varint(encoded, &mut position);
+        out.resize(out.len() + zeros, 0);
+        let literals = read_varint(encoded, &mut position);
+        let end = (position + literals).min(encoded.len());
+        out.extend_from_slice(&encoded[position..end]);
+        position = end;
+    }
+    out.resize(len, 0);
+    out
+}
+
+fn xor(data: &[u8], base: &[u8]) -> Vec<u8> {
+    data.iter()
+        .enumerate()
+        .map(|(index, byte)| byte ^ base.get(index).copied().unwrap_or(0))
+        .collect()
+}
+
+impl Rewind {
+    // Takes a snapshot every interval frames, a keyframe every
+    // keyframe_interval snapshots, and keeps at most budget bytes.
+    pub fn new(interval: u64, keyframe_interval: usize, budget: usize) -> Rewind {
+        Rewind {
+            interval: interval.max(1),
+            keyframe_interval: keyframe_interval.max(1),
+            budget,
+            used: 0,
+            snapshots: VecDeque::new(),
+            keyframe: Vec::new(),
+            since_keyframe: 0,
+        }
+    }
+
+    pub fn interval(&self) -> u64 {
+        self.interval
+    }
+
+    pub fn len(&self) -> usize {
+        self.snapshots.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.snapshots.is_empty()
+    }
+
+    // Compressed bytes held
+    pub fn memory_used(&self) -> usize {
+        self.used
+    }
+
+    // Frame number of the oldest snapshot that can still be reached
+    pub fn oldest_frame(&self) -> Option<u64> {
+        self.snapshots.front().map(|snapshot| snapshot.frame)
+    }
+
+    pub fn clear(&mut self) {
+        self.snapshots.clear();
+        self.keyframe.clear();
+        self.used = 0;
+        self.since_keyframe = 0;
+    }
+
+    // Offers the state at the given frame; it is kept when the frame falls
+    // on the snapshot interval.
+    pub fn capture(&mut self, frame: u64, state: &[u8]) {
+        if !frame.is_multiple_of(self.interval) {
+            return;
+        }
+
+        let keyframe = self.snapshots.is_empty()
+            || self.since_keyframe + 1 >= self.keyframe_interval
+            || state.len() != self.keyframe.len();
+        let data = if keyframe {
+            self.keyframe = state.to_vec();
+            self.since_keyframe = 0;
+            encode_zero_runs(state)
+        } else {
+            self.since_keyframe += 1;
+            encode_zero_runs(&xor(state, &self.keyframe))
+        };
+
+        self.used += data.len();
+        self.snapshots.push_back(Snapshot {
+            frame,
+            keyframe,
+            len: state.len(),
+            data,
+        });
+        self.enforce_budget();
+    }
+
+    // Drops whole keyframe groups from the front, as deltas are useless
+    // without their keyframe. The newest group is always kept.
+    fn enforce_budget(&mut self) {
+        while self.used > self.budget {
+            let next_keyframe = self
+                .snapshots
+                .iter()
+                .skip(1)
+                .position(|snapshot| snapshot.keyframe);
+            let count = match next_keyframe {
+                Some(position) => position + 1,
+                None => break,
+            };
+            for snapshot in self.snapshots.drain(..count) {
+                self.used -= snapshot.data.len();
+            }
+        }
+    }
+
+    fn decode(&self, index: usize) -> Vec<u8> {
+        let snapshot = &self.snapshots[index];
+        let data = decode_zero_runs(&snapshot.data, snapshot.len);
+        if snapshot.keyframe {
+            return data;
+        }
+
+        let base_index = (0..index)
+            .rev()
+            .find(|index| self.snapshots[*index].keyframe)
+            .unwrap();
+        let base = &self.snapshots[base_index];
+        xor(&data, &decode_zero_runs(&base.data, base.len))
+    }
+
+    // Rewinds to the newest snapshot at or before frame, dropping the ones
+    // after it. Returns the frame and state, or None when the buffer does not
+    // reach back that far.
+    pub fn seek(&mut self, frame: u64) -> Option<(u64, Vec<u8>)> {
+        let index = self
+            .snapshots
+            .iter()
+            .rposition(|snapshot| snapshot.frame <= frame)?;
+        let state = self.decode(index);
+
+        for snapshot in self.snapshots.drain(index + 1..) {
+            self.used -= snapshot.data.len();
+        }
+        // New deltas continue from the keyframe of the restored snapshot
+        let base_index = (0..=index)
+            .rev()
+            .find(|index| self.snapshots[*index].keyframe)
+            .unwrap();
+        self.since_keyframe = index - base_index;
+        self.keyframe = self.decode(base_index);
+        Some((self.snapshots[index].frame, state))
+    }
+
+    // Same as seek, counting back from current_frame in seconds of emulated
+    // time.
+    pub fn seek_seconds(&mut self, current_frame: u64, seconds: f64) -> Option<(u64, Vec<u8>)> {
+        let frames = (seconds * FRAME_RATE).round() as u64;
+        self.seek(current_frame.saturating_sub(frames))
+    }
+
+    // For holding a rewind key: restores the newest snapshot before frame,
+    // so feeding the returned frame back in walks backwards.
+    pub fn step_back(&mut self, frame: u64) -> Option<(u64, Vec<u8>)> {
+        self.seek(frame.checked_sub(1)?)
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,117 @@
+use super::*;
+
+fn state(seed: u8) -> Vec<u8> {
+    let mut state = vec![0; 4096];
+    state[10] = seed;
+    state[2000] = seed.wrapping_mul(3);
+    state[4095] = 0xFF;
+    state
+}
+
+#[test]
+fn zero_runs_round_trip() {
+    let cases: Vec<Vec<u8>> = vec![
+        vec![],
+        vec![0; 1000],
+        vec![1, 2, 3],
+        vec![1, 0, 0, 2, 0, 0, 0, 0, 3, 0],
+        (0..=255).collect(),
+    ];
+    for data in cases {
+        let encoded = encode_zero_runs(&data);
+        assert_eq!(decode_zero_runs(&encoded, data.len()), data);
+    }
+}
+
+#[test]
+fn zero_runs_compress() {
+    let mut data = vec![0; 0x10000];
+    data[0x8000] = 1;
+
+    assert!(encode_zero_runs(&data).len() < 10);
+}
+
+#[test]
+fn captures_on_interval() {
+    let mut rewind = Rewind::new(4, 10, 1 << 20);
+
+    for frame in 0..10 {
+        rewind.capture(frame, &state(frame as u8));
+    }
+    assert_eq!(rewind.len(), 3);
+    assert_eq!(rewind.oldest_frame(), Some(0));
+}
+
+#[test]
+fn seek_restores_state() {
+    let mut rewind = Rewind::new(1, 4, 1 << 20);
+    for frame in 0..10 {
+        rewind.capture(frame, &state(frame as u8));
+    }
+
+    assert_eq!(rewind.seek(6), Some((6, state(6))));
+    // Later snapshots are gone
+    assert_eq!(rewind.len(), 7);
+    assert_eq!(rewind.seek(8), Some((6, state(6))));
+
+    // Capturing continues from the restored snapshot
+    rewind.capture(7, &state(70));
+    assert_eq!(rewind.seek(7), Some((7, state(70))));
+    assert_eq!(rewind.seek(5), Some((5, state(5))));
+}
+
+#[test]
+fn seek_seconds_back() {
+    let mut rewind = Rewind::new(1, 30, 1 << 20);
+    for frame in 0..300 {
+        rewind.capture(frame, &state(frame as u8));
+    }
+
+    let (frame, restored) = rewind.seek_seconds(299, 2.0).unwrap();
+    assert_eq!(frame, 299 - 119);
+    assert_eq!(restored, state(frame as u8));
+    assert_eq!(rewind.seek_seconds(frame, 10.0), Some((0, state(0))));
+}
+
+#[test]
+fn step_back_walks_backwards() {
+    let mut rewind = Rewind::new(2, 3, 1 << 20);
+    for frame in 0..10 {
+        rewind.capture(frame, &state(frame as u8));
+    }
+
+    let mut frame = 9;
+    let mut visited = Vec::new();
+    while let Some((previous, restored)) = rewind.step_back(frame) {
+        assert_eq!(restored, state(previous as u8));
+        visited.push(previous);
+        frame = previous;
+    }
+    assert_eq!(visited, vec![8, 6, 4, 2, 0]);
+}
+
+#[test]
+fn budget_drops_oldest_groups() {
+    let mut rewind = Rewind::new(1, 5, 0);
+    for frame in 0..12 {
+        rewind.capture(frame, &state(frame as u8));
+    }
+
+    // Only the newest keyframe group survives a zero budget
+    assert_eq!(rewind.oldest_frame(), Some(10));
+    assert_eq!(rewind.seek(11), Some((11, state(11))));
+    assert!(rewind.seek(9).is_none());
+}
+
+#[test]
+fn deltas_are_small() {
+    let mut rewind = Rewind::new(1, 100, 1 << 20);
+    let mut big = vec![0x55; 0x8000];
+    rewind.capture(0, &big);
+    let keyframe = rewind.memory_used();
+
+    big[100] = 0x66;
+    rewind.capture(1, &big);
+    assert!(rewind.memory_used() - keyframe < 16);
+    assert_eq!(rewind.seek(1).unwrap().1, big);
+}
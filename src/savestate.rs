@@ -0,0 +1,247 @@
+// Save state file format.
+//
+// A state is a header followed by tagged sections:
+//
+//   "GBST"  magic
+//   u16     format version (STATE_VERSION)
+//   string  emulator version that wrote the file
+//   u8      model (0 DMG, 1 CGB)
+//   u32     CRC-32 of the whole ROM
+//   string  ROM title from the cartridge header
+//   u32     length of the thumbnail, then the framebuffer at 2 bits a pixel
+//   sections until the end of the file:
+//     [u8; 4] tag, u32 payload length, payload
+//
+// Every payload starts with a version byte of its own. Loaders skip sections
+// they do not know and leave the state of missing ones at their reset
+// values, so newer sections can be added without breaking old files and a
+// section can migrate its own older layouts. Integers are little endian,
+// strings are a u32 byte length followed by UTF-8.
+//
+// Bank controllers, the RTC, the PPU and the timer are not emulated yet and
+// get sections of their own once they are.
+
+use super::model::Model;
+use super::screen::{Framebuffer, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::collections::HashMap;
+
+pub const STATE_MAGIC: [u8; 4] = *b"GBST";
+pub const STATE_VERSION: u16 = 1;
+
+pub const SECTION_CPU: [u8; 4] = *b"CPU ";
+pub const SECTION_MEMORY: [u8; 4] = *b"MEM ";
+pub const SECTION_APU: [u8; 4] = *b"APU ";
+pub const SECTION_JOYPAD: [u8; 4] = *b"JOYP";
+pub const SECTION_SERIAL: [u8; 4] = *b"SER ";
+pub const SECTION_PALETTE: [u8; 4] = *b"PAL ";
+
+#[derive(Debug, Default)]
+pub struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    pub fn new() -> StateWriter {
+        StateWriter { data: Vec::new() }
+    }
+
+    pub fn into_bytes(self) -> Vec<u8> {
+        self.data
+    }
+
+    pub fn write_u8(&mut self, value: u8) {
+        self.data.push(value);
+    }
+
+    pub fn write_bool(&mut self, value: bool) {
+        self.data.push(value as u8);
+    }
+
+    pub fn write_u16(&mut self, value: u16) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    pub fn write_u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+
+    // Length prefixed
+    pub fn write_bytes(&mut self, bytes: &[u8]) {
+        self.write_u32(bytes.len() as u32);
+        self.data.extend_from_slice(bytes);
+    }
+
+    pub fn write_str(&mut self, text: &str) {
+        self.write_bytes(text.as_bytes());
+    }
+
+    // Writes a tagged section with whatever the closure writes as payload
+    pub fn section<F: FnOnce(&mut StateWriter)>(&mut self, tag: [u8; 4], write: F) {
+        let mut payload = StateWriter::new();
+        write(&mut payload);
+        self.data.extend_from_slice(&tag);
+        self.write_bytes(&payload.data);
+    }
+}
+
+#[derive(Debug)]
+pub struct StateReader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> StateReader<'a> {
+    pub fn new(data: &'a [u8]) -> StateReader<'a> {
+        StateReader { data, position: 0 }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.position >= self.data.len()
+    }
+
+    fn take(&mut self, count: usize) -> Result<&'a [u8], String> {
+        if self.data.len() - self.position < count {
+            return Err("save state is truncated".to_string());
+        }
+        let bytes = &self.data[self.position..self.position + count];
+        self.position += count;
+        Ok(bytes)
+    }
+
+    pub fn read_u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    pub fn read_bool(&mut self) -> Result<bool, String> {
+        Ok(self.read_u8()? != 0)
+    }
+
+    pub fn read_u16(&mut self) -> Result<u16, String> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    pub fn read_u32(&mut self) -> Result<u32, String> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    pub fn read_u64(&mut self) -> Result<u64, String> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(u64::from_le_bytes(bytes))
+    }
+
+    pub fn read_bytes(&mut self) -> Result<&'a [u8], String> {
+        let len = self.read_u32()? as usize;
+        self.take(len)
+    }
+
+    pub fn read_str(&mut self) -> Result<String, String> {
+        String::from_utf8(self.read_bytes()?.to_vec())
+            .map_err(|_| "save state holds an invalid string".to_string())
+    }
+
+    // Reads the section version byte, refusing versions newer than supported
+    pub fn read_version(&mut self, name: &str, supported: u8) -> Result<u8, String> {
+        let version = self.read_u8()?;
+        if version == 0 || version > supported {
+            return Err(format!(
+                "{} section version {} is not supported, this build reads up to {}",
+                name, version, supported
+            ));
+        }
+        Ok(version)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct StateHeader {
+    pub version: u16,
+    pub emulator_version: String,
+    pub model: Model,
+    pub rom_crc: u32,
+    pub title: String,
+    pub thumbnail: Framebuffer,
+}
+
+impl StateHeader {
+    pub fn write(&self, writer: &mut StateWriter) {
+        writer.data.extend_from_slice(&STATE_MAGIC);
+        writer.write_u16(self.version);
+        writer.write_str(&self.emulator_version);
+        writer.write_u8(match self.model {
+            Model::Dmg => 0,
+            Model::Cgb => 1,
+        });
+        writer.write_u32(self.rom_crc);
+        writer.write_str(&self.title);
+
+        let mut packed = vec![0; SCREEN_WIDTH * SCREEN_HEIGHT / 4];
+        for (index, shade) in self.thumbnail.pixels().iter().enumerate() {
+            packed[index / 4] |= shade << ((index % 4) * 2);
+        }
+        writer.write_bytes(&packed);
+    }
+
+    pub fn read(reader: &mut StateReader) -> Result<StateHeader, String> {
+        if reader.take(4).ok() != Some(&STATE_MAGIC[..]) {
+            return Err("not a save state".to_string());
+        }
+        let version = reader.read_u16()?;
+        if version == 0 || version > STATE_VERSION {
+            return Err(format!(
+                "save state format {} is not supported, this build reads up to {}",
+                version, STATE_VERSION
+            ));
+        }
+        let emulator_version = reader.read_str()?;
+        let model = match reader.read_u8()? {
+            0 => Model::Dmg,
+            1 => Model::Cgb,
+            other => return Err(format!("save state has unknown model {}", other)),
+        };
+        let rom_crc = reader.read_u32()?;
+        let title = reader.read_str()?;
+
+        let packed = reader.read_bytes()?;
+        let mut thumbnail = Framebuffer::new();
+        for y in 0..SCREEN_HEIGHT {
+            for x in 0..SCREEN_WIDTH {
+                let index = y * SCREEN_WIDTH + x;
+                let byte = packed.get(index / 4).copied().unwrap_or(0);
+                thumbnail.set_pixel(x, y, byte >> ((index % 4) * 2));
+            }
+        }
+
+        Ok(StateHeader {
+            version,
+            emulator_version,
+            model,
+            rom_crc,
+            title,
+            thumbnail,
+        })
+    }
+}
+
+// Splits the rest of a state into its sections by tag
+pub fn read_sections<'a>(
+    reader: &mut StateReader<'a>,
+) -> Result<HashMap<[u8; 4], &'a [u8]>, String> {
+    let mut sections = HashMap::new();
+    while !reader.is_empty() {
+        let mut tag = [0; 4];
+        tag.copy_from_slice(reader.take(4)?);
+        sections.insert(tag, reader.read_bytes()?);
+    }
+    Ok(sections)
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,107 @@
+use super::*;
+
+#[test]
+fn values_round_trip() {
+    let mut writer = StateWriter::new();
+    writer.write_u8(0x12);
+    writer.write_bool(true);
+    writer.write_u16(0x3456);
+    writer.write_u32(0x789A_BCDE);
+    writer.write_u64(0x0102_0304_0506_0708);
+    writer.write_str("tetris");
+    let data = writer.into_bytes();
+
+    let mut reader = StateReader::new(&data);
+    assert_eq!(reader.read_u8(), Ok(0x12));
+    assert_eq!(reader.read_bool(), Ok(true));
+    assert_eq!(reader.read_u16(), Ok(0x3456));
+    assert_eq!(reader.read_u32(), Ok(0x789A_BCDE));
+    assert_eq!(reader.read_u64(), Ok(0x0102_0304_0506_0708));
+    assert_eq!(reader.read_str(), Ok("tetris".to_string()));
+    assert!(reader.is_empty());
+    assert!(reader.read_u8().is_err());
+}
+
+#[test]
+fn little_endian_layout() {
+    let mut writer = StateWriter::new();
+    writer.write_u16(0x1234);
+    writer.write_bytes(&[0xAA]);
+
+    assert_eq!(writer.into_bytes(), vec![0x34, 0x12, 1, 0, 0, 0, 0xAA]);
+}
+
+#[test]
+fn truncated_bytes() {
+    let data = [0x10, 0x00, 0x00, 0x00, 0x01];
+
+    assert!(StateReader::new(&data).read_bytes().is_err());
+}
+
+#[test]
+fn section_versions() {
+    let data = [2];
+
+    assert!(StateReader::new(&data).read_version("CPU", 1).is_err());
+    assert_eq!(StateReader::new(&data).read_version("CPU", 2), Ok(2));
+    assert!(StateReader::new(&[0]).read_version("CPU", 1).is_err());
+}
+
+fn header() -> StateHeader {
+    let mut thumbnail = Framebuffer::new();
+    thumbnail.set_pixel(0, 0, 3);
+    thumbnail.set_pixel(5, 7, 1);
+    thumbnail.set_pixel(SCREEN_WIDTH - 1, SCREEN_HEIGHT - 1, 2);
+    StateHeader {
+        version: STATE_VERSION,
+        emulator_version: "0.1.0".to_string(),
+        model: Model::Cgb,
+        rom_crc: 0xDEAD_BEEF,
+        title: "ZELDA".to_string(),
+        thumbnail,
+    }
+}
+
+#[test]
+fn header_round_trip() {
+    let mut writer = StateWriter::new();
+    header().write(&mut writer);
+    let data = writer.into_bytes();
+
+    assert_eq!(&data[0..4], b"GBST");
+    let read = StateHeader::read(&mut StateReader::new(&data)).unwrap();
+    assert_eq!(read, header());
+}
+
+#[test]
+fn unsupported_format_refused() {
+    let mut writer = StateWriter::new();
+    let mut newer = header();
+    newer.version = STATE_VERSION + 1;
+    newer.write(&mut writer);
+
+    let data = writer.into_bytes();
+    assert!(StateHeader::read(&mut StateReader::new(&data)).is_err());
+    assert!(StateHeader::read(&mut StateReader::new(b"PNG.....")).is_err());
+
+    let mut writer = StateWriter::new();
+    let mut zero = header();
+    zero.version = 0;
+    zero.write(&mut writer);
+    let data = writer.into_bytes();
+    assert!(StateHeader::read(&mut StateReader::new(&data)).is_err());
+}
+
+#[test]
+fn sections_by_tag() {
+    let mut writer = StateWriter::new();
+    writer.section(SECTION_CPU, |writer| writer.write_u8(1));
+    writer.section(*b"NEW ", |writer| writer.write_u32(7));
+    writer.section(SECTION_APU, |writer| writer.write_u16(2));
+    let data = writer.into_bytes();
+
+    let sections = read_sections(&mut StateReader::new(&data)).unwrap();
+    assert_eq!(sections.len(), 3);
+    assert_eq!(sections[&SECTION_CPU], &[1]);
+    assert_eq!(sections[&SECTION_APU], &[2, 0]);
+}
@@ -0,0 +1,76 @@
+// The LCD: a 160x144 grid of 2 bit shades, 0 being the lightest
+
+use super::image::{save_image, scale_rgb};
+use std::path::Path;
+
+pub const SCREEN_WIDTH: usize = 160;
+pub const SCREEN_HEIGHT: usize = 144;
+
+// RGB of each shade, a neutral grey ramp
+pub const DMG_PALETTE: [[u8; 3]; 4] = [
+    [0xFF, 0xFF, 0xFF],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+    [0x00, 0x00, 0x00],
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Framebuffer {
+    pixels: Vec<u8>,
+}
+
+impl Framebuffer {
+    pub fn new() -> Framebuffer {
+        Framebuffer {
+            pixels: vec![0; SCREEN_WIDTH * SCREEN_HEIGHT],
+        }
+    }
+
+    // Shades in row major order
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> u8 {
+        self.pixels[y * SCREEN_WIDTH + x]
+    }
+
+    pub fn set_pixel(&mut self, x: usize, y: usize, shade: u8) {
+        self.pixels[y * SCREEN_WIDTH + x] = shade & 0x03;
+    }
+
+    pub fn clear(&mut self) {
+        for pixel in self.pixels.iter_mut() {
+            *pixel = 0;
+        }
+    }
+
+    // Packed 8 bit RGB, three bytes per pixel
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|shade| DMG_PALETTE[*shade as usize].iter().copied())
+            .collect()
+    }
+
+    // Saves a screenshot, PNG or PPM by the extension of path, enlarged by a
+    // whole scale factor (1 for the plain 160x144)
+    pub fn save(&self, path: &Path, scale: usize) -> Result<(), String> {
+        let scale = scale.max(1);
+        save_image(
+            path,
+            SCREEN_WIDTH * scale,
+            SCREEN_HEIGHT * scale,
+            &scale_rgb(SCREEN_WIDTH, SCREEN_HEIGHT, &self.to_rgb(), scale),
+        )
+    }
+}
+
+impl Default for Framebuffer {
+    fn default() -> Framebuffer {
+        Framebuffer::new()
+    }
+}
+
+#[cfg(test)]
+mod test;
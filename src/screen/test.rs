@@ -0,0 +1,39 @@
+use super::*;
+
+#[test]
+fn starts_blank() {
+    let framebuffer = Framebuffer::new();
+
+    assert_eq!(framebuffer.pixels().len(), SCREEN_WIDTH * SCREEN_HEIGHT);
+    assert!(framebuffer.pixels().iter().all(|shade| *shade == 0));
+}
+
+#[test]
+fn rgb_conversion() {
+    let mut framebuffer = Framebuffer::new();
+
+    framebuffer.set_pixel(1, 0, 3);
+    framebuffer.set_pixel(0, 1, 6);
+    let rgb = framebuffer.to_rgb();
+    assert_eq!(rgb.len(), SCREEN_WIDTH * SCREEN_HEIGHT * 3);
+    assert_eq!(&rgb[0..6], &[0xFF, 0xFF, 0xFF, 0x00, 0x00, 0x00]);
+    assert_eq!(framebuffer.pixel(0, 1), 2);
+}
+
+#[test]
+fn saves_scaled_screenshots() {
+    let mut framebuffer = Framebuffer::new();
+    framebuffer.set_pixel(0, 0, 3);
+    let path = std::env::temp_dir().join(format!("gba-screen-{}.ppm", std::process::id()));
+    framebuffer.save(&path, 2).unwrap();
+    let data = std::fs::read(&path).unwrap();
+    std::fs::remove_file(&path).unwrap();
+
+    let header = b"P6\n320 288\n255\n";
+    assert_eq!(&data[..header.len()], &header[..]);
+    let pixels = &data[header.len()..];
+    assert_eq!(pixels.len(), 320 * 288 * 3);
+    // The black pixel covers 2x2
+    assert_eq!(&pixels[0..9], &[0, 0, 0, 0, 0, 0, 0xFF, 0xFF, 0xFF]);
+    assert_eq!(&pixels[320 * 3..320 * 3 + 6], &[0, 0, 0, 0, 0, 0]);
+}
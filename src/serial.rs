@@ -0,0 +1,269 @@
+// Serial port (SB/SC) and the link cable backends it can be connected to
+
+use super::savestate::{StateReader, StateWriter};
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::rc::Rc;
+
+pub const SB: usize = 0xFF01;
+pub const SC: usize = 0xFF02;
+
+const SC_START: u8 = 0x80;
+const SC_INTERNAL_CLOCK: u8 = 0x01;
+
+const STATE_VERSION: u8 = 1;
+
+// 8 bits at 8192 Hz
+pub const TRANSFER_CYCLES: u32 = 4096;
+
+// What sits at the other end of the link cable
+pub trait SerialLink: Debug {
+    // This side drives the clock: sends byte and returns the byte shifted in
+    // from the other end, or None when that has not arrived yet. The serial
+    // port then calls finish_transfer on every step until it has.
+    fn transfer(&mut self, byte: u8) -> Option<u8>;
+
+    // Only needed by links whose transfer can return None
+    fn finish_transfer(&mut self) -> Option<u8> {
+        Some(0xFF)
+    }
+
+    // The other end drives the clock. Called while a transfer is pending with
+    // the byte this side will shift out; returns the received byte once the
+    // other end has clocked a transfer.
+    fn receive(&mut self, outgoing: u8) -> Option<u8>;
+}
+
+// Nothing plugged in: the line floats high and nobody clocks us
+#[derive(Debug)]
+pub struct DisconnectedLink;
+
+impl SerialLink for DisconnectedLink {
+    fn transfer(&mut self, _byte: u8) -> Option<u8> {
+        Some(0xFF)
+    }
+
+    fn receive(&mut self, _outgoing: u8) -> Option<u8> {
+        None
+    }
+}
+
+// Records every byte sent, e.g. the text output of the Blargg test ROMs
+#[derive(Debug)]
+pub struct CaptureLink {
+    output: Rc<RefCell<Vec<u8>>>,
+}
+
+impl CaptureLink {
+    pub fn new() -> CaptureLink {
+        CaptureLink {
+            output: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    // Shared handle to the captured bytes that stays valid after the link
+    // is handed to the serial port.
+    pub fn output(&self) -> Rc<RefCell<Vec<u8>>> {
+        self.output.clone()
+    }
+}
+
+impl Default for CaptureLink {
+    fn default() -> CaptureLink {
+        CaptureLink::new()
+    }
+}
+
+impl SerialLink for CaptureLink {
+    fn transfer(&mut self, byte: u8) -> Option<u8> {
+        self.output.borrow_mut().push(byte);
+        Some(0xFF)
+    }
+
+    fn receive(&mut self, _outgoing: u8) -> Option<u8> {
+        None
+    }
+}
+
+#[derive(Debug)]
+struct CableState {
+    // Byte each end is waiting to shift out on an external clock
+    waiting: [Option<u8>; 2],
+    // Byte clocked in to each end by the other one
+    delivered: [Option<u8>; 2],
+}
+
+// One end of a cable between two emulator instances in the same process
+#[derive(Debug)]
+pub struct LinkPort {
+    end: usize,
+    state: Rc<RefCell<CableState>>,
+}
+
+pub fn link_cable() -> (LinkPort, LinkPort) {
+    let state = Rc::new(RefCell::new(CableState {
+        waiting: [None, None],
+        delivered: [None, None],
+    }));
+    (
+        LinkPort {
+            end: 0,
+            state: state.clone(),
+        },
+        LinkPort { end: 1, state },
+    )
+}
+
+impl SerialLink for LinkPort {
+    fn transfer(&mut self, byte: u8) -> Option<u8> {
+        let mut state = self.state.borrow_mut();
+        let other = 1 - self.end;
+        match state.waiting[other].take() {
+            Some(reply) => {
+                state.delivered[other] = Some(byte);
+                Some(reply)
+            }
+            // The other side isn't listening, so nothing comes back
+            None => Some(0xFF),
+        }
+    }
+
+    fn receive(&mut self, outgoing: u8) -> Option<u8> {
+        let mut state = self.state.borrow_mut();
+        match state.delivered[self.end].take() {
+            Some(byte) => {
+                state.waiting[self.end] = None;
+                Some(byte)
+            }
+            None => {
+                state.waiting[self.end] = Some(outgoing);
+                None
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct Serial {
+    sb: u8,
+    sc: u8,
+    cycles_left: u32,
+    // The clock ran out and the link is still waiting for the other end
+    awaiting_reply: bool,
+    link: Box<dyn SerialLink>,
+}
+
+impl Serial {
+    pub fn new() -> Serial {
+        Serial {
+            sb: 0,
+            sc: 0,
+            cycles_left: 0,
+            awaiting_reply: false,
+            link: Box::new(DisconnectedLink),
+        }
+    }
+
+    pub fn set_link(&mut self, link: Box<dyn SerialLink>) {
+        self.link = link;
+    }
+
+    // Clears the registers, the link stays plugged in
+    pub fn reset(&mut self) {
+        self.sb = 0;
+        self.sc = 0;
+        self.cycles_left = 0;
+        self.awaiting_reply = false;
+    }
+
+    pub fn read(&self, address: usize) -> u8 {
+        match address {
+            SB => self.sb,
+            SC => self.sc | 0x7E,
+            _ => 0xFF,
+        }
+    }
+
+    pub fn write(&mut self, address: usize, value: u8) {
+        match address {
+            SB => self.sb = value,
+            SC => {
+                self.sc = value & (SC_START | SC_INTERNAL_CLOCK);
+                self.awaiting_reply = false;
+                if self.transferring() && self.sc & SC_INTERNAL_CLOCK != 0 {
+                    self.cycles_left = TRANSFER_CYCLES;
+                }
+            }
+            _ => {}
+        }
+    }
+
+    // The link is not part of the state, it stays plugged in
+    pub fn save_state(&self, writer: &mut StateWriter) {
+        writer.write_u8(STATE_VERSION);
+        writer.write_u8(self.sb);
+        writer.write_u8(self.sc);
+        writer.write_u32(self.cycles_left);
+    }
+
+    pub fn load_state(&mut self, reader: &mut StateReader) -> Result<(), String> {
+        reader.read_version("serial", STATE_VERSION)?;
+        self.sb = reader.read_u8()?;
+        self.sc = reader.read_u8()? & (SC_START | SC_INTERNAL_CLOCK);
+        self.cycles_left = reader.read_u32()?;
+        self.awaiting_reply = false;
+        Ok(())
+    }
+
+    pub fn transferring(&self) -> bool {
+        self.sc & SC_START != 0
+    }
+
+    // Returns true when a transfer finished, which requests the serial interrupt
+    pub fn step(&mut self, cycles: u32) -> bool {
+        if !self.transferring() {
+            return false;
+        }
+
+        let received = if self.sc & SC_INTERNAL_CLOCK != 0 {
+            if cycles < self.cycles_left {
+                self.cycles_left -= cycles;
+                return false;
+            }
+            self.cycles_left = 0;
+            let reply = if self.awaiting_reply {
+                self.link.finish_transfer()
+            } else {
+                self.link.transfer(self.sb)
+            };
+            match reply {
+                Some(byte) => {
+                    self.awaiting_reply = false;
+                    byte
+                }
+                None => {
+                    self.awaiting_reply = true;
+                    return false;
+                }
+            }
+        } else {
+            match self.link.receive(self.sb) {
+                Some(byte) => byte,
+                None => return false,
+            }
+        };
+
+        self.sb = received;
+        self.sc &= !SC_START;
+        true
+    }
+}
+
+impl Default for Serial {
+    fn default() -> Serial {
+        Serial::new()
+    }
+}
+
+#[cfg(test)]
+mod test;
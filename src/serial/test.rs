@@ -0,0 +1,86 @@
+use super::*;
+
+#[test]
+fn register_reads() {
+    let mut serial = Serial::new();
+
+    serial.write(SB, 0x42);
+    serial.write(SC, 0x01);
+    assert_eq!(serial.read(SB), 0x42);
+    assert_eq!(serial.read(SC), 0x7F);
+}
+
+#[test]
+fn disconnected_internal_transfer() {
+    let mut serial = Serial::new();
+
+    serial.write(SB, 0x42);
+    serial.write(SC, 0x81);
+    assert!(!serial.step(TRANSFER_CYCLES - 1));
+    assert_eq!(serial.read(SC), 0xFF);
+
+    assert!(serial.step(1));
+    assert_eq!(serial.read(SB), 0xFF);
+    assert_eq!(serial.read(SC), 0x7F);
+    assert!(!serial.step(TRANSFER_CYCLES));
+}
+
+#[test]
+fn disconnected_external_clock_never_completes() {
+    let mut serial = Serial::new();
+
+    serial.write(SB, 0x42);
+    serial.write(SC, 0x80);
+    assert!(!serial.step(TRANSFER_CYCLES * 10));
+    assert_eq!(serial.read(SB), 0x42);
+    assert_eq!(serial.read(SC), 0xFE);
+}
+
+#[test]
+fn capture_output() {
+    let link = CaptureLink::new();
+    let output = link.output();
+    let mut serial = Serial::new();
+    serial.set_link(Box::new(link));
+
+    for byte in b"Passed".iter() {
+        serial.write(SB, *byte);
+        serial.write(SC, 0x81);
+        serial.step(TRANSFER_CYCLES);
+    }
+    assert_eq!(&output.borrow()[..], b"Passed");
+}
+
+#[test]
+fn cable_between_two_ports() {
+    let (first, second) = link_cable();
+    let mut master = Serial::new();
+    let mut slave = Serial::new();
+    master.set_link(Box::new(first));
+    slave.set_link(Box::new(second));
+
+    slave.write(SB, 0x34);
+    slave.write(SC, 0x80);
+    assert!(!slave.step(4));
+
+    master.write(SB, 0x12);
+    master.write(SC, 0x81);
+    assert!(master.step(TRANSFER_CYCLES));
+    assert_eq!(master.read(SB), 0x34);
+
+    assert!(slave.step(4));
+    assert_eq!(slave.read(SB), 0x12);
+    assert_eq!(slave.read(SC), 0x7E);
+}
+
+#[test]
+fn cable_with_idle_partner() {
+    let (first, _second) = link_cable();
+    let mut master = Serial::new();
+    master.set_link(Box::new(first));
+
+    master.write(SB, 0x12);
+    master.write(SC, 0x81);
+    assert!(master.step(TRANSFER_CYCLES));
+    assert_eq!(master.read(SB), 0xFF);
+}
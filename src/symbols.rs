@@ -0,0 +1,212 @@
+// Symbol files written by the RGBDS linker, one label per line with its bank
+// and address in hex:
+//
+//   ; File generated by rgblink
+//   00:0150 Main
+//   01:4000 Main.loop
+//
+// The bank tells apart labels that share an address in the switchable ROM
+// and WRAM areas, so lookups take the bank mapped at the time.
+
+use super::disasm::{Instruction, Operand};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+// Where memory areas start. A label only names addresses up to the end of
+// its own area, so the last ROM label does not swallow all of VRAM.
+const AREA_STARTS: [u16; 10] = [
+    0x0000, 0x4000, 0x8000, 0xA000, 0xC000, 0xD000, 0xE000, 0xFE00, 0xFF00, 0xFF80,
+];
+
+fn area_start(address: u16) -> u16 {
+    AREA_STARTS
+        .iter()
+        .rev()
+        .find(|start| **start <= address)
+        .copied()
+        .unwrap_or(0)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Location {
+    pub bank: u16,
+    pub address: u16,
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Symbols {
+    locations: HashMap<String, Location>,
+    // Every label at a location, in file order
+    labels: BTreeMap<Location, Vec<String>>,
+}
+
+fn parse_hex(text: &str) -> Result<u16, String> {
+    let digits = text.trim_start_matches("0x").trim_start_matches('$');
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid hex value '{}'", text))
+}
+
+// "BB:AAAA", as in the symbol file
+fn parse_location(text: &str) -> Result<Location, String> {
+    match text.split_once(':') {
+        Some((bank, address)) => Ok(Location {
+            bank: parse_hex(bank)?,
+            address: parse_hex(address)?,
+        }),
+        None => Err(format!("expected bank:address, found '{}'", text)),
+    }
+}
+
+impl Symbols {
+    pub fn new() -> Symbols {
+        Symbols::default()
+    }
+
+    pub fn parse(text: &str) -> Result<Symbols, String> {
+        let mut symbols = Symbols::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = match line.find(';') {
+                Some(comment) => &line[..comment],
+                None => line,
+            }
+            .trim();
+            if line.is_empty() {
+                continue;
+            }
+            let error = |message: String| format!("line {}: {}", number + 1, message);
+
+            let mut words = line.split_whitespace();
+            let location = parse_location(words.next().unwrap_or("")).map_err(error)?;
+            let name = match (words.next(), words.next()) {
+                (Some(name), None) => name,
+                (None, _) => return Err(error("missing label name".to_string())),
+                (Some(_), Some(extra)) => {
+                    return Err(error(format!("unexpected '{}' after the label", extra)))
+                }
+            };
+            symbols.add(name, location);
+        }
+        Ok(symbols)
+    }
+
+    pub fn load(path: &Path) -> Result<Symbols, String> {
+        let text = fs::read_to_string(path)
+            .map_err(|e| format!("could not read {}: {}", path.display(), e))?;
+        Symbols::parse(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    // The file rgblink -n writes next to the ROM
+    pub fn path_for_rom(rom_path: &Path) -> PathBuf {
+        rom_path.with_extension("sym")
+    }
+
+    // Loads the symbols next to the ROM, if it has any
+    pub fn load_for_rom(rom_path: &Path) -> Result<Option<Symbols>, String> {
+        let path = Symbols::path_for_rom(rom_path);
+        if path.is_file() {
+            Symbols::load(&path).map(Some)
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub fn add(&mut self, name: &str, location: Location) {
+        if self.locations.contains_key(name) {
+            return;
+        }
+        self.locations.insert(name.to_string(), location);
+        self.labels
+            .entry(location)
+            .or_default()
+            .push(name.to_string());
+    }
+
+    pub fn len(&self) -> usize {
+        self.locations.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.locations.is_empty()
+    }
+
+    pub fn location(&self, name: &str) -> Option<Location> {
+        self.locations.get(name).copied()
+    }
+
+    // The first label at exactly this address
+    pub fn label(&self, bank: u16, address: u16) -> Option<&str> {
+        self.labels
+            .get(&Location { bank, address })
+            .and_then(|names| names.first())
+            .map(|name| name.as_str())
+    }
+
+    // The closest label at or before address in the same memory area, with
+    // the distance from it: "Main.loop+3"
+    pub fn describe(&self, bank: u16, address: u16) -> Option<String> {
+        let start = Location {
+            bank,
+            address: area_start(address),
+        };
+        let (location, names) = self
+            .labels
+            .range(start..=Location { bank, address })
+            .next_back()?;
+        let offset = address - location.address;
+        Some(if offset == 0 {
+            names[0].clone()
+        } else {
+            format!("{}+{}", names[0], offset)
+        })
+    }
+
+    // An address typed by the user: a label, "BB:AAAA" or plain hex. Labels
+    // win over hex digits, so "$" or "0x" forces a name like "beef" to be
+    // read as a number. Plain addresses come without a bank.
+    pub fn resolve(&self, text: &str) -> Result<(u16, Option<u16>), String> {
+        if let Some(location) = self.location(text) {
+            return Ok((location.address, Some(location.bank)));
+        }
+        if text.contains(':') {
+            let location = parse_location(text)?;
+            return Ok((location.address, Some(location.bank)));
+        }
+        match parse_hex(text) {
+            Ok(address) => Ok((address, None)),
+            Err(_) if !self.is_empty() => {
+                Err(format!("'{}' is neither a label nor a hex address", text))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // RGBDS syntax with labels in place of jump targets and memory operands.
+    // Other immediate values are as likely to be constants and stay numbers.
+    // bank gives the bank mapped at an address.
+    pub fn format_instruction<F: Fn(u16) -> u16>(
+        &self,
+        instruction: &Instruction,
+        bank: F,
+    ) -> String {
+        let branch = instruction.branch_target().is_some();
+        instruction.format_with(|operand| {
+            let (address, memory) = match *operand {
+                Operand::Immediate16(target) if branch => (target, false),
+                Operand::Relative { target, .. } => (target, false),
+                Operand::Vector(vector) => (vector as u16, false),
+                Operand::Address(address) => (address, true),
+                Operand::HighAddress(offset) => (0xFF00 | offset as u16, true),
+                _ => return None,
+            };
+            let name = self.label(bank(address), address)?;
+            Some(if memory {
+                format!("[{}]", name)
+            } else {
+                name.to_string()
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,114 @@
+use super::*;
+use crate::disasm::decode;
+
+const FILE: &str = "; File generated by rgblink
+00:0000 RST_00
+00:0150 Main
+00:0150 EntryPoint
+00:0158 Main.loop ; comment
+01:4000 Bank1Routine
+02:4000 Bank2Routine
+00:c000 wBuffer
+
+00:ff80 hCounter
+";
+
+#[test]
+fn parses_rgbds_files() {
+    let symbols = Symbols::parse(FILE).unwrap();
+    assert_eq!(symbols.len(), 8);
+    assert_eq!(
+        symbols.location("Main.loop"),
+        Some(Location {
+            bank: 0,
+            address: 0x0158
+        })
+    );
+    assert_eq!(
+        symbols.location("Bank2Routine"),
+        Some(Location {
+            bank: 2,
+            address: 0x4000
+        })
+    );
+    assert_eq!(symbols.location("Missing"), None);
+
+    assert_eq!(
+        Symbols::parse("00:0150").unwrap_err(),
+        "line 1: missing label name"
+    );
+    assert_eq!(
+        Symbols::parse("\n0150 Main").unwrap_err(),
+        "line 2: expected bank:address, found '0150'"
+    );
+    assert!(Symbols::parse("00:zz Main").is_err());
+}
+
+#[test]
+fn looks_up_by_bank() {
+    let symbols = Symbols::parse(FILE).unwrap();
+    // The first label at an address is the one shown
+    assert_eq!(symbols.label(0, 0x0150), Some("Main"));
+    assert_eq!(symbols.label(1, 0x4000), Some("Bank1Routine"));
+    assert_eq!(symbols.label(2, 0x4000), Some("Bank2Routine"));
+    assert_eq!(symbols.label(3, 0x4000), None);
+    assert_eq!(symbols.label(0, 0x0151), None);
+}
+
+#[test]
+fn describes_addresses_after_labels() {
+    let symbols = Symbols::parse(FILE).unwrap();
+    assert_eq!(symbols.describe(0, 0x0150).unwrap(), "Main");
+    assert_eq!(symbols.describe(0, 0x015B).unwrap(), "Main.loop+3");
+    assert_eq!(symbols.describe(2, 0x4010).unwrap(), "Bank2Routine+16");
+    assert_eq!(symbols.describe(0, 0xFF81).unwrap(), "hCounter+1");
+    // Labels do not reach into the next memory area
+    assert_eq!(symbols.describe(0, 0x8000), None);
+    assert_eq!(symbols.describe(0, 0xFF40), None);
+}
+
+#[test]
+fn resolves_user_addresses() {
+    let symbols = Symbols::parse(FILE).unwrap();
+    assert_eq!(symbols.resolve("Main.loop"), Ok((0x0158, Some(0))));
+    assert_eq!(symbols.resolve("02:4000"), Ok((0x4000, Some(2))));
+    assert_eq!(symbols.resolve("$c000"), Ok((0xC000, None)));
+    assert_eq!(symbols.resolve("150"), Ok((0x0150, None)));
+    assert!(symbols.resolve("Nowhere").is_err());
+
+    assert_eq!(Symbols::new().resolve("150"), Ok((0x0150, None)));
+    assert_eq!(
+        Symbols::new().resolve("xyz").unwrap_err(),
+        "invalid hex value 'xyz'"
+    );
+}
+
+#[test]
+fn finds_the_file_next_to_the_rom() {
+    assert_eq!(
+        Symbols::path_for_rom(Path::new("build/game.gb")),
+        PathBuf::from("build/game.sym")
+    );
+    assert_eq!(
+        Symbols::load_for_rom(Path::new("/nonexistent/game.gb")),
+        Ok(None)
+    );
+}
+
+#[test]
+fn names_operands() {
+    let symbols = Symbols::parse(FILE).unwrap();
+    let format = |bytes: &[u8], address: u16, bank: u16| {
+        let bytes = bytes.to_vec();
+        let instruction = decode(|at| bytes[(at - address) as usize], address);
+        symbols.format_instruction(&instruction, |_| bank)
+    };
+    assert_eq!(format(&[0xCD, 0x00, 0x40], 0x0150, 2), "call Bank2Routine");
+    assert_eq!(format(&[0xCD, 0x00, 0x40], 0x0150, 3), "call $4000");
+    assert_eq!(format(&[0x18, 0x06], 0x0150, 0), "jr Main.loop");
+    assert_eq!(format(&[0xEA, 0x00, 0xC0], 0x0150, 0), "ld [wBuffer], a");
+    assert_eq!(format(&[0xF0, 0x80], 0x0150, 0), "ldh a, [hCounter]");
+    assert_eq!(format(&[0xC7], 0x0150, 0), "rst RST_00");
+    // A value that happens to match a label is left alone
+    assert_eq!(format(&[0x21, 0x00, 0xC0], 0x0150, 0), "ld hl, $C000");
+}
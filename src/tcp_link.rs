@@ -0,0 +1,222 @@
+// Link cable over a TCP socket, for connecting two emulator processes.
+//
+// Every message is three bytes: a kind, a sequence number and a data byte.
+// The side that drives the clock sends TRANSFER with its byte and the serial
+// port keeps stepping until the REPLY with the same sequence number brings
+// the other side's byte. A TRANSFER that arrives before the other side is
+// ready stays queued in the socket until its game arms an external clock
+// transfer, so latency stalls the transfer instead of dropping the byte.
+// A REPLY that comes after the transfer gave up is dropped.
+
+use super::serial::SerialLink;
+use std::fmt;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+const MAGIC: &[u8; 4] = b"GBLK";
+const PROTOCOL_VERSION: u8 = 2;
+const MESSAGE_SIZE: usize = 3;
+
+const MSG_TRANSFER: u8 = 0x01;
+const MSG_REPLY: u8 = 0x02;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(5);
+// How long the clocking side waits for the other process to answer
+const REPLY_TIMEOUT: Duration = Duration::from_secs(2);
+
+// Things the emulator should tell the user about
+#[derive(Debug, Clone, PartialEq)]
+pub enum LinkEvent {
+    Disconnected(String),
+    NoReply,
+}
+
+impl fmt::Display for LinkEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            LinkEvent::Disconnected(reason) => write!(f, "link cable disconnected: {}", reason),
+            LinkEvent::NoReply => write!(f, "link cable: no reply from the other side"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct TcpLink {
+    stream: TcpStream,
+    inbox: Vec<u8>,
+    connected: bool,
+    sequence: u8,
+    // Sequence number of the transfer waiting for its reply, and when to
+    // give up on it
+    pending: Option<(u8, Instant)>,
+    events: Arc<Mutex<Vec<LinkEvent>>>,
+}
+
+impl TcpLink {
+    // Waits for the other emulator to connect
+    pub fn listen<A: ToSocketAddrs>(address: A) -> io::Result<TcpLink> {
+        let listener = TcpListener::bind(address)?;
+        TcpLink::accept(&listener)
+    }
+
+    pub fn accept(listener: &TcpListener) -> io::Result<TcpLink> {
+        let (stream, _) = listener.accept()?;
+        TcpLink::from_stream(stream)
+    }
+
+    pub fn connect<A: ToSocketAddrs>(address: A) -> io::Result<TcpLink> {
+        TcpLink::from_stream(TcpStream::connect(address)?)
+    }
+
+    fn from_stream(mut stream: TcpStream) -> io::Result<TcpLink> {
+        stream.set_nodelay(true)?;
+        stream.set_read_timeout(Some(HANDSHAKE_TIMEOUT))?;
+
+        let mut hello = [0; 5];
+        hello[..4].copy_from_slice(MAGIC);
+        hello[4] = PROTOCOL_VERSION;
+        stream.write_all(&hello)?;
+
+        let mut peer = [0; 5];
+        stream.read_exact(&mut peer)?;
+        if &peer[..4] != MAGIC {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                "peer is not a link cable",
+            ));
+        }
+        if peer[4] != PROTOCOL_VERSION {
+            return Err(io::Error::new(
+                ErrorKind::InvalidData,
+                format!("peer speaks link protocol version {}", peer[4]),
+            ));
+        }
+
+        stream.set_read_timeout(None)?;
+        stream.set_nonblocking(true)?;
+        Ok(TcpLink {
+            stream,
+            inbox: Vec::new(),
+            connected: true,
+            sequence: 0,
+            pending: None,
+            events: Arc::new(Mutex::new(Vec::new())),
+        })
+    }
+
+    pub fn connected(&self) -> bool {
+        self.connected
+    }
+
+    // Shared handle to the events not yet collected, which stays valid after
+    // the link is handed to the serial port
+    pub fn events(&self) -> Arc<Mutex<Vec<LinkEvent>>> {
+        self.events.clone()
+    }
+
+    fn disconnect(&mut self, reason: &str) {
+        if self.connected {
+            self.connected = false;
+            self.events
+                .lock()
+                .unwrap()
+                .push(LinkEvent::Disconnected(reason.to_string()));
+        }
+    }
+
+    fn send(&mut self, kind: u8, sequence: u8, byte: u8) {
+        if !self.connected {
+            return;
+        }
+        // The socket is non-blocking, but three bytes always fit in the send
+        // buffer unless the peer stopped reading altogether.
+        let mut message = &[kind, sequence, byte][..];
+        while !message.is_empty() {
+            match self.stream.write(message) {
+                Ok(0) => return self.disconnect("connection closed"),
+                Ok(written) => message = &message[written..],
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => std::thread::yield_now(),
+                Err(e) => return self.disconnect(&e.to_string()),
+            }
+        }
+    }
+
+    // Pulls whatever arrived on the socket into the inbox without blocking
+    fn poll(&mut self) {
+        if !self.connected {
+            return;
+        }
+        let mut buffer = [0; 64];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return self.disconnect("connection closed"),
+                Ok(read) => self.inbox.extend_from_slice(&buffer[..read]),
+                Err(ref e) if e.kind() == ErrorKind::WouldBlock => return,
+                Err(e) => return self.disconnect(&e.to_string()),
+            }
+        }
+    }
+
+    // Removes and returns the sequence number and data byte of the first
+    // complete message of the given kind
+    fn take_message(&mut self, kind: u8) -> Option<(u8, u8)> {
+        let index = self
+            .inbox
+            .chunks(MESSAGE_SIZE)
+            .position(|message| message.len() == MESSAGE_SIZE && message[0] == kind)?;
+        let start = index * MESSAGE_SIZE;
+        let message: Vec<u8> = self.inbox.drain(start..start + MESSAGE_SIZE).collect();
+        Some((message[1], message[2]))
+    }
+}
+
+impl SerialLink for TcpLink {
+    fn transfer(&mut self, byte: u8) -> Option<u8> {
+        self.sequence = self.sequence.wrapping_add(1);
+        self.send(MSG_TRANSFER, self.sequence, byte);
+        self.pending = Some((self.sequence, Instant::now() + REPLY_TIMEOUT));
+        self.finish_transfer()
+    }
+
+    fn finish_transfer(&mut self) -> Option<u8> {
+        let (sequence, deadline) = match self.pending {
+            Some(pending) => pending,
+            None => return Some(0xFF),
+        };
+        self.poll();
+        while let Some((reply_to, reply)) = self.take_message(MSG_REPLY) {
+            // Anything else answers a transfer that already gave up
+            if reply_to == sequence {
+                self.pending = None;
+                return Some(reply);
+            }
+        }
+        // Both sides clocked at once; neither gets a real byte
+        while let Some((other, _)) = self.take_message(MSG_TRANSFER) {
+            self.send(MSG_REPLY, other, 0xFF);
+        }
+
+        if !self.connected {
+            self.pending = None;
+            return Some(0xFF);
+        }
+        if Instant::now() >= deadline {
+            self.pending = None;
+            self.events.lock().unwrap().push(LinkEvent::NoReply);
+            return Some(0xFF);
+        }
+        None
+    }
+
+    fn receive(&mut self, outgoing: u8) -> Option<u8> {
+        self.poll();
+        let (sequence, byte) = self.take_message(MSG_TRANSFER)?;
+        self.send(MSG_REPLY, sequence, outgoing);
+        Some(byte)
+    }
+}
+
+#[cfg(test)]
+mod test;
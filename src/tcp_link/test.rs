@@ -0,0 +1,138 @@
+use super::*;
+use crate::serial::{Serial, SB, SC, TRANSFER_CYCLES};
+use std::thread;
+
+fn connected_pair() -> (TcpLink, thread::JoinHandle<TcpLink>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || TcpLink::accept(&listener).unwrap());
+    (TcpLink::connect(address).unwrap(), server)
+}
+
+// Steps a transfer that already started until its reply is in
+fn finish(link: &mut TcpLink, mut reply: Option<u8>) -> u8 {
+    while reply.is_none() {
+        thread::sleep(Duration::from_millis(1));
+        reply = link.finish_transfer();
+    }
+    reply.unwrap()
+}
+
+#[test]
+fn exchange_bytes() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+
+    let slave = thread::spawn(move || {
+        let mut serial = Serial::new();
+        serial.set_link(Box::new(TcpLink::accept(&listener).unwrap()));
+        serial.write(SB, 0x34);
+        serial.write(SC, 0x80);
+        while !serial.step(4) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        serial.read(SB)
+    });
+
+    let mut master = Serial::new();
+    master.set_link(Box::new(TcpLink::connect(address).unwrap()));
+    master.write(SB, 0x12);
+    master.write(SC, 0x81);
+    // The reply takes a few steps to come back over the socket
+    assert!(!master.step(TRANSFER_CYCLES));
+    while !master.step(4) {
+        thread::sleep(Duration::from_millis(1));
+    }
+    assert_eq!(master.read(SB), 0x34);
+    assert_eq!(slave.join().unwrap(), 0x12);
+}
+
+#[test]
+fn transfer_waits_for_slow_slave() {
+    let (mut master, server) = connected_pair();
+    let mut slave = server.join().unwrap();
+
+    let slave = thread::spawn(move || {
+        // The slave arms its transfer late, the byte must still arrive
+        thread::sleep(Duration::from_millis(100));
+        loop {
+            if let Some(byte) = slave.receive(0x99) {
+                return byte;
+            }
+            thread::sleep(Duration::from_millis(1));
+        }
+    });
+
+    let reply = master.transfer(0x55);
+    assert_eq!(finish(&mut master, reply), 0x99);
+    assert_eq!(slave.join().unwrap(), 0x55);
+}
+
+#[test]
+fn queued_transfers_keep_order() {
+    let (mut first, server) = connected_pair();
+    let mut second = server.join().unwrap();
+
+    first.send(MSG_TRANSFER, 1, 0x01);
+    first.send(MSG_TRANSFER, 2, 0x02);
+    thread::sleep(Duration::from_millis(50));
+
+    assert_eq!(second.receive(0xA0), Some(0x01));
+    assert_eq!(second.receive(0xA1), Some(0x02));
+    assert_eq!(second.receive(0xA2), None);
+
+    thread::sleep(Duration::from_millis(50));
+    first.poll();
+    assert_eq!(first.take_message(MSG_REPLY), Some((1, 0xA0)));
+    assert_eq!(first.take_message(MSG_REPLY), Some((2, 0xA1)));
+}
+
+#[test]
+fn late_reply_is_dropped() {
+    let (mut master, server) = connected_pair();
+    let mut slave = server.join().unwrap();
+
+    assert_eq!(master.transfer(0x01), None);
+    // Give up on it right away
+    master.pending = master
+        .pending
+        .map(|(sequence, _)| (sequence, Instant::now()));
+    assert_eq!(master.finish_transfer(), Some(0xFF));
+    assert_eq!(master.events().lock().unwrap()[..], [LinkEvent::NoReply]);
+
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(slave.receive(0xAA), Some(0x01));
+    assert_eq!(master.transfer(0x02), None);
+    thread::sleep(Duration::from_millis(50));
+    assert_eq!(slave.receive(0xBB), Some(0x02));
+
+    assert_eq!(finish(&mut master, None), 0xBB);
+}
+
+#[test]
+fn disconnected_peer() {
+    let (mut master, server) = connected_pair();
+    drop(server.join().unwrap());
+
+    let reply = master.transfer(0x12);
+    assert_eq!(finish(&mut master, reply), 0xFF);
+    assert!(!master.connected());
+    assert!(matches!(
+        master.events().lock().unwrap()[..],
+        [LinkEvent::Disconnected(_)]
+    ));
+}
+
+#[test]
+fn rejects_foreign_peer() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        stream.write_all(b"HTTP/").unwrap();
+    });
+
+    let result = TcpLink::connect(address);
+    server.join().unwrap();
+    assert_eq!(result.unwrap_err().kind(), ErrorKind::InvalidData);
+}
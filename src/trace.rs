@@ -0,0 +1,137 @@
+// Per-instruction execution trace in the Gameboy Doctor format:
+//
+//   A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02
+//
+// One line is written before every traced instruction executes. Comparing
+// the file against a reference log pins down the first instruction where the
+// CPU goes wrong. With symbols attached, each line also gets the label the
+// instruction belongs to as a "; Main.loop+3" comment, which reference logs
+// do not have.
+
+use super::cpu::Registers;
+use super::symbols::Symbols;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::ops::RangeInclusive;
+use std::path::Path;
+
+pub fn doctor_line(registers: &Registers, pcmem: [u8; 4]) -> String {
+    format!(
+        "A:{:02X} F:{:02X} B:{:02X} C:{:02X} D:{:02X} E:{:02X} H:{:02X} L:{:02X} SP:{:04X} PC:{:04X} PCMEM:{:02X},{:02X},{:02X},{:02X}",
+        registers.a,
+        registers.f,
+        registers.b,
+        registers.c,
+        registers.d,
+        registers.e,
+        registers.h,
+        registers.l,
+        registers.sp,
+        registers.pc,
+        pcmem[0],
+        pcmem[1],
+        pcmem[2],
+        pcmem[3]
+    )
+}
+
+pub struct Trace {
+    writer: Box<dyn Write>,
+    enabled: bool,
+    // Only instructions at these addresses are traced; empty traces all
+    ranges: Vec<RangeInclusive<u16>>,
+    symbols: Option<Symbols>,
+    error: Option<io::Error>,
+}
+
+impl Trace {
+    pub fn new(writer: Box<dyn Write>) -> Trace {
+        Trace {
+            writer,
+            enabled: true,
+            ranges: Vec::new(),
+            symbols: None,
+            error: None,
+        }
+    }
+
+    pub fn create(path: &Path) -> io::Result<Trace> {
+        Ok(Trace::new(Box::new(BufWriter::new(File::create(path)?))))
+    }
+
+    pub fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    pub fn add_range(&mut self, range: RangeInclusive<u16>) {
+        self.ranges.push(range);
+    }
+
+    pub fn clear_ranges(&mut self) {
+        self.ranges.clear();
+    }
+
+    pub fn set_symbols(&mut self, symbols: Option<Symbols>) {
+        self.symbols = symbols;
+    }
+
+    // Whether an instruction at pc would be written
+    pub fn traces(&self, pc: u16) -> bool {
+        self.enabled && (self.ranges.is_empty() || self.ranges.iter().any(|r| r.contains(&pc)))
+    }
+
+    // Writes a line when pc passes the filters. The first write error stops
+    // the trace and is reported by finish. bank is the one mapped at pc.
+    pub fn record(&mut self, registers: &Registers, pcmem: [u8; 4], bank: u16) {
+        if !self.traces(registers.pc) || self.error.is_some() {
+            return;
+        }
+        let mut line = doctor_line(registers, pcmem);
+        if let Some(label) = self
+            .symbols
+            .as_ref()
+            .and_then(|symbols| symbols.describe(bank, registers.pc))
+        {
+            line.push_str(" ; ");
+            line.push_str(&label);
+        }
+        if let Err(e) = writeln!(self.writer, "{}", line) {
+            self.error = Some(e);
+        }
+    }
+
+    pub fn finish(mut self) -> io::Result<()> {
+        if let Some(e) = self.error.take() {
+            return Err(e);
+        }
+        self.writer.flush()
+    }
+}
+
+// Parses "start-end" or a single address, both hex
+pub fn parse_range(text: &str) -> Result<RangeInclusive<u16>, String> {
+    let parse = |text: &str| {
+        let digits = text.trim().trim_start_matches("0x").trim_start_matches('$');
+        u16::from_str_radix(digits, 16).map_err(|_| format!("invalid address '{}'", text))
+    };
+    match text.split_once('-') {
+        Some((start, end)) => {
+            let (start, end) = (parse(start)?, parse(end)?);
+            if start > end {
+                return Err(format!("range '{}' ends before it starts", text));
+            }
+            Ok(start..=end)
+        }
+        None => {
+            let address = parse(text)?;
+            Ok(address..=address)
+        }
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,86 @@
+use super::*;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+// Writer that keeps its output readable after being boxed
+#[derive(Clone, Default)]
+struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+impl Write for SharedBuffer {
+    fn write(&mut self, data: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(data);
+        Ok(data.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn registers(pc: u16) -> Registers {
+    Registers {
+        a: 0x01,
+        f: 0xB0,
+        b: 0x00,
+        c: 0x13,
+        d: 0x00,
+        e: 0xD8,
+        h: 0x01,
+        l: 0x4D,
+        sp: 0xFFFE,
+        pc,
+    }
+}
+
+#[test]
+fn doctor_format() {
+    assert_eq!(
+        doctor_line(&registers(0x0100), [0x00, 0xC3, 0x13, 0x02]),
+        "A:01 F:B0 B:00 C:13 D:00 E:D8 H:01 L:4D SP:FFFE PC:0100 PCMEM:00,C3,13,02"
+    );
+}
+
+#[test]
+fn filters_and_toggle() {
+    let buffer = SharedBuffer::default();
+    let mut trace = Trace::new(Box::new(buffer.clone()));
+    trace.add_range(0x0150..=0x0160);
+
+    trace.record(&registers(0x0100), [0; 4], 0);
+    trace.record(&registers(0x0150), [0; 4], 0);
+    trace.set_enabled(false);
+    trace.record(&registers(0x0151), [0; 4], 0);
+    trace.set_enabled(true);
+    trace.record(&registers(0x0160), [0; 4], 0);
+    trace.finish().unwrap();
+
+    let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    let pcs: Vec<&str> = output.lines().map(|line| &line[48..55]).collect();
+    assert_eq!(pcs, vec!["PC:0150", "PC:0160"]);
+}
+
+#[test]
+fn labels_from_symbols() {
+    let buffer = SharedBuffer::default();
+    let mut trace = Trace::new(Box::new(buffer.clone()));
+    trace.set_symbols(Some(Symbols::parse("00:0150 Main\n01:4000 Far").unwrap()));
+
+    trace.record(&registers(0x0152), [0; 4], 0);
+    trace.record(&registers(0x4000), [0; 4], 1);
+    trace.record(&registers(0x4000), [0; 4], 2);
+    trace.finish().unwrap();
+
+    let output = String::from_utf8(buffer.0.borrow().clone()).unwrap();
+    let lines: Vec<&str> = output.lines().collect();
+    assert!(lines[0].ends_with("PCMEM:00,00,00,00 ; Main+2"));
+    assert!(lines[1].ends_with("PCMEM:00,00,00,00 ; Far"));
+    assert!(lines[2].ends_with("PCMEM:00,00,00,00"));
+}
+
+#[test]
+fn ranges() {
+    assert_eq!(parse_range("100-1ff"), Ok(0x0100..=0x01FF));
+    assert_eq!(parse_range("$C000"), Ok(0xC000..=0xC000));
+    assert!(parse_range("200-100").is_err());
+    assert!(parse_range("xyz").is_err());
+}
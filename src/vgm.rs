@@ -0,0 +1,145 @@
+// Register write logs in the VGM format, so recorded music can be played
+// back outside the emulator with any VGM player that supports the DMG.
+
+use super::apu::{Apu, RegisterWrite, CPU_CLOCK_HZ, NR10};
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const VGM_VERSION: u32 = 0x161;
+const VGM_SAMPLE_RATE: u64 = 44_100;
+const HEADER_SIZE: u32 = 0x100;
+
+const CMD_DMG_WRITE: u8 = 0xB3;
+const CMD_WAIT: u8 = 0x61;
+const CMD_WAIT_NTSC_FRAME: u8 = 0x62;
+const CMD_WAIT_PAL_FRAME: u8 = 0x63;
+const CMD_WAIT_SHORT: u8 = 0x70;
+const CMD_END: u8 = 0x66;
+
+pub struct VgmWriter<W: Write + Seek> {
+    writer: W,
+    // Log time in cycles when the APU was at base_cycle. The APU clock goes
+    // back to 0 on a reset and jumps on a state load, the log time only
+    // moves forward.
+    base: u64,
+    base_cycle: u64,
+    elapsed: u64,
+    // Position in 44.1 kHz samples reached by the wait commands so far
+    samples: u64,
+    data_bytes: u32,
+}
+
+impl VgmWriter<BufWriter<File>> {
+    pub fn create(path: &Path, apu: &Apu) -> io::Result<VgmWriter<BufWriter<File>>> {
+        VgmWriter::new(BufWriter::new(File::create(path)?), apu)
+    }
+}
+
+impl<W: Write + Seek> VgmWriter<W> {
+    // Starts the log at the current APU time, opening with the writes that
+    // restore the current register state.
+    pub fn new(mut writer: W, apu: &Apu) -> io::Result<VgmWriter<W>> {
+        writer.write_all(&[0; HEADER_SIZE as usize])?;
+
+        let mut vgm = VgmWriter {
+            writer,
+            base: 0,
+            base_cycle: apu.cycles(),
+            elapsed: 0,
+            samples: 0,
+            data_bytes: 0,
+        };
+        vgm.write_register_state(apu)?;
+        Ok(vgm)
+    }
+
+    // Carries on from where the log is after the APU was reset or loaded
+    // from a save state, with the writes that restore its new registers
+    pub fn restart(&mut self, apu: &Apu) -> io::Result<()> {
+        self.base = self.elapsed;
+        self.base_cycle = apu.cycles();
+        self.write_register_state(apu)
+    }
+
+    fn write_register_state(&mut self, apu: &Apu) -> io::Result<()> {
+        for (address, value) in apu.register_state() {
+            self.write_register(address, value)?;
+        }
+        Ok(())
+    }
+
+    pub fn write_registers(&mut self, writes: &[RegisterWrite]) -> io::Result<()> {
+        for write in writes.iter() {
+            self.wait_until(write.cycle)?;
+            self.write_register(write.address, write.value)?;
+        }
+        Ok(())
+    }
+
+    fn write_register(&mut self, address: usize, value: u8) -> io::Result<()> {
+        self.write_bytes(&[CMD_DMG_WRITE, (address - NR10) as u8, value])
+    }
+
+    pub fn wait_until(&mut self, cycle: u64) -> io::Result<()> {
+        let elapsed = self.base + cycle.saturating_sub(self.base_cycle);
+        self.elapsed = self.elapsed.max(elapsed);
+        let target = self.elapsed * VGM_SAMPLE_RATE / CPU_CLOCK_HZ as u64;
+        while self.samples < target {
+            let remaining = target - self.samples;
+            let waited = match remaining {
+                735 => {
+                    self.write_bytes(&[CMD_WAIT_NTSC_FRAME])?;
+                    735
+                }
+                882 => {
+                    self.write_bytes(&[CMD_WAIT_PAL_FRAME])?;
+                    882
+                }
+                1..=16 => {
+                    self.write_bytes(&[CMD_WAIT_SHORT + (remaining - 1) as u8])?;
+                    remaining
+                }
+                _ => {
+                    let wait = remaining.min(0xFFFF) as u16;
+                    let bytes = wait.to_le_bytes();
+                    self.write_bytes(&[CMD_WAIT, bytes[0], bytes[1]])?;
+                    wait as u64
+                }
+            };
+            self.samples += waited;
+        }
+        Ok(())
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) -> io::Result<()> {
+        self.writer.write_all(bytes)?;
+        self.data_bytes += bytes.len() as u32;
+        Ok(())
+    }
+
+    // Pads the log out to the given cycle, ends it and fills in the header
+    pub fn finish(mut self, end_cycle: u64) -> io::Result<W> {
+        self.wait_until(end_cycle)?;
+        self.write_bytes(&[CMD_END])?;
+
+        let header = [
+            (0x00, u32::from_le_bytes(*b"Vgm ")),
+            (0x04, HEADER_SIZE + self.data_bytes - 4),
+            (0x08, VGM_VERSION),
+            (0x18, self.samples as u32),
+            (0x34, HEADER_SIZE - 0x34),
+            (0x80, CPU_CLOCK_HZ),
+        ];
+        for (offset, value) in header.iter() {
+            self.writer.seek(SeekFrom::Start(*offset))?;
+            self.writer.write_all(&value.to_le_bytes())?;
+        }
+        self.writer.seek(SeekFrom::End(0))?;
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod test;
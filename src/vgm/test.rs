@@ -0,0 +1,121 @@
+use super::*;
+use crate::apu::{NR11, NR12, NR52};
+use std::io::Cursor;
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+fn commands(bytes: &[u8]) -> &[u8] {
+    // Skip the header and the state restore of a powered off APU
+    &bytes[HEADER_SIZE as usize + 3 * (3 + 16 + 18)..]
+}
+
+#[test]
+fn header() {
+    let apu = Apu::new();
+    let vgm = VgmWriter::new(Cursor::new(Vec::new()), &apu).unwrap();
+    let bytes = vgm.finish(CPU_CLOCK_HZ as u64).unwrap().into_inner();
+
+    assert_eq!(&bytes[0..4], b"Vgm ");
+    assert_eq!(read_u32(&bytes, 0x04) as usize, bytes.len() - 4);
+    assert_eq!(read_u32(&bytes, 0x08), 0x161);
+    assert_eq!(read_u32(&bytes, 0x18), 44_100);
+    assert_eq!(read_u32(&bytes, 0x34), 0xCC);
+    assert_eq!(read_u32(&bytes, 0x80), CPU_CLOCK_HZ);
+    assert_eq!(bytes[bytes.len() - 1], CMD_END);
+}
+
+#[test]
+fn initial_state() {
+    let mut apu = Apu::new();
+    apu.write(NR52, 0x80);
+    apu.write(NR12, 0xF3);
+
+    let vgm = VgmWriter::new(Cursor::new(Vec::new()), &apu).unwrap();
+    let bytes = vgm.finish(0).unwrap().into_inner();
+
+    assert_eq!(&bytes[0x100..0x103], &[CMD_DMG_WRITE, 0x16, 0x80]);
+    assert!(bytes[0x100..]
+        .chunks(3)
+        .any(|command| command == [CMD_DMG_WRITE, 0x02, 0xF3]));
+}
+
+#[test]
+fn writes_and_waits() {
+    let apu = Apu::new();
+    let mut vgm = VgmWriter::new(Cursor::new(Vec::new()), &apu).unwrap();
+
+    // Rounded up so the write lands exactly on the 735th sample
+    let frame = (CPU_CLOCK_HZ as u64 * 735 + 44_099) / 44_100;
+    vgm.write_registers(&[
+        RegisterWrite {
+            cycle: 0,
+            address: NR52,
+            value: 0x80,
+        },
+        RegisterWrite {
+            cycle: frame,
+            address: NR11,
+            value: 0x80,
+        },
+        RegisterWrite {
+            cycle: frame + 1000,
+            address: NR12,
+            value: 0xF0,
+        },
+    ])
+    .unwrap();
+    let bytes = vgm.finish(frame + 1000).unwrap().into_inner();
+
+    assert_eq!(
+        commands(&bytes),
+        &[
+            CMD_DMG_WRITE,
+            0x16,
+            0x80,
+            CMD_WAIT_NTSC_FRAME,
+            CMD_DMG_WRITE,
+            0x01,
+            0x80,
+            CMD_WAIT_SHORT + 9,
+            CMD_DMG_WRITE,
+            0x02,
+            0xF0,
+            CMD_END
+        ]
+    );
+}
+
+#[test]
+fn long_waits() {
+    let apu = Apu::new();
+    let vgm = VgmWriter::new(Cursor::new(Vec::new()), &apu).unwrap();
+    let bytes = vgm.finish(CPU_CLOCK_HZ as u64 * 2).unwrap().into_inner();
+
+    assert_eq!(
+        commands(&bytes),
+        &[CMD_WAIT, 0xFF, 0xFF, CMD_WAIT, 0x89, 0x58, CMD_END]
+    );
+    assert_eq!(read_u32(&bytes, 0x18), 88_200);
+}
+
+#[test]
+fn time_keeps_going_after_a_reset() {
+    let mut apu = Apu::new();
+    let mut vgm = VgmWriter::new(Cursor::new(Vec::new()), &apu).unwrap();
+    let frame = (CPU_CLOCK_HZ as u64 * 735 + 44_099) / 44_100;
+    apu.step(frame as u32);
+    vgm.wait_until(apu.cycles()).unwrap();
+
+    apu.reset();
+    vgm.restart(&apu).unwrap();
+    let bytes = vgm.finish(frame).unwrap().into_inner();
+
+    assert_eq!(read_u32(&bytes, 0x18), 2 * 735);
+}
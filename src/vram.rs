@@ -0,0 +1,354 @@
+// Views of what the PPU works from, for art debugging: the tile data, both
+// background maps, the sprites in OAM and the palettes. Everything is read
+// with MemoryMap::peek, so drawing a view never disturbs the emulation.
+
+use super::image::save_image;
+use super::memory::MemoryMap;
+use super::palette::{rgb555_to_rgb, PALETTE_COUNT};
+use super::screen::{DMG_PALETTE, SCREEN_HEIGHT, SCREEN_WIDTH};
+use std::fmt;
+use std::path::Path;
+
+const TILE_DATA: u16 = 0x8000;
+const TILE_COUNT: usize = 384;
+const TILE_BYTES: u16 = 16;
+const MAPS: [u16; 2] = [0x9800, 0x9C00];
+const MAP_SIZE: usize = 256;
+const OAM: u16 = 0xFE00;
+pub const SPRITE_COUNT: usize = 40;
+
+const LCDC: u16 = 0xFF40;
+const SCY: u16 = 0xFF42;
+const SCX: u16 = 0xFF43;
+const BGP: u16 = 0xFF47;
+const OBP0: u16 = 0xFF48;
+const OBP1: u16 = 0xFF49;
+
+// LCDC bits
+const BG_MAP_HIGH: u8 = 0x08;
+const TILES_UNSIGNED: u8 = 0x10;
+const TALL_SPRITES: u8 = 0x04;
+
+// OAM attribute bits
+const BEHIND_BACKGROUND: u8 = 0x80;
+const Y_FLIP: u8 = 0x40;
+const X_FLIP: u8 = 0x20;
+const SECOND_PALETTE: u8 = 0x10;
+
+const TILES_PER_ROW: usize = 16;
+const SPRITES_PER_ROW: usize = 8;
+// Each sprite gets room for 8x16 and a margin
+const SPRITE_CELL: (usize, usize) = (12, 20);
+const SWATCH: usize = 16;
+const CGB_SWATCH: usize = 8;
+const GAP: usize = 8;
+
+const BACKDROP: [u8; 3] = [0x30, 0x30, 0x38];
+// Transparent sprite pixels
+const CLEAR: [u8; 3] = [0x80, 0x60, 0x90];
+const VIEWPORT: [u8; 3] = [0xFF, 0x20, 0x20];
+
+// An RGB picture, three bytes per pixel
+#[derive(Debug, Clone, PartialEq)]
+pub struct View {
+    pub width: usize,
+    pub height: usize,
+    pub rgb: Vec<u8>,
+}
+
+impl View {
+    pub fn new(width: usize, height: usize) -> View {
+        let mut view = View {
+            width,
+            height,
+            rgb: vec![0; width * height * 3],
+        };
+        view.fill(BACKDROP);
+        view
+    }
+
+    fn fill(&mut self, color: [u8; 3]) {
+        for pixel in self.rgb.chunks_mut(3) {
+            pixel.copy_from_slice(&color);
+        }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> [u8; 3] {
+        let index = (y * self.width + x) * 3;
+        [self.rgb[index], self.rgb[index + 1], self.rgb[index + 2]]
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: [u8; 3]) {
+        let index = (y * self.width + x) * 3;
+        self.rgb[index..index + 3].copy_from_slice(&color);
+    }
+
+    // A size x size square with its top left corner at (x, y)
+    fn fill_rect(&mut self, x: usize, y: usize, size: usize, color: [u8; 3]) {
+        for row in y..y + size {
+            for column in x..x + size {
+                self.set_pixel(column, row, color);
+            }
+        }
+    }
+
+    // Copies other in with its top left corner at (x, y)
+    fn draw(&mut self, other: &View, x: usize, y: usize) {
+        for row in 0..other.height {
+            let from = row * other.width * 3;
+            let to = ((y + row) * self.width + x) * 3;
+            self.rgb[to..to + other.width * 3]
+                .copy_from_slice(&other.rgb[from..from + other.width * 3]);
+        }
+    }
+
+    // PNG or PPM by the extension of path
+    pub fn save(&self, path: &Path) -> Result<(), String> {
+        save_image(path, self.width, self.height, &self.rgb)
+    }
+}
+
+// Color number 0-3 of a pixel in the tile at address
+fn tile_pixel(memory: &MemoryMap, address: u16, x: usize, y: usize) -> u8 {
+    let low = memory.peek(address as usize + y * 2);
+    let high = memory.peek(address as usize + y * 2 + 1);
+    let bit = 7 - x;
+    (((high >> bit) & 1) << 1) | ((low >> bit) & 1)
+}
+
+fn shade(palette: u8, color: u8) -> [u8; 3] {
+    DMG_PALETTE[((palette >> (color * 2)) & 0x03) as usize]
+}
+
+// Address of a background tile, following the addressing mode in LCDC
+fn background_tile(lcdc: u8, index: u8) -> u16 {
+    if lcdc & TILES_UNSIGNED != 0 {
+        TILE_DATA + index as u16 * TILE_BYTES
+    } else {
+        (0x9000i32 + (index as i8) as i32 * TILE_BYTES as i32) as u16
+    }
+}
+
+// All 384 tiles in color number order, 16 to a row. Rows 0-7 are the block
+// at 0x8000, 8-15 the one at 0x8800 shared by both addressing modes and
+// 16-23 the one at 0x9000.
+pub fn tiles(memory: &MemoryMap) -> View {
+    let rows = TILE_COUNT / TILES_PER_ROW;
+    let mut view = View::new(TILES_PER_ROW * 8, rows * 8);
+    for tile in 0..TILE_COUNT {
+        let address = TILE_DATA + tile as u16 * TILE_BYTES;
+        let (left, top) = ((tile % TILES_PER_ROW) * 8, (tile / TILES_PER_ROW) * 8);
+        for y in 0..8 {
+            for x in 0..8 {
+                let color = tile_pixel(memory, address, x, y);
+                view.set_pixel(left + x, top + y, DMG_PALETTE[color as usize]);
+            }
+        }
+    }
+    view
+}
+
+// One of the two 32x32 tile maps through BGP. The map the background uses
+// gets the part the screen shows, from SCX and SCY, outlined.
+pub fn background_map(memory: &MemoryMap, map: usize) -> View {
+    let lcdc = memory.peek(LCDC as usize);
+    let palette = memory.peek(BGP as usize);
+    let mut view = View::new(MAP_SIZE, MAP_SIZE);
+    for row in 0..32 {
+        for column in 0..32 {
+            let index = memory.peek(MAPS[map] as usize + row * 32 + column);
+            let address = background_tile(lcdc, index);
+            for y in 0..8 {
+                for x in 0..8 {
+                    let color = tile_pixel(memory, address, x, y);
+                    view.set_pixel(column * 8 + x, row * 8 + y, shade(palette, color));
+                }
+            }
+        }
+    }
+
+    let shown = if lcdc & BG_MAP_HIGH != 0 { 1 } else { 0 };
+    if map == shown {
+        let left = memory.peek(SCX as usize) as usize;
+        let top = memory.peek(SCY as usize) as usize;
+        // The screen wraps around the map edges
+        for x in 0..SCREEN_WIDTH {
+            let x = (left + x) % MAP_SIZE;
+            view.set_pixel(x, top, VIEWPORT);
+            view.set_pixel(x, (top + SCREEN_HEIGHT - 1) % MAP_SIZE, VIEWPORT);
+        }
+        for y in 0..SCREEN_HEIGHT {
+            let y = (top + y) % MAP_SIZE;
+            view.set_pixel(left, y, VIEWPORT);
+            view.set_pixel((left + SCREEN_WIDTH - 1) % MAP_SIZE, y, VIEWPORT);
+        }
+    }
+    view
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sprite {
+    pub index: usize,
+    // As stored, 16 and 8 above the screen position
+    pub y: u8,
+    pub x: u8,
+    pub tile: u8,
+    pub attributes: u8,
+}
+
+impl Sprite {
+    pub fn visible(&self) -> bool {
+        self.y > 0 && self.y < 160 && self.x > 0 && self.x < 168
+    }
+}
+
+impl fmt::Display for Sprite {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{:2}  x {:3}  y {:3}  tile {:02X}  attr {:02X}  {}",
+            self.index,
+            self.x,
+            self.y,
+            self.tile,
+            self.attributes,
+            if self.attributes & SECOND_PALETTE != 0 {
+                "OBP1"
+            } else {
+                "OBP0"
+            }
+        )?;
+        for &(bit, name) in [
+            (X_FLIP, "xflip"),
+            (Y_FLIP, "yflip"),
+            (BEHIND_BACKGROUND, "behind"),
+        ]
+        .iter()
+        {
+            if self.attributes & bit != 0 {
+                write!(f, " {}", name)?;
+            }
+        }
+        if !self.visible() {
+            write!(f, " offscreen")?;
+        }
+        Ok(())
+    }
+}
+
+pub fn sprites(memory: &MemoryMap) -> Vec<Sprite> {
+    (0..SPRITE_COUNT)
+        .map(|index| {
+            let entry = OAM as usize + index * 4;
+            Sprite {
+                index,
+                y: memory.peek(entry),
+                x: memory.peek(entry + 1),
+                tile: memory.peek(entry + 2),
+                attributes: memory.peek(entry + 3),
+            }
+        })
+        .collect()
+}
+
+// The 40 sprites 8 to a row with their palette and flips applied, 8x16 ones
+// when LCDC asks for them
+pub fn oam(memory: &MemoryMap) -> View {
+    let lcdc = memory.peek(LCDC as usize);
+    let tall = lcdc & TALL_SPRITES != 0;
+    let (cell_width, cell_height) = SPRITE_CELL;
+    let rows = SPRITE_COUNT / SPRITES_PER_ROW;
+    let mut view = View::new(SPRITES_PER_ROW * cell_width, rows * cell_height);
+    for sprite in sprites(memory) {
+        let palette = memory.peek(if sprite.attributes & SECOND_PALETTE != 0 {
+            OBP1 as usize
+        } else {
+            OBP0 as usize
+        });
+        let height = if tall { 16 } else { 8 };
+        let tile = if tall {
+            sprite.tile & 0xFE
+        } else {
+            sprite.tile
+        };
+        let left = (sprite.index % SPRITES_PER_ROW) * cell_width + 2;
+        let top = (sprite.index / SPRITES_PER_ROW) * cell_height + 2;
+        for y in 0..height {
+            let row = if sprite.attributes & Y_FLIP != 0 {
+                height - 1 - y
+            } else {
+                y
+            };
+            let address = TILE_DATA + tile as u16 * TILE_BYTES + (row / 8) as u16 * TILE_BYTES;
+            for x in 0..8 {
+                let column = if sprite.attributes & X_FLIP != 0 {
+                    7 - x
+                } else {
+                    x
+                };
+                let color = tile_pixel(memory, address, column, row % 8);
+                let rgb = if color == 0 {
+                    CLEAR
+                } else {
+                    shade(palette, color)
+                };
+                view.set_pixel(left + x, top + y, rgb);
+            }
+        }
+    }
+    view
+}
+
+// BGP, OBP0 and OBP1 top to bottom, the shade of each color number left to
+// right. Below them the CGB palette RAM, one palette per row: background
+// palettes 0-7 on the left and object palettes 0-7 on the right.
+pub fn palettes(memory: &MemoryMap) -> View {
+    let cgb_top = 3 * SWATCH + GAP;
+    let cgb_width = 4 * CGB_SWATCH;
+    let mut view = View::new(
+        (4 * SWATCH).max(cgb_width + GAP + cgb_width),
+        cgb_top + PALETTE_COUNT * CGB_SWATCH,
+    );
+    for (row, register) in [BGP, OBP0, OBP1].iter().enumerate() {
+        let palette = memory.peek(*register as usize);
+        for color in 0..4 {
+            let rgb = shade(palette, color as u8);
+            view.fill_rect(color * SWATCH, row * SWATCH, SWATCH, rgb);
+        }
+    }
+
+    let cgb = memory.palettes.borrow();
+    for palette in 0..PALETTE_COUNT {
+        let y = cgb_top + palette * CGB_SWATCH;
+        for color in 0..4 {
+            let x = color * CGB_SWATCH;
+            let background = rgb555_to_rgb(cgb.background_color(palette, color));
+            view.fill_rect(x, y, CGB_SWATCH, background);
+            let object = rgb555_to_rgb(cgb.object_color(palette, color));
+            view.fill_rect(cgb_width + GAP + x, y, CGB_SWATCH, object);
+        }
+    }
+    view
+}
+
+// Every view in one picture: the tiles and both maps on top, the sprites and
+// palettes below
+pub fn overview(memory: &MemoryMap) -> View {
+    let tiles = tiles(memory);
+    let maps = [background_map(memory, 0), background_map(memory, 1)];
+    let oam = oam(memory);
+    let palettes = palettes(memory);
+
+    let width = tiles.width + GAP + MAP_SIZE + GAP + MAP_SIZE;
+    let height = MAP_SIZE + GAP + oam.height.max(palettes.height);
+    let mut view = View::new(width, height);
+    view.draw(&tiles, 0, 0);
+    view.draw(&maps[0], tiles.width + GAP, 0);
+    view.draw(&maps[1], tiles.width + GAP + MAP_SIZE + GAP, 0);
+    view.draw(&oam, 0, MAP_SIZE + GAP);
+    view.draw(&palettes, tiles.width + GAP, MAP_SIZE + GAP);
+    view
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,107 @@
+use super::*;
+
+fn memory() -> MemoryMap {
+    let memory = MemoryMap::with_io(0x10000);
+    // Tile 1: top row color 1, second row color 3 on the left half
+    memory.write(0x8010, 0xFF);
+    memory.write(0x8012, 0xF0);
+    memory.write(0x8013, 0xF0);
+    memory.write(BGP as usize, 0xE4);
+    memory
+}
+
+const WHITE: [u8; 3] = DMG_PALETTE[0];
+const LIGHT: [u8; 3] = DMG_PALETTE[1];
+const BLACK: [u8; 3] = DMG_PALETTE[3];
+
+#[test]
+fn draws_tile_data() {
+    let view = tiles(&memory());
+    assert_eq!((view.width, view.height), (128, 192));
+    assert_eq!(view.pixel(8, 0), LIGHT);
+    assert_eq!(view.pixel(11, 1), BLACK);
+    assert_eq!(view.pixel(12, 1), WHITE);
+    assert_eq!(view.pixel(0, 0), WHITE);
+}
+
+#[test]
+fn draws_maps_with_the_viewport() {
+    let memory = memory();
+    memory.write(0x9800, 0x01);
+    memory.write(LCDC as usize, TILES_UNSIGNED);
+    memory.write(SCX as usize, 200);
+    memory.write(SCY as usize, 4);
+
+    let view = background_map(&memory, 0);
+    assert_eq!(view.pixel(1, 2), WHITE);
+    assert_eq!(view.pixel(1, 0), LIGHT);
+    // The viewport wraps around to the left edge
+    assert_eq!(view.pixel(200, 4), VIEWPORT);
+    assert_eq!(view.pixel((200 + 159) % 256, 10), VIEWPORT);
+    assert_eq!(view.pixel(200, 4 + 143), VIEWPORT);
+    assert_ne!(view.pixel(199, 10), VIEWPORT);
+    // Only the map in use gets it
+    assert_ne!(background_map(&memory, 1).pixel(200, 4), VIEWPORT);
+
+    // Signed addressing reads tile 1 at 0x9010
+    memory.write(LCDC as usize, 0);
+    assert_eq!(background_map(&memory, 0).pixel(1, 0), WHITE);
+}
+
+#[test]
+fn lists_and_draws_sprites() {
+    let memory = memory();
+    memory.write(OBP1 as usize, 0xFF);
+    memory.write(OAM as usize + 4, 16);
+    memory.write(OAM as usize + 5, 8);
+    memory.write(OAM as usize + 6, 0x01);
+    memory.write(OAM as usize + 7, SECOND_PALETTE | X_FLIP);
+
+    let sprites = sprites(&memory);
+    assert_eq!(sprites.len(), SPRITE_COUNT);
+    assert_eq!(
+        sprites[1].to_string(),
+        " 1  x   8  y  16  tile 01  attr 30  OBP1 xflip"
+    );
+    assert!(sprites[0].to_string().ends_with("OBP0 offscreen"));
+
+    let view = oam(&memory);
+    let (left, top) = (SPRITE_CELL.0 + 2, 2);
+    assert_eq!(view.pixel(left, top), BLACK);
+    // Flipped, so the right half of the second row is drawn
+    assert_eq!(view.pixel(left + 7, top + 1), BLACK);
+    assert_eq!(view.pixel(left, top + 1), CLEAR);
+}
+
+#[test]
+fn draws_palettes_and_the_overview() {
+    let memory = memory();
+    let view = palettes(&memory);
+    assert_eq!(view.pixel(0, 0), DMG_PALETTE[0]);
+    assert_eq!(view.pixel(3 * SWATCH, 0), DMG_PALETTE[3]);
+
+    // CGB background palette 1 color 2, and object palette 7 color 3
+    memory.write(0xFF68, 0x80 | 0x0C);
+    memory.write(0xFF69, 0x1F);
+    memory.write(0xFF69, 0x00);
+    memory.write(0xFF6A, 0x80 | 0x3E);
+    memory.write(0xFF6B, 0xE0);
+    memory.write(0xFF6B, 0x03);
+    let view = palettes(&memory);
+    let top = 3 * SWATCH + GAP;
+    assert_eq!(
+        view.pixel(2 * CGB_SWATCH, top + CGB_SWATCH),
+        [0xFF, 0x00, 0x00]
+    );
+    assert_eq!(
+        view.pixel(4 * CGB_SWATCH + GAP + 3 * CGB_SWATCH, top + 7 * CGB_SWATCH),
+        [0x00, 0xFF, 0x00]
+    );
+
+    let all = overview(&memory);
+    assert_eq!(all.width, 128 + 8 + 256 + 8 + 256);
+    assert_eq!(
+        all.pixel(136 + 1, 0),
+        background_map(&memory, 0).pixel(1, 0)
+    );
+}
@@ -0,0 +1,287 @@
+// Memory watchpoints, checked by MemoryMap on every access while any are set.
+// A hit records the PC of the instruction doing the access and the cycle it
+// started on, which the CPU hands over before each instruction.
+
+use super::trace::parse_range;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+// I/O registers that can be watched by name
+const IO_REGISTERS: [(&str, u16); 55] = [
+    ("P1", 0xFF00),
+    ("SB", 0xFF01),
+    ("SC", 0xFF02),
+    ("DIV", 0xFF04),
+    ("TIMA", 0xFF05),
+    ("TMA", 0xFF06),
+    ("TAC", 0xFF07),
+    ("IF", 0xFF0F),
+    ("NR10", 0xFF10),
+    ("NR11", 0xFF11),
+    ("NR12", 0xFF12),
+    ("NR13", 0xFF13),
+    ("NR14", 0xFF14),
+    ("NR21", 0xFF16),
+    ("NR22", 0xFF17),
+    ("NR23", 0xFF18),
+    ("NR24", 0xFF19),
+    ("NR30", 0xFF1A),
+    ("NR31", 0xFF1B),
+    ("NR32", 0xFF1C),
+    ("NR33", 0xFF1D),
+    ("NR34", 0xFF1E),
+    ("NR41", 0xFF20),
+    ("NR42", 0xFF21),
+    ("NR43", 0xFF22),
+    ("NR44", 0xFF23),
+    ("NR50", 0xFF24),
+    ("NR51", 0xFF25),
+    ("NR52", 0xFF26),
+    ("LCDC", 0xFF40),
+    ("STAT", 0xFF41),
+    ("SCY", 0xFF42),
+    ("SCX", 0xFF43),
+    ("LY", 0xFF44),
+    ("LYC", 0xFF45),
+    ("DMA", 0xFF46),
+    ("BGP", 0xFF47),
+    ("OBP0", 0xFF48),
+    ("OBP1", 0xFF49),
+    ("WY", 0xFF4A),
+    ("WX", 0xFF4B),
+    ("KEY1", 0xFF4D),
+    ("VBK", 0xFF4F),
+    ("BOOT", 0xFF50),
+    ("HDMA1", 0xFF51),
+    ("HDMA2", 0xFF52),
+    ("HDMA3", 0xFF53),
+    ("HDMA4", 0xFF54),
+    ("HDMA5", 0xFF55),
+    ("BCPS", 0xFF68),
+    ("BCPD", 0xFF69),
+    ("OCPS", 0xFF6A),
+    ("OCPD", 0xFF6B),
+    ("SVBK", 0xFF70),
+    ("IE", 0xFFFF),
+];
+
+pub fn io_register(name: &str) -> Option<u16> {
+    IO_REGISTERS
+        .iter()
+        .find(|(register, _)| register.eq_ignore_ascii_case(name))
+        .map(|(_, address)| *address)
+}
+
+// An I/O register name, or a hex address or range as for the trace
+pub fn parse_target(text: &str) -> Result<RangeInclusive<u16>, String> {
+    match io_register(text) {
+        Some(address) => Ok(address..=address),
+        None => parse_range(text),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchKind {
+    Read,
+    Write,
+    // Writes that leave a different value behind
+    Change,
+    // Reads and writes
+    Access,
+}
+
+impl WatchKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            WatchKind::Read => "read",
+            WatchKind::Write => "write",
+            WatchKind::Change => "change",
+            WatchKind::Access => "access",
+        }
+    }
+}
+
+impl FromStr for WatchKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<WatchKind, String> {
+        match s {
+            "read" | "r" => Ok(WatchKind::Read),
+            "write" | "w" => Ok(WatchKind::Write),
+            "change" => Ok(WatchKind::Change),
+            "access" | "rw" => Ok(WatchKind::Access),
+            _ => Err(format!(
+                "unknown watch kind '{}', expected read, write, change or access",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchAction {
+    Pause,
+    Log,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watchpoint {
+    pub range: RangeInclusive<u16>,
+    pub kind: WatchKind,
+    pub action: WatchAction,
+}
+
+impl Watchpoint {
+    // Parses "[kind:]target", a write watchpoint when the kind is left out
+    pub fn parse(text: &str, action: WatchAction) -> Result<Watchpoint, String> {
+        let (kind, target) = match text.split_once(':') {
+            Some((kind, target)) => (kind.parse()?, target),
+            None => (WatchKind::Write, text),
+        };
+        Ok(Watchpoint {
+            range: parse_target(target)?,
+            kind,
+            action,
+        })
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {:04X}", self.kind.name(), self.range.start())?;
+        if self.range.end() != self.range.start() {
+            write!(f, "-{:04X}", self.range.end())?;
+        }
+        if self.action == WatchAction::Log {
+            write!(f, " (log)")?;
+        }
+        Ok(())
+    }
+}
+
+// One access that matched a watchpoint. Reads have the same old and new value.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WatchHit {
+    pub address: u16,
+    pub kind: WatchKind,
+    pub old: u8,
+    pub new: u8,
+    pub pc: u16,
+    pub cycle: u64,
+    pub action: WatchAction,
+}
+
+impl fmt::Display for WatchHit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.kind {
+            WatchKind::Read => write!(f, "read {:04X} = {:02X}", self.address, self.new)?,
+            kind => write!(
+                f,
+                "{} {:04X} {:02X} -> {:02X}",
+                kind.name(),
+                self.address,
+                self.old,
+                self.new
+            )?,
+        }
+        write!(f, " at PC {:04X}, cycle {}", self.pc, self.cycle)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Watchpoints {
+    list: Vec<Watchpoint>,
+    pc: u16,
+    cycle: u64,
+    hits: Vec<WatchHit>,
+}
+
+impl Watchpoints {
+    pub fn new() -> Watchpoints {
+        Watchpoints::default()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.list.is_empty()
+    }
+
+    pub fn list(&self) -> &[Watchpoint] {
+        &self.list
+    }
+
+    pub fn add(&mut self, watchpoint: Watchpoint) {
+        self.list.push(watchpoint);
+    }
+
+    pub fn remove(&mut self, index: usize) -> Option<Watchpoint> {
+        if index < self.list.len() {
+            Some(self.list.remove(index))
+        } else {
+            None
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.list.clear();
+    }
+
+    // The instruction about to run, reported with every hit it causes
+    pub fn set_context(&mut self, pc: u16, cycle: u64) {
+        self.pc = pc;
+        self.cycle = cycle;
+    }
+
+    fn hit(&mut self, address: u16, kind: WatchKind, old: u8, new: u8, action: WatchAction) {
+        self.hits.push(WatchHit {
+            address,
+            kind,
+            old,
+            new,
+            pc: self.pc,
+            cycle: self.cycle,
+            action,
+        });
+    }
+
+    pub fn check_read(&mut self, address: u16, value: u8) {
+        for index in 0..self.list.len() {
+            let watchpoint = &self.list[index];
+            let kind = watchpoint.kind;
+            if watchpoint.range.contains(&address)
+                && (kind == WatchKind::Read || kind == WatchKind::Access)
+            {
+                let action = watchpoint.action;
+                self.hit(address, WatchKind::Read, value, value, action);
+            }
+        }
+    }
+
+    // old and after are the values read back before and after the write,
+    // which differ from the value written for ROM and most I/O registers
+    pub fn check_write(&mut self, address: u16, old: u8, value: u8, after: u8) {
+        for index in 0..self.list.len() {
+            let watchpoint = &self.list[index];
+            if !watchpoint.range.contains(&address) {
+                continue;
+            }
+            let action = watchpoint.action;
+            match watchpoint.kind {
+                WatchKind::Write | WatchKind::Access => {
+                    self.hit(address, WatchKind::Write, old, value, action)
+                }
+                WatchKind::Change if after != old => {
+                    self.hit(address, WatchKind::Change, old, after, action)
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn take_hits(&mut self) -> Vec<WatchHit> {
+        std::mem::take(&mut self.hits)
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,99 @@
+use super::*;
+use crate::memory::MemoryMap;
+
+fn watch(memory: &MemoryMap, text: &str, action: WatchAction) {
+    let watchpoint = Watchpoint::parse(text, action).unwrap();
+    memory.watchpoints.borrow_mut().add(watchpoint);
+}
+
+#[test]
+fn parses_targets() {
+    assert_eq!(parse_target("lcdc"), Ok(0xFF40..=0xFF40));
+    assert_eq!(parse_target("OCPD"), Ok(0xFF6B..=0xFF6B));
+    assert_eq!(parse_target("c000-c0ff"), Ok(0xC000..=0xC0FF));
+    assert!(parse_target("NOPE").is_err());
+
+    let watchpoint = Watchpoint::parse("change:IE", WatchAction::Log).unwrap();
+    assert_eq!(watchpoint.kind, WatchKind::Change);
+    assert_eq!(watchpoint.range, 0xFFFF..=0xFFFF);
+    assert_eq!(watchpoint.to_string(), "change FFFF (log)");
+    assert_eq!(
+        Watchpoint::parse("d000-d00f", WatchAction::Pause)
+            .unwrap()
+            .to_string(),
+        "write D000-D00F"
+    );
+    assert!(Watchpoint::parse("fetch:c000", WatchAction::Pause).is_err());
+}
+
+#[test]
+fn reports_writes_with_context() {
+    let memory = MemoryMap::with_io(0x10000);
+    watch(&memory, "c000-c00f", WatchAction::Pause);
+    memory.write(0xC000, 0x12);
+    memory.watchpoints.borrow_mut().set_context(0x0150, 1234);
+    memory.write(0xC001, 0x34);
+    memory.write(0xC010, 0x56);
+
+    let hits = memory.watchpoints.borrow_mut().take_hits();
+    assert_eq!(hits.len(), 2);
+    assert_eq!(
+        hits[1],
+        WatchHit {
+            address: 0xC001,
+            kind: WatchKind::Write,
+            old: 0x00,
+            new: 0x34,
+            pc: 0x0150,
+            cycle: 1234,
+            action: WatchAction::Pause,
+        }
+    );
+    assert_eq!(
+        hits[1].to_string(),
+        "write C001 00 -> 34 at PC 0150, cycle 1234"
+    );
+    assert!(memory.watchpoints.borrow_mut().take_hits().is_empty());
+}
+
+#[test]
+fn change_ignores_same_value() {
+    let memory = MemoryMap::with_io(0x10000);
+    watch(&memory, "change:c000", WatchAction::Log);
+    memory.write(0xC000, 0x00);
+    memory.write(0xC000, 0x01);
+    memory.write(0xC000, 0x01);
+    // ROM does not change when written
+    watch(&memory, "change:2000", WatchAction::Log);
+    memory.write(0x2000, 0x01);
+
+    let hits = memory.watchpoints.borrow_mut().take_hits();
+    assert_eq!(hits.len(), 1);
+    assert_eq!((hits[0].old, hits[0].new), (0x00, 0x01));
+}
+
+#[test]
+fn reads_and_peeks() {
+    let memory = MemoryMap::with_io(0x10000);
+    memory.write(0xFF80, 0x99);
+    watch(&memory, "read:ff80", WatchAction::Pause);
+    watch(&memory, "access:ff80", WatchAction::Log);
+
+    assert_eq!(memory.peek(0xFF80), 0x99);
+    assert!(memory.watchpoints.borrow_mut().take_hits().is_empty());
+    assert_eq!(memory.read(0xFF80), 0x99);
+    memory.write(0xFF80, 0x98);
+
+    let hits = memory.watchpoints.borrow_mut().take_hits();
+    let kinds: Vec<(WatchKind, WatchAction)> =
+        hits.iter().map(|hit| (hit.kind, hit.action)).collect();
+    assert_eq!(
+        kinds,
+        vec![
+            (WatchKind::Read, WatchAction::Pause),
+            (WatchKind::Read, WatchAction::Log),
+            (WatchKind::Write, WatchAction::Log),
+        ]
+    );
+    assert_eq!(hits[0].to_string(), "read FF80 = 99 at PC 0000, cycle 0");
+}
@@ -0,0 +1,41 @@
+use super::*;
+use std::io::Cursor;
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[test]
+fn empty_file_header() {
+    let writer = WavWriter::new(Cursor::new(Vec::new()), 44_100).unwrap();
+    let bytes = writer.finish().unwrap().into_inner();
+
+    assert_eq!(bytes.len(), 44);
+    assert_eq!(&bytes[0..4], b"RIFF");
+    assert_eq!(read_u32(&bytes, 4), 36);
+    assert_eq!(&bytes[8..16], b"WAVEfmt ");
+    assert_eq!(read_u32(&bytes, 24), 44_100);
+    assert_eq!(read_u32(&bytes, 28), 44_100 * 4);
+    assert_eq!(&bytes[36..40], b"data");
+    assert_eq!(read_u32(&bytes, 40), 0);
+}
+
+#[test]
+fn samples_are_clamped() {
+    let mut writer = WavWriter::new(Cursor::new(Vec::new()), 44_100).unwrap();
+    writer.write_sample(1.0, -2.0).unwrap();
+    writer.write_sample(0.0, 0.5).unwrap();
+    let bytes = writer.finish().unwrap().into_inner();
+
+    assert_eq!(read_u32(&bytes, 4), 36 + 8);
+    assert_eq!(read_u32(&bytes, 40), 8);
+    assert_eq!(&bytes[44..46], &i16::MAX.to_le_bytes());
+    assert_eq!(&bytes[46..48], &(-i16::MAX).to_le_bytes());
+    assert_eq!(&bytes[48..50], &0i16.to_le_bytes());
+    assert_eq!(&bytes[50..52], &16383i16.to_le_bytes());
+}
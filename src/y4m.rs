@@ -0,0 +1,117 @@
+// Minimal writer for YUV4MPEG2 (Y4M) video, the uncompressed format ffmpeg,
+// mpv and x264 read directly. Frames are 4:2:0 with JPEG (full range BT.601)
+// colors, as C420jpeg and XCOLORRANGE=FULL in the header say.
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+pub struct Y4mWriter<W: Write> {
+    writer: W,
+    width: usize,
+    height: usize,
+    frames: u64,
+}
+
+impl Y4mWriter<BufWriter<File>> {
+    pub fn create(
+        path: &Path,
+        width: usize,
+        height: usize,
+        rate: (u32, u32),
+    ) -> io::Result<Y4mWriter<BufWriter<File>>> {
+        Y4mWriter::new(BufWriter::new(File::create(path)?), width, height, rate)
+    }
+}
+
+fn gcd(a: u32, b: u32) -> u32 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+// Full range BT.601, as JPEG uses
+fn to_ycbcr(rgb: &[u8]) -> (f32, f32, f32) {
+    let (r, g, b) = (rgb[0] as f32, rgb[1] as f32, rgb[2] as f32);
+    (
+        0.299 * r + 0.587 * g + 0.114 * b,
+        128.0 - 0.168_736 * r - 0.331_264 * g + 0.5 * b,
+        128.0 + 0.5 * r - 0.418_688 * g - 0.081_312 * b,
+    )
+}
+
+fn to_byte(value: f32) -> u8 {
+    value.round().clamp(0.0, 255.0) as u8
+}
+
+impl<W: Write> Y4mWriter<W> {
+    // rate is frames per second as numerator and denominator. Width and
+    // height must be even for the halved chroma planes.
+    pub fn new(
+        mut writer: W,
+        width: usize,
+        height: usize,
+        (numerator, denominator): (u32, u32),
+    ) -> io::Result<Y4mWriter<W>> {
+        assert!(width & 1 == 0 && height & 1 == 0);
+        let divisor = gcd(numerator, denominator);
+        writeln!(
+            writer,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C420jpeg XCOLORRANGE=FULL",
+            width,
+            height,
+            numerator / divisor,
+            denominator / divisor
+        )?;
+        Ok(Y4mWriter {
+            writer,
+            width,
+            height,
+            frames: 0,
+        })
+    }
+
+    pub fn frames(&self) -> u64 {
+        self.frames
+    }
+
+    // Takes 8 bit RGB, three bytes per pixel
+    pub fn write_frame(&mut self, rgb: &[u8]) -> io::Result<()> {
+        assert_eq!(rgb.len(), self.width * self.height * 3);
+        let pixels: Vec<(f32, f32, f32)> = rgb.chunks(3).map(to_ycbcr).collect();
+        let luma: Vec<u8> = pixels.iter().map(|(y, _, _)| to_byte(*y)).collect();
+
+        // Each chroma sample is the average of a 2x2 block
+        let mut cb = Vec::with_capacity(luma.len() / 4);
+        let mut cr = Vec::with_capacity(luma.len() / 4);
+        for y in (0..self.height).step_by(2) {
+            for x in (0..self.width).step_by(2) {
+                let block = [
+                    pixels[y * self.width + x],
+                    pixels[y * self.width + x + 1],
+                    pixels[(y + 1) * self.width + x],
+                    pixels[(y + 1) * self.width + x + 1],
+                ];
+                cb.push(to_byte(block.iter().map(|p| p.1).sum::<f32>() / 4.0));
+                cr.push(to_byte(block.iter().map(|p| p.2).sum::<f32>() / 4.0));
+            }
+        }
+
+        self.writer.write_all(b"FRAME\n")?;
+        self.writer.write_all(&luma)?;
+        self.writer.write_all(&cb)?;
+        self.writer.write_all(&cr)?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,29 @@
+use super::*;
+
+#[test]
+fn header_reduces_the_frame_rate() {
+    let writer = Y4mWriter::new(Vec::new(), 160, 144, (4_194_304, 70_224)).unwrap();
+    let bytes = writer.finish().unwrap();
+    assert_eq!(
+        String::from_utf8(bytes).unwrap(),
+        "YUV4MPEG2 W160 H144 F262144:4389 Ip A1:1 C420jpeg XCOLORRANGE=FULL\n"
+    );
+}
+
+#[test]
+fn frames_are_planar_420() {
+    let mut writer = Y4mWriter::new(Vec::new(), 2, 2, (60, 1)).unwrap();
+    // White, black, pure red and pure blue
+    let rgb = [255, 255, 255, 0, 0, 0, 255, 0, 0, 0, 0, 255];
+    writer.write_frame(&rgb).unwrap();
+    assert_eq!(writer.frames(), 1);
+    let bytes = writer.finish().unwrap();
+
+    let header = bytes.iter().position(|byte| *byte == b'\n').unwrap() + 1;
+    let frame = &bytes[header..];
+    assert_eq!(&frame[..6], b"FRAME\n");
+    assert_eq!(&frame[6..10], &[255, 0, 76, 29]);
+    // One chroma sample for the block, where the greys are neutral
+    assert_eq!(&frame[10..], &[149, 155]);
+    assert_eq!(frame.len(), 6 + 4 + 2);
+}